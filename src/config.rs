@@ -0,0 +1,389 @@
+/*
+Server settings, taken from the command line the way `redis-server` takes them:
+
+    xredis --port 6380 --databases 16 --save "3600 1 300 100" --appendonly yes --appendfsync everysec
+    xredis --hz 10 --active-expire-effort 1
+*/
+
+/// The port a server listens on unless told otherwise (Redis's default).
+pub const DEFAULT_PORT: u16 = 6379;
+
+/// How many logical databases a server has unless told otherwise (Redis's default).
+pub const DEFAULT_DATABASES: usize = 16;
+
+/// Save in the background once at least `changes` writes are `seconds` old.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SavePoint {
+    pub seconds: u64,
+    pub changes: u64,
+}
+
+/// Redis's default save points: after an hour if anything changed, after five
+/// minutes if 100 keys did, and after a minute if 10000 did.
+const DEFAULT_SAVE_POINTS: [SavePoint; 3] = [
+    SavePoint {
+        seconds: 3600,
+        changes: 1,
+    },
+    SavePoint {
+        seconds: 300,
+        changes: 100,
+    },
+    SavePoint {
+        seconds: 60,
+        changes: 10000,
+    },
+];
+
+/// How the snapshot file is written and read.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SnapshotFormat {
+    /// xredis's own JSON snapshot.
+    Json,
+    /// Redis's binary RDB format, readable by Redis and its tooling.
+    Rdb,
+}
+
+impl SnapshotFormat {
+    /// The snapshot file used when `--dbfilename` isn't given.
+    pub fn default_file_name(self) -> &'static str {
+        match self {
+            SnapshotFormat::Json => "xredisDB.json",
+            SnapshotFormat::Rdb => "dump.rdb",
+        }
+    }
+}
+
+/// When the append-only file is fsynced.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AppendFsync {
+    /// After every write: nothing acknowledged is ever lost, at the cost of speed.
+    Always,
+    /// Once a second, in the background: a crash loses at most about a second.
+    EverySec,
+    /// Never; the OS flushes when it likes.
+    No,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+    pub port: u16,
+    /// Number of logical databases, selectable as 0 to `databases - 1`.
+    pub databases: usize,
+    /// Start with empty databases instead of refusing to when the snapshot file
+    /// can't be parsed.
+    pub ignore_corrupt_snapshot: bool,
+    /// When to save automatically. Empty turns automatic saving off.
+    pub save_points: Vec<SavePoint>,
+    pub snapshot_format: SnapshotFormat,
+    /// The snapshot file. `None` uses the format's default name.
+    pub dbfilename: Option<String>,
+    /// Log every write to the append-only file, and rebuild from it on startup.
+    pub appendonly: bool,
+    pub appendfsync: AppendFsync,
+    pub appendfilename: String,
+    /// Rewrite the append-only file once it has grown by this many percent since
+    /// the last rewrite. 0 turns automatic rewrites off.
+    pub auto_aof_rewrite_percentage: u64,
+    /// ... but not while it's smaller than this many bytes.
+    pub auto_aof_rewrite_min_size: u64,
+    /// How many times a second background tasks such as the active expire cycle run.
+    pub hz: u32,
+    /// How hard, from 1 to 10, the active expire cycle works to evict expired
+    /// keys: higher samples more keys and may take more CPU.
+    pub active_expire_effort: u32,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            port: DEFAULT_PORT,
+            databases: DEFAULT_DATABASES,
+            ignore_corrupt_snapshot: false,
+            save_points: DEFAULT_SAVE_POINTS.to_vec(),
+            snapshot_format: SnapshotFormat::Json,
+            dbfilename: None,
+            appendonly: false,
+            appendfsync: AppendFsync::EverySec,
+            appendfilename: "appendonly.aof".to_string(),
+            auto_aof_rewrite_percentage: 100,
+            auto_aof_rewrite_min_size: 64 * 1024 * 1024,
+            hz: 10,
+            active_expire_effort: 1,
+        }
+    }
+}
+
+impl Config {
+    pub fn snapshot_file(&self) -> &str {
+        self.dbfilename
+            .as_deref()
+            .unwrap_or(self.snapshot_format.default_file_name())
+    }
+
+    /// Parses `--name value` options, the program name already skipped.
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Config, String> {
+        let mut config = Config::default();
+        let mut args = args.into_iter();
+        while let Some(name) = args.next() {
+            let Some(value) = args.next() else {
+                return Err(format!("missing value for '{}'", name));
+            };
+            match name.as_str() {
+                "--port" => {
+                    config.port = value
+                        .parse()
+                        .map_err(|_| format!("invalid port '{}'", value))?
+                }
+                "--databases" => {
+                    config.databases = match value.parse::<usize>() {
+                        Ok(count) if count > 0 => count,
+                        _ => return Err(format!("invalid number of databases '{}'", value)),
+                    }
+                }
+                "--ignore-corrupt-snapshot" => {
+                    config.ignore_corrupt_snapshot = parse_yes_no(&name, &value)?
+                }
+                "--save" => config.save_points = parse_save_points(&value)?,
+                "--snapshot-format" => {
+                    config.snapshot_format = match value.as_str() {
+                        "json" => SnapshotFormat::Json,
+                        "rdb" => SnapshotFormat::Rdb,
+                        _ => return Err(format!("invalid snapshot format '{}'", value)),
+                    }
+                }
+                "--dbfilename" => config.dbfilename = Some(value),
+                "--appendonly" => config.appendonly = parse_yes_no(&name, &value)?,
+                "--appendfsync" => {
+                    config.appendfsync = match value.as_str() {
+                        "always" => AppendFsync::Always,
+                        "everysec" => AppendFsync::EverySec,
+                        "no" => AppendFsync::No,
+                        _ => return Err(format!("invalid appendfsync policy '{}'", value)),
+                    }
+                }
+                "--appendfilename" => config.appendfilename = value,
+                "--auto-aof-rewrite-percentage" => {
+                    config.auto_aof_rewrite_percentage = value
+                        .parse()
+                        .map_err(|_| format!("invalid percentage '{}'", value))?
+                }
+                "--auto-aof-rewrite-min-size" => {
+                    config.auto_aof_rewrite_min_size = parse_size(&value)?
+                }
+                "--hz" => {
+                    config.hz = match value.parse::<u32>() {
+                        Ok(hz) if (1..=500).contains(&hz) => hz,
+                        _ => return Err(format!("invalid hz '{}', must be 1 to 500", value)),
+                    }
+                }
+                "--active-expire-effort" => {
+                    config.active_expire_effort = match value.parse::<u32>() {
+                        Ok(effort) if (1..=10).contains(&effort) => effort,
+                        _ => {
+                            return Err(format!(
+                                "invalid active-expire-effort '{}', must be 1 to 10",
+                                value
+                            ))
+                        }
+                    }
+                }
+                _ => return Err(format!("unknown option '{}'", name)),
+            }
+        }
+        Ok(config)
+    }
+}
+
+fn parse_yes_no(name: &str, value: &str) -> Result<bool, String> {
+    match value {
+        "yes" => Ok(true),
+        "no" => Ok(false),
+        _ => Err(format!("'{}' must be yes or no, not '{}'", name, value)),
+    }
+}
+
+/// Parses a size in bytes the way Redis does: `1k` is 1000 bytes, `1kb` is 1024,
+/// and likewise for `m`/`mb` and `g`/`gb`.
+fn parse_size(value: &str) -> Result<u64, String> {
+    let lower = value.to_ascii_lowercase();
+    let digits_end = lower
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(lower.len());
+    let (number, unit) = lower.split_at(digits_end);
+    let multiplier: u64 = match unit {
+        "" | "b" => 1,
+        "k" => 1000,
+        "kb" => 1024,
+        "m" => 1000 * 1000,
+        "mb" => 1024 * 1024,
+        "g" => 1000 * 1000 * 1000,
+        "gb" => 1024 * 1024 * 1024,
+        _ => return Err(format!("invalid size '{}'", value)),
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(multiplier))
+        .ok_or_else(|| format!("invalid size '{}'", value))
+}
+
+/// Parses `"seconds changes [seconds changes ...]"`; `""` means no save points.
+fn parse_save_points(value: &str) -> Result<Vec<SavePoint>, String> {
+    let numbers = value
+        .split_whitespace()
+        .map(|n| n.parse::<u64>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| format!("invalid save points '{}'", value))?;
+    if numbers.len() % 2 != 0 {
+        return Err(format!("invalid save points '{}'", value));
+    }
+    Ok(numbers
+        .chunks(2)
+        .map(|pair| SavePoint {
+            seconds: pair[0],
+            changes: pair[1],
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AppendFsync, Config, SavePoint, SnapshotFormat, DEFAULT_DATABASES};
+
+    fn parse(args: &[&str]) -> Result<Config, String> {
+        Config::from_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_parses_port() {
+        assert_eq!(parse(&[]).unwrap().port, 6379);
+        assert_eq!(parse(&["--port", "6380"]).unwrap().port, 6380);
+        assert!(parse(&["--port", "70000"]).is_err());
+    }
+
+    #[test]
+    fn test_defaults_to_sixteen_databases() {
+        assert_eq!(parse(&[]).unwrap().databases, DEFAULT_DATABASES);
+    }
+
+    #[test]
+    fn test_parses_database_count() {
+        assert_eq!(parse(&["--databases", "4"]).unwrap().databases, 4);
+        assert!(parse(&["--databases", "0"]).is_err());
+        assert!(parse(&["--databases"]).is_err());
+        assert!(parse(&["--bind", "0.0.0.0"]).is_err());
+    }
+
+    #[test]
+    fn test_parses_yes_no_flags() {
+        assert!(!parse(&[]).unwrap().ignore_corrupt_snapshot);
+        assert!(
+            parse(&["--ignore-corrupt-snapshot", "yes"])
+                .unwrap()
+                .ignore_corrupt_snapshot
+        );
+        assert!(parse(&["--ignore-corrupt-snapshot", "true"]).is_err());
+    }
+
+    #[test]
+    fn test_parses_save_points() {
+        assert_eq!(parse(&[]).unwrap().save_points.len(), 3);
+        assert_eq!(
+            parse(&["--save", "300 10 60 10000"]).unwrap().save_points,
+            vec![
+                SavePoint {
+                    seconds: 300,
+                    changes: 10
+                },
+                SavePoint {
+                    seconds: 60,
+                    changes: 10000
+                }
+            ]
+        );
+        assert!(parse(&["--save", ""]).unwrap().save_points.is_empty());
+        assert!(parse(&["--save", "300"]).is_err());
+        assert!(parse(&["--save", "300 ten"]).is_err());
+    }
+
+    #[test]
+    fn test_parses_snapshot_format_and_file() {
+        let config = parse(&[]).unwrap();
+        assert_eq!(config.snapshot_format, SnapshotFormat::Json);
+        assert_eq!(config.snapshot_file(), "xredisDB.json");
+
+        let config = parse(&["--snapshot-format", "rdb"]).unwrap();
+        assert_eq!(config.snapshot_format, SnapshotFormat::Rdb);
+        assert_eq!(config.snapshot_file(), "dump.rdb");
+        assert_eq!(
+            parse(&["--snapshot-format", "rdb", "--dbfilename", "backup.rdb"])
+                .unwrap()
+                .snapshot_file(),
+            "backup.rdb"
+        );
+        assert!(parse(&["--snapshot-format", "xml"]).is_err());
+    }
+
+    #[test]
+    fn test_parses_append_only_options() {
+        let config = parse(&[]).unwrap();
+        assert!(!config.appendonly);
+        assert_eq!(config.appendfsync, AppendFsync::EverySec);
+
+        let config = parse(&[
+            "--appendonly",
+            "yes",
+            "--appendfsync",
+            "always",
+            "--appendfilename",
+            "log.aof",
+        ])
+        .unwrap();
+        assert!(config.appendonly);
+        assert_eq!(config.appendfsync, AppendFsync::Always);
+        assert_eq!(config.appendfilename, "log.aof");
+        assert!(parse(&["--appendfsync", "sometimes"]).is_err());
+    }
+
+    #[test]
+    fn test_parses_auto_rewrite_thresholds() {
+        let config = parse(&[]).unwrap();
+        assert_eq!(config.auto_aof_rewrite_percentage, 100);
+        assert_eq!(config.auto_aof_rewrite_min_size, 64 * 1024 * 1024);
+
+        let config = parse(&[
+            "--auto-aof-rewrite-percentage",
+            "0",
+            "--auto-aof-rewrite-min-size",
+            "16MB",
+        ])
+        .unwrap();
+        assert_eq!(config.auto_aof_rewrite_percentage, 0);
+        assert_eq!(config.auto_aof_rewrite_min_size, 16 * 1024 * 1024);
+        assert_eq!(
+            parse(&["--auto-aof-rewrite-min-size", "2k"])
+                .unwrap()
+                .auto_aof_rewrite_min_size,
+            2000
+        );
+        assert!(parse(&["--auto-aof-rewrite-min-size", "2tb"]).is_err());
+        assert!(parse(&["--auto-aof-rewrite-min-size", "mb"]).is_err());
+        assert!(parse(&["--auto-aof-rewrite-percentage", "-1"]).is_err());
+    }
+
+    #[test]
+    fn test_parses_active_expire_options() {
+        let config = parse(&[]).unwrap();
+        assert_eq!(config.hz, 10);
+        assert_eq!(config.active_expire_effort, 1);
+
+        let config = parse(&["--hz", "100", "--active-expire-effort", "10"]).unwrap();
+        assert_eq!(config.hz, 100);
+        assert_eq!(config.active_expire_effort, 10);
+        assert!(parse(&["--hz", "0"]).is_err());
+        assert!(parse(&["--hz", "501"]).is_err());
+        assert!(parse(&["--active-expire-effort", "0"]).is_err());
+        assert!(parse(&["--active-expire-effort", "11"]).is_err());
+    }
+}
@@ -0,0 +1,203 @@
+use crate::handler::aof::Aof;
+use crate::handler::commands::random_u64;
+use crate::handler::databases::Databases;
+use crate::handler::keyspace::Keyspace;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/*
+Active expiration: a background task that evicts expired keys nobody reads.
+
+Lazy expiry only drops a key when a command touches it, so this mirrors Redis's
+active expire cycle. Every `period` it samples random keys that have a TTL and
+deletes the expired ones. If enough of a sample was expired, more probably are,
+so it samples again, until that stops being true or the cycle's time budget runs
+out.
+
+Every database takes its turn within the same time budget. Each period starts
+one database further along, so a slow one can't keep the last ones from ever
+being looked at. With the append-only file on, a cycle holds the log like a
+command does, and logs a DEL for each key it evicts.
+*/
+
+/// Keys that may have a TTL, with O(1) random sampling.
+///
+/// Writers only have to add a key when they give it a TTL. Deleted keys and keys
+/// whose TTL was removed are pruned when a sample runs into them.
+#[derive(Default)]
+pub struct VolatileKeys {
+    keys: Vec<Vec<u8>>,
+    positions: HashMap<Vec<u8>, usize>,
+}
+
+impl VolatileKeys {
+    pub fn insert(&mut self, key: &[u8]) {
+        if !self.positions.contains_key(key) {
+            self.positions.insert(key.to_vec(), self.keys.len());
+            self.keys.push(key.to_vec());
+        }
+    }
+
+    pub fn remove(&mut self, key: &[u8]) {
+        let Some(position) = self.positions.remove(key) else {
+            return;
+        };
+        self.keys.swap_remove(position);
+        if let Some(moved) = self.keys.get(position) {
+            self.positions.insert(moved.clone(), position);
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    fn random(&self) -> Option<Vec<u8>> {
+        if self.keys.is_empty() {
+            return None;
+        }
+        Some(self.keys[random_u64() as usize % self.keys.len()].clone())
+    }
+}
+
+/// Tuning for the active expire cycle.
+#[derive(Clone, Debug)]
+pub struct ActiveExpireConfig {
+    /// How often a cycle runs.
+    pub period: Duration,
+    /// Share of each period, in percent, a cycle may spend holding the lock.
+    pub cpu_budget_percent: u32,
+    /// Keys sampled per round.
+    pub keys_per_round: usize,
+    /// Keep sampling while more than this percentage of a round was expired.
+    pub stale_percent: usize,
+}
+
+impl Default for ActiveExpireConfig {
+    // Redis's defaults: 10 cycles a second, 25% CPU, 20 keys, 10% stale.
+    fn default() -> Self {
+        ActiveExpireConfig {
+            period: Duration::from_millis(100),
+            cpu_budget_percent: 25,
+            keys_per_round: 20,
+            stale_percent: 10,
+        }
+    }
+}
+
+impl ActiveExpireConfig {
+    /// The tuning for `hz` cycles a second at `effort` 1 to 10, scaled the way
+    /// Redis scales `active-expire-effort`: each step above 1 samples 5 more keys
+    /// a round, allows 2% more CPU and tolerates 1% fewer expired keys.
+    pub fn new(hz: u32, effort: u32) -> ActiveExpireConfig {
+        let extra = effort.clamp(1, 10) - 1;
+        let defaults = ActiveExpireConfig::default();
+        ActiveExpireConfig {
+            period: Duration::from_secs(1) / hz.max(1),
+            cpu_budget_percent: defaults.cpu_budget_percent + 2 * extra,
+            keys_per_round: defaults.keys_per_round + defaults.keys_per_round / 4 * extra as usize,
+            stale_percent: defaults.stale_percent - extra as usize,
+        }
+    }
+
+    /// When a cycle starting now has used up its time budget.
+    pub fn deadline(&self) -> Instant {
+        Instant::now() + self.period * self.cpu_budget_percent / 100
+    }
+}
+
+/// Expiry counters, reported by INFO.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ExpireStats {
+    /// Keys deleted because their TTL had passed, by the cycle or by a command
+    /// that ran into them.
+    pub expired_keys: u64,
+    /// Cycles run so far.
+    pub cycles: u64,
+    /// Cycles cut short by the time budget while keys were still expiring.
+    pub time_limit_exits: u64,
+}
+
+/// Runs one cycle against `keyspace`, cut short once `deadline` passes. Returns
+/// how many keys it expired.
+pub fn active_expire_cycle(
+    keyspace: &mut Keyspace,
+    config: &ActiveExpireConfig,
+    deadline: Instant,
+) -> u64 {
+    let mut expired_total = 0;
+    keyspace.expire_stats.cycles += 1;
+
+    loop {
+        let now = keyspace.now();
+        let mut sampled = 0;
+        let mut stale = 0;
+        while sampled < config.keys_per_round.min(keyspace.volatile.len()) {
+            let Some(key) = keyspace.volatile.random() else {
+                break;
+            };
+            sampled += 1;
+            match keyspace.entries.get(&key).map(|entry| entry.expiry) {
+                Some(Some(expiry)) if now >= expiry => {
+                    keyspace.entries.remove(&key);
+                    keyspace.volatile.remove(&key);
+                    keyspace.dirty += 1;
+                    if let Some(propagation) = &keyspace.propagation {
+                        propagation.expired(&key);
+                    }
+                    expired_total += 1;
+                    stale += 1;
+                }
+                Some(Some(_)) => {}
+                // Deleted or made persistent since it was indexed.
+                _ => {
+                    keyspace.volatile.remove(&key);
+                    stale += 1;
+                }
+            }
+        }
+
+        if sampled == 0 || stale * 100 <= sampled * config.stale_percent {
+            break;
+        }
+        if Instant::now() >= deadline {
+            keyspace.expire_stats.time_limit_exits += 1;
+            break;
+        }
+    }
+
+    keyspace.expire_stats.expired_keys += expired_total;
+    expired_total
+}
+
+/// Runs the active expire cycle over every database every `config.period`, forever,
+/// logging the evictions to `aof` if there is one.
+pub async fn run_active_expire(
+    databases: Databases,
+    config: ActiveExpireConfig,
+    aof: Option<Arc<Aof>>,
+) {
+    let mut interval = tokio::time::interval(config.period);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let mut first = 0;
+    loop {
+        interval.tick().await;
+        let deadline = config.deadline();
+        for offset in 0..databases.count() {
+            if Instant::now() >= deadline {
+                break;
+            }
+            let index = (first + offset) % databases.count();
+            let log = match &aof {
+                Some(aof) => Some(aof.lock().await),
+                None => None,
+            };
+            active_expire_cycle(&mut *databases.get(index).lock().await, &config, deadline);
+            if let (Some(aof), Some(mut log)) = (&aof, log) {
+                log.append(&aof.expired_dels(index..index + 1));
+            }
+        }
+        first = (first + 1) % databases.count();
+    }
+}
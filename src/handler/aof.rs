@@ -0,0 +1,1072 @@
+use crate::config::{AppendFsync, SnapshotFormat};
+use crate::handler::blocking::BlockingOp;
+use crate::handler::clock::{LoadingClock, SystemClock};
+use crate::handler::commands::{format_float, handle_session_command, parse_i64};
+use crate::handler::databases::{Databases, Session};
+use crate::handler::expire_commands::{absolute_millis, ExpireAt, TimeUnit};
+use crate::handler::keyspace::Keyspace;
+use crate::handler::list_commands::ListEnd;
+use crate::handler::persistence::{
+    capture, sync_parent_dir, temp_path, write_file_atomically, SavedDatabase, Snapshotter,
+};
+use crate::handler::stream_commands::{parse_trim, Delivery};
+use crate::handler::string_commands::{expiry_option, parse_expiry};
+use crate::handler::value::{Stream, StreamId, Value, ValueWithExpiry};
+use crate::handler::zset_commands::ScoreEnd;
+use crate::resp::resp_protocol::{RespDecoder, RespMessage};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, PoisonError};
+use std::time::Duration;
+use tokio::sync::{Mutex, MutexGuard};
+use tokio::task::JoinHandle;
+
+/*
+The append-only file: every write command, in RESP, in the order it was applied.
+
+With `--appendonly yes` the server rebuilds its databases on startup by replaying
+the file, and the JSON snapshot is only read when there is no file yet, to seed it.
+
+Commands are logged as what they did rather than as what the client sent, so a
+replay long after the fact lands on the same data: relative TTLs become absolute
+`PEXPIREAT` / `PXAT` times, `XADD key * ...` gets its generated ID, `SPOP` becomes
+an `SREM` of the members it popped, `XREADGROUP` becomes an `XCLAIM` of each entry
+it delivered, stamped with when, and blocking commands become the plain pop or move
+they ended up doing. A `SELECT` goes in whenever the database changes.
+
+A write keeps the log locked for as long as it runs, so the file has writes in the
+order the databases saw them. Every expiration, by a command or by the active
+expire cycle, is logged as a `DEL` ahead of whatever caused it, as Redis does.
+Replay can't expire anything itself, since a key's TTL may be followed by commands
+that need the key. Reads delete the expired keys they run into too, so each
+database also takes turns: a read waits for the write running in its database to
+be logged, and only locks the log if it has `DEL`s to add, leaving reads in other
+databases free to run. A command that parks its client lets go of both while it
+waits; the write that serves it logs the pop or move it did for it right after
+itself.
+
+A crash can leave half a command at the end of the file. Loading drops it and
+truncates the file back to the last complete command.
+
+BGREWRITEAOF, or the file doubling in size, replaces the file with the shortest one
+that rebuilds the current data. Like BGSAVE, the rewrite copies the data under the
+locks and writes it out on a blocking thread; writes that come in meanwhile are kept
+in memory as well as logged, added to the end of the new file, which is then
+renamed over the old one with the log locked.
+*/
+
+/// The most elements a rewritten collection puts in one command (Redis's
+/// `AOF_REWRITE_ITEMS_PER_CMD`).
+const ITEMS_PER_COMMAND: usize = 64;
+
+/// How long to wait after a failed automatic rewrite before trying again.
+const REWRITE_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// A command as it is logged: its name, then its arguments.
+pub type LoggedCommand = Vec<Vec<u8>>;
+
+/// What the log has to record that a database's commands don't report themselves.
+#[derive(Default)]
+struct SideEffects {
+    /// Keys deleted because their TTL was up.
+    expired: Vec<Vec<u8>>,
+    /// Commands the running write logs in place of itself.
+    propagated: Vec<LoggedCommand>,
+    /// What was done for the blocked clients a write served.
+    served: Vec<LoggedCommand>,
+    /// Whether the running command parked its client.
+    parked: bool,
+}
+
+/// A database's link to the append-only file, for recording its side effects.
+#[derive(Clone)]
+pub struct Propagation {
+    effects: Arc<std::sync::Mutex<SideEffects>>,
+}
+
+impl Propagation {
+    fn effects(&self) -> std::sync::MutexGuard<'_, SideEffects> {
+        self.effects.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Records that `key` expired and was deleted.
+    pub fn expired(&self, key: &[u8]) {
+        self.effects().expired.push(key.to_vec());
+    }
+
+    /// Records `command` to be logged in place of the running write.
+    pub fn propagate(&self, command: LoggedCommand) {
+        self.effects().propagated.push(command);
+    }
+
+    /// Records `command` as done for a blocked client the running write served.
+    pub fn served(&self, command: LoggedCommand) {
+        self.effects().served.push(command);
+    }
+
+    /// Records that the running command parked its client.
+    pub fn parked(&self) {
+        self.effects().parked = true;
+    }
+}
+
+/// What the log keeps for each database.
+#[derive(Default)]
+struct LoggedDatabase {
+    /// Held by a command for as long as it runs in the database, so nothing it
+    /// logs can swap places with the expirations a read there runs into.
+    order: Mutex<()>,
+    effects: Arc<std::sync::Mutex<SideEffects>>,
+}
+
+impl LoggedDatabase {
+    fn effects(&self) -> std::sync::MutexGuard<'_, SideEffects> {
+        self.effects.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum AofError {
+    #[error("can't read the append-only file: {0}")]
+    Io(#[from] io::Error),
+    #[error("corrupt append-only file: {0}")]
+    Corrupt(String),
+}
+
+/// What replaying the append-only file found.
+#[derive(Debug, Default, PartialEq)]
+pub struct LoadedAof {
+    pub commands: usize,
+    /// Bytes of an incomplete last command, cut from the end of the file.
+    pub truncated_bytes: u64,
+}
+
+/// The append-only file a server logs its writes to, shared by every client.
+pub struct Aof {
+    path: PathBuf,
+    log: Mutex<AofLog>,
+    rewrite_in_progress: AtomicBool,
+    last_rewrite_ok: AtomicBool,
+    databases: Vec<LoggedDatabase>,
+}
+
+pub struct AofLog {
+    file: Arc<File>,
+    fsync: AppendFsync,
+    /// The database the last logged command ran in.
+    selected: Option<usize>,
+    /// How much of the file holds complete commands.
+    len: u64,
+    /// Commands not written yet, because the last write failed.
+    unwritten: Vec<u8>,
+    /// Whether anything was written since the last fsync.
+    unsynced: bool,
+    /// Why the last write or fsync failed. Writes are refused until it clears.
+    error: Option<String>,
+    /// The size of the file after the last rewrite, or at startup.
+    base_size: u64,
+    /// Writes logged since a running rewrite copied the data.
+    rewrite_buffer: Option<Vec<u8>>,
+}
+
+impl Aof {
+    /// Opens the file at `path`, which logs `databases` databases, for appending,
+    /// creating it if needed.
+    pub fn open(path: &Path, fsync: AppendFsync, databases: usize) -> io::Result<Aof> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let len = file.metadata()?.len();
+        Ok(Aof {
+            path: path.to_path_buf(),
+            log: Mutex::new(AofLog {
+                file: Arc::new(file),
+                fsync,
+                selected: None,
+                len,
+                unwritten: Vec::new(),
+                unsynced: false,
+                error: None,
+                base_size: len,
+                rewrite_buffer: None,
+            }),
+            rewrite_in_progress: AtomicBool::new(false),
+            last_rewrite_ok: AtomicBool::new(true),
+            databases: (0..databases).map(|_| LoggedDatabase::default()).collect(),
+        })
+    }
+
+    /// Has every database in `databases`, the ones logged here, record its side
+    /// effects for the log.
+    pub async fn attach(&self, databases: &Databases) {
+        for (db, logged) in databases.iter().zip(&self.databases) {
+            db.lock().await.propagation = Some(Propagation {
+                effects: Arc::clone(&logged.effects),
+            });
+        }
+    }
+
+    /// Takes what `field` holds for each of `databases`, paired with its index.
+    fn take_effects<T>(
+        &self,
+        databases: Range<usize>,
+        field: impl Fn(&mut SideEffects) -> &mut Vec<T>,
+    ) -> Vec<(usize, T)> {
+        let mut taken = Vec::new();
+        for index in databases {
+            let mut effects = self.databases[index].effects();
+            taken.extend(
+                std::mem::take(field(&mut effects))
+                    .into_iter()
+                    .map(|t| (index, t)),
+            );
+        }
+        taken
+    }
+
+    /// A `DEL` for each key of `databases` that expired since the last call.
+    /// Taken in their order, so they go in before anything that follows.
+    pub fn expired_dels(&self, databases: Range<usize>) -> Vec<(usize, LoggedCommand)> {
+        self.take_effects(databases, |effects| &mut effects.expired)
+            .into_iter()
+            .map(|(db, key)| (db, logged(&[b"DEL", &key])))
+            .collect()
+    }
+
+    /// What the running write in `databases` logs in place of itself, if it said.
+    pub fn propagated_commands(&self, databases: Range<usize>) -> Vec<(usize, LoggedCommand)> {
+        self.take_effects(databases, |effects| &mut effects.propagated)
+    }
+
+    /// What was done for blocked clients of `databases` since the last call, to log
+    /// right after the write that served them.
+    pub fn served_commands(&self, databases: Range<usize>) -> Vec<(usize, LoggedCommand)> {
+        self.take_effects(databases, |effects| &mut effects.served)
+    }
+
+    /// Whether the command running in database `db` parked its client, clearing
+    /// the flag.
+    pub fn take_parked(&self, db: usize) -> bool {
+        std::mem::take(&mut self.databases[db].effects().parked)
+    }
+
+    /// Waits for the commands running in `databases` to finish, and keeps others
+    /// from starting there until the guards are dropped. Taken before the log.
+    pub async fn order(&self, databases: Range<usize>) -> Vec<MutexGuard<'_, ()>> {
+        let mut guards = Vec::new();
+        for logged in &self.databases[databases] {
+            guards.push(logged.order.lock().await);
+        }
+        guards
+    }
+
+    /// Locks the log. Holding it keeps every other write command from running.
+    pub async fn lock(&self) -> MutexGuard<'_, AofLog> {
+        self.log.lock().await
+    }
+
+    pub fn rewrite_in_progress(&self) -> bool {
+        self.rewrite_in_progress.load(Ordering::SeqCst)
+    }
+
+    pub fn last_rewrite_ok(&self) -> bool {
+        self.last_rewrite_ok.load(Ordering::SeqCst)
+    }
+
+    /// Whether the file has grown by `percentage` percent since the last rewrite,
+    /// and to at least `min_size` bytes. A `percentage` of 0 never calls for one.
+    pub async fn rewrite_due(&self, percentage: u64, min_size: u64) -> bool {
+        if percentage == 0 || self.rewrite_in_progress() {
+            return false;
+        }
+        let log = self.lock().await;
+        let base = log.base_size.max(1);
+        log.len >= min_size && log.len.saturating_sub(base) * 100 / base >= percentage
+    }
+
+    /// Starts rewriting the file in the background from the data in `databases`.
+    /// Returns the rewriting task, or `None` if a rewrite is already running.
+    pub async fn start_rewrite(self: &Arc<Self>, databases: &Databases) -> Option<JoinHandle<()>> {
+        if self.rewrite_in_progress.swap(true, Ordering::SeqCst) {
+            return None;
+        }
+        let dataset = {
+            let mut log = self.lock().await;
+            let guards = databases.lock_all().await;
+            log.rewrite_buffer = Some(Vec::new());
+            // Start the writes the new file gets with a SELECT, whatever database
+            // the copied data happens to end on.
+            log.selected = None;
+            capture(guards.iter().map(|keyspace| &**keyspace))
+        };
+
+        let aof = Arc::clone(self);
+        Some(tokio::spawn(async move {
+            let result = aof.rewrite(dataset).await;
+            if let Err(e) = &result {
+                eprintln!("Background AOF rewrite error: {}", e);
+                aof.lock().await.rewrite_buffer = None;
+                let _ = fs::remove_file(temp_path(&aof.path));
+            }
+            aof.last_rewrite_ok.store(result.is_ok(), Ordering::SeqCst);
+            aof.rewrite_in_progress.store(false, Ordering::SeqCst);
+        }))
+    }
+
+    async fn rewrite(&self, dataset: Vec<SavedDatabase>) -> io::Result<()> {
+        let temp = temp_path(&self.path);
+        let mut file = {
+            let temp = temp.clone();
+            tokio::task::spawn_blocking(move || {
+                let base = encode_base(dataset.iter().map(|(index, entries)| {
+                    (*index, entries.iter().map(|(key, entry)| (key, entry)))
+                }));
+                let mut file = File::create(&temp)?;
+                file.write_all(&base)?;
+                file.sync_data()?;
+                Ok::<_, io::Error>(file)
+            })
+            .await
+            .map_err(io::Error::other)??
+        };
+
+        // With the log locked no write can land in the old file once its tail has
+        // been copied, and the new file is opened before it takes the old one's
+        // name, so nothing after the rename can fail.
+        let mut log = self.lock().await;
+        file.write_all(log.rewrite_buffer.as_deref().unwrap_or_default())?;
+        file.sync_data()?;
+        let len = file.metadata()?.len();
+        let appender = OpenOptions::new().append(true).open(&temp)?;
+        fs::rename(&temp, &self.path)?;
+
+        log.file = Arc::new(appender);
+        log.len = len;
+        log.base_size = len;
+        log.rewrite_buffer = None;
+        // Anything a failed write left behind was in the rewrite buffer.
+        log.unwritten.clear();
+        log.unsynced = false;
+        log.error = None;
+        drop(log);
+        if let Err(e) = sync_parent_dir(&self.path) {
+            eprintln!("Error syncing the AOF directory: {}", e);
+        }
+        Ok(())
+    }
+}
+
+impl AofLog {
+    /// Refuses writes while the log can't be written, like Redis does, so clients
+    /// never get a write acknowledged that a restart would lose.
+    pub fn check_writable(&self) -> Result<(), RespMessage> {
+        match &self.error {
+            Some(e) => Err(RespMessage::Error(format!(
+                "MISCONF Errors writing to the AOF file: {}",
+                e
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    pub fn last_write_ok(&self) -> bool {
+        self.error.is_none()
+    }
+
+    /// The size of the file, in bytes.
+    pub fn current_size(&self) -> u64 {
+        self.len
+    }
+
+    /// The size of the file after the last rewrite, or at startup.
+    pub fn base_size(&self) -> u64 {
+        self.base_size
+    }
+
+    /// Logs `commands`, each run in the database it's paired with.
+    pub fn append(&mut self, commands: &[(usize, LoggedCommand)]) {
+        if commands.is_empty() {
+            return;
+        }
+        for (db, command) in commands {
+            self.encode(*db, command);
+        }
+        self.flush();
+    }
+
+    /// Queues `command`, run in database `db`, for the next flush.
+    fn encode(&mut self, db: usize, command: &LoggedCommand) {
+        let start = self.unwritten.len();
+        if self.selected != Some(db) {
+            encode_command(&select_command(db), &mut self.unwritten);
+            self.selected = Some(db);
+        }
+        encode_command(command, &mut self.unwritten);
+        if let Some(buffer) = &mut self.rewrite_buffer {
+            buffer.extend_from_slice(&self.unwritten[start..]);
+        }
+    }
+
+    /// Writes out whatever is waiting, fsyncing too if the policy is `always`.
+    fn flush(&mut self) {
+        if !self.unwritten.is_empty() {
+            if let Err(e) = (&*self.file).write_all(&self.unwritten) {
+                // Cut off whatever part did get written, so the file still ends on
+                // a complete command, and try the whole lot again later.
+                let _ = self.file.set_len(self.len);
+                return self.fail(e);
+            }
+            self.len += self.unwritten.len() as u64;
+            self.unwritten.clear();
+            self.unsynced = true;
+        }
+        if self.fsync == AppendFsync::Always && self.unsynced {
+            if let Err(e) = self.file.sync_data() {
+                return self.fail(e);
+            }
+            self.unsynced = false;
+        }
+        self.error = None;
+    }
+
+    fn fail(&mut self, e: io::Error) {
+        if self.error.is_none() {
+            eprintln!("Error writing to the AOF file: {}", e);
+        }
+        self.error = Some(e.to_string());
+    }
+}
+
+/// Once a second, forever: retries a failed write, and with `everysec` fsyncs
+/// what was written since the last tick, off the async threads.
+pub async fn run_aof_fsync(aof: Arc<Aof>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        interval.tick().await;
+        let file = {
+            let mut log = aof.lock().await;
+            log.flush();
+            if log.fsync != AppendFsync::EverySec || !log.unsynced {
+                continue;
+            }
+            log.unsynced = false;
+            Arc::clone(&log.file)
+        };
+        let synced = tokio::task::spawn_blocking(move || file.sync_data()).await;
+        if let Ok(Err(e)) = synced {
+            eprintln!("Error fsyncing the AOF file: {}", e);
+            aof.lock().await.unsynced = true;
+        }
+    }
+}
+
+/// Once a second, forever: rewrites the file once it has grown by `percentage`
+/// percent since the last rewrite and is at least `min_size` bytes.
+pub async fn run_auto_rewrite(aof: Arc<Aof>, databases: Databases, percentage: u64, min_size: u64) {
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        interval.tick().await;
+        if !aof.rewrite_due(percentage, min_size).await {
+            continue;
+        }
+        if let Some(rewrite) = aof.start_rewrite(&databases).await {
+            let _ = rewrite.await;
+            if !aof.last_rewrite_ok() {
+                tokio::time::sleep(REWRITE_RETRY_DELAY).await;
+            }
+        }
+    }
+}
+
+fn encode_command(command: &LoggedCommand, out: &mut Vec<u8>) {
+    RespMessage::Array(
+        command
+            .iter()
+            .map(|arg| RespMessage::BulkString(Some(arg.clone())))
+            .collect(),
+    )
+    .encode(out);
+}
+
+fn logged(parts: &[&[u8]]) -> LoggedCommand {
+    parts.iter().map(|part| part.to_vec()).collect()
+}
+
+fn select_command(db: usize) -> LoggedCommand {
+    logged(&[b"SELECT", db.to_string().as_bytes()])
+}
+
+fn list_end(end: ListEnd) -> &'static [u8] {
+    match end {
+        ListEnd::Left => b"LEFT",
+        ListEnd::Right => b"RIGHT",
+    }
+}
+
+/// What serving a client blocked on `key` with `op` did, as a command that does it
+/// again. XREAD only reads, so it has none, and what XREADGROUP did depends on the
+/// entries it delivered (see `delivery_form`).
+pub fn served_form(key: &[u8], op: &BlockingOp) -> Option<LoggedCommand> {
+    match op {
+        BlockingOp::Pop(ListEnd::Left) => Some(logged(&[b"LPOP", key])),
+        BlockingOp::Pop(ListEnd::Right) => Some(logged(&[b"RPOP", key])),
+        BlockingOp::Move {
+            from,
+            to,
+            destination,
+        } => Some(logged(&[
+            b"LMOVE",
+            key,
+            destination,
+            list_end(*from),
+            list_end(*to),
+        ])),
+        BlockingOp::PopScored(ScoreEnd::Min) => Some(logged(&[b"ZPOPMIN", key])),
+        BlockingOp::PopScored(ScoreEnd::Max) => Some(logged(&[b"ZPOPMAX", key])),
+        BlockingOp::ReadStream { .. } | BlockingOp::ReadGroup { .. } => None,
+    }
+}
+
+/// What XREADGROUP handing `delivery` from `key` to `consumer` at `now` did, as
+/// Redis propagates it: an XCLAIM per pending entry with its delivery time spelled
+/// out, then the group's new last delivered ID. Replaying the read itself would
+/// stamp the entries with the time of the replay.
+pub fn delivery_form(
+    key: &[u8],
+    group: &[u8],
+    consumer: &[u8],
+    delivery: &Delivery,
+    no_ack: bool,
+    now: u128,
+) -> Vec<LoggedCommand> {
+    let mut commands = Vec::new();
+    if delivery.new_consumer && (no_ack || delivery.entries.is_empty()) {
+        // No XCLAIM creates it.
+        commands.push(logged(&[
+            b"XGROUP",
+            b"CREATECONSUMER",
+            key,
+            group,
+            consumer,
+        ]));
+    }
+    let now = now.to_string();
+    if !no_ack {
+        for (id, _) in &delivery.entries {
+            commands.push(logged(&[
+                b"XCLAIM",
+                key,
+                group,
+                consumer,
+                b"0",
+                id.to_string().as_bytes(),
+                b"TIME",
+                now.as_bytes(),
+                b"RETRYCOUNT",
+                b"1",
+                b"FORCE",
+                b"JUSTID",
+            ]));
+        }
+    }
+    if let Some((last, _)) = delivery.entries.last() {
+        commands.push(logged(&[
+            b"XGROUP",
+            b"SETID",
+            key,
+            group,
+            last.to_string().as_bytes(),
+        ]));
+    }
+    commands
+}
+
+/// The commands to log for `args`, which ran at `now` (Unix milliseconds) and
+/// replied `reply`: ones that do the same thing whenever they're replayed. Empty
+/// when the command turned out not to change anything worth logging.
+pub fn propagated_form(
+    cmd: &str,
+    args: &[&[u8]],
+    reply: &RespMessage,
+    now: u128,
+) -> Vec<LoggedCommand> {
+    match cmd {
+        "EXPIRE" | "PEXPIRE" | "EXPIREAT" | "PEXPIREAT" => {
+            if *reply != RespMessage::Integer(1) {
+                return Vec::new();
+            }
+            let unit = match cmd {
+                "EXPIRE" | "EXPIREAT" => TimeUnit::Seconds,
+                _ => TimeUnit::Milliseconds,
+            };
+            let at = match cmd {
+                "EXPIRE" | "PEXPIRE" => ExpireAt::Relative,
+                _ => ExpireAt::Absolute,
+            };
+            let when = parse_i64(args[2])
+                .ok()
+                .and_then(|amount| absolute_millis(amount, unit, at, now as i64));
+            match when {
+                Some(when) if when > now as i64 => {
+                    vec![logged(&[
+                        b"PEXPIREAT",
+                        args[1],
+                        when.to_string().as_bytes(),
+                    ])]
+                }
+                // The TTL was already up, so the key was deleted.
+                _ => vec![logged(&[b"DEL", args[1]])],
+            }
+        }
+        "SETEX" | "PSETEX" => {
+            let unit = match cmd {
+                "SETEX" => TimeUnit::Seconds,
+                _ => TimeUnit::Milliseconds,
+            };
+            match parse_expiry(args[0], args[2], unit, ExpireAt::Relative, now) {
+                Ok(when) => vec![logged(&[
+                    b"SET",
+                    args[1],
+                    args[3],
+                    b"PXAT",
+                    when.to_string().as_bytes(),
+                ])],
+                Err(_) => Vec::new(),
+            }
+        }
+        "SET" => vec![with_absolute_expiry(args, 3, now)],
+        "GETEX" => match reply {
+            RespMessage::BulkString(None) => Vec::new(),
+            _ => vec![with_absolute_expiry(args, 2, now)],
+        },
+        "SPOP" => {
+            let members: Vec<&[u8]> = match reply {
+                RespMessage::BulkString(Some(member)) => vec![member],
+                RespMessage::Array(members) => members.iter().filter_map(bulk_bytes).collect(),
+                _ => Vec::new(),
+            };
+            if members.is_empty() {
+                return Vec::new();
+            }
+            let mut command = logged(&[b"SREM", args[1]]);
+            command.extend(members.into_iter().map(<[u8]>::to_vec));
+            vec![command]
+        }
+        "BLPOP" | "BRPOP" | "BZPOPMIN" | "BZPOPMAX" => {
+            let Some(key) = reply_items(reply).first().and_then(bulk_bytes) else {
+                return Vec::new();
+            };
+            let pop: &[u8] = match cmd {
+                "BLPOP" => b"LPOP",
+                "BRPOP" => b"RPOP",
+                "BZPOPMIN" => b"ZPOPMIN",
+                _ => b"ZPOPMAX",
+            };
+            vec![logged(&[pop, key])]
+        }
+        "BLMOVE" => match reply {
+            RespMessage::BulkString(Some(_)) => {
+                vec![logged(&[b"LMOVE", args[1], args[2], args[3], args[4]])]
+            }
+            _ => Vec::new(),
+        },
+        "XADD" => match reply {
+            RespMessage::BulkString(Some(id)) => {
+                let mut command = logged(args);
+                command[xadd_id_index(args)] = id.clone();
+                vec![command]
+            }
+            _ => Vec::new(),
+        },
+        "RESTORE" => vec![restore_form(args, now)],
+        "MIGRATE" => {
+            let copy = args[6..]
+                .iter()
+                .take_while(|arg| !arg.eq_ignore_ascii_case(b"KEYS"))
+                .any(|arg| arg.eq_ignore_ascii_case(b"COPY"));
+            if copy || *reply != RespMessage::SimpleString("OK".to_string()) {
+                return Vec::new();
+            }
+            // The keys that existed were moved away; deleting the rest changes nothing.
+            let keys = match args[6..]
+                .iter()
+                .position(|arg| arg.eq_ignore_ascii_case(b"KEYS"))
+            {
+                Some(at) => &args[7 + at..],
+                None => &args[3..4],
+            };
+            let mut command = logged(&[b"DEL"]);
+            command.extend(keys.iter().map(|key| key.to_vec()));
+            vec![command]
+        }
+        // Logged by the command itself, which knows what it delivered when.
+        "XREADGROUP" => Vec::new(),
+        "XCLAIM" => {
+            let claimed: Vec<&[u8]> = reply_items(reply).iter().filter_map(entry_id).collect();
+            vec![xclaim_form(args, &claimed, now)]
+        }
+        "XAUTOCLAIM" => {
+            let parts = reply_items(reply);
+            let claimed: Vec<&[u8]> = parts
+                .get(1)
+                .map(|claimed| reply_items(claimed).iter().filter_map(entry_id).collect())
+                .unwrap_or_default();
+            let deleted: Vec<&[u8]> = parts
+                .get(2)
+                .map(|deleted| reply_items(deleted).iter().filter_map(bulk_bytes).collect())
+                .unwrap_or_default();
+            let (key, group) = (args[1], args[2]);
+            let mut commands = vec![xclaim_form(args, &claimed, now)];
+            if !deleted.is_empty() {
+                let mut ack = logged(&[b"XACK", key, group]);
+                ack.extend(deleted.iter().map(|id| id.to_vec()));
+                commands.push(ack);
+            }
+            commands
+        }
+        _ => vec![logged(args)],
+    }
+}
+
+fn bulk_bytes(reply: &RespMessage) -> Option<&[u8]> {
+    match reply {
+        RespMessage::BulkString(Some(bytes)) => Some(bytes),
+        _ => None,
+    }
+}
+
+fn reply_items(reply: &RespMessage) -> &[RespMessage] {
+    match reply {
+        RespMessage::Array(items) => items,
+        _ => &[],
+    }
+}
+
+/// The ID of an XCLAIM reply element, whether it's a bare ID or a whole entry.
+fn entry_id(reply: &RespMessage) -> Option<&[u8]> {
+    bulk_bytes(reply).or_else(|| reply_items(reply).first().and_then(bulk_bytes))
+}
+
+/// RESTORE with its TTL made absolute (`ABSTTL`) and IDLETIME, which changes
+/// nothing, left out. A key whose TTL was already up was deleted instead.
+fn restore_form(args: &[&[u8]], now: u128) -> LoggedCommand {
+    let options: Vec<Vec<u8>> = args[4..]
+        .iter()
+        .map(|arg| arg.to_ascii_uppercase())
+        .collect();
+    let has = |option: &[u8]| options.iter().any(|arg| arg == option);
+    let ttl = parse_i64(args[2]).unwrap_or(0) as u128;
+    let expiry = match ttl {
+        0 => None,
+        ttl if has(b"ABSTTL") => Some(ttl),
+        ttl => Some(now + ttl),
+    };
+    if expiry.is_some_and(|expiry| expiry <= now) {
+        return logged(&[b"DEL", args[1]]);
+    }
+    let expiry = expiry.unwrap_or(0).to_string();
+    let mut command = logged(&[b"RESTORE", args[1], expiry.as_bytes(), args[3]]);
+    if expiry != "0" {
+        command.push(b"ABSTTL".to_vec());
+    }
+    if has(b"REPLACE") {
+        command.push(b"REPLACE".to_vec());
+    }
+    command
+}
+
+/// SET / GETEX with any `EX`-style option from `args[from..]` turned into `PXAT`.
+fn with_absolute_expiry(args: &[&[u8]], from: usize, now: u128) -> LoggedCommand {
+    let mut command = logged(&args[..from]);
+    let mut i = from;
+    while i < args.len() {
+        let when = match (expiry_option(args[i]), args.get(i + 1)) {
+            (Some((unit, at)), Some(arg)) => parse_expiry(args[0], arg, unit, at, now).ok(),
+            _ => None,
+        };
+        match when {
+            Some(when) => {
+                command.extend(logged(&[b"PXAT", when.to_string().as_bytes()]));
+                i += 2;
+            }
+            None => {
+                command.push(args[i].to_vec());
+                i += 1;
+            }
+        }
+    }
+    command
+}
+
+/// Where XADD's ID argument is, past `NOMKSTREAM` and the trim options.
+fn xadd_id_index(args: &[&[u8]]) -> usize {
+    let mut i = 2;
+    while let Some(arg) = args.get(i) {
+        if arg.eq_ignore_ascii_case(b"NOMKSTREAM") {
+            i += 1;
+        } else if arg.eq_ignore_ascii_case(b"MAXLEN") || arg.eq_ignore_ascii_case(b"MINID") {
+            match parse_trim(&args[i..]) {
+                Ok((_, used)) => i += used,
+                Err(_) => break,
+            }
+        } else {
+            break;
+        }
+    }
+    i
+}
+
+/// XCLAIM of exactly the entries it claimed, with no idle time to wait for and the
+/// delivery time it gave them spelled out.
+fn xclaim_form(args: &[&[u8]], claimed: &[&[u8]], now: u128) -> LoggedCommand {
+    let (key, group, consumer) = (args[1], args[2], args[3]);
+    if claimed.is_empty() {
+        // Nothing was claimed, but the consumer was still created.
+        return logged(&[b"XGROUP", b"CREATECONSUMER", key, group, consumer]);
+    }
+    let mut i = 5;
+    while i < args.len() && StreamId::parse(args[i], 0).is_some() {
+        i += 1;
+    }
+    let mut delivery_time = now;
+    let mut options = Vec::new();
+    while i < args.len() {
+        let option = args[i].to_ascii_uppercase();
+        let value = args.get(i + 1).and_then(|value| parse_i64(value).ok());
+        match (option.as_slice(), value) {
+            (b"IDLE", Some(idle)) => delivery_time = now.saturating_sub(idle.max(0) as u128),
+            (b"TIME", Some(time)) => delivery_time = time.max(0) as u128,
+            (b"RETRYCOUNT", _) | (b"LASTID", _) => {
+                options.extend(logged(&args[i..(i + 2).min(args.len())]));
+            }
+            _ => {
+                options.push(args[i].to_vec());
+                i += 1;
+                continue;
+            }
+        }
+        i += 2;
+    }
+
+    let mut command = logged(&[b"XCLAIM", key, group, consumer, b"0"]);
+    command.extend(claimed.iter().map(|id| id.to_vec()));
+    command.extend(logged(&[b"TIME", delivery_time.to_string().as_bytes()]));
+    command.extend(options);
+    command
+}
+
+/// Commands that rebuild `entries` from nothing.
+fn entries_commands<'a>(
+    entries: impl IntoIterator<Item = (&'a Vec<u8>, &'a ValueWithExpiry)>,
+) -> Vec<LoggedCommand> {
+    let mut commands = Vec::new();
+    for (key, entry) in entries {
+        match &entry.value {
+            Value::String(value) => {
+                let mut set = logged(&[b"SET", key, value]);
+                if let Some(expiry) = entry.expiry {
+                    set.extend(logged(&[b"PXAT", expiry.to_string().as_bytes()]));
+                }
+                commands.push(set);
+                continue;
+            }
+            Value::List(list) => {
+                let items: Vec<&[u8]> = list.iter().map(Vec::as_slice).collect();
+                push_in_chunks(&mut commands, &[b"RPUSH", key], &items, 1);
+            }
+            Value::Hash(hash) => {
+                let items: Vec<&[u8]> = hash
+                    .iter()
+                    .flat_map(|(field, value)| [field.as_slice(), value.as_slice()])
+                    .collect();
+                push_in_chunks(&mut commands, &[b"HSET", key], &items, 2);
+            }
+            Value::Set(set) => {
+                let items: Vec<&[u8]> = set.iter().map(Vec::as_slice).collect();
+                push_in_chunks(&mut commands, &[b"SADD", key], &items, 1);
+            }
+            Value::SortedSet(zset) => {
+                let scores: Vec<String> =
+                    zset.iter().map(|(_, score)| format_float(score)).collect();
+                let items: Vec<&[u8]> = zset
+                    .iter()
+                    .zip(&scores)
+                    .flat_map(|((member, _), score)| [score.as_bytes(), member])
+                    .collect();
+                push_in_chunks(&mut commands, &[b"ZADD", key], &items, 2);
+            }
+            Value::Stream(stream) => stream_commands(&mut commands, key, stream),
+        }
+        if let Some(expiry) = entry.expiry {
+            commands.push(logged(&[b"PEXPIREAT", key, expiry.to_string().as_bytes()]));
+        }
+    }
+    commands
+}
+
+/// `prefix` followed by `items`, split so no command carries more than
+/// `ITEMS_PER_COMMAND` elements of `width` arguments each.
+fn push_in_chunks(
+    commands: &mut Vec<LoggedCommand>,
+    prefix: &[&[u8]],
+    items: &[&[u8]],
+    width: usize,
+) {
+    for chunk in items.chunks(ITEMS_PER_COMMAND * width) {
+        let mut command = logged(prefix);
+        command.extend(logged(chunk));
+        commands.push(command);
+    }
+}
+
+fn stream_commands(commands: &mut Vec<LoggedCommand>, key: &[u8], stream: &Stream) {
+    for (id, fields) in &stream.entries {
+        let mut add = logged(&[b"XADD", key, id.to_string().as_bytes()]);
+        for (field, value) in fields {
+            add.extend(logged(&[field, value]));
+        }
+        commands.push(add);
+    }
+    if stream.entries.is_empty() {
+        // Adding an entry and trimming it straight away leaves an empty stream.
+        let id = stream.last_id.max(StreamId { ms: 0, seq: 1 });
+        commands.push(logged(&[
+            b"XADD",
+            key,
+            b"MAXLEN",
+            b"0",
+            id.to_string().as_bytes(),
+            b"x",
+            b"y",
+        ]));
+    }
+    commands.push(logged(&[
+        b"XSETID",
+        key,
+        stream.last_id.to_string().as_bytes(),
+    ]));
+
+    for (name, group) in &stream.groups {
+        let last_delivered = group.last_delivered.to_string();
+        commands.push(logged(&[
+            b"XGROUP",
+            b"CREATE",
+            key,
+            name,
+            last_delivered.as_bytes(),
+        ]));
+        for consumer in group.consumers.keys() {
+            commands.push(logged(&[b"XGROUP", b"CREATECONSUMER", key, name, consumer]));
+        }
+        for (id, pending) in &group.pending {
+            if !stream.entries.contains_key(id) {
+                continue;
+            }
+            commands.push(logged(&[
+                b"XCLAIM",
+                key,
+                name,
+                &pending.consumer,
+                b"0",
+                id.to_string().as_bytes(),
+                b"TIME",
+                pending.delivery_time.to_string().as_bytes(),
+                b"RETRYCOUNT",
+                pending.delivery_count.to_string().as_bytes(),
+                b"FORCE",
+                b"JUSTID",
+            ]));
+        }
+    }
+}
+
+/// The commands, in RESP, that rebuild each `(index, entries)` database.
+fn encode_base<'a, E>(databases: impl IntoIterator<Item = (usize, E)>) -> Vec<u8>
+where
+    E: IntoIterator<Item = (&'a Vec<u8>, &'a ValueWithExpiry)>,
+{
+    let mut out = Vec::new();
+    for (index, entries) in databases {
+        let commands = entries_commands(entries);
+        if commands.is_empty() {
+            continue;
+        }
+        encode_command(&select_command(index), &mut out);
+        for command in &commands {
+            encode_command(command, &mut out);
+        }
+    }
+    out
+}
+
+/// Writes an append-only file at `path` that rebuilds `keyspaces`, numbered in
+/// order, replacing whatever was there.
+pub fn write_aof_base<'a>(
+    path: &Path,
+    keyspaces: impl IntoIterator<Item = &'a Keyspace>,
+) -> io::Result<()> {
+    let base = encode_base(
+        keyspaces
+            .into_iter()
+            .enumerate()
+            .map(|(index, keyspace)| (index, keyspace.live_entries())),
+    );
+    write_file_atomically(path, &base)
+}
+
+/// Rebuilds `count` databases by replaying the append-only file at `path`. An
+/// incomplete command at the end is dropped and cut from the file.
+pub async fn load_aof(path: &Path, count: usize) -> Result<(Databases, LoadedAof), AofError> {
+    let contents = fs::read(path)?;
+    // Nothing may expire halfway through: a key's TTL could be followed by
+    // commands that expect the key to still be there.
+    let databases = Databases::from_keyspaces(
+        (0..count)
+            .map(|_| Keyspace::with_clock(Arc::new(LoadingClock)))
+            .collect(),
+    );
+    // The replay never saves, so this snapshotter's file is never written.
+    let mut session = Session::new(
+        databases.clone(),
+        Arc::new(Snapshotter::new(path, SnapshotFormat::Json)),
+    );
+
+    let mut decoder = RespDecoder::new();
+    decoder.feed(&contents);
+    let mut loaded = LoadedAof::default();
+    loop {
+        match decoder.next_frame() {
+            Ok(Some(RespMessage::Array(command))) => {
+                if let RespMessage::Error(e) = handle_session_command(command, &mut session).await {
+                    return Err(AofError::Corrupt(format!(
+                        "command {} failed: {}",
+                        loaded.commands + 1,
+                        e
+                    )));
+                }
+                loaded.commands += 1;
+            }
+            Ok(Some(_)) => {
+                return Err(AofError::Corrupt(format!(
+                    "command {} is not an array",
+                    loaded.commands + 1
+                )))
+            }
+            Ok(None) => break,
+            Err(e) => return Err(AofError::Corrupt(e)),
+        }
+    }
+
+    loaded.truncated_bytes = decoder.pending_len() as u64;
+    if loaded.truncated_bytes > 0 {
+        OpenOptions::new()
+            .write(true)
+            .open(path)?
+            .set_len(contents.len() as u64 - loaded.truncated_bytes)?;
+    }
+    for db in databases.iter() {
+        db.lock().await.set_clock(Arc::new(SystemClock));
+    }
+    Ok((databases, loaded))
+}
@@ -0,0 +1,328 @@
+use crate::handler::aof::{delivery_form, served_form};
+use crate::handler::client_handler::Db;
+use crate::handler::commands::{parse_float, remove_if_empty};
+use crate::handler::keyspace::Keyspace;
+use crate::handler::list_commands::{get_list, get_or_create_list, move_element, ListEnd};
+use crate::handler::stream_commands::{deliver_new, entries_reply, get_stream, stream_reply};
+use crate::handler::value::{StreamId, Value};
+use crate::handler::zset_commands::{
+    get_or_create_zset, get_zset, pop_scored, score_pair, ScoreEnd,
+};
+use crate::resp::resp_protocol::RespMessage;
+use std::collections::{HashMap, VecDeque};
+use std::ops::Bound;
+use std::time::Duration;
+use tokio::sync::oneshot;
+
+/// What a blocked client wants done once one of its keys can serve it.
+#[derive(Clone, Debug)]
+pub enum BlockingOp {
+    /// BLPOP / BRPOP: pop one element and reply with `[key, element]`.
+    Pop(ListEnd),
+    /// BLMOVE: pop from the ready key, push onto `destination` and reply with the element.
+    Move {
+        from: ListEnd,
+        to: ListEnd,
+        destination: Vec<u8>,
+    },
+    /// BZPOPMIN / BZPOPMAX: pop one member and reply with `[key, member, score]`.
+    PopScored(ScoreEnd),
+    /// XREAD: reply with the entries of the ready key newer than its ID in `after`.
+    /// Nothing is consumed, so every waiting reader is served.
+    ReadStream {
+        after: HashMap<Vec<u8>, StreamId>,
+        count: Option<usize>,
+    },
+    /// XREADGROUP with `>`: deliver new entries of the ready key to `consumer`.
+    ReadGroup {
+        group: Vec<u8>,
+        consumer: Vec<u8>,
+        count: Option<usize>,
+        no_ack: bool,
+    },
+}
+
+impl BlockingOp {
+    /// Whether `value`, just written at `key`, has something for this operation.
+    fn is_ready(&self, key: &[u8], value: &Value) -> bool {
+        match (self, value) {
+            (BlockingOp::Pop(_) | BlockingOp::Move { .. }, Value::List(_)) => true,
+            (BlockingOp::PopScored(_), Value::SortedSet(_)) => true,
+            (BlockingOp::ReadStream { after, .. }, Value::Stream(stream)) => after
+                .get(key)
+                .is_some_and(|after| stream.has_entries_after(*after)),
+            // A group deleted while clients wait on it is reported to them as an error.
+            (BlockingOp::ReadGroup { group, .. }, Value::Stream(stream)) => {
+                stream.groups.get(group).is_none_or(|consumer_group| {
+                    stream.has_entries_after(consumer_group.last_delivered)
+                })
+            }
+            _ => false,
+        }
+    }
+}
+
+struct Waiter {
+    keys: Vec<Vec<u8>>,
+    op: BlockingOp,
+    reply: oneshot::Sender<RespMessage>,
+}
+
+/// Registry of clients parked on blocking commands.
+///
+/// Each key keeps its waiters in arrival order, so when data shows up the client
+/// that has been waiting longest is served first.
+#[derive(Default)]
+pub struct BlockedClients {
+    next_id: u64,
+    queues: HashMap<Vec<u8>, VecDeque<u64>>,
+    waiters: HashMap<u64, Waiter>,
+}
+
+/// Handle returned to a client that has just been parked.
+pub struct Blocked {
+    id: u64,
+    reply: oneshot::Receiver<RespMessage>,
+}
+
+impl BlockedClients {
+    pub fn block(&mut self, keys: Vec<Vec<u8>>, op: BlockingOp) -> Blocked {
+        let id = self.next_id;
+        self.next_id += 1;
+        for key in &keys {
+            self.queues.entry(key.clone()).or_default().push_back(id);
+        }
+        let (reply, rx) = oneshot::channel();
+        self.waiters.insert(id, Waiter { keys, op, reply });
+        Blocked { id, reply: rx }
+    }
+
+    fn unblock(&mut self, id: u64) -> Option<Waiter> {
+        let waiter = self.waiters.remove(&id)?;
+        for key in &waiter.keys {
+            if let Some(queue) = self.queues.get_mut(key) {
+                queue.retain(|&waiting| waiting != id);
+                if queue.is_empty() {
+                    self.queues.remove(key);
+                }
+            }
+        }
+        Some(waiter)
+    }
+
+    /// Every key some client is waiting on.
+    pub fn waited_keys(&self) -> Vec<Vec<u8>> {
+        self.queues.keys().cloned().collect()
+    }
+
+    /// The longest-waiting client on `key` whose operation `accepts` allows.
+    fn first_waiting_on(&self, key: &[u8], accepts: impl Fn(&BlockingOp) -> bool) -> Option<u64> {
+        self.queues.get(key)?.iter().copied().find(|id| {
+            self.waiters
+                .get(id)
+                .is_some_and(|waiter| accepts(&waiter.op))
+        })
+    }
+}
+
+/// Serves clients blocked on `key` for as long as it holds data.
+///
+/// Must be called by every write that can turn an empty key into a non-empty list
+/// or sorted set, or append to a stream, while the lock is still held, so no other
+/// command can take the data first. Only clients whose command matches the key's type are served.
+/// What is done for them is recorded for the append-only file to log after the write.
+pub fn serve_blocked(keyspace: &mut Keyspace, key: &[u8]) {
+    let mut ready = vec![key.to_vec()];
+
+    while let Some(key) = ready.pop() {
+        loop {
+            keyspace.expire_if_needed(&key);
+            let Some(value) = keyspace.entries.get(&key).map(|v| &v.value) else {
+                break;
+            };
+            let Some(id) = keyspace
+                .blocked
+                .first_waiting_on(&key, |op| op.is_ready(&key, value))
+            else {
+                break;
+            };
+            let Some(waiter) = keyspace.blocked.unblock(id) else {
+                break;
+            };
+            // The client timed out or disconnected; leave the data for the next one.
+            if waiter.reply.is_closed() {
+                continue;
+            }
+
+            // What was done for the client, beyond what `served_form` can tell.
+            let mut logged = Vec::new();
+            let reply = match &waiter.op {
+                BlockingOp::Pop(end) => {
+                    let Ok(Some(list)) = get_list(keyspace, &key) else {
+                        break;
+                    };
+                    let item = match end {
+                        ListEnd::Left => list.pop_front(),
+                        ListEnd::Right => list.pop_back(),
+                    };
+                    remove_if_empty(keyspace, &key);
+                    match item {
+                        Some(item) => RespMessage::Array(vec![
+                            RespMessage::BulkString(Some(key.clone())),
+                            RespMessage::BulkString(Some(item)),
+                        ]),
+                        None => break,
+                    }
+                }
+                BlockingOp::Move {
+                    from,
+                    to,
+                    destination,
+                } => match move_element(keyspace, &key, destination, *from, *to) {
+                    Ok(item) => {
+                        ready.push(destination.clone());
+                        RespMessage::BulkString(item)
+                    }
+                    Err(e) => e,
+                },
+                BlockingOp::PopScored(end) => {
+                    let Ok(Some(zset)) = get_zset(keyspace, &key) else {
+                        break;
+                    };
+                    let popped = pop_scored(zset, *end);
+                    remove_if_empty(keyspace, &key);
+                    match popped {
+                        Some((member, score)) => {
+                            let mut reply = vec![RespMessage::BulkString(Some(key.clone()))];
+                            reply.extend(score_pair(member, score));
+                            RespMessage::Array(reply)
+                        }
+                        None => break,
+                    }
+                }
+                BlockingOp::ReadStream { after, count } => {
+                    let Ok(Some(stream)) = get_stream(keyspace, &key) else {
+                        break;
+                    };
+                    let after = after.get(&key).copied().unwrap_or(StreamId::MIN);
+                    let entries =
+                        stream.range(Bound::Excluded(after), Bound::Unbounded, false, *count);
+                    RespMessage::Array(vec![stream_reply(&key, entries_reply(entries))])
+                }
+                BlockingOp::ReadGroup {
+                    group,
+                    consumer,
+                    count,
+                    no_ack,
+                } => {
+                    let now = keyspace.now();
+                    let Ok(Some(stream)) = get_stream(keyspace, &key) else {
+                        break;
+                    };
+                    match deliver_new(stream, group, consumer, *count, *no_ack, now) {
+                        Some(delivery) => {
+                            logged = delivery_form(&key, group, consumer, &delivery, *no_ack, now);
+                            RespMessage::Array(vec![stream_reply(
+                                &key,
+                                entries_reply(
+                                    delivery.entries.iter().map(|(id, fields)| (*id, fields)),
+                                ),
+                            )])
+                        }
+                        None => RespMessage::Error(
+                            "NOGROUP the consumer group this client was blocked on no longer exists"
+                                .to_string(),
+                        ),
+                    }
+                }
+            };
+
+            // The receiver can only vanish between the `is_closed` check and here if the
+            // client dropped at that exact moment; put a popped element back so it isn't lost.
+            let failed = matches!(reply, RespMessage::Error(_));
+            match waiter.reply.send(reply) {
+                Ok(()) if !failed => logged.extend(served_form(&key, &waiter.op)),
+                Ok(()) => {}
+                // Delivered stream entries stay pending for the consumer all the same.
+                Err(reply) => restore(keyspace, &key, &waiter.op, reply),
+            }
+            if !logged.is_empty() {
+                keyspace.dirty += 1;
+                if let Some(propagation) = &keyspace.propagation {
+                    for command in logged {
+                        propagation.served(command);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Puts back whatever an undelivered BLPOP / BRPOP / BZPOP* `reply` popped from `key`.
+fn restore(keyspace: &mut Keyspace, key: &[u8], op: &BlockingOp, reply: RespMessage) {
+    let RespMessage::Array(reply) = reply else {
+        return;
+    };
+    match (op, reply.as_slice()) {
+        (BlockingOp::Pop(end), [_, RespMessage::BulkString(Some(item))]) => {
+            if let Ok(list) = get_or_create_list(keyspace, key) {
+                match end {
+                    ListEnd::Left => list.push_front(item.clone()),
+                    ListEnd::Right => list.push_back(item.clone()),
+                }
+            }
+        }
+        (
+            BlockingOp::PopScored(_),
+            [_, RespMessage::BulkString(Some(member)), RespMessage::BulkString(Some(score))],
+        ) => {
+            if let (Ok(zset), Ok(score)) = (get_or_create_zset(keyspace, key), parse_float(score)) {
+                zset.insert(member, score);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Waits for a parked client to be served, giving up after `timeout` (`None` waits forever).
+/// Returns `None` on timeout.
+pub async fn wait_until_served(
+    db: &Db,
+    blocked: Blocked,
+    timeout: Option<Duration>,
+) -> Option<RespMessage> {
+    let Blocked { id, mut reply } = blocked;
+    let served = match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, &mut reply).await.ok(),
+        None => Some((&mut reply).await),
+    };
+    if let Some(Ok(reply)) = served {
+        return Some(reply);
+    }
+
+    // Deregister under the lock; a writer may have served us just before we got it.
+    let mut db_guard = db.lock().await;
+    db_guard.blocked.unblock(id);
+    reply.try_recv().ok()
+}
+
+/// Parses a blocking command's timeout in (possibly fractional) seconds.
+/// Zero means block forever.
+pub fn parse_timeout(arg: &[u8]) -> Result<Option<Duration>, RespMessage> {
+    let seconds = std::str::from_utf8(arg)
+        .ok()
+        .and_then(|s| s.parse::<f64>().ok())
+        .filter(|s| s.is_finite())
+        .ok_or_else(|| {
+            RespMessage::Error("ERR timeout is not a float or out of range".to_string())
+        })?;
+    if seconds < 0.0 {
+        return Err(RespMessage::Error("ERR timeout is negative".to_string()));
+    }
+    if seconds == 0.0 {
+        return Ok(None);
+    }
+    Duration::try_from_secs_f64(seconds)
+        .map(Some)
+        .map_err(|_| RespMessage::Error("ERR timeout is out of range".to_string()))
+}
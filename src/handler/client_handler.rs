@@ -1,33 +1,90 @@
-use crate::handler::commands::{handle_array_command, handle_simple_string};
-use crate::resp::resp_protocol::{parse_resp, RespMessage};
-use std::collections::HashMap;
+use crate::handler::commands::{handle_session_command, handle_simple_string};
+use crate::handler::databases::Session;
+use crate::resp::resp_protocol::{RespDecoder, RespMessage};
+use std::future::{poll_fn, Future};
 use std::sync::Arc;
+use std::task::Poll;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpStream;
 use tokio::sync::Mutex;
 
-use super::value::ValueWithExpiry;
+use super::keyspace::Keyspace;
 
-pub type Db = Arc<Mutex<HashMap<String, ValueWithExpiry>>>;
+/// One logical database.
+pub type Db = Arc<Mutex<Keyspace>>;
 
-pub async fn handle_client(mut stream: TcpStream, db: Db) {
-    let mut buf = vec![0; 1024];
+pub async fn handle_client(mut stream: TcpStream, mut session: Session) {
+    let mut buf = vec![0; 16 * 1024];
+    let mut decoder = RespDecoder::new();
 
     while let Ok(n) = stream.read(&mut buf).await {
         if n == 0 {
             return;
         }
+        decoder.feed(&buf[..n]);
 
-        let input = &buf[..n];
-        let response = match parse_resp(input) {
-            Ok(RespMessage::SimpleString(cmd)) => handle_simple_string(cmd),
-            Ok(RespMessage::Array(vec)) => handle_array_command(vec, &db).await,
-            _ => RespMessage::Error("ERR unknown command".to_string()),
-        };
+        // Answer every complete frame in this read before writing, so a pipelined
+        // batch gets its replies back in a single write and in request order.
+        let mut out = Vec::new();
+        let mut protocol_error = None;
+        loop {
+            match decoder.next_frame() {
+                Ok(Some(frame)) => {
+                    let command = execute(frame, &mut session);
+                    tokio::pin!(command);
+                    let first_poll = poll_fn(|cx| Poll::Ready(command.as_mut().poll(cx))).await;
+                    if let Poll::Ready(response) = first_poll {
+                        response.encode(&mut out);
+                        continue;
+                    }
 
-        if let Err(e) = stream.write_all(response.to_string().as_bytes()).await {
+                    // The command is waiting (a blocking pop, or lock contention): send the
+                    // replies already produced, then keep reading so a client that
+                    // disconnects while parked is noticed and its wait is dropped.
+                    if let Err(e) = stream.write_all(&out).await {
+                        eprintln!("Failed to write response: {}", e);
+                        return;
+                    }
+                    out.clear();
+                    let response = loop {
+                        tokio::select! {
+                            response = &mut command => break response,
+                            read = stream.read(&mut buf) => match read {
+                                Ok(0) | Err(_) => return,
+                                Ok(n) => decoder.feed(&buf[..n]),
+                            },
+                        }
+                    };
+                    response.encode(&mut out);
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    protocol_error = Some(e);
+                    break;
+                }
+            }
+        }
+
+        if let Some(e) = &protocol_error {
+            RespMessage::Error(format!("ERR Protocol error: {}", e)).encode(&mut out);
+        }
+
+        if let Err(e) = stream.write_all(&out).await {
             eprintln!("Failed to write response: {}", e);
             return;
         }
+
+        // The stream can't be resynchronised after a malformed frame.
+        if protocol_error.is_some() {
+            return;
+        }
+    }
+}
+
+async fn execute(frame: RespMessage, session: &mut Session) -> RespMessage {
+    match frame {
+        RespMessage::SimpleString(cmd) => handle_simple_string(cmd),
+        RespMessage::Array(vec) => handle_session_command(vec, session).await,
+        _ => RespMessage::Error("ERR unknown command".to_string()),
     }
 }
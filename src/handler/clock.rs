@@ -0,0 +1,51 @@
+use crate::handler::commands::now_millis;
+#[cfg(test)]
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Where the keyspace gets the current time from when deciding whether a key has
+/// expired. Swapping it out lets tests move time forward deterministically.
+pub trait Clock: Send + Sync {
+    /// The current Unix time in milliseconds.
+    fn now_millis(&self) -> u128;
+}
+
+/// The wall clock.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_millis(&self) -> u128 {
+        now_millis()
+    }
+}
+
+/// A clock stopped at the Unix epoch, for replaying the append-only file: every
+/// expiry lies in its future, so no key expires halfway through the replay.
+pub struct LoadingClock;
+
+impl Clock for LoadingClock {
+    fn now_millis(&self) -> u128 {
+        0
+    }
+}
+
+/// A clock that only moves when told to.
+#[cfg(test)]
+pub struct ManualClock(AtomicU64);
+
+#[cfg(test)]
+impl ManualClock {
+    pub fn new(now_millis: u64) -> ManualClock {
+        ManualClock(AtomicU64::new(now_millis))
+    }
+
+    pub fn advance(&self, millis: u64) {
+        self.0.fetch_add(millis, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+impl Clock for ManualClock {
+    fn now_millis(&self) -> u128 {
+        self.0.load(Ordering::SeqCst) as u128
+    }
+}
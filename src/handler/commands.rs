@@ -1,8 +1,23 @@
+use crate::handler::aof::{propagated_form, Aof};
 use crate::handler::client_handler::Db;
-use crate::handler::value::ValueWithExpiry;
+use crate::handler::database_commands;
+use crate::handler::databases::Session;
+use crate::handler::dump_commands;
+use crate::handler::expire_commands::{self, ExpireAt, TimeUnit};
+use crate::handler::hash_commands::{self, HashParts};
+use crate::handler::key_commands;
+use crate::handler::keyspace::Keyspace;
+use crate::handler::list_commands::{self, ListEnd};
+use crate::handler::set_commands::{self, SetOp};
+use crate::handler::stream_commands;
+use crate::handler::string_commands;
+use crate::handler::value::Value;
+use crate::handler::zset_commands::{self, RangeBy, ScoreEnd, ZsetOp};
 use crate::resp::resp_protocol::RespMessage;
-use std::fs::File;
-use std::io::Write;
+use std::collections::hash_map::RandomState;
+use std::collections::HashSet;
+use std::future::Future;
+use std::hash::{BuildHasher, Hash, Hasher};
 use std::time::{SystemTime, UNIX_EPOCH};
 
 pub fn handle_simple_string(cmd: String) -> RespMessage {
@@ -13,9 +28,379 @@ pub fn handle_simple_string(cmd: String) -> RespMessage {
     }
 }
 
+pub const WRONGTYPE: &str = "WRONGTYPE Operation against a key holding the wrong kind of value";
+
+pub fn wrong_type() -> RespMessage {
+    RespMessage::Error(WRONGTYPE.to_string())
+}
+
+pub fn wrong_arity(cmd: &[u8]) -> RespMessage {
+    RespMessage::Error(format!(
+        "ERR wrong number of arguments for '{}' command",
+        String::from_utf8_lossy(cmd).to_lowercase()
+    ))
+}
+
+pub fn bulk(bytes: &[u8]) -> RespMessage {
+    RespMessage::BulkString(Some(bytes.to_vec()))
+}
+
+pub fn syntax_error() -> RespMessage {
+    RespMessage::Error("ERR syntax error".to_string())
+}
+
+pub fn not_an_integer() -> RespMessage {
+    RespMessage::Error("ERR value is not an integer or out of range".to_string())
+}
+
+/// Parses a command argument as a signed 64-bit integer, the way Redis does.
+pub fn parse_i64(arg: &[u8]) -> Result<i64, RespMessage> {
+    std::str::from_utf8(arg)
+        .ok()
+        .and_then(|s| s.parse::<i64>().ok())
+        .ok_or_else(not_an_integer)
+}
+
+/// Parses a command argument as a double. `inf` and `-inf` are accepted, NaN is not.
+pub fn parse_float(arg: &[u8]) -> Result<f64, RespMessage> {
+    std::str::from_utf8(arg)
+        .ok()
+        .and_then(|s| s.parse::<f64>().ok())
+        .filter(|f| !f.is_nan())
+        .ok_or_else(|| RespMessage::Error("ERR value is not a valid float".to_string()))
+}
+
+/// Formats a double in plain decimal notation, with no exponent and no trailing
+/// zeros: the shortest text that parses back to the same value.
+pub fn format_float(value: f64) -> String {
+    format!("{}", value)
+}
+
+pub fn now_millis() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis()
+}
+
+/// Collections never exist empty: once the last element is gone, so is the key.
+pub fn remove_if_empty(keyspace: &mut Keyspace, key: &[u8]) {
+    if keyspace
+        .entries
+        .get(key)
+        .is_some_and(|v| v.value.is_empty_collection())
+    {
+        keyspace.entries.remove(key);
+    }
+}
+
+/// A fresh pseudo-random number, for commands that pick random elements.
+pub fn random_u64() -> u64 {
+    // Every `RandomState` is seeded differently, so hashing nothing yields a new value.
+    RandomState::new().build_hasher().finish()
+}
+
+/// The most items a negative count may ask the random-pick commands for. Redis
+/// takes any count, and tries to build a reply that size.
+const MAX_RANDOM_PICKS: u64 = 1 << 24;
+
+/// Parses the count of SRANDMEMBER and HRANDFIELD, which may be negative.
+pub fn parse_random_count(arg: &[u8]) -> Result<i64, RespMessage> {
+    let count = parse_i64(arg)?;
+    if count.unsigned_abs() > MAX_RANDOM_PICKS {
+        return Err(RespMessage::Error("ERR value is out of range".to_string()));
+    }
+    Ok(count)
+}
+
+/// Picks `count` random items out of `len`: distinct ones for a positive count (at
+/// most all of them), and `|count|` independent picks that may repeat for a
+/// negative count. `random` draws one item and `all` lists every one, which is
+/// only called on when the picks are a good part of the whole.
+pub fn pick_random<T: Copy + Eq + Hash>(
+    len: usize,
+    count: i64,
+    random: impl Fn() -> T,
+    all: impl FnOnce() -> Vec<T>,
+) -> Vec<T> {
+    if count < 0 {
+        return (0..count.unsigned_abs()).map(|_| random()).collect();
+    }
+    let count = (count as usize).min(len);
+    if count * 3 < len {
+        // Few enough that drawing until they're distinct wastes little.
+        let mut picked = HashSet::with_capacity(count);
+        return std::iter::repeat_with(random)
+            .filter(|item| picked.insert(*item))
+            .take(count)
+            .collect();
+    }
+    let mut items = all();
+    // Partial Fisher-Yates: the first `count` slots end up a uniform random sample.
+    for i in 0..count {
+        let j = i + random_u64() as usize % (items.len() - i);
+        items.swap(i, j);
+    }
+    items.truncate(count);
+    items
+}
+
+/// Raw argument bytes, command name included, for handlers that take `&[&[u8]]`.
+fn command_args(vec: &[RespMessage]) -> Option<Vec<&[u8]>> {
+    vec.iter()
+        .map(|arg| match arg {
+            RespMessage::BulkString(Some(bytes)) => Some(bytes.as_slice()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Commands that can modify the data. The keys each one changes count towards
+/// the save points.
+const WRITE_COMMANDS: &[&str] = &[
+    "SET",
+    "SETNX",
+    "SETEX",
+    "PSETEX",
+    "DEL",
+    "UNLINK",
+    "RENAME",
+    "RENAMENX",
+    "COPY",
+    "MOVE",
+    "RESTORE",
+    "MIGRATE",
+    "SWAPDB",
+    "FLUSHDB",
+    "FLUSHALL",
+    "INCR",
+    "DECR",
+    "INCRBY",
+    "DECRBY",
+    "INCRBYFLOAT",
+    "APPEND",
+    "SETRANGE",
+    "MSET",
+    "MSETNX",
+    "GETSET",
+    "GETDEL",
+    "GETEX",
+    "EXPIRE",
+    "PEXPIRE",
+    "EXPIREAT",
+    "PEXPIREAT",
+    "PERSIST",
+    "LPUSH",
+    "RPUSH",
+    "LPUSHX",
+    "RPUSHX",
+    "LPOP",
+    "RPOP",
+    "LMOVE",
+    "BLPOP",
+    "BRPOP",
+    "BLMOVE",
+    "LSET",
+    "LREM",
+    "LTRIM",
+    "LINSERT",
+    "HSET",
+    "HMSET",
+    "HSETNX",
+    "HDEL",
+    "HINCRBY",
+    "HINCRBYFLOAT",
+    "SADD",
+    "SREM",
+    "SPOP",
+    "SMOVE",
+    "SINTERSTORE",
+    "SUNIONSTORE",
+    "SDIFFSTORE",
+    "ZADD",
+    "ZINCRBY",
+    "ZREM",
+    "ZRANGESTORE",
+    "ZREMRANGEBYRANK",
+    "ZREMRANGEBYSCORE",
+    "ZREMRANGEBYLEX",
+    "ZPOPMIN",
+    "ZPOPMAX",
+    "BZPOPMIN",
+    "BZPOPMAX",
+    "ZUNIONSTORE",
+    "ZINTERSTORE",
+    "ZDIFFSTORE",
+    "XADD",
+    "XDEL",
+    "XTRIM",
+    "XSETID",
+    "XGROUP",
+    "XREADGROUP",
+    "XACK",
+    "XCLAIM",
+    "XAUTOCLAIM",
+];
+
+pub fn is_write_command(cmd: &str) -> bool {
+    WRITE_COMMANDS.contains(&cmd)
+}
+
+/// Whether `cmd` can change databases other than the selected one.
+fn changes_other_databases(cmd: &str) -> bool {
+    matches!(cmd, "SWAPDB" | "MOVE" | "COPY" | "FLUSHALL")
+}
+
+/// Runs a client's command. Commands that change the connection's state or
+/// touch more than one database are handled here; the rest run against the
+/// selected database.
+pub async fn handle_session_command(vec: Vec<RespMessage>, session: &mut Session) -> RespMessage {
+    let cmd = match vec.first() {
+        Some(RespMessage::BulkString(Some(cmd_bytes))) => {
+            String::from_utf8_lossy(cmd_bytes).to_uppercase()
+        }
+        _ => return handle_array_command(vec, session.db()).await,
+    };
+    // INFO and BGREWRITEAOF take the log themselves.
+    let logged = !matches!(cmd.as_str(), "INFO" | "BGREWRITEAOF");
+    let reply = match session.aof.clone().filter(|_| logged) {
+        Some(aof) => run_logged(&cmd, vec, session, &aof).await,
+        None => run_session_command(&cmd, vec, session).await,
+    };
+    if is_write_command(&cmd) {
+        let changes = if changes_other_databases(&cmd) {
+            let mut changes = 0;
+            for db in session.databases.iter() {
+                changes += db.lock().await.take_dirty();
+            }
+            changes
+        } else {
+            session.db().lock().await.take_dirty()
+        };
+        session.snapshotter.record_changes(changes);
+    }
+    reply
+}
+
+async fn run_session_command(
+    cmd: &str,
+    vec: Vec<RespMessage>,
+    session: &mut Session,
+) -> RespMessage {
+    match cmd {
+        "SELECT" | "SWAPDB" | "MOVE" | "COPY" | "FLUSHALL" | "SAVE" | "BGSAVE" | "BGREWRITEAOF"
+        | "LASTSAVE" | "INFO" => match command_args(&vec) {
+            Some(args) => handle_server_command(cmd, &args, session).await,
+            None => RespMessage::Error("ERR invalid command format".to_string()),
+        },
+        _ => handle_array_command(vec, session.db()).await,
+    }
+}
+
+/// Runs a command with the append-only file on, logging the keys it found expired,
+/// then, for a write that succeeded, the command itself (or what it logged in its
+/// place), then what it did for the blocked clients it served.
+///
+/// Every command holds its databases' turn while it runs, so reads in a database
+/// wait for the write running there to be logged. Writes hold the log too, so the
+/// file gets them in the order they happened; reads only lock it to log the keys
+/// they found expired.
+async fn run_logged(
+    cmd: &str,
+    vec: Vec<RespMessage>,
+    session: &mut Session,
+    aof: &Aof,
+) -> RespMessage {
+    let write = is_write_command(cmd);
+    let args: Vec<Vec<u8>> = match command_args(&vec) {
+        Some(args) if write => args.iter().map(|arg| arg.to_vec()).collect(),
+        Some(_) => Vec::new(),
+        None => return RespMessage::Error("ERR invalid command format".to_string()),
+    };
+    let args: Vec<&[u8]> = args.iter().map(Vec::as_slice).collect();
+
+    let db = session.selected;
+    let databases = if changes_other_databases(cmd) {
+        0..session.databases.count()
+    } else {
+        db..db + 1
+    };
+    let order = aof.order(databases.clone()).await;
+    let mut log = None;
+    let mut now = 0;
+    if write {
+        let locked = aof.lock().await;
+        if let Err(e) = locked.check_writable() {
+            return e;
+        }
+        log = Some(locked);
+        now = session.db().lock().await.now();
+    }
+
+    // A command that parks its client lets go of its turn and the log, or it would
+    // hold up every other command while it waits. What a write did until then is
+    // logged first, and the write that serves it logs the rest; a read leaves the
+    // keys it found expired for the next command in its database to log.
+    let mut held = Some((order, log));
+    let mut run = std::pin::pin!(run_session_command(cmd, vec, session));
+    let reply = std::future::poll_fn(|cx| {
+        let poll = run.as_mut().poll(cx);
+        if poll.is_pending() && held.is_some() && aof.take_parked(db) {
+            if let Some((_, Some(mut log))) = held.take() {
+                let mut commands = aof.expired_dels(databases.clone());
+                commands.extend(aof.propagated_commands(databases.clone()));
+                commands.extend(aof.served_commands(databases.clone()));
+                log.append(&commands);
+            }
+        }
+        poll
+    })
+    .await;
+    let Some((_order, log)) = held else {
+        return reply;
+    };
+
+    // Parked and given up on before letting go.
+    let parked = aof.take_parked(db);
+    let mut commands = aof.expired_dels(databases.clone());
+    if write && !parked && !matches!(reply, RespMessage::Error(_)) {
+        commands.extend(
+            propagated_form(cmd, &args, &reply, now)
+                .into_iter()
+                .map(|command| (db, command)),
+        );
+    }
+    commands.extend(aof.propagated_commands(databases.clone()));
+    commands.extend(aof.served_commands(databases));
+    match log {
+        Some(mut log) => log.append(&commands),
+        None if !commands.is_empty() => aof.lock().await.append(&commands),
+        None => {}
+    }
+    reply
+}
+
+async fn handle_server_command(cmd: &str, args: &[&[u8]], session: &mut Session) -> RespMessage {
+    match cmd {
+        "SELECT" => database_commands::select(args, session).await,
+        "SWAPDB" => database_commands::swapdb(args, &session.databases).await,
+        "MOVE" => database_commands::move_key(args, session).await,
+        "COPY" => key_commands::copy(args, session).await,
+        "FLUSHALL" => database_commands::flushall(args, &session.databases).await,
+        "SAVE" => database_commands::save(args, session).await,
+        "BGSAVE" => database_commands::bgsave(args, session).await,
+        "BGREWRITEAOF" => database_commands::bgrewriteaof(args, session).await,
+        "LASTSAVE" => database_commands::lastsave(args, session).await,
+        _ => database_commands::info(args, session).await,
+    }
+}
+
 pub async fn handle_array_command(vec: Vec<RespMessage>, db: &Db) -> RespMessage {
-    if let Some(RespMessage::BulkString(Some(cmd_bytes))) = vec.get(0) {
+    if let Some(RespMessage::BulkString(Some(cmd_bytes))) = vec.first() {
         let cmd = String::from_utf8_lossy(cmd_bytes).to_uppercase();
+        let Some(args) = command_args(&vec) else {
+            return RespMessage::Error("ERR invalid command format".to_string());
+        };
 
         match cmd.as_str() {
             "PING" => RespMessage::SimpleString("PONG".to_string()),
@@ -28,441 +413,177 @@ pub async fn handle_array_command(vec: Vec<RespMessage>, db: &Db) -> RespMessage
                 }
             }
 
-            "SET" if vec.len() >= 3 => {
-                if let (
-                    RespMessage::BulkString(Some(key_bytes)),
-                    RespMessage::BulkString(Some(value_bytes)),
-                ) = (&vec[1], &vec[2])
-                {
-                    let key = String::from_utf8_lossy(key_bytes).to_string();
-                    let value = String::from_utf8_lossy(value_bytes).to_string();
-                    let mut expiry: Option<u128> = None;
-                    let mut i = 3;
-
-                    while i < vec.len() {
-                        if let RespMessage::BulkString(Some(opt_bytes)) = &vec[i] {
-                            let opt = String::from_utf8_lossy(opt_bytes).to_uppercase();
-
-                            if i + 1 >= vec.len() {
-                                return RespMessage::Error("ERR invalid SET arguments".to_string());
-                            }
-
-                            match opt.as_str() {
-                                "EX" => {
-                                    if let RespMessage::BulkString(Some(seconds)) = &vec[i + 1] {
-                                        if let Ok(secs) =
-                                            String::from_utf8_lossy(seconds).parse::<u64>()
-                                        {
-                                            let now = SystemTime::now()
-                                                .duration_since(UNIX_EPOCH)
-                                                .unwrap()
-                                                .as_millis();
-                                            expiry = Some(now + (secs as u128 * 1000));
-                                            i += 2;
-                                        } else {
-                                            return RespMessage::Error(
-                                                "ERR invalid expire time".to_string(),
-                                            );
-                                        }
-                                    }
-                                }
-                                "PX" => {
-                                    if let RespMessage::BulkString(Some(millis)) = &vec[i + 1] {
-                                        if let Ok(ms) =
-                                            String::from_utf8_lossy(millis).parse::<u128>()
-                                        {
-                                            let now = SystemTime::now()
-                                                .duration_since(UNIX_EPOCH)
-                                                .unwrap()
-                                                .as_millis();
-                                            expiry = Some(now + ms);
-                                            i += 2;
-                                        } else {
-                                            return RespMessage::Error(
-                                                "ERR invalid expire time".to_string(),
-                                            );
-                                        }
-                                    }
-                                }
-                                "EXAT" => {
-                                    if let RespMessage::BulkString(Some(seconds)) = &vec[i + 1] {
-                                        if let Ok(secs) =
-                                            String::from_utf8_lossy(seconds).parse::<u64>()
-                                        {
-                                            expiry = Some(secs as u128 * 1000);
-                                            i += 2;
-                                        } else {
-                                            return RespMessage::Error(
-                                                "ERR invalid expire time".to_string(),
-                                            );
-                                        }
-                                    }
-                                }
-                                "PXAT" => {
-                                    if let RespMessage::BulkString(Some(millis)) = &vec[i + 1] {
-                                        if let Ok(ms) =
-                                            String::from_utf8_lossy(millis).parse::<u128>()
-                                        {
-                                            expiry = Some(ms);
-                                            i += 2;
-                                        } else {
-                                            return RespMessage::Error(
-                                                "ERR invalid expire time".to_string(),
-                                            );
-                                        }
-                                    }
-                                }
-                                _ => return RespMessage::Error("ERR syntax error".to_string()),
-                            }
-                        } else {
-                            return RespMessage::Error("ERR invalid option format".to_string());
-                        }
-                    }
-
-                    db.lock()
-                        .await
-                        .insert(key, ValueWithExpiry { value, expiry });
-                    RespMessage::SimpleString("OK".to_string())
-                } else {
-                    RespMessage::Error("ERR invalid SET arguments".to_string())
-                }
-            }
+            "SET" => string_commands::set(&args, db).await,
+            "SETNX" => string_commands::setnx(&args, db).await,
+            "SETEX" => string_commands::setex(&args, db, TimeUnit::Seconds).await,
+            "PSETEX" => string_commands::setex(&args, db, TimeUnit::Milliseconds).await,
 
             "GET" if vec.len() > 1 => {
                 if let RespMessage::BulkString(Some(key_bytes)) = &vec[1] {
-                    let key = String::from_utf8_lossy(key_bytes).to_string();
-                    let mut db_guard = db.lock().await;
-
-                    if let Some(value_with_expiry) = db_guard.get(&key) {
-                        if let Some(expiry_time) = value_with_expiry.expiry {
-                            let now = SystemTime::now()
-                                .duration_since(UNIX_EPOCH)
-                                .unwrap()
-                                .as_millis();
-                            if now >= expiry_time {
-                                db_guard.remove(&key);
-                                return RespMessage::BulkString(None);
-                            }
-                        }
-                        RespMessage::BulkString(Some(value_with_expiry.value.as_bytes().to_vec()))
-                    } else {
-                        RespMessage::BulkString(None)
+                    match db.lock().await.lookup_read(key_bytes) {
+                        Some(entry) => match &entry.value {
+                            Value::String(value) => RespMessage::BulkString(Some(value.clone())),
+                            _ => wrong_type(),
+                        },
+                        None => RespMessage::BulkString(None),
                     }
                 } else {
                     RespMessage::Error("ERR invalid GET argument. Expected key".to_string())
                 }
             }
 
-            "EXISTS" if vec.len() > 1 => {
-                let mut counter = 0;
-                let mut db_guard = db.lock().await;
-                for i in 1..vec.len() {
-                    if let RespMessage::BulkString(Some(key_bytes)) = &vec[i] {
-                        let key = String::from_utf8_lossy(key_bytes).to_string();
-                        if let Some(value_with_expiry) = db_guard.get(&key) {
-                            if let Some(expiry_time) = value_with_expiry.expiry {
-                                let now = SystemTime::now()
-                                    .duration_since(UNIX_EPOCH)
-                                    .unwrap()
-                                    .as_millis();
-                                if now < expiry_time {
-                                    counter += 1;
-                                } else {
-                                    db_guard.remove(&key);
-                                }
-                            } else {
-                                counter += 1;
-                            }
-                        }
-                    } else {
-                        return RespMessage::Error("ERR invalid EXISTS argument".to_string());
-                    }
-                }
-                RespMessage::Integer(counter)
-            }
+            "EXISTS" | "TOUCH" => key_commands::exists(&args, db).await,
+            "DEL" | "UNLINK" => key_commands::del(&args, db).await,
+            "TYPE" => key_commands::key_type(&args, db).await,
+            "KEYS" => key_commands::keys(&args, db).await,
+            "SCAN" => key_commands::scan(&args, db).await,
+            "RANDOMKEY" => key_commands::randomkey(&args, db).await,
+            "DBSIZE" => key_commands::dbsize(&args, db).await,
+            "RENAME" => key_commands::rename(&args, db, false).await,
+            "RENAMENX" => key_commands::rename(&args, db, true).await,
+            "FLUSHDB" => database_commands::flushdb(&args, db).await,
+            "DUMP" => dump_commands::dump(&args, db).await,
+            "RESTORE" => dump_commands::restore(&args, db).await,
+            "MIGRATE" => dump_commands::migrate(&args, db).await,
 
-            "DEL" if vec.len() > 1 => {
-                let mut counter = 0;
-                let mut db_guard = db.lock().await;
-                for i in 1..vec.len() {
-                    if let RespMessage::BulkString(Some(key_bytes)) = &vec[i] {
-                        let key = String::from_utf8_lossy(key_bytes).to_string();
-                        if db_guard.remove(&key).is_some() {
-                            counter += 1;
-                        }
-                    } else {
-                        return RespMessage::Error("ERR invalid DEL argument".to_string());
-                    }
-                }
-                RespMessage::Integer(counter)
-            }
+            "INCR" => string_commands::incr(&args, db, 1).await,
+            "DECR" => string_commands::incr(&args, db, -1).await,
+            "INCRBY" => string_commands::incrby(&args, db, false).await,
+            "DECRBY" => string_commands::incrby(&args, db, true).await,
+            "INCRBYFLOAT" => string_commands::incrbyfloat(&args, db).await,
 
-            "INCR" if vec.len() > 1 => {
-                if let RespMessage::BulkString(Some(key_bytes)) = &vec[1] {
-                    let key = String::from_utf8_lossy(key_bytes).to_string();
-                    let mut db_guard = db.lock().await;
-
-                    if let Some(value_with_expiry) = db_guard.get_mut(&key) {
-                        if let Some(expiry_time) = value_with_expiry.expiry {
-                            let now = SystemTime::now()
-                                .duration_since(UNIX_EPOCH)
-                                .unwrap()
-                                .as_millis();
-                            if now >= expiry_time {
-                                db_guard.remove(&key);
-                                return RespMessage::Error("ERR key has expired".to_string());
-                            }
-                        }
-
-                        let value = value_with_expiry.value.parse::<i64>();
-                        if let Ok(mut value) = value {
-                            value += 1;
-                            value_with_expiry.value = value.to_string();
-                            RespMessage::Integer(value)
-                        } else {
-                            RespMessage::Error("ERR value is not an integer".to_string())
-                        }
-                    } else {
-                        RespMessage::Error("ERR key does not exist".to_string())
-                    }
-                } else {
-                    RespMessage::Error("ERR invalid INC argument".to_string())
-                }
-            }
+            "APPEND" => string_commands::append(&args, db).await,
+            "STRLEN" => string_commands::strlen(&args, db).await,
+            "GETRANGE" | "SUBSTR" => string_commands::getrange(&args, db).await,
+            "SETRANGE" => string_commands::setrange(&args, db).await,
+            "MGET" => string_commands::mget(&args, db).await,
+            "MSET" => string_commands::mset(&args, db, false).await,
+            "MSETNX" => string_commands::mset(&args, db, true).await,
+            "GETSET" => string_commands::getset(&args, db).await,
+            "GETDEL" => string_commands::getdel(&args, db).await,
+            "GETEX" => string_commands::getex(&args, db).await,
 
-            "DECR" if vec.len() > 1 => {
-                if let RespMessage::BulkString(Some(key_bytes)) = &vec[1] {
-                    let key = String::from_utf8_lossy(key_bytes).to_string();
-                    let mut db_guard = db.lock().await;
-
-                    if let Some(value_with_expiry) = db_guard.get_mut(&key) {
-                        if let Some(expiry_time) = value_with_expiry.expiry {
-                            let now = SystemTime::now()
-                                .duration_since(UNIX_EPOCH)
-                                .unwrap()
-                                .as_millis();
-                            if now >= expiry_time {
-                                db_guard.remove(&key);
-                                return RespMessage::Error("ERR key has expired".to_string());
-                            }
-                        }
-
-                        let value = value_with_expiry.value.parse::<i64>();
-                        if let Ok(mut value) = value {
-                            value -= 1;
-                            value_with_expiry.value = value.to_string();
-                            RespMessage::Integer(value)
-                        } else {
-                            RespMessage::Error("ERR value is not an integer".to_string())
-                        }
-                    } else {
-                        RespMessage::Error("ERR key does not exist".to_string())
-                    }
-                } else {
-                    RespMessage::Error("ERR invalid DEC argument".to_string())
-                }
+            "EXPIRE" => {
+                expire_commands::expire(&args, db, TimeUnit::Seconds, ExpireAt::Relative).await
             }
-
-            "LPUSH" if vec.len() > 1 => {
-                if let RespMessage::BulkString(Some(key_bytes)) = &vec[1] {
-                    let key = String::from_utf8_lossy(key_bytes).to_string();
-                    let mut db_guard = db.lock().await;
-
-                    if let Some(value_with_expiry) = db_guard.get_mut(&key) {
-                        if let Some(expiry_time) = value_with_expiry.expiry {
-                            let now = SystemTime::now()
-                                .duration_since(UNIX_EPOCH)
-                                .unwrap()
-                                .as_millis();
-                            if now >= expiry_time {
-                                db_guard.remove(&key);
-                                return RespMessage::Error("ERR key has expired".to_string());
-                            }
-                        }
-
-                        if let Some(list) = value_with_expiry
-                            .value
-                            .split(",")
-                            .collect::<Vec<&str>>()
-                            .first()
-                        {
-                            let mut new_list = vec![];
-                            for i in 2..vec.len() {
-                                if let RespMessage::BulkString(Some(item_bytes)) = &vec[i] {
-                                    let item = String::from_utf8_lossy(item_bytes).to_string();
-                                    new_list.insert(0, item);
-                                } else {
-                                    return RespMessage::Error(
-                                        "ERR invalid LPUSH argument".to_string(),
-                                    );
-                                }
-                            }
-                            new_list.push(list.to_string());
-                            value_with_expiry.value = new_list.join(",");
-                            RespMessage::Integer(new_list.len() as i64)
-                        } else {
-                            RespMessage::Error("ERR key is not a list".to_string())
-                        }
-                    } else {
-                        let mut new_list = vec![];
-                        for i in 2..vec.len() {
-                            if let RespMessage::BulkString(Some(item_bytes)) = &vec[i] {
-                                let item = String::from_utf8_lossy(item_bytes).to_string();
-                                new_list.push(item);
-                            } else {
-                                return RespMessage::Error(
-                                    "ERR invalid LPUSH argument".to_string(),
-                                );
-                            }
-                        }
-                        db_guard.insert(
-                            key,
-                            ValueWithExpiry {
-                                value: new_list.join(","),
-                                expiry: None,
-                            },
-                        );
-                        RespMessage::Integer(new_list.len() as i64)
-                    }
-                } else {
-                    RespMessage::Error("ERR invalid LPUSH argument".to_string())
-                }
+            "PEXPIRE" => {
+                expire_commands::expire(&args, db, TimeUnit::Milliseconds, ExpireAt::Relative).await
             }
-
-            "RPUSH" if vec.len() > 1 => {
-                if let RespMessage::BulkString(Some(key_bytes)) = &vec[1] {
-                    let key = String::from_utf8_lossy(key_bytes).to_string();
-                    let mut db_guard = db.lock().await;
-
-                    if let Some(value_with_expiry) = db_guard.get_mut(&key) {
-                        if let Some(expiry_time) = value_with_expiry.expiry {
-                            let now = SystemTime::now()
-                                .duration_since(UNIX_EPOCH)
-                                .unwrap()
-                                .as_millis();
-                            if now >= expiry_time {
-                                db_guard.remove(&key);
-                                return RespMessage::Error("ERR key has expired".to_string());
-                            }
-                        }
-
-                        if let Some(list) = value_with_expiry
-                            .value
-                            .split(",")
-                            .collect::<Vec<&str>>()
-                            .first()
-                        {
-                            let mut new_list = vec![];
-                            for i in 2..vec.len() {
-                                if let RespMessage::BulkString(Some(item_bytes)) = &vec[i] {
-                                    let item = String::from_utf8_lossy(item_bytes).to_string();
-                                    new_list.push(item);
-                                } else {
-                                    return RespMessage::Error(
-                                        "ERR invalid RPUSH argument".to_string(),
-                                    );
-                                }
-                            }
-                            new_list.insert(0, list.to_string());
-                            value_with_expiry.value = new_list.join(",");
-                            RespMessage::Integer(new_list.len() as i64)
-                        } else {
-                            RespMessage::Error("ERR key is not a list".to_string())
-                        }
-                    } else {
-                        let mut new_list = vec![];
-                        for i in 2..vec.len() {
-                            if let RespMessage::BulkString(Some(item_bytes)) = &vec[i] {
-                                let item = String::from_utf8_lossy(item_bytes).to_string();
-                                new_list.push(item);
-                            } else {
-                                return RespMessage::Error(
-                                    "ERR invalid RPUSH argument".to_string(),
-                                );
-                            }
-                        }
-                        db_guard.insert(
-                            key,
-                            ValueWithExpiry {
-                                value: new_list.join(","),
-                                expiry: None,
-                            },
-                        );
-                        RespMessage::Integer(new_list.len() as i64)
-                    }
-                } else {
-                    RespMessage::Error("ERR invalid RPUSH argument".to_string())
-                }
+            "EXPIREAT" => {
+                expire_commands::expire(&args, db, TimeUnit::Seconds, ExpireAt::Absolute).await
             }
-
-            "LRANGE" if vec.len() == 4 => {
-                if let (
-                    RespMessage::BulkString(Some(key_bytes)),
-                    RespMessage::BulkString(Some(start_bytes)),
-                    RespMessage::BulkString(Some(stop_bytes)),
-                ) = (&vec[1], &vec[2], &vec[3])
-                {
-                    let key = String::from_utf8_lossy(key_bytes).to_string();
-                    let start = String::from_utf8_lossy(start_bytes)
-                        .parse::<usize>()
-                        .unwrap_or(0);
-                    let stop = String::from_utf8_lossy(stop_bytes)
-                        .parse::<usize>()
-                        .unwrap_or(0);
-                    let mut db_guard = db.lock().await;
-
-                    if let Some(value_with_expiry) = db_guard.get(&key) {
-                        if let Some(expiry_time) = value_with_expiry.expiry {
-                            let now = SystemTime::now()
-                                .duration_since(UNIX_EPOCH)
-                                .unwrap()
-                                .as_millis();
-                            if now >= expiry_time {
-                                db_guard.remove(&key);
-                                return RespMessage::Error("ERR key has expired".to_string());
-                            }
-                        }
-
-                        if let Some(list) = value_with_expiry
-                            .value
-                            .split(",")
-                            .collect::<Vec<&str>>()
-                            .first()
-                        {
-                            let list = list.split(",").collect::<Vec<&str>>();
-                            let mut new_list = vec![];
-                            for i in start..=stop {
-                                if let Some(item) = list.get(i) {
-                                    new_list.push(item.to_string());
-                                }
-                            }
-                            RespMessage::Array(
-                                new_list
-                                    .iter()
-                                    .map(|item| {
-                                        RespMessage::BulkString(Some(item.as_bytes().to_vec()))
-                                    })
-                                    .collect(),
-                            )
-                        } else {
-                            RespMessage::Error("ERR key is not a list".to_string())
-                        }
-                    } else {
-                        RespMessage::Error("ERR key does not exist".to_string())
-                    }
-                } else {
-                    RespMessage::Error("ERR invalid LRANGE argument".to_string())
-                }
+            "PEXPIREAT" => {
+                expire_commands::expire(&args, db, TimeUnit::Milliseconds, ExpireAt::Absolute).await
             }
+            "TTL" => expire_commands::ttl(&args, db, TimeUnit::Seconds).await,
+            "PTTL" => expire_commands::ttl(&args, db, TimeUnit::Milliseconds).await,
+            "EXPIRETIME" => expire_commands::expiretime(&args, db, TimeUnit::Seconds).await,
+            "PEXPIRETIME" => expire_commands::expiretime(&args, db, TimeUnit::Milliseconds).await,
+            "PERSIST" => expire_commands::persist(&args, db).await,
+
+            "LPUSH" => list_commands::push(&args, db, ListEnd::Left, false).await,
+            "RPUSH" => list_commands::push(&args, db, ListEnd::Right, false).await,
+            "LPUSHX" => list_commands::push(&args, db, ListEnd::Left, true).await,
+            "RPUSHX" => list_commands::push(&args, db, ListEnd::Right, true).await,
+            "LPOP" => list_commands::pop(&args, db, ListEnd::Left).await,
+            "RPOP" => list_commands::pop(&args, db, ListEnd::Right).await,
+            "LMOVE" => list_commands::lmove(&args, db).await,
+            "BLPOP" => list_commands::blocking_pop(&args, db, ListEnd::Left).await,
+            "BRPOP" => list_commands::blocking_pop(&args, db, ListEnd::Right).await,
+            "BLMOVE" => list_commands::blmove(&args, db).await,
+            "LLEN" => list_commands::llen(&args, db).await,
+            "LRANGE" => list_commands::lrange(&args, db).await,
+            "LINDEX" => list_commands::lindex(&args, db).await,
+            "LSET" => list_commands::lset(&args, db).await,
+            "LREM" => list_commands::lrem(&args, db).await,
+            "LTRIM" => list_commands::ltrim(&args, db).await,
+            "LINSERT" => list_commands::linsert(&args, db).await,
+            "LPOS" => list_commands::lpos(&args, db).await,
+
+            "HSET" => hash_commands::hset(&args, db, false).await,
+            "HMSET" => hash_commands::hset(&args, db, true).await,
+            "HSETNX" => hash_commands::hsetnx(&args, db).await,
+            "HGET" => hash_commands::hget(&args, db).await,
+            "HMGET" => hash_commands::hmget(&args, db).await,
+            "HDEL" => hash_commands::hdel(&args, db).await,
+            "HEXISTS" => hash_commands::hexists(&args, db).await,
+            "HLEN" => hash_commands::hlen(&args, db).await,
+            "HSTRLEN" => hash_commands::hstrlen(&args, db).await,
+            "HKEYS" => hash_commands::hgetall(&args, db, HashParts::Fields).await,
+            "HVALS" => hash_commands::hgetall(&args, db, HashParts::Values).await,
+            "HGETALL" => hash_commands::hgetall(&args, db, HashParts::Both).await,
+            "HINCRBY" => hash_commands::hincrby(&args, db).await,
+            "HINCRBYFLOAT" => hash_commands::hincrbyfloat(&args, db).await,
+            "HRANDFIELD" => hash_commands::hrandfield(&args, db).await,
+            "HSCAN" => hash_commands::hscan(&args, db).await,
 
-            // let save the database to a file as a JSON object
-            "SAVE" => {
-                let db_guard = db.lock().await;
-                let json = serde_json::to_string(&*db_guard).unwrap();
-                let mut file = File::create("xredisDB.json").unwrap();
-                file.write_all(json.as_bytes()).unwrap();
-                RespMessage::SimpleString("OK".to_string())
+            "SADD" => set_commands::sadd(&args, db).await,
+            "SREM" => set_commands::srem(&args, db).await,
+            "SISMEMBER" => set_commands::sismember(&args, db).await,
+            "SMISMEMBER" => set_commands::smismember(&args, db).await,
+            "SCARD" => set_commands::scard(&args, db).await,
+            "SMEMBERS" => set_commands::smembers(&args, db).await,
+            "SPOP" => set_commands::spop(&args, db).await,
+            "SRANDMEMBER" => set_commands::srandmember(&args, db).await,
+            "SMOVE" => set_commands::smove(&args, db).await,
+            "SINTER" => set_commands::set_op(&args, db, SetOp::Inter).await,
+            "SUNION" => set_commands::set_op(&args, db, SetOp::Union).await,
+            "SDIFF" => set_commands::set_op(&args, db, SetOp::Diff).await,
+            "SINTERSTORE" => set_commands::set_op_store(&args, db, SetOp::Inter).await,
+            "SUNIONSTORE" => set_commands::set_op_store(&args, db, SetOp::Union).await,
+            "SDIFFSTORE" => set_commands::set_op_store(&args, db, SetOp::Diff).await,
+            "SINTERCARD" => set_commands::sintercard(&args, db).await,
+            "SSCAN" => set_commands::sscan(&args, db).await,
+
+            "ZADD" => zset_commands::zadd(&args, db).await,
+            "ZINCRBY" => zset_commands::zincrby(&args, db).await,
+            "ZREM" => zset_commands::zrem(&args, db).await,
+            "ZSCORE" => zset_commands::zscore(&args, db).await,
+            "ZCARD" => zset_commands::zcard(&args, db).await,
+            "ZCOUNT" => zset_commands::zcount(&args, db).await,
+            "ZLEXCOUNT" => zset_commands::zlexcount(&args, db).await,
+            "ZRANK" => zset_commands::zrank(&args, db, false).await,
+            "ZREVRANK" => zset_commands::zrank(&args, db, true).await,
+            "ZRANGE" => zset_commands::zrange(&args, db, None).await,
+            "ZREVRANGE" => zset_commands::zrange(&args, db, Some((RangeBy::Rank, true))).await,
+            "ZRANGEBYSCORE" => {
+                zset_commands::zrange(&args, db, Some((RangeBy::Score, false))).await
+            }
+            "ZREVRANGEBYSCORE" => {
+                zset_commands::zrange(&args, db, Some((RangeBy::Score, true))).await
             }
+            "ZRANGEBYLEX" => zset_commands::zrange(&args, db, Some((RangeBy::Lex, false))).await,
+            "ZREVRANGEBYLEX" => zset_commands::zrange(&args, db, Some((RangeBy::Lex, true))).await,
+            "ZRANGESTORE" => zset_commands::zrangestore(&args, db).await,
+            "ZREMRANGEBYRANK" => zset_commands::zremrange(&args, db, RangeBy::Rank).await,
+            "ZREMRANGEBYSCORE" => zset_commands::zremrange(&args, db, RangeBy::Score).await,
+            "ZREMRANGEBYLEX" => zset_commands::zremrange(&args, db, RangeBy::Lex).await,
+            "ZMSCORE" => zset_commands::zmscore(&args, db).await,
+            "ZPOPMIN" => zset_commands::zpop(&args, db, ScoreEnd::Min).await,
+            "ZPOPMAX" => zset_commands::zpop(&args, db, ScoreEnd::Max).await,
+            "BZPOPMIN" => zset_commands::blocking_zpop(&args, db, ScoreEnd::Min).await,
+            "BZPOPMAX" => zset_commands::blocking_zpop(&args, db, ScoreEnd::Max).await,
+            "ZUNION" => zset_commands::zset_op(&args, db, ZsetOp::Union).await,
+            "ZINTER" => zset_commands::zset_op(&args, db, ZsetOp::Inter).await,
+            "ZDIFF" => zset_commands::zset_op(&args, db, ZsetOp::Diff).await,
+            "ZUNIONSTORE" => zset_commands::zset_op_store(&args, db, ZsetOp::Union).await,
+            "ZINTERSTORE" => zset_commands::zset_op_store(&args, db, ZsetOp::Inter).await,
+            "ZDIFFSTORE" => zset_commands::zset_op_store(&args, db, ZsetOp::Diff).await,
+
+            "XADD" => stream_commands::xadd(&args, db).await,
+            "XLEN" => stream_commands::xlen(&args, db).await,
+            "XRANGE" => stream_commands::xrange(&args, db, false).await,
+            "XREVRANGE" => stream_commands::xrange(&args, db, true).await,
+            "XDEL" => stream_commands::xdel(&args, db).await,
+            "XTRIM" => stream_commands::xtrim(&args, db).await,
+            "XSETID" => stream_commands::xsetid(&args, db).await,
+            "XREAD" => stream_commands::xread(&args, db).await,
+            "XGROUP" => stream_commands::xgroup(&args, db).await,
+            "XREADGROUP" => stream_commands::xreadgroup(&args, db).await,
+            "XACK" => stream_commands::xack(&args, db).await,
+            "XPENDING" => stream_commands::xpending(&args, db).await,
+            "XCLAIM" => stream_commands::xclaim(&args, db).await,
+            "XAUTOCLAIM" => stream_commands::xautoclaim(&args, db).await,
 
             _ => RespMessage::Error("ERR unknown command".to_string()),
         }
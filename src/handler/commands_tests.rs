@@ -0,0 +1,3493 @@
+use super::active_expire::{active_expire_cycle, run_active_expire, ActiveExpireConfig};
+use super::aof::{load_aof, propagated_form, write_aof_base, Aof, LoadedAof};
+use super::client_handler::{handle_client, Db};
+use super::clock::ManualClock;
+use super::commands::{handle_array_command, handle_session_command, now_millis};
+use super::databases::{Databases, Session};
+use super::keyspace::Keyspace;
+use super::persistence::{
+    load_snapshot, write_snapshot, LoadedSnapshot, SnapshotError, Snapshotter,
+};
+use super::rdb::crc64;
+use super::value::Value;
+use crate::config::{AppendFsync, SavePoint, SnapshotFormat};
+use crate::resp::resp_protocol::RespMessage;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+fn new_db() -> Db {
+    Arc::new(Mutex::new(Keyspace::default()))
+}
+
+fn bulk(bytes: &[u8]) -> RespMessage {
+    RespMessage::BulkString(Some(bytes.to_vec()))
+}
+
+async fn run(db: &Db, args: &[&[u8]]) -> RespMessage {
+    handle_array_command(args.iter().map(|a| bulk(a)).collect(), db).await
+}
+
+fn new_session(databases: usize) -> Session {
+    Session::new(
+        Databases::new(databases),
+        Arc::new(Snapshotter::new(
+            temp_path("session.json"),
+            SnapshotFormat::Json,
+        )),
+    )
+}
+
+/// A second connection to the same server as `session`.
+fn other_client(session: &Session) -> Session {
+    let mut client = Session::new(session.databases.clone(), session.snapshotter.clone());
+    client.aof = session.aof.clone();
+    client
+}
+
+/// Runs a command as a client connection would, with its selected database.
+async fn run_in(session: &mut Session, args: &[&[u8]]) -> RespMessage {
+    handle_session_command(args.iter().map(|a| bulk(a)).collect(), session).await
+}
+
+#[tokio::test]
+async fn test_set_get_round_trips_binary_key_and_value() {
+    let db = new_db();
+    let key: &[u8] = &[0xff, 0x00, b'k'];
+    let value: &[u8] = &[0x89, b'P', b'N', b'G', 0x00, 0xc3, 0x28];
+
+    assert_eq!(
+        run(&db, &[b"SET", key, value]).await,
+        RespMessage::SimpleString("OK".to_string())
+    );
+    assert_eq!(run(&db, &[b"GET", key]).await, bulk(value));
+    assert_eq!(run(&db, &[b"EXISTS", key]).await, RespMessage::Integer(1));
+    // A lossy conversion would have mapped both keys to the same string.
+    assert_eq!(
+        run(&db, &[b"GET", &[0xfe, 0x00, b'k']]).await,
+        RespMessage::BulkString(None)
+    );
+}
+
+fn bulk_array(items: &[&[u8]]) -> RespMessage {
+    RespMessage::Array(items.iter().map(|i| bulk(i)).collect())
+}
+
+#[tokio::test]
+async fn test_push_keeps_every_element_including_commas() {
+    let db = new_db();
+    assert_eq!(
+        run(&db, &[b"RPUSH", b"list", b"a,b", b"c"]).await,
+        RespMessage::Integer(2)
+    );
+    assert_eq!(
+        run(&db, &[b"RPUSH", b"list", b"d"]).await,
+        RespMessage::Integer(3)
+    );
+    assert_eq!(
+        run(&db, &[b"LPUSH", b"list", b"y", b"z"]).await,
+        RespMessage::Integer(5)
+    );
+    assert_eq!(
+        run(&db, &[b"LRANGE", b"list", b"0", b"10"]).await,
+        bulk_array(&[b"z", b"y", b"a,b", b"c", b"d"])
+    );
+}
+
+#[tokio::test]
+async fn test_wrong_type_operations_are_rejected() {
+    let db = new_db();
+    run(&db, &[b"SET", b"str", b"1"]).await;
+    run(&db, &[b"RPUSH", b"list", b"a"]).await;
+
+    let wrongtype = RespMessage::Error(
+        "WRONGTYPE Operation against a key holding the wrong kind of value".to_string(),
+    );
+    assert_eq!(run(&db, &[b"LPUSH", b"str", b"x"]).await, wrongtype);
+    assert_eq!(run(&db, &[b"LRANGE", b"str", b"0", b"1"]).await, wrongtype);
+    assert_eq!(run(&db, &[b"GET", b"list"]).await, wrongtype);
+    assert_eq!(run(&db, &[b"INCR", b"list"]).await, wrongtype);
+
+    // SET replaces a value of any type.
+    run(&db, &[b"SET", b"list", b"v"]).await;
+    assert_eq!(run(&db, &[b"GET", b"list"]).await, bulk(b"v"));
+}
+
+#[tokio::test]
+async fn test_list_pops_and_negative_indices() {
+    let db = new_db();
+    run(&db, &[b"RPUSH", b"q", b"a", b"b", b"c", b"d", b"e"]).await;
+
+    assert_eq!(
+        run(&db, &[b"LRANGE", b"q", b"-3", b"-1"]).await,
+        bulk_array(&[b"c", b"d", b"e"])
+    );
+    assert_eq!(run(&db, &[b"LINDEX", b"q", b"-1"]).await, bulk(b"e"));
+    assert_eq!(run(&db, &[b"LPOP", b"q"]).await, bulk(b"a"));
+    assert_eq!(
+        run(&db, &[b"RPOP", b"q", b"2"]).await,
+        bulk_array(&[b"e", b"d"])
+    );
+    assert_eq!(run(&db, &[b"LLEN", b"q"]).await, RespMessage::Integer(2));
+
+    // Popping the last elements deletes the key.
+    run(&db, &[b"LPOP", b"q", b"5"]).await;
+    assert_eq!(run(&db, &[b"EXISTS", b"q"]).await, RespMessage::Integer(0));
+    assert_eq!(
+        run(&db, &[b"LPOP", b"q"]).await,
+        RespMessage::BulkString(None)
+    );
+    assert_eq!(
+        run(&db, &[b"LPOP", b"q", b"1"]).await,
+        RespMessage::NullArray
+    );
+    assert_eq!(
+        run(&db, &[b"LRANGE", b"q", b"0", b"-1"]).await,
+        RespMessage::Array(vec![])
+    );
+    assert_eq!(
+        run(&db, &[b"RPUSHX", b"q", b"x"]).await,
+        RespMessage::Integer(0)
+    );
+}
+
+#[tokio::test]
+async fn test_lrem_counts_from_either_end() {
+    let db = new_db();
+    run(
+        &db,
+        &[b"RPUSH", b"l", b"x", b"a", b"x", b"b", b"x", b"c", b"x"],
+    )
+    .await;
+    assert_eq!(
+        run(&db, &[b"LREM", b"l", b"2", b"x"]).await,
+        RespMessage::Integer(2)
+    );
+    assert_eq!(
+        run(&db, &[b"LREM", b"l", b"-1", b"x"]).await,
+        RespMessage::Integer(1)
+    );
+    assert_eq!(
+        run(&db, &[b"LRANGE", b"l", b"0", b"-1"]).await,
+        bulk_array(&[b"a", b"b", b"x", b"c"])
+    );
+    assert_eq!(
+        run(&db, &[b"LREM", b"l", b"-5", b"x"]).await,
+        RespMessage::Integer(1)
+    );
+
+    // Removing many matches from the middle of a long list takes one pass.
+    let mut push: Vec<&[u8]> = vec![b"RPUSH", b"long"];
+    for _ in 0..100_000 {
+        push.extend([b"x".as_slice(), b"y"]);
+    }
+    run(&db, &push).await;
+    let started = std::time::Instant::now();
+    assert_eq!(
+        run(&db, &[b"LREM", b"long", b"0", b"x"]).await,
+        RespMessage::Integer(100_000)
+    );
+    assert_eq!(
+        run(&db, &[b"LREM", b"long", b"-99999", b"y"]).await,
+        RespMessage::Integer(99_999)
+    );
+    assert!(started.elapsed() < std::time::Duration::from_secs(1));
+    assert_eq!(
+        run(&db, &[b"LRANGE", b"long", b"0", b"-1"]).await,
+        bulk_array(&[b"y"])
+    );
+}
+
+#[tokio::test]
+async fn test_list_edit_commands() {
+    let db = new_db();
+    run(&db, &[b"RPUSH", b"l", b"x", b"a", b"x", b"b", b"x"]).await;
+
+    assert_eq!(
+        run(&db, &[b"LREM", b"l", b"-2", b"x"]).await,
+        RespMessage::Integer(2)
+    );
+    assert_eq!(
+        run(&db, &[b"LINSERT", b"l", b"AFTER", b"a", b"y"]).await,
+        RespMessage::Integer(4)
+    );
+    assert_eq!(
+        run(&db, &[b"LINSERT", b"l", b"BEFORE", b"zz", b"y"]).await,
+        RespMessage::Integer(-1)
+    );
+    assert_eq!(
+        run(&db, &[b"LSET", b"l", b"-1", b"B"]).await,
+        RespMessage::SimpleString("OK".to_string())
+    );
+    assert_eq!(
+        run(&db, &[b"LSET", b"l", b"9", b"B"]).await,
+        RespMessage::Error("ERR index out of range".to_string())
+    );
+    assert_eq!(
+        run(&db, &[b"LRANGE", b"l", b"0", b"-1"]).await,
+        bulk_array(&[b"x", b"a", b"y", b"B"])
+    );
+
+    assert_eq!(
+        run(&db, &[b"LTRIM", b"l", b"1", b"-2"]).await,
+        RespMessage::SimpleString("OK".to_string())
+    );
+    assert_eq!(
+        run(&db, &[b"LRANGE", b"l", b"0", b"-1"]).await,
+        bulk_array(&[b"a", b"y"])
+    );
+    run(&db, &[b"LTRIM", b"l", b"5", b"10"]).await;
+    assert_eq!(run(&db, &[b"EXISTS", b"l"]).await, RespMessage::Integer(0));
+}
+
+#[tokio::test]
+async fn test_lpos_rank_count_and_maxlen() {
+    let db = new_db();
+    run(
+        &db,
+        &[
+            b"RPUSH", b"l", b"a", b"b", b"c", b"1", b"2", b"3", b"c", b"c",
+        ],
+    )
+    .await;
+
+    assert_eq!(
+        run(&db, &[b"LPOS", b"l", b"c"]).await,
+        RespMessage::Integer(2)
+    );
+    assert_eq!(
+        run(&db, &[b"LPOS", b"l", b"c", b"RANK", b"2"]).await,
+        RespMessage::Integer(6)
+    );
+    assert_eq!(
+        run(&db, &[b"LPOS", b"l", b"c", b"RANK", b"-1"]).await,
+        RespMessage::Integer(7)
+    );
+    assert_eq!(
+        run(&db, &[b"LPOS", b"l", b"c", b"COUNT", b"0"]).await,
+        RespMessage::Array(vec![
+            RespMessage::Integer(2),
+            RespMessage::Integer(6),
+            RespMessage::Integer(7),
+        ])
+    );
+    assert_eq!(
+        run(&db, &[b"LPOS", b"l", b"c", b"COUNT", b"0", b"MAXLEN", b"3"]).await,
+        RespMessage::Array(vec![RespMessage::Integer(2)])
+    );
+    assert_eq!(
+        run(&db, &[b"LPOS", b"l", b"zz"]).await,
+        RespMessage::BulkString(None)
+    );
+    assert_eq!(
+        run(&db, &[b"LPOS", b"l", b"c", b"RANK", b"0"]).await,
+        RespMessage::Error("ERR RANK can't be zero: use 1 to start from the first match, 2 from the second ... or use negative to start from the end of the list".to_string())
+    );
+}
+
+/// Runs a command on its own task, the way a separate client connection would.
+fn spawn_run(db: &Db, args: &[&[u8]]) -> tokio::task::JoinHandle<RespMessage> {
+    let db = db.clone();
+    let args: Vec<RespMessage> = args.iter().map(|a| bulk(a)).collect();
+    tokio::spawn(async move { handle_array_command(args, &db).await })
+}
+
+async fn settle() {
+    tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+}
+
+#[tokio::test]
+async fn test_blpop_serves_waiters_in_fifo_order() {
+    let db = new_db();
+    let first = spawn_run(&db, &[b"BLPOP", b"jobs", b"other", b"0"]);
+    settle().await;
+    let second = spawn_run(&db, &[b"BLPOP", b"other", b"jobs", b"0"]);
+    settle().await;
+
+    assert_eq!(
+        run(&db, &[b"RPUSH", b"jobs", b"j1"]).await,
+        RespMessage::Integer(1)
+    );
+    assert_eq!(first.await.unwrap(), bulk_array(&[b"jobs", b"j1"]));
+    settle().await;
+    assert!(!second.is_finished());
+
+    run(&db, &[b"LPUSH", b"other", b"o1"]).await;
+    assert_eq!(second.await.unwrap(), bulk_array(&[b"other", b"o1"]));
+    // Both elements went to the blocked clients, so the lists are gone.
+    assert_eq!(
+        run(&db, &[b"EXISTS", b"jobs", b"other"]).await,
+        RespMessage::Integer(0)
+    );
+}
+
+#[tokio::test]
+async fn test_blocking_pops_time_out_or_return_immediately() {
+    let db = new_db();
+    assert_eq!(
+        run(&db, &[b"BRPOP", b"empty", b"0.05"]).await,
+        RespMessage::NullArray
+    );
+
+    run(&db, &[b"RPUSH", b"full", b"a", b"b"]).await;
+    assert_eq!(
+        run(&db, &[b"BRPOP", b"empty", b"full", b"1"]).await,
+        bulk_array(&[b"full", b"b"])
+    );
+    assert_eq!(
+        run(&db, &[b"BLPOP", b"full", b"-1"]).await,
+        RespMessage::Error("ERR timeout is negative".to_string())
+    );
+    for args in [
+        &[&b"BLPOP"[..], b"full", b"1e20"][..],
+        &[b"BLMOVE", b"full", b"dst", b"LEFT", b"LEFT", b"1e20"],
+        &[b"BZPOPMIN", b"zset", b"1e20"],
+    ] {
+        assert_eq!(
+            run(&db, args).await,
+            RespMessage::Error("ERR timeout is out of range".to_string())
+        );
+    }
+
+    // A timed-out waiter must not swallow a later push.
+    run(&db, &[b"RPUSH", b"empty", b"x"]).await;
+    assert_eq!(
+        run(&db, &[b"LRANGE", b"empty", b"0", b"-1"]).await,
+        bulk_array(&[b"x"])
+    );
+}
+
+#[tokio::test]
+async fn test_blmove_waits_for_source_and_wakes_destination_waiters() {
+    let db = new_db();
+    let mover = spawn_run(&db, &[b"BLMOVE", b"src", b"dst", b"LEFT", b"RIGHT", b"0"]);
+    settle().await;
+    let popper = spawn_run(&db, &[b"BLPOP", b"dst", b"0"]);
+    settle().await;
+
+    run(&db, &[b"RPUSH", b"src", b"item"]).await;
+    assert_eq!(mover.await.unwrap(), bulk(b"item"));
+    assert_eq!(popper.await.unwrap(), bulk_array(&[b"dst", b"item"]));
+
+    run(&db, &[b"RPUSH", b"src", b"a", b"b"]).await;
+    assert_eq!(
+        run(&db, &[b"LMOVE", b"src", b"src", b"LEFT", b"RIGHT"]).await,
+        bulk(b"a")
+    );
+    assert_eq!(
+        run(&db, &[b"LRANGE", b"src", b"0", b"-1"]).await,
+        bulk_array(&[b"b", b"a"])
+    );
+}
+
+fn sorted_bulks(reply: RespMessage) -> Vec<Vec<u8>> {
+    let RespMessage::Array(items) = reply else {
+        panic!("expected an array, got {:?}", reply);
+    };
+    let mut items: Vec<Vec<u8>> = items
+        .into_iter()
+        .map(|item| match item {
+            RespMessage::BulkString(Some(bytes)) => bytes,
+            other => panic!("expected a bulk string, got {:?}", other),
+        })
+        .collect();
+    items.sort();
+    items
+}
+
+#[tokio::test]
+async fn test_hash_field_commands() {
+    let db = new_db();
+    assert_eq!(
+        run(&db, &[b"HSET", b"h", b"name", b"xredis", b"lang", b"rust"]).await,
+        RespMessage::Integer(2)
+    );
+    assert_eq!(
+        run(&db, &[b"HSET", b"h", b"name", b"xr", b"stars", b"5"]).await,
+        RespMessage::Integer(1)
+    );
+    assert_eq!(run(&db, &[b"HGET", b"h", b"name"]).await, bulk(b"xr"));
+    assert_eq!(
+        run(&db, &[b"HMGET", b"h", b"lang", b"nope"]).await,
+        RespMessage::Array(vec![bulk(b"rust"), RespMessage::BulkString(None)])
+    );
+    assert_eq!(
+        run(&db, &[b"HSETNX", b"h", b"lang", b"c"]).await,
+        RespMessage::Integer(0)
+    );
+    assert_eq!(
+        run(&db, &[b"HSTRLEN", b"h", b"lang"]).await,
+        RespMessage::Integer(4)
+    );
+    assert_eq!(run(&db, &[b"HLEN", b"h"]).await, RespMessage::Integer(3));
+    assert_eq!(
+        sorted_bulks(run(&db, &[b"HGETALL", b"h"]).await),
+        vec![
+            b"5".to_vec(),
+            b"lang".to_vec(),
+            b"name".to_vec(),
+            b"rust".to_vec(),
+            b"stars".to_vec(),
+            b"xr".to_vec(),
+        ]
+    );
+
+    assert_eq!(
+        run(&db, &[b"HDEL", b"h", b"name", b"lang", b"stars", b"nope"]).await,
+        RespMessage::Integer(3)
+    );
+    assert_eq!(run(&db, &[b"EXISTS", b"h"]).await, RespMessage::Integer(0));
+}
+
+#[tokio::test]
+async fn test_hash_increments() {
+    let db = new_db();
+    assert_eq!(
+        run(&db, &[b"HINCRBY", b"h", b"n", b"5"]).await,
+        RespMessage::Integer(5)
+    );
+    assert_eq!(
+        run(&db, &[b"HINCRBY", b"h", b"n", b"-7"]).await,
+        RespMessage::Integer(-2)
+    );
+    assert_eq!(
+        run(&db, &[b"HINCRBYFLOAT", b"h", b"f", b"10.5"]).await,
+        bulk(b"10.5")
+    );
+    assert_eq!(
+        run(&db, &[b"HINCRBYFLOAT", b"h", b"f", b"0.1"]).await,
+        bulk(b"10.6")
+    );
+    assert_eq!(
+        run(&db, &[b"HINCRBYFLOAT", b"h", b"f", b"-5.6"]).await,
+        bulk(b"5")
+    );
+
+    run(
+        &db,
+        &[b"HSET", b"h", b"big", b"9223372036854775807", b"s", b"abc"],
+    )
+    .await;
+    assert_eq!(
+        run(&db, &[b"HINCRBY", b"h", b"big", b"1"]).await,
+        RespMessage::Error("ERR increment or decrement would overflow".to_string())
+    );
+    assert_eq!(
+        run(&db, &[b"HINCRBY", b"h", b"s", b"1"]).await,
+        RespMessage::Error("ERR hash value is not an integer".to_string())
+    );
+    // A rejected increment must not leave an empty hash behind.
+    assert_eq!(
+        run(&db, &[b"HINCRBYFLOAT", b"new", b"f", b"inf"]).await,
+        RespMessage::Error("ERR increment would produce NaN or Infinity".to_string())
+    );
+    assert_eq!(
+        run(&db, &[b"EXISTS", b"new"]).await,
+        RespMessage::Integer(0)
+    );
+}
+
+#[tokio::test]
+async fn test_hrandfield_counts() {
+    let db = new_db();
+    run(&db, &[b"HSET", b"h", b"a", b"1", b"b", b"2", b"c", b"3"]).await;
+
+    assert_eq!(
+        sorted_bulks(run(&db, &[b"HRANDFIELD", b"h", b"10"]).await),
+        vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()]
+    );
+    let RespMessage::Array(repeated) = run(&db, &[b"HRANDFIELD", b"h", b"-8"]).await else {
+        panic!("expected an array");
+    };
+    assert_eq!(repeated.len(), 8);
+    let RespMessage::Array(with_values) =
+        run(&db, &[b"HRANDFIELD", b"h", b"2", b"WITHVALUES"]).await
+    else {
+        panic!("expected an array");
+    };
+    assert_eq!(with_values.len(), 4);
+    assert_eq!(
+        run(&db, &[b"HRANDFIELD", b"missing"]).await,
+        RespMessage::BulkString(None)
+    );
+    assert_eq!(
+        run(&db, &[b"HRANDFIELD", b"h", b"-100000000000"]).await,
+        RespMessage::Error("ERR value is out of range".to_string())
+    );
+
+    // Small samples of a big hash are drawn one by one, and still distinct.
+    for i in 0..1000 {
+        let field = i.to_string();
+        run(&db, &[b"HSET", b"big", field.as_bytes(), b"v"]).await;
+    }
+    let mut sample = sorted_bulks(run(&db, &[b"HRANDFIELD", b"big", b"50"]).await);
+    sample.dedup();
+    assert_eq!(sample.len(), 50);
+}
+
+#[tokio::test]
+async fn test_hscan_visits_every_field_once() {
+    let db = new_db();
+    for i in 0..50 {
+        let field = format!("field:{}", i);
+        run(&db, &[b"HSET", b"h", field.as_bytes(), b"v"]).await;
+    }
+
+    let mut seen = Vec::new();
+    let mut cursor = b"0".to_vec();
+    loop {
+        let reply = run(&db, &[b"HSCAN", b"h", &cursor, b"COUNT", b"7", b"NOVALUES"]).await;
+        let RespMessage::Array(mut parts) = reply else {
+            panic!("expected an array");
+        };
+        let page = parts.pop().unwrap();
+        let RespMessage::BulkString(Some(next)) = parts.pop().unwrap() else {
+            panic!("expected a cursor");
+        };
+        seen.extend(sorted_bulks(page));
+        if next == b"0" {
+            break;
+        }
+        cursor = next;
+    }
+    seen.sort();
+    let mut expected: Vec<Vec<u8>> = (0..50)
+        .map(|i| format!("field:{}", i).into_bytes())
+        .collect();
+    expected.sort();
+    assert_eq!(seen, expected);
+
+    let reply = run(
+        &db,
+        &[
+            b"HSCAN",
+            b"h",
+            b"0",
+            b"MATCH",
+            b"field:1?",
+            b"COUNT",
+            b"100",
+        ],
+    )
+    .await;
+    let RespMessage::Array(mut parts) = reply else {
+        panic!("expected an array");
+    };
+    // Ten matching fields, each followed by its value.
+    assert_eq!(sorted_bulks(parts.pop().unwrap()).len(), 20);
+}
+
+#[test]
+fn test_glob_match() {
+    use super::scan::glob_match;
+
+    assert!(glob_match(b"*", b""));
+    assert!(glob_match(b"user:*", b"user:42"));
+    assert!(glob_match(b"h?llo", b"hello"));
+    assert!(glob_match(b"h[ae]llo", b"hallo"));
+    assert!(!glob_match(b"h[^e]llo", b"hello"));
+    assert!(glob_match(b"h[a-c]llo", b"hbllo"));
+    assert!(glob_match(b"a\\*b", b"a*b"));
+    assert!(!glob_match(b"a\\*b", b"axb"));
+    assert!(glob_match(b"*:*:end", b"x:y:z:end"));
+    assert!(!glob_match(b"user:*", b"account:1"));
+    assert!(glob_match(b"*[0-9]", b"key:7"));
+    assert!(glob_match(b"a*b*", b"axxbyy"));
+    assert!(!glob_match(b"a*b?", b"axxb"));
+
+    // Each `*` must not retry the whole rest of the pattern at every offset.
+    let started = std::time::Instant::now();
+    let pattern = b"*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*b";
+    assert!(!glob_match(pattern, &[b'a'; 4096]));
+    assert!(started.elapsed() < std::time::Duration::from_secs(1));
+}
+
+#[tokio::test]
+async fn test_set_membership_commands() {
+    let db = new_db();
+    assert_eq!(
+        run(&db, &[b"SADD", b"s", b"a", b"b", b"a", b"c"]).await,
+        RespMessage::Integer(3)
+    );
+    assert_eq!(run(&db, &[b"SCARD", b"s"]).await, RespMessage::Integer(3));
+    assert_eq!(
+        run(&db, &[b"SMISMEMBER", b"s", b"a", b"z"]).await,
+        RespMessage::Array(vec![RespMessage::Integer(1), RespMessage::Integer(0)])
+    );
+    assert_eq!(
+        run(&db, &[b"SMOVE", b"s", b"t", b"a"]).await,
+        RespMessage::Integer(1)
+    );
+    assert_eq!(
+        run(&db, &[b"SISMEMBER", b"t", b"a"]).await,
+        RespMessage::Integer(1)
+    );
+    assert_eq!(
+        run(&db, &[b"SREM", b"s", b"b", b"nope"]).await,
+        RespMessage::Integer(1)
+    );
+
+    let RespMessage::Array(popped) = run(&db, &[b"SPOP", b"s", b"5"]).await else {
+        panic!("expected an array");
+    };
+    assert_eq!(popped, vec![bulk(b"c")]);
+    assert_eq!(run(&db, &[b"EXISTS", b"s"]).await, RespMessage::Integer(0));
+    assert_eq!(
+        run(&db, &[b"SPOP", b"s"]).await,
+        RespMessage::BulkString(None)
+    );
+    let RespMessage::Array(sampled) = run(&db, &[b"SRANDMEMBER", b"t", b"-3"]).await else {
+        panic!("expected an array");
+    };
+    assert_eq!(sampled, vec![bulk(b"a"), bulk(b"a"), bulk(b"a")]);
+    assert_eq!(
+        run(&db, &[b"SRANDMEMBER", b"t", b"-100000000000"]).await,
+        RespMessage::Error("ERR value is out of range".to_string())
+    );
+}
+
+#[tokio::test]
+async fn test_set_algebra() {
+    let db = new_db();
+    run(&db, &[b"SADD", b"a", b"1", b"2", b"3", b"4"]).await;
+    run(&db, &[b"SADD", b"b", b"3", b"4", b"5"]).await;
+    run(&db, &[b"SADD", b"c", b"4", b"6"]).await;
+
+    assert_eq!(
+        sorted_bulks(run(&db, &[b"SINTER", b"a", b"b", b"c"]).await),
+        vec![b"4".to_vec()]
+    );
+    assert_eq!(
+        sorted_bulks(run(&db, &[b"SDIFF", b"a", b"b", b"missing"]).await),
+        vec![b"1".to_vec(), b"2".to_vec()]
+    );
+    assert_eq!(
+        run(&db, &[b"SUNIONSTORE", b"u", b"a", b"b", b"c"]).await,
+        RespMessage::Integer(6)
+    );
+    assert_eq!(
+        run(&db, &[b"SINTERCARD", b"2", b"a", b"b", b"LIMIT", b"1"]).await,
+        RespMessage::Integer(1)
+    );
+    assert_eq!(
+        sorted_bulks(run(&db, &[b"SINTER", b"a", b"missing"]).await),
+        Vec::<Vec<u8>>::new()
+    );
+
+    // An empty result removes the destination, whatever it held.
+    run(&db, &[b"SET", b"dest", b"string"]).await;
+    assert_eq!(
+        run(&db, &[b"SINTERSTORE", b"dest", b"a", b"missing"]).await,
+        RespMessage::Integer(0)
+    );
+    assert_eq!(
+        run(&db, &[b"EXISTS", b"dest"]).await,
+        RespMessage::Integer(0)
+    );
+
+    run(&db, &[b"SET", b"str", b"x"]).await;
+    assert_eq!(
+        run(&db, &[b"SUNION", b"a", b"str"]).await,
+        RespMessage::Error(
+            "WRONGTYPE Operation against a key holding the wrong kind of value".to_string()
+        )
+    );
+    assert_eq!(
+        run(&db, &[b"SINTERCARD", b"3", b"a", b"b"]).await,
+        RespMessage::Error("ERR Number of keys can't be greater than number of args".to_string())
+    );
+}
+
+#[tokio::test]
+async fn test_set_commands_treat_expired_keys_as_missing() {
+    let db = new_db();
+    run(&db, &[b"SET", b"s", b"v", b"PX", b"1"]).await;
+    tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+    // The expired string no longer blocks the key from becoming a set.
+    assert_eq!(
+        run(&db, &[b"SADD", b"s", b"a"]).await,
+        RespMessage::Integer(1)
+    );
+    assert_eq!(
+        sorted_bulks(run(&db, &[b"SMEMBERS", b"s"]).await),
+        vec![b"a".to_vec()]
+    );
+}
+
+#[tokio::test]
+async fn test_zadd_flags_and_scores() {
+    let db = new_db();
+    assert_eq!(
+        run(&db, &[b"ZADD", b"z", b"1", b"a", b"2", b"b"]).await,
+        RespMessage::Integer(2)
+    );
+    // NX leaves existing members alone; XX never adds.
+    assert_eq!(
+        run(&db, &[b"ZADD", b"z", b"NX", b"5", b"a", b"3", b"c"]).await,
+        RespMessage::Integer(1)
+    );
+    assert_eq!(
+        run(&db, &[b"ZADD", b"z", b"XX", b"CH", b"5", b"a", b"9", b"d"]).await,
+        RespMessage::Integer(1)
+    );
+    // GT only ever raises a score.
+    assert_eq!(
+        run(&db, &[b"ZADD", b"z", b"GT", b"CH", b"1", b"a", b"4", b"b"]).await,
+        RespMessage::Integer(1)
+    );
+    assert_eq!(run(&db, &[b"ZSCORE", b"z", b"a"]).await, bulk(b"5"));
+    assert_eq!(run(&db, &[b"ZSCORE", b"z", b"b"]).await, bulk(b"4"));
+    assert_eq!(
+        run(&db, &[b"ZADD", b"z", b"INCR", b"0.5", b"a"]).await,
+        bulk(b"5.5")
+    );
+    assert_eq!(
+        run(&db, &[b"ZADD", b"z", b"LT", b"INCR", b"1", b"a"]).await,
+        RespMessage::BulkString(None)
+    );
+    assert_eq!(
+        run(&db, &[b"ZINCRBY", b"z", b"-10", b"new"]).await,
+        bulk(b"-10")
+    );
+    assert_eq!(run(&db, &[b"ZCARD", b"z"]).await, RespMessage::Integer(4));
+
+    assert_eq!(
+        run(&db, &[b"ZADD", b"z", b"NX", b"XX", b"1", b"a"]).await,
+        RespMessage::Error("ERR XX and NX options at the same time are not compatible".to_string())
+    );
+    assert_eq!(
+        run(&db, &[b"ZADD", b"z", b"1", b"a", b"nope", b"b"]).await,
+        RespMessage::Error("ERR value is not a valid float".to_string())
+    );
+    assert_eq!(
+        run(&db, &[b"ZADD", b"z", b"INCR", b"inf", b"a"]).await,
+        bulk(b"inf")
+    );
+    assert_eq!(
+        run(&db, &[b"ZADD", b"z", b"INCR", b"-inf", b"a"]).await,
+        RespMessage::Error("ERR resulting score is not a number (NaN)".to_string())
+    );
+    assert_eq!(
+        run(&db, &[b"ZADD", b"missing", b"XX", b"1", b"a"]).await,
+        RespMessage::Integer(0)
+    );
+    assert_eq!(
+        run(&db, &[b"EXISTS", b"missing"]).await,
+        RespMessage::Integer(0)
+    );
+
+    assert_eq!(
+        run(&db, &[b"ZREM", b"z", b"a", b"b", b"c", b"new", b"x"]).await,
+        RespMessage::Integer(4)
+    );
+    assert_eq!(run(&db, &[b"EXISTS", b"z"]).await, RespMessage::Integer(0));
+}
+
+#[tokio::test]
+async fn test_zrank_zcount_and_ranges() {
+    let db = new_db();
+    run(
+        &db,
+        &[
+            b"ZADD", b"board", b"10", b"alice", b"20", b"bob", b"20", b"bea", b"30", b"carol",
+        ],
+    )
+    .await;
+
+    assert_eq!(
+        run(&db, &[b"ZRANK", b"board", b"bob"]).await,
+        RespMessage::Integer(2)
+    );
+    assert_eq!(
+        run(&db, &[b"ZREVRANK", b"board", b"carol", b"WITHSCORE"]).await,
+        RespMessage::Array(vec![RespMessage::Integer(0), bulk(b"30")])
+    );
+    assert_eq!(
+        run(&db, &[b"ZRANK", b"board", b"nobody"]).await,
+        RespMessage::BulkString(None)
+    );
+    assert_eq!(
+        run(&db, &[b"ZCOUNT", b"board", b"(10", b"+inf"]).await,
+        RespMessage::Integer(3)
+    );
+
+    assert_eq!(
+        run(&db, &[b"ZRANGE", b"board", b"0", b"-1"]).await,
+        bulk_array(&[b"alice", b"bea", b"bob", b"carol"])
+    );
+    assert_eq!(
+        run(
+            &db,
+            &[b"ZRANGE", b"board", b"0", b"1", b"REV", b"WITHSCORES"]
+        )
+        .await,
+        bulk_array(&[b"carol", b"30", b"bob", b"20"])
+    );
+    assert_eq!(
+        run(&db, &[b"ZRANGE", b"board", b"15", b"(30", b"BYSCORE"]).await,
+        bulk_array(&[b"bea", b"bob"])
+    );
+    assert_eq!(
+        run(
+            &db,
+            &[b"ZRANGE", b"board", b"+inf", b"-inf", b"BYSCORE", b"REV", b"LIMIT", b"1", b"2"]
+        )
+        .await,
+        bulk_array(&[b"bob", b"bea"])
+    );
+    assert_eq!(
+        run(
+            &db,
+            &[b"ZRANGEBYSCORE", b"board", b"20", b"20", b"WITHSCORES"]
+        )
+        .await,
+        bulk_array(&[b"bea", b"20", b"bob", b"20"])
+    );
+    assert_eq!(
+        run(&db, &[b"ZREVRANGE", b"board", b"0", b"0"]).await,
+        bulk_array(&[b"carol"])
+    );
+    assert_eq!(
+        run(
+            &db,
+            &[b"ZRANGE", b"board", b"0", b"-1", b"LIMIT", b"0", b"1"]
+        )
+        .await,
+        RespMessage::Error(
+            "ERR syntax error, LIMIT is only supported in combination with either BYSCORE or BYLEX"
+                .to_string()
+        )
+    );
+    assert_eq!(
+        run(&db, &[b"ZRANGE", b"board", b"x", b"1", b"BYSCORE"]).await,
+        RespMessage::Error("ERR min or max is not a float".to_string())
+    );
+
+    run(
+        &db,
+        &[
+            b"ZADD", b"lex", b"0", b"a", b"0", b"b", b"0", b"c", b"0", b"d",
+        ],
+    )
+    .await;
+    assert_eq!(
+        run(&db, &[b"ZRANGE", b"lex", b"[b", b"+", b"BYLEX"]).await,
+        bulk_array(&[b"b", b"c", b"d"])
+    );
+    assert_eq!(
+        run(
+            &db,
+            &[b"ZREVRANGEBYLEX", b"lex", b"(d", b"-", b"LIMIT", b"0", b"2"]
+        )
+        .await,
+        bulk_array(&[b"c", b"b"])
+    );
+    assert_eq!(
+        run(&db, &[b"ZLEXCOUNT", b"lex", b"-", b"(c"]).await,
+        RespMessage::Integer(2)
+    );
+}
+
+#[tokio::test]
+async fn test_zset_aggregation_with_weights_and_sets() {
+    let db = new_db();
+    run(&db, &[b"ZADD", b"a", b"1", b"x", b"2", b"y", b"3", b"z"]).await;
+    run(&db, &[b"ZADD", b"b", b"10", b"y", b"20", b"z"]).await;
+    run(&db, &[b"SADD", b"plain", b"z", b"w"]).await;
+
+    assert_eq!(
+        run(
+            &db,
+            &[
+                b"ZUNIONSTORE",
+                b"out",
+                b"2",
+                b"a",
+                b"b",
+                b"WEIGHTS",
+                b"2",
+                b"1"
+            ]
+        )
+        .await,
+        RespMessage::Integer(3)
+    );
+    assert_eq!(
+        run(&db, &[b"ZRANGE", b"out", b"0", b"-1", b"WITHSCORES"]).await,
+        bulk_array(&[b"x", b"2", b"y", b"14", b"z", b"26"])
+    );
+    // Plain sets take part with every score at 1.
+    assert_eq!(
+        run(
+            &db,
+            &[
+                b"ZINTER",
+                b"3",
+                b"a",
+                b"b",
+                b"plain",
+                b"AGGREGATE",
+                b"MAX",
+                b"WITHSCORES"
+            ]
+        )
+        .await,
+        bulk_array(&[b"z", b"20"])
+    );
+    assert_eq!(
+        run(&db, &[b"ZDIFF", b"2", b"a", b"b", b"WITHSCORES"]).await,
+        bulk_array(&[b"x", b"1"])
+    );
+
+    // An empty result removes the destination.
+    assert_eq!(
+        run(&db, &[b"ZINTERSTORE", b"out", b"2", b"a", b"missing"]).await,
+        RespMessage::Integer(0)
+    );
+    assert_eq!(
+        run(&db, &[b"EXISTS", b"out"]).await,
+        RespMessage::Integer(0)
+    );
+
+    assert_eq!(
+        run(&db, &[b"ZUNION", b"0", b"a"]).await,
+        RespMessage::Error("ERR at least 1 input key is needed for 'zunion' command".to_string())
+    );
+    assert_eq!(
+        run(&db, &[b"ZUNION", b"2", b"a", b"b", b"WEIGHTS", b"1"]).await,
+        RespMessage::Error("ERR syntax error".to_string())
+    );
+    assert_eq!(
+        run(
+            &db,
+            &[b"ZDIFFSTORE", b"out", b"1", b"a", b"AGGREGATE", b"SUM"]
+        )
+        .await,
+        RespMessage::Error("ERR syntax error".to_string())
+    );
+}
+
+#[tokio::test]
+async fn test_zset_pops_removals_and_rangestore() {
+    let db = new_db();
+    run(
+        &db,
+        &[
+            b"ZADD", b"z", b"1", b"a", b"2", b"b", b"3", b"c", b"4", b"d", b"5", b"e",
+        ],
+    )
+    .await;
+
+    assert_eq!(
+        run(&db, &[b"ZPOPMIN", b"z"]).await,
+        bulk_array(&[b"a", b"1"])
+    );
+    assert_eq!(
+        run(&db, &[b"ZPOPMAX", b"z", b"2"]).await,
+        bulk_array(&[b"e", b"5", b"d", b"4"])
+    );
+    assert_eq!(
+        run(&db, &[b"ZMSCORE", b"z", b"b", b"a"]).await,
+        RespMessage::Array(vec![bulk(b"2"), RespMessage::BulkString(None)])
+    );
+
+    assert_eq!(
+        run(&db, &[b"ZRANGESTORE", b"copy", b"z", b"0", b"-1"]).await,
+        RespMessage::Integer(2)
+    );
+    assert_eq!(
+        run(&db, &[b"ZREMRANGEBYSCORE", b"copy", b"(2", b"+inf"]).await,
+        RespMessage::Integer(1)
+    );
+    assert_eq!(
+        run(&db, &[b"ZREMRANGEBYRANK", b"copy", b"0", b"-1"]).await,
+        RespMessage::Integer(1)
+    );
+    assert_eq!(
+        run(&db, &[b"EXISTS", b"copy"]).await,
+        RespMessage::Integer(0)
+    );
+
+    run(&db, &[b"ZADD", b"lex", b"0", b"a", b"0", b"b", b"0", b"c"]).await;
+    assert_eq!(
+        run(&db, &[b"ZREMRANGEBYLEX", b"lex", b"[b", b"+"]).await,
+        RespMessage::Integer(2)
+    );
+    assert_eq!(
+        run(&db, &[b"ZPOPMIN", b"z", b"-1"]).await,
+        RespMessage::Error("ERR value is out of range, must be positive".to_string())
+    );
+}
+
+#[tokio::test]
+async fn test_bzpopmin_waits_for_zadd_and_ignores_list_pushes() {
+    let db = new_db();
+    assert_eq!(
+        run(&db, &[b"BZPOPMAX", b"missing", b"0.05"]).await,
+        RespMessage::NullArray
+    );
+
+    let zpopper = spawn_run(&db, &[b"BZPOPMIN", b"key", b"0"]);
+    settle().await;
+    let lpopper = spawn_run(&db, &[b"BLPOP", b"key", b"0"]);
+    settle().await;
+
+    // The list waiter is served even though the zset waiter has been waiting longer.
+    run(&db, &[b"RPUSH", b"key", b"item"]).await;
+    assert_eq!(lpopper.await.unwrap(), bulk_array(&[b"key", b"item"]));
+
+    run(&db, &[b"ZADD", b"key", b"2", b"two", b"1", b"one"]).await;
+    assert_eq!(zpopper.await.unwrap(), bulk_array(&[b"key", b"one", b"1"]));
+    assert_eq!(
+        run(&db, &[b"ZRANGE", b"key", b"0", b"-1"]).await,
+        bulk_array(&[b"two"])
+    );
+}
+
+#[tokio::test]
+async fn test_stream_ids_ranges_and_trimming() {
+    let db = new_db();
+    assert_eq!(
+        run(&db, &[b"XADD", b"s", b"1-1", b"f", b"a"]).await,
+        bulk(b"1-1")
+    );
+    assert_eq!(
+        run(&db, &[b"XADD", b"s", b"1-*", b"f", b"b"]).await,
+        bulk(b"1-2")
+    );
+    assert_eq!(
+        run(&db, &[b"XADD", b"s", b"5", b"f", b"c", b"g", b"d"]).await,
+        bulk(b"5-0")
+    );
+    assert_eq!(
+        run(&db, &[b"XADD", b"s", b"5-0", b"f", b"x"]).await,
+        RespMessage::Error(
+            "ERR The ID specified in XADD is equal or smaller than the target stream top item"
+                .to_string()
+        )
+    );
+    assert_eq!(
+        run(&db, &[b"XADD", b"s", b"bad-id", b"f", b"x"]).await,
+        RespMessage::Error(
+            "ERR Invalid stream ID specified as stream command argument".to_string()
+        )
+    );
+    assert_eq!(
+        run(&db, &[b"XADD", b"s", b"6-0", b"odd"]).await,
+        RespMessage::Error("ERR wrong number of arguments for 'xadd' command".to_string())
+    );
+
+    let entry =
+        |id: &[u8], fields: &[&[u8]]| RespMessage::Array(vec![bulk(id), bulk_array(fields)]);
+    assert_eq!(
+        run(&db, &[b"XRANGE", b"s", b"(1-1", b"+"]).await,
+        RespMessage::Array(vec![
+            entry(b"1-2", &[b"f", b"b"]),
+            entry(b"5-0", &[b"f", b"c", b"g", b"d"]),
+        ])
+    );
+    assert_eq!(
+        run(&db, &[b"XREVRANGE", b"s", b"+", b"-", b"COUNT", b"1"]).await,
+        RespMessage::Array(vec![entry(b"5-0", &[b"f", b"c", b"g", b"d"])])
+    );
+    // A bare time covers every sequence number in that millisecond.
+    assert_eq!(
+        run(&db, &[b"XRANGE", b"s", b"1", b"1"]).await,
+        RespMessage::Array(vec![
+            entry(b"1-1", &[b"f", b"a"]),
+            entry(b"1-2", &[b"f", b"b"])
+        ])
+    );
+
+    assert_eq!(
+        run(&db, &[b"XDEL", b"s", b"1-2", b"9-9"]).await,
+        RespMessage::Integer(1)
+    );
+    // Deleting the top entry never lets IDs go backwards.
+    run(&db, &[b"XDEL", b"s", b"5-0"]).await;
+    assert_eq!(
+        run(&db, &[b"XADD", b"s", b"4-0", b"f", b"x"]).await,
+        RespMessage::Error(
+            "ERR The ID specified in XADD is equal or smaller than the target stream top item"
+                .to_string()
+        )
+    );
+
+    for id in [b"6", b"7", b"8"] {
+        run(&db, &[b"XADD", b"s", id, b"f", b"v"]).await;
+    }
+    assert_eq!(run(&db, &[b"XLEN", b"s"]).await, RespMessage::Integer(4));
+    assert_eq!(
+        run(&db, &[b"XADD", b"s", b"MAXLEN", b"2", b"9", b"f", b"v"]).await,
+        bulk(b"9-0")
+    );
+    assert_eq!(run(&db, &[b"XLEN", b"s"]).await, RespMessage::Integer(2));
+    assert_eq!(
+        run(&db, &[b"XTRIM", b"s", b"MINID", b"9"]).await,
+        RespMessage::Integer(1)
+    );
+    assert_eq!(
+        run(&db, &[b"XTRIM", b"s", b"MAXLEN", b"0", b"LIMIT", b"1"]).await,
+        RespMessage::Error(
+            "ERR syntax error, LIMIT cannot be used without the special ~ option".to_string()
+        )
+    );
+    assert_eq!(
+        run(&db, &[b"XADD", b"missing", b"NOMKSTREAM", b"*", b"f", b"v"]).await,
+        RespMessage::BulkString(None)
+    );
+    assert_eq!(
+        run(&db, &[b"EXISTS", b"missing"]).await,
+        RespMessage::Integer(0)
+    );
+}
+
+#[tokio::test]
+async fn test_xread_returns_new_entries_and_wakes_every_blocked_reader() {
+    let db = new_db();
+    run(&db, &[b"XADD", b"s", b"1-0", b"f", b"a"]).await;
+    assert_eq!(
+        run(&db, &[b"XREAD", b"STREAMS", b"s", b"0"]).await,
+        RespMessage::Array(vec![RespMessage::Array(vec![
+            bulk(b"s"),
+            RespMessage::Array(vec![RespMessage::Array(vec![
+                bulk(b"1-0"),
+                bulk_array(&[b"f", b"a"])
+            ])]),
+        ])])
+    );
+    assert_eq!(
+        run(&db, &[b"XREAD", b"BLOCK", b"50", b"STREAMS", b"s", b"$"]).await,
+        RespMessage::NullArray
+    );
+
+    let first = spawn_run(&db, &[b"XREAD", b"BLOCK", b"0", b"STREAMS", b"s", b"$"]);
+    let second = spawn_run(
+        &db,
+        &[
+            b"XREAD", b"BLOCK", b"0", b"STREAMS", b"other", b"s", b"0", b"1-0",
+        ],
+    );
+    settle().await;
+    run(&db, &[b"XADD", b"s", b"2-0", b"f", b"b"]).await;
+
+    let expected = RespMessage::Array(vec![RespMessage::Array(vec![
+        bulk(b"s"),
+        RespMessage::Array(vec![RespMessage::Array(vec![
+            bulk(b"2-0"),
+            bulk_array(&[b"f", b"b"]),
+        ])]),
+    ])]);
+    assert_eq!(first.await.unwrap(), expected);
+    assert_eq!(second.await.unwrap(), expected);
+}
+
+/// Zeroes the idle times in an extended XPENDING reply, which depend on timing.
+fn without_idle(reply: RespMessage) -> RespMessage {
+    let RespMessage::Array(entries) = reply else {
+        panic!("expected an array, got {:?}", reply);
+    };
+    RespMessage::Array(
+        entries
+            .into_iter()
+            .map(|entry| match entry {
+                RespMessage::Array(mut parts) => {
+                    parts[2] = RespMessage::Integer(0);
+                    RespMessage::Array(parts)
+                }
+                other => panic!("expected a pending entry, got {:?}", other),
+            })
+            .collect(),
+    )
+}
+
+#[tokio::test]
+async fn test_consumer_groups_track_pending_entries_per_consumer() {
+    let db = new_db();
+    assert_eq!(
+        run(&db, &[b"XGROUP", b"CREATE", b"s", b"g", b"$", b"MKSTREAM"]).await,
+        RespMessage::SimpleString("OK".to_string())
+    );
+    assert_eq!(
+        run(&db, &[b"XGROUP", b"CREATE", b"s", b"g", b"$"]).await,
+        RespMessage::Error("BUSYGROUP Consumer Group name already exists".to_string())
+    );
+    for id in [b"1", b"2", b"3"] {
+        run(&db, &[b"XADD", b"s", id, b"n", id]).await;
+    }
+
+    let ids_of = |reply: RespMessage| -> Vec<Vec<u8>> {
+        let RespMessage::Array(streams) = reply else {
+            panic!("expected streams, got {:?}", reply);
+        };
+        let RespMessage::Array(stream) = &streams[0] else {
+            panic!("expected a stream");
+        };
+        let RespMessage::Array(entries) = &stream[1] else {
+            panic!("expected entries");
+        };
+        entries
+            .iter()
+            .map(|entry| match entry {
+                RespMessage::Array(parts) => match &parts[0] {
+                    RespMessage::BulkString(Some(id)) => id.clone(),
+                    other => panic!("expected an id, got {:?}", other),
+                },
+                other => panic!("expected an entry, got {:?}", other),
+            })
+            .collect()
+    };
+
+    let alice = run(
+        &db,
+        &[
+            b"XREADGROUP",
+            b"GROUP",
+            b"g",
+            b"alice",
+            b"COUNT",
+            b"2",
+            b"STREAMS",
+            b"s",
+            b">",
+        ],
+    )
+    .await;
+    assert_eq!(ids_of(alice), vec![b"1-0".to_vec(), b"2-0".to_vec()]);
+    let bob = run(
+        &db,
+        &[
+            b"XREADGROUP",
+            b"GROUP",
+            b"g",
+            b"bob",
+            b"STREAMS",
+            b"s",
+            b">",
+        ],
+    )
+    .await;
+    assert_eq!(ids_of(bob), vec![b"3-0".to_vec()]);
+
+    assert_eq!(
+        run(&db, &[b"XPENDING", b"s", b"g"]).await,
+        RespMessage::Array(vec![
+            RespMessage::Integer(3),
+            bulk(b"1-0"),
+            bulk(b"3-0"),
+            RespMessage::Array(vec![
+                bulk_array(&[b"alice", b"2"]),
+                bulk_array(&[b"bob", b"1"])
+            ]),
+        ])
+    );
+    assert_eq!(
+        run(&db, &[b"XACK", b"s", b"g", b"1-0", b"1-0"]).await,
+        RespMessage::Integer(1)
+    );
+    // Re-reading history only shows the consumer's own unacknowledged entries.
+    let history = run(
+        &db,
+        &[
+            b"XREADGROUP",
+            b"GROUP",
+            b"g",
+            b"alice",
+            b"STREAMS",
+            b"s",
+            b"0",
+        ],
+    )
+    .await;
+    assert_eq!(ids_of(history), vec![b"2-0".to_vec()]);
+
+    assert_eq!(
+        run(
+            &db,
+            &[b"XCLAIM", b"s", b"g", b"bob", b"0", b"2-0", b"JUSTID"]
+        )
+        .await,
+        bulk_array(&[b"2-0"])
+    );
+    assert_eq!(
+        without_idle(run(&db, &[b"XPENDING", b"s", b"g", b"-", b"+", b"10", b"bob"]).await),
+        RespMessage::Array(vec![
+            RespMessage::Array(vec![
+                bulk(b"2-0"),
+                bulk(b"bob"),
+                RespMessage::Integer(0),
+                RespMessage::Integer(1),
+            ]),
+            RespMessage::Array(vec![
+                bulk(b"3-0"),
+                bulk(b"bob"),
+                RespMessage::Integer(0),
+                RespMessage::Integer(1),
+            ]),
+        ])
+    );
+
+    // Entries deleted from the stream are dropped from the PEL by XAUTOCLAIM.
+    run(&db, &[b"XDEL", b"s", b"3-0"]).await;
+    assert_eq!(
+        run(
+            &db,
+            &[b"XAUTOCLAIM", b"s", b"g", b"carol", b"0", b"0", b"JUSTID"]
+        )
+        .await,
+        RespMessage::Array(vec![
+            bulk(b"0-0"),
+            bulk_array(&[b"2-0"]),
+            bulk_array(&[b"3-0"]),
+        ])
+    );
+    assert_eq!(
+        run(&db, &[b"XGROUP", b"DELCONSUMER", b"s", b"g", b"carol"]).await,
+        RespMessage::Integer(1)
+    );
+    assert_eq!(
+        run(
+            &db,
+            &[
+                b"XREADGROUP",
+                b"GROUP",
+                b"nope",
+                b"c",
+                b"STREAMS",
+                b"s",
+                b">"
+            ]
+        )
+        .await,
+        RespMessage::Error(
+            "NOGROUP No such key 's' or consumer group 'nope' in XREADGROUP with GROUP option"
+                .to_string()
+        )
+    );
+
+    let waiter = spawn_run(
+        &db,
+        &[
+            b"XREADGROUP",
+            b"GROUP",
+            b"g",
+            b"dave",
+            b"BLOCK",
+            b"0",
+            b"STREAMS",
+            b"s",
+            b">",
+        ],
+    );
+    settle().await;
+    run(&db, &[b"XADD", b"s", b"4", b"n", b"4"]).await;
+    assert_eq!(ids_of(waiter.await.unwrap()), vec![b"4-0".to_vec()]);
+    assert_eq!(
+        without_idle(run(&db, &[b"XPENDING", b"s", b"g", b"-", b"+", b"10", b"dave"]).await),
+        RespMessage::Array(vec![RespMessage::Array(vec![
+            bulk(b"4-0"),
+            bulk(b"dave"),
+            RespMessage::Integer(0),
+            RespMessage::Integer(1),
+        ])])
+    );
+}
+
+#[tokio::test]
+async fn test_expire_flags_and_ttl_queries() {
+    let db = new_db();
+    run(&db, &[b"RPUSH", b"list", b"a"]).await;
+    let int = RespMessage::Integer;
+
+    assert_eq!(run(&db, &[b"TTL", b"missing"]).await, int(-2));
+    assert_eq!(run(&db, &[b"TTL", b"list"]).await, int(-1));
+    assert_eq!(run(&db, &[b"EXPIRETIME", b"list"]).await, int(-1));
+    assert_eq!(run(&db, &[b"EXPIRE", b"missing", b"10"]).await, int(0));
+
+    // No TTL counts as infinite: XX and GT refuse, LT accepts.
+    assert_eq!(run(&db, &[b"EXPIRE", b"list", b"100", b"XX"]).await, int(0));
+    assert_eq!(run(&db, &[b"EXPIRE", b"list", b"100", b"GT"]).await, int(0));
+    assert_eq!(run(&db, &[b"EXPIRE", b"list", b"100", b"NX"]).await, int(1));
+    assert_eq!(run(&db, &[b"EXPIRE", b"list", b"200", b"NX"]).await, int(0));
+    assert_eq!(run(&db, &[b"TTL", b"list"]).await, int(100));
+    assert_eq!(run(&db, &[b"EXPIRE", b"list", b"50", b"GT"]).await, int(0));
+    assert_eq!(
+        run(&db, &[b"EXPIRE", b"list", b"50", b"XX", b"LT"]).await,
+        int(1)
+    );
+    let RespMessage::Integer(pttl) = run(&db, &[b"PTTL", b"list"]).await else {
+        panic!("PTTL should reply with an integer");
+    };
+    assert!(pttl > 49_000 && pttl <= 50_000);
+
+    assert_eq!(
+        run(&db, &[b"PEXPIREAT", b"list", b"4102444800000"]).await,
+        int(1)
+    );
+    assert_eq!(run(&db, &[b"EXPIRETIME", b"list"]).await, int(4102444800));
+    assert_eq!(
+        run(&db, &[b"PEXPIRETIME", b"list"]).await,
+        int(4102444800000)
+    );
+
+    assert_eq!(run(&db, &[b"PERSIST", b"list"]).await, int(1));
+    assert_eq!(run(&db, &[b"PERSIST", b"list"]).await, int(0));
+    assert_eq!(run(&db, &[b"TTL", b"list"]).await, int(-1));
+
+    // A time in the past deletes the key straight away.
+    assert_eq!(run(&db, &[b"EXPIREAT", b"list", b"1"]).await, int(1));
+    assert_eq!(run(&db, &[b"EXISTS", b"list"]).await, int(0));
+
+    run(&db, &[b"SET", b"s", b"v"]).await;
+    assert_eq!(
+        run(&db, &[b"EXPIRE", b"s", b"10", b"NX", b"XX"]).await,
+        RespMessage::Error(
+            "ERR NX and XX, GT or LT options at the same time are not compatible".to_string()
+        )
+    );
+    assert_eq!(
+        run(&db, &[b"EXPIRE", b"s", b"10", b"GT", b"LT"]).await,
+        RespMessage::Error("ERR GT and LT options at the same time are not compatible".to_string())
+    );
+    assert_eq!(
+        run(&db, &[b"EXPIRE", b"s", b"9223372036854775807"]).await,
+        RespMessage::Error("ERR invalid expire time in 'expire' command".to_string())
+    );
+    assert_eq!(run(&db, &[b"PEXPIRE", b"s", b"1"]).await, int(1));
+    tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+    assert_eq!(run(&db, &[b"PTTL", b"s"]).await, int(-2));
+}
+
+#[tokio::test]
+async fn test_active_expire_cycle_evicts_keys_nobody_reads() {
+    let db = new_db();
+    for i in 0..100 {
+        let key = format!("short:{}", i);
+        run(&db, &[b"SET", key.as_bytes(), b"v", b"PX", b"1"]).await;
+    }
+    for i in 0..5 {
+        let key = format!("long:{}", i);
+        run(&db, &[b"SET", key.as_bytes(), b"v", b"EX", b"100"]).await;
+        let key = format!("plain:{}", i);
+        run(&db, &[b"SET", key.as_bytes(), b"v"]).await;
+    }
+    // Indexed, then made persistent: pruned from the sample pool, not deleted.
+    run(&db, &[b"SET", b"persisted", b"v", b"EX", b"100"]).await;
+    run(&db, &[b"PERSIST", b"persisted"]).await;
+    tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+
+    let config = ActiveExpireConfig::default();
+    let mut keyspace = db.lock().await;
+    let expired = active_expire_cycle(&mut keyspace, &config, config.deadline());
+    // A sample that was mostly expired triggers further rounds within one cycle.
+    assert!(expired > config.keys_per_round as u64);
+    for _ in 0..1000 {
+        if keyspace.entries.len() == 11 {
+            break;
+        }
+        active_expire_cycle(&mut keyspace, &config, config.deadline());
+    }
+
+    assert_eq!(keyspace.entries.len(), 11);
+    assert!(keyspace.entries.contains_key(b"persisted".as_slice()));
+    assert_eq!(keyspace.expire_stats.expired_keys, 100);
+    assert!(keyspace.expire_stats.cycles >= 1);
+}
+
+#[test]
+fn test_active_expire_effort_scales_the_cycle_budget() {
+    let lowest = ActiveExpireConfig::new(10, 1);
+    let defaults = ActiveExpireConfig::default();
+    assert_eq!(lowest.period, defaults.period);
+    assert_eq!(lowest.cpu_budget_percent, defaults.cpu_budget_percent);
+    assert_eq!(lowest.keys_per_round, defaults.keys_per_round);
+    assert_eq!(lowest.stale_percent, defaults.stale_percent);
+
+    let highest = ActiveExpireConfig::new(100, 10);
+    assert_eq!(highest.period, std::time::Duration::from_millis(10));
+    assert_eq!(highest.cpu_budget_percent, 43);
+    assert_eq!(highest.keys_per_round, 65);
+    assert_eq!(highest.stale_percent, 1);
+}
+
+#[tokio::test]
+async fn test_info_counts_lazy_and_active_expirations() {
+    let mut session = new_session(2);
+    run_in(&mut session, &[b"SET", b"read", b"v", b"PX", b"1"]).await;
+    run_in(&mut session, &[b"SET", b"unread", b"v", b"PX", b"1"]).await;
+    run_in(&mut session, &[b"SELECT", b"1"]).await;
+    run_in(&mut session, &[b"SET", b"other", b"v", b"PX", b"1"]).await;
+    tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+
+    assert_eq!(
+        run_in(&mut session, &[b"GET", b"other"]).await,
+        RespMessage::BulkString(None)
+    );
+    let info = run_in(&mut session, &[b"INFO", b"stats"]).await;
+    assert_eq!(info_field(&info, "expired_keys"), "1");
+
+    let config = ActiveExpireConfig::default();
+    let db = session.databases.get(0);
+    active_expire_cycle(&mut *db.lock().await, &config, config.deadline());
+    let info = run_in(&mut session, &[b"INFO"]).await;
+    assert_eq!(info_field(&info, "expired_keys"), "3");
+}
+
+#[tokio::test]
+async fn test_expired_keys_behave_as_missing_everywhere() {
+    let clock = Arc::new(ManualClock::new(1_000_000));
+    let db: Db = Arc::new(Mutex::new(Keyspace::with_clock(clock.clone())));
+    let int = RespMessage::Integer;
+
+    run(&db, &[b"SET", b"counter", b"10", b"PX", b"100"]).await;
+    run(&db, &[b"SET", b"doomed", b"v", b"EX", b"1"]).await;
+    run(&db, &[b"RPUSH", b"list", b"a"]).await;
+    run(&db, &[b"PEXPIRE", b"list", b"100"]).await;
+    assert_eq!(run(&db, &[b"PTTL", b"counter"]).await, int(100));
+
+    clock.advance(99);
+    assert_eq!(run(&db, &[b"PTTL", b"counter"]).await, int(1));
+    assert_eq!(run(&db, &[b"INCR", b"counter"]).await, int(11));
+
+    clock.advance(1);
+    assert_eq!(run(&db, &[b"PTTL", b"counter"]).await, int(-2));
+    assert_eq!(
+        run(&db, &[b"GET", b"counter"]).await,
+        RespMessage::BulkString(None)
+    );
+    // Recreated from 0, without the old TTL.
+    assert_eq!(run(&db, &[b"INCR", b"counter"]).await, int(1));
+    assert_eq!(run(&db, &[b"PTTL", b"counter"]).await, int(-1));
+    assert_eq!(run(&db, &[b"EXISTS", b"list", b"doomed"]).await, int(1));
+    assert_eq!(run(&db, &[b"LLEN", b"list"]).await, int(0));
+
+    // DEL only counts keys that were still live.
+    clock.advance(1_000);
+    assert_eq!(run(&db, &[b"DEL", b"doomed", b"list"]).await, int(0));
+
+    // The active cycle goes by the same clock.
+    run(&db, &[b"SET", b"later", b"v", b"PX", b"50"]).await;
+    let config = ActiveExpireConfig::default();
+    assert_eq!(
+        active_expire_cycle(&mut *db.lock().await, &config, config.deadline()),
+        0
+    );
+    clock.advance(50);
+    assert_eq!(
+        active_expire_cycle(&mut *db.lock().await, &config, config.deadline()),
+        1
+    );
+}
+
+#[tokio::test]
+async fn test_string_byte_range_commands() {
+    let db = new_db();
+    let int = RespMessage::Integer;
+
+    assert_eq!(run(&db, &[b"APPEND", b"log", b"Hello"]).await, int(5));
+    assert_eq!(run(&db, &[b"APPEND", b"log", b" World"]).await, int(11));
+    assert_eq!(run(&db, &[b"STRLEN", b"log"]).await, int(11));
+    assert_eq!(run(&db, &[b"STRLEN", b"missing"]).await, int(0));
+
+    for (start, end, expected) in [
+        (&b"0"[..], &b"3"[..], &b"Hell"[..]),
+        (b"-3", b"-1", b"rld"),
+        (b"0", b"-1", b"Hello World"),
+        (b"10", b"100", b"d"),
+        (b"0", b"-100", b"H"),
+        (b"-1", b"-5", b""),
+        (b"5", b"3", b""),
+        (b"20", b"30", b""),
+    ] {
+        assert_eq!(
+            run(&db, &[b"GETRANGE", b"log", start, end]).await,
+            bulk(expected),
+            "GETRANGE {:?} {:?}",
+            String::from_utf8_lossy(start),
+            String::from_utf8_lossy(end)
+        );
+    }
+    assert_eq!(
+        run(&db, &[b"SUBSTR", b"log", b"6", b"-1"]).await,
+        bulk(b"World")
+    );
+    assert_eq!(
+        run(&db, &[b"GETRANGE", b"missing", b"0", b"-1"]).await,
+        bulk(b"")
+    );
+
+    assert_eq!(
+        run(&db, &[b"SETRANGE", b"log", b"6", b"Redis"]).await,
+        int(11)
+    );
+    assert_eq!(run(&db, &[b"GET", b"log"]).await, bulk(b"Hello Redis"));
+    // Writing past the end pads with zero bytes; an empty write creates nothing.
+    assert_eq!(run(&db, &[b"SETRANGE", b"pad", b"3", b"x"]).await, int(4));
+    assert_eq!(run(&db, &[b"GET", b"pad"]).await, bulk(b"\0\0\0x"));
+    assert_eq!(run(&db, &[b"SETRANGE", b"empty", b"5", b""]).await, int(0));
+    assert_eq!(run(&db, &[b"EXISTS", b"empty"]).await, int(0));
+    assert_eq!(
+        run(&db, &[b"SETRANGE", b"log", b"-1", b"x"]).await,
+        RespMessage::Error("ERR offset is out of range".to_string())
+    );
+    assert_eq!(
+        run(&db, &[b"SETRANGE", b"log", b"536870911", b"xy"]).await,
+        RespMessage::Error(
+            "ERR string exceeds maximum allowed size (proto-max-bulk-len)".to_string()
+        )
+    );
+
+    run(&db, &[b"RPUSH", b"list", b"a"]).await;
+    assert_eq!(
+        run(&db, &[b"APPEND", b"list", b"a"]).await,
+        RespMessage::Error(
+            "WRONGTYPE Operation against a key holding the wrong kind of value".to_string()
+        )
+    );
+}
+
+#[tokio::test]
+async fn test_multi_key_and_get_and_set_string_commands() {
+    let db = new_db();
+    let int = RespMessage::Integer;
+
+    assert_eq!(
+        run(&db, &[b"MSET", b"a", b"1", b"b", b"2"]).await,
+        RespMessage::SimpleString("OK".to_string())
+    );
+    assert_eq!(
+        run(&db, &[b"MSET", b"a", b"1", b"b"]).await,
+        RespMessage::Error("ERR wrong number of arguments for 'mset' command".to_string())
+    );
+    run(&db, &[b"RPUSH", b"list", b"x"]).await;
+    assert_eq!(
+        run(&db, &[b"MGET", b"a", b"missing", b"list", b"b"]).await,
+        RespMessage::Array(vec![
+            bulk(b"1"),
+            RespMessage::BulkString(None),
+            RespMessage::BulkString(None),
+            bulk(b"2")
+        ])
+    );
+    assert_eq!(run(&db, &[b"MSETNX", b"c", b"3", b"a", b"9"]).await, int(0));
+    assert_eq!(run(&db, &[b"EXISTS", b"c"]).await, int(0));
+    assert_eq!(run(&db, &[b"MSETNX", b"c", b"3", b"d", b"4"]).await, int(1));
+
+    run(&db, &[b"SET", b"ttl", b"old", b"EX", b"100"]).await;
+    assert_eq!(run(&db, &[b"GETSET", b"ttl", b"new"]).await, bulk(b"old"));
+    assert_eq!(run(&db, &[b"TTL", b"ttl"]).await, int(-1));
+    assert_eq!(
+        run(&db, &[b"GETSET", b"fresh", b"v"]).await,
+        RespMessage::BulkString(None)
+    );
+
+    assert_eq!(run(&db, &[b"GETDEL", b"ttl"]).await, bulk(b"new"));
+    assert_eq!(
+        run(&db, &[b"GETDEL", b"ttl"]).await,
+        RespMessage::BulkString(None)
+    );
+    assert_eq!(
+        run(&db, &[b"GETDEL", b"list"]).await,
+        RespMessage::Error(
+            "WRONGTYPE Operation against a key holding the wrong kind of value".to_string()
+        )
+    );
+
+    assert_eq!(run(&db, &[b"GETEX", b"a", b"EX", b"100"]).await, bulk(b"1"));
+    assert_eq!(run(&db, &[b"TTL", b"a"]).await, int(100));
+    assert_eq!(run(&db, &[b"GETEX", b"a"]).await, bulk(b"1"));
+    assert_eq!(run(&db, &[b"TTL", b"a"]).await, int(100));
+    assert_eq!(run(&db, &[b"GETEX", b"a", b"PERSIST"]).await, bulk(b"1"));
+    assert_eq!(run(&db, &[b"TTL", b"a"]).await, int(-1));
+    assert_eq!(
+        run(&db, &[b"GETEX", b"a", b"PX", b"0"]).await,
+        RespMessage::Error("ERR invalid expire time in 'getex' command".to_string())
+    );
+    assert_eq!(
+        run(&db, &[b"GETEX", b"a", b"EX", b"10", b"PERSIST"]).await,
+        RespMessage::Error("ERR syntax error".to_string())
+    );
+    assert_eq!(
+        run(&db, &[b"GETEX", b"missing", b"EX", b"10"]).await,
+        RespMessage::BulkString(None)
+    );
+    // A timestamp already in the past deletes the key after returning it.
+    assert_eq!(run(&db, &[b"GETEX", b"b", b"EXAT", b"1"]).await, bulk(b"2"));
+    assert_eq!(run(&db, &[b"EXISTS", b"b"]).await, int(0));
+}
+
+#[tokio::test]
+async fn test_set_conditions_get_and_keepttl() {
+    let db = new_db();
+    let ok = || RespMessage::SimpleString("OK".to_string());
+    let nil = || RespMessage::BulkString(None);
+    let int = RespMessage::Integer;
+    let syntax = || RespMessage::Error("ERR syntax error".to_string());
+
+    // A lock: only the first NX wins.
+    assert_eq!(
+        run(&db, &[b"SET", b"lock", b"a", b"NX", b"PX", b"30000"]).await,
+        ok()
+    );
+    assert_eq!(
+        run(&db, &[b"SET", b"lock", b"b", b"NX", b"PX", b"30000"]).await,
+        nil()
+    );
+    assert_eq!(
+        run(&db, &[b"SET", b"lock", b"b", b"NX", b"GET"]).await,
+        bulk(b"a")
+    );
+    assert_eq!(run(&db, &[b"GET", b"lock"]).await, bulk(b"a"));
+
+    assert_eq!(run(&db, &[b"SET", b"new", b"v", b"XX"]).await, nil());
+    assert_eq!(run(&db, &[b"EXISTS", b"new"]).await, int(0));
+    assert_eq!(
+        run(&db, &[b"SET", b"lock", b"c", b"XX", b"GET"]).await,
+        bulk(b"a")
+    );
+    assert_eq!(run(&db, &[b"SET", b"new", b"v", b"GET"]).await, nil());
+
+    // KEEPTTL refreshes the value without touching the expiry; a plain SET clears it.
+    run(&db, &[b"SET", b"cached", b"v1", b"EX", b"100"]).await;
+    assert_eq!(
+        run(&db, &[b"SET", b"cached", b"v2", b"KEEPTTL"]).await,
+        ok()
+    );
+    assert_eq!(run(&db, &[b"TTL", b"cached"]).await, int(100));
+    run(&db, &[b"SET", b"cached", b"v3"]).await;
+    assert_eq!(run(&db, &[b"TTL", b"cached"]).await, int(-1));
+
+    for options in [
+        &[&b"NX"[..], b"XX"][..],
+        &[b"EX", b"10", b"PX", b"100"],
+        &[b"EX", b"10", b"KEEPTTL"],
+        &[b"KEEPTTL", b"PXAT", b"100"],
+        &[b"EX"],
+        &[b"BOGUS"],
+    ] {
+        let mut args: Vec<&[u8]> = vec![b"SET", b"k", b"v"];
+        args.extend_from_slice(options);
+        assert_eq!(run(&db, &args).await, syntax(), "SET k v {:?}", options);
+    }
+    assert_eq!(
+        run(&db, &[b"SET", b"k", b"v", b"EX", b"0"]).await,
+        RespMessage::Error("ERR invalid expire time in 'set' command".to_string())
+    );
+    assert_eq!(
+        run(&db, &[b"SET", b"k", b"v", b"PX", b"soon"]).await,
+        RespMessage::Error("ERR value is not an integer or out of range".to_string())
+    );
+    run(&db, &[b"RPUSH", b"list", b"a"]).await;
+    assert_eq!(
+        run(&db, &[b"SET", b"list", b"v", b"GET"]).await,
+        RespMessage::Error(
+            "WRONGTYPE Operation against a key holding the wrong kind of value".to_string()
+        )
+    );
+    assert_eq!(run(&db, &[b"LLEN", b"list"]).await, int(1));
+
+    assert_eq!(run(&db, &[b"SETNX", b"once", b"1"]).await, int(1));
+    assert_eq!(run(&db, &[b"SETNX", b"once", b"2"]).await, int(0));
+    assert_eq!(run(&db, &[b"SETEX", b"temp", b"100", b"v"]).await, ok());
+    assert_eq!(run(&db, &[b"TTL", b"temp"]).await, int(100));
+    assert_eq!(run(&db, &[b"PSETEX", b"temp", b"5000", b"v"]).await, ok());
+    assert_eq!(run(&db, &[b"TTL", b"temp"]).await, int(5));
+    assert_eq!(
+        run(&db, &[b"SETEX", b"temp", b"-1", b"v"]).await,
+        RespMessage::Error("ERR invalid expire time in 'setex' command".to_string())
+    );
+}
+
+#[tokio::test]
+async fn test_integer_and_float_increments() {
+    let db = new_db();
+    let int = RespMessage::Integer;
+
+    assert_eq!(run(&db, &[b"INCR", b"hits"]).await, int(1));
+    assert_eq!(run(&db, &[b"DECR", b"other"]).await, int(-1));
+    assert_eq!(run(&db, &[b"INCRBY", b"hits", b"10"]).await, int(11));
+    assert_eq!(run(&db, &[b"DECRBY", b"hits", b"20"]).await, int(-9));
+
+    // A rate limiter's window survives the increments.
+    run(&db, &[b"SET", b"window", b"0", b"EX", b"60"]).await;
+    assert_eq!(run(&db, &[b"INCR", b"window"]).await, int(1));
+    assert_eq!(run(&db, &[b"TTL", b"window"]).await, int(60));
+
+    let overflow = || RespMessage::Error("ERR increment or decrement would overflow".to_string());
+    run(&db, &[b"SET", b"max", b"9223372036854775807"]).await;
+    assert_eq!(run(&db, &[b"INCR", b"max"]).await, overflow());
+    assert_eq!(
+        run(&db, &[b"GET", b"max"]).await,
+        bulk(b"9223372036854775807")
+    );
+    run(&db, &[b"SET", b"min", b"-9223372036854775808"]).await;
+    assert_eq!(run(&db, &[b"DECRBY", b"min", b"1"]).await, overflow());
+    assert_eq!(
+        run(&db, &[b"DECRBY", b"hits", b"-9223372036854775808"]).await,
+        RespMessage::Error("ERR decrement would overflow".to_string())
+    );
+
+    run(&db, &[b"SET", b"text", b"abc"]).await;
+    for args in [
+        &[&b"INCR"[..], b"text"][..],
+        &[b"INCRBY", b"hits", b"1.5"],
+        &[b"INCRBY", b"hits", b"99999999999999999999"],
+    ] {
+        assert_eq!(
+            run(&db, args).await,
+            RespMessage::Error("ERR value is not an integer or out of range".to_string())
+        );
+    }
+
+    run(&db, &[b"SET", b"price", b"10.50"]).await;
+    assert_eq!(
+        run(&db, &[b"INCRBYFLOAT", b"price", b"0.1"]).await,
+        bulk(b"10.6")
+    );
+    assert_eq!(
+        run(&db, &[b"INCRBYFLOAT", b"price", b"-5"]).await,
+        bulk(b"5.6")
+    );
+    run(&db, &[b"SET", b"sci", b"5.0e3"]).await;
+    assert_eq!(
+        run(&db, &[b"INCRBYFLOAT", b"sci", b"2.0e2"]).await,
+        bulk(b"5200")
+    );
+    assert_eq!(
+        run(&db, &[b"INCRBYFLOAT", b"sum", b"0.1"]).await,
+        bulk(b"0.1")
+    );
+    assert_eq!(
+        run(&db, &[b"INCRBYFLOAT", b"sum", b"0.2"]).await,
+        bulk(b"0.30000000000000004")
+    );
+    assert_eq!(
+        run(&db, &[b"GET", b"sum"]).await,
+        bulk(b"0.30000000000000004")
+    );
+    run(&db, &[b"SET", b"big", b"12345678901234567"]).await;
+    assert_eq!(
+        run(&db, &[b"INCRBYFLOAT", b"big", b"1"]).await,
+        bulk(b"12345678901234568")
+    );
+    assert_eq!(
+        run(&db, &[b"INCRBYFLOAT", b"sum", b"inf"]).await,
+        RespMessage::Error("ERR increment would produce NaN or Infinity".to_string())
+    );
+    assert_eq!(
+        run(&db, &[b"INCRBYFLOAT", b"text", b"1"]).await,
+        RespMessage::Error("ERR value is not a valid float".to_string())
+    );
+    run(&db, &[b"RPUSH", b"list", b"1"]).await;
+    assert_eq!(
+        run(&db, &[b"INCRBY", b"list", b"1"]).await,
+        RespMessage::Error(
+            "WRONGTYPE Operation against a key holding the wrong kind of value".to_string()
+        )
+    );
+}
+
+#[tokio::test]
+async fn test_keys_type_dbsize_and_randomkey() {
+    let db = new_db();
+    run(&db, &[b"SET", b"user:1", b"a"]).await;
+    run(&db, &[b"SET", b"user:2", b"b"]).await;
+    run(&db, &[b"RPUSH", b"queue", b"x"]).await;
+    run(&db, &[b"HSET", b"user:hash", b"f", b"v"]).await;
+    run(&db, &[b"SET", b"gone", b"v", b"PX", b"1"]).await;
+    tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+
+    assert_eq!(
+        sorted_bulks(run(&db, &[b"KEYS", b"user:?"]).await),
+        vec![b"user:1".to_vec(), b"user:2".to_vec()]
+    );
+    assert_eq!(sorted_bulks(run(&db, &[b"KEYS", b"*"]).await).len(), 4);
+    assert_eq!(run(&db, &[b"DBSIZE"]).await, RespMessage::Integer(4));
+
+    for (key, expected) in [
+        (&b"user:1"[..], "string"),
+        (b"queue", "list"),
+        (b"user:hash", "hash"),
+        (b"gone", "none"),
+    ] {
+        assert_eq!(
+            run(&db, &[b"TYPE", key]).await,
+            RespMessage::SimpleString(expected.to_string())
+        );
+    }
+    run(&db, &[b"ZADD", b"z", b"1", b"m"]).await;
+    run(&db, &[b"XADD", b"s", b"*", b"f", b"v"]).await;
+    run(&db, &[b"SADD", b"set", b"m"]).await;
+    for (key, expected) in [(&b"z"[..], "zset"), (b"s", "stream"), (b"set", "set")] {
+        assert_eq!(
+            run(&db, &[b"TYPE", key]).await,
+            RespMessage::SimpleString(expected.to_string())
+        );
+    }
+
+    let RespMessage::BulkString(Some(key)) = run(&db, &[b"RANDOMKEY"]).await else {
+        panic!("RANDOMKEY should return a key");
+    };
+    assert_eq!(run(&db, &[b"EXISTS", &key]).await, RespMessage::Integer(1));
+    assert_eq!(
+        run(&db, &[b"TOUCH", b"user:1", b"user:1", b"missing"]).await,
+        RespMessage::Integer(2)
+    );
+    assert_eq!(
+        run(&db, &[b"UNLINK", b"user:1", b"missing"]).await,
+        RespMessage::Integer(1)
+    );
+    assert_eq!(
+        run(&db, &[b"DEL"]).await,
+        RespMessage::Error("ERR wrong number of arguments for 'del' command".to_string())
+    );
+
+    let empty = new_db();
+    assert_eq!(
+        run(&empty, &[b"RANDOMKEY"]).await,
+        RespMessage::BulkString(None)
+    );
+}
+
+#[tokio::test]
+async fn test_scan_covers_stable_keys_while_the_keyspace_changes() {
+    let db = new_db();
+    for i in 0..60 {
+        let key = format!("key:{}", i);
+        run(&db, &[b"SET", key.as_bytes(), b"v"]).await;
+    }
+    for i in 0..5 {
+        let key = format!("list:{}", i);
+        run(&db, &[b"RPUSH", key.as_bytes(), b"v"]).await;
+    }
+
+    let mut seen = Vec::new();
+    let mut cursor = b"0".to_vec();
+    let mut round = 0;
+    loop {
+        let reply = run(&db, &[b"SCAN", &cursor, b"MATCH", b"key:*", b"COUNT", b"9"]).await;
+        let RespMessage::Array(mut parts) = reply else {
+            panic!("expected an array");
+        };
+        let page = parts.pop().unwrap();
+        let RespMessage::BulkString(Some(next)) = parts.pop().unwrap() else {
+            panic!("expected a cursor");
+        };
+        seen.extend(sorted_bulks(page));
+        // Churn between pages: new keys appear and keys outside the stable set vanish.
+        let added = format!("key:new:{}", round);
+        run(&db, &[b"SET", added.as_bytes(), b"v"]).await;
+        run(&db, &[b"DEL", format!("list:{}", round % 5).as_bytes()]).await;
+        round += 1;
+        if next == b"0" {
+            break;
+        }
+        cursor = next;
+    }
+    for i in 0..60 {
+        let key = format!("key:{}", i).into_bytes();
+        assert!(seen.contains(&key), "missing {:?}", key);
+    }
+    assert!(seen.iter().all(|key| key.starts_with(b"key:")));
+
+    let reply = run(&db, &[b"SCAN", b"0", b"COUNT", b"1000", b"TYPE", b"list"]).await;
+    let RespMessage::Array(mut parts) = reply else {
+        panic!("expected an array");
+    };
+    assert_eq!(sorted_bulks(parts.pop().unwrap()), Vec::<Vec<u8>>::new());
+    run(&db, &[b"RPUSH", b"fresh", b"v"]).await;
+    let reply = run(&db, &[b"SCAN", b"0", b"COUNT", b"1000", b"TYPE", b"LIST"]).await;
+    let RespMessage::Array(mut parts) = reply else {
+        panic!("expected an array");
+    };
+    assert_eq!(sorted_bulks(parts.pop().unwrap()), vec![b"fresh".to_vec()]);
+}
+
+#[tokio::test]
+async fn test_rename_and_copy_move_values_with_their_ttl() {
+    let mut session = new_session(2);
+    let db = session.db().clone();
+    let int = RespMessage::Integer;
+    let ok = || RespMessage::SimpleString("OK".to_string());
+
+    run(&db, &[b"SET", b"a", b"1", b"EX", b"100"]).await;
+    run(&db, &[b"SET", b"b", b"2"]).await;
+    assert_eq!(run(&db, &[b"RENAME", b"a", b"c"]).await, ok());
+    assert_eq!(run(&db, &[b"EXISTS", b"a"]).await, int(0));
+    assert_eq!(run(&db, &[b"GET", b"c"]).await, bulk(b"1"));
+    assert_eq!(run(&db, &[b"TTL", b"c"]).await, int(100));
+    assert_eq!(run(&db, &[b"RENAME", b"c", b"c"]).await, ok());
+    assert_eq!(
+        run(&db, &[b"RENAME", b"missing", b"x"]).await,
+        RespMessage::Error("ERR no such key".to_string())
+    );
+    assert_eq!(run(&db, &[b"RENAMENX", b"c", b"b"]).await, int(0));
+    assert_eq!(run(&db, &[b"RENAMENX", b"c", b"d"]).await, int(1));
+    // RENAME overwrites the destination, TTL included.
+    run(&db, &[b"SET", b"e", b"5", b"EX", b"50"]).await;
+    assert_eq!(run(&db, &[b"RENAME", b"b", b"e"]).await, ok());
+    assert_eq!(run(&db, &[b"TTL", b"e"]).await, int(-1));
+
+    run(&db, &[b"RPUSH", b"list", b"x", b"y"]).await;
+    assert_eq!(
+        run_in(&mut session, &[b"COPY", b"list", b"copy"]).await,
+        int(1)
+    );
+    run(&db, &[b"RPUSH", b"copy", b"z"]).await;
+    assert_eq!(run(&db, &[b"LLEN", b"list"]).await, int(2));
+    assert_eq!(
+        run_in(&mut session, &[b"COPY", b"list", b"copy"]).await,
+        int(0)
+    );
+    assert_eq!(
+        run_in(&mut session, &[b"COPY", b"list", b"copy", b"REPLACE"]).await,
+        int(1)
+    );
+    assert_eq!(run(&db, &[b"LLEN", b"copy"]).await, int(2));
+    assert_eq!(run_in(&mut session, &[b"COPY", b"d", b"d2"]).await, int(1));
+    assert_eq!(run(&db, &[b"TTL", b"d2"]).await, int(100));
+    assert_eq!(
+        run_in(&mut session, &[b"COPY", b"missing", b"x"]).await,
+        int(0)
+    );
+    assert_eq!(
+        run_in(&mut session, &[b"COPY", b"list", b"list"]).await,
+        RespMessage::Error("ERR source and destination objects are the same".to_string())
+    );
+    assert_eq!(
+        run_in(&mut session, &[b"COPY", b"list", b"x", b"BOGUS"]).await,
+        RespMessage::Error("ERR syntax error".to_string())
+    );
+
+    // A list renamed onto a key with a blocked reader wakes it up.
+    let waiter = {
+        let db = db.clone();
+        tokio::spawn(async move { run(&db, &[b"BLPOP", b"jobs", b"0"]).await })
+    };
+    tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    assert_eq!(run(&db, &[b"RENAME", b"list", b"jobs"]).await, ok());
+    assert_eq!(
+        waiter.await.unwrap(),
+        RespMessage::Array(vec![bulk(b"jobs"), bulk(b"x")])
+    );
+    assert_eq!(run(&db, &[b"LLEN", b"jobs"]).await, int(1));
+}
+
+#[tokio::test]
+async fn test_select_isolates_databases() {
+    let mut session = new_session(16);
+    let int = RespMessage::Integer;
+    let ok = || RespMessage::SimpleString("OK".to_string());
+
+    run_in(&mut session, &[b"SET", b"k", b"zero"]).await;
+    assert_eq!(run_in(&mut session, &[b"SELECT", b"15"]).await, ok());
+    assert_eq!(
+        run_in(&mut session, &[b"GET", b"k"]).await,
+        RespMessage::BulkString(None)
+    );
+    run_in(&mut session, &[b"SET", b"k", b"fifteen"]).await;
+    assert_eq!(run_in(&mut session, &[b"DBSIZE"]).await, int(1));
+
+    // Another connection starts in database 0.
+    let mut other = other_client(&session);
+    assert_eq!(run_in(&mut other, &[b"GET", b"k"]).await, bulk(b"zero"));
+
+    for index in [b"16".as_slice(), b"-1"] {
+        assert_eq!(
+            run_in(&mut session, &[b"SELECT", index]).await,
+            RespMessage::Error("ERR DB index is out of range".to_string())
+        );
+    }
+    assert_eq!(
+        run_in(&mut session, &[b"SELECT", b"one"]).await,
+        RespMessage::Error("ERR value is not an integer or out of range".to_string())
+    );
+    assert_eq!(session.selected, 15);
+}
+
+#[tokio::test]
+async fn test_move_and_copy_between_databases() {
+    let mut session = new_session(4);
+    let int = RespMessage::Integer;
+
+    run_in(&mut session, &[b"SET", b"k", b"v", b"EX", b"100"]).await;
+    assert_eq!(run_in(&mut session, &[b"MOVE", b"k", b"2"]).await, int(1));
+    assert_eq!(run_in(&mut session, &[b"EXISTS", b"k"]).await, int(0));
+    assert_eq!(run_in(&mut session, &[b"MOVE", b"k", b"2"]).await, int(0));
+    assert_eq!(
+        run_in(&mut session, &[b"MOVE", b"k", b"0"]).await,
+        RespMessage::Error("ERR source and destination objects are the same".to_string())
+    );
+    assert_eq!(
+        run_in(&mut session, &[b"MOVE", b"k", b"4"]).await,
+        RespMessage::Error("ERR DB index is out of range".to_string())
+    );
+
+    run_in(&mut session, &[b"SELECT", b"2"]).await;
+    assert_eq!(run_in(&mut session, &[b"TTL", b"k"]).await, int(100));
+    // MOVE never overwrites.
+    run_in(&mut session, &[b"SELECT", b"0"]).await;
+    run_in(&mut session, &[b"SET", b"k", b"other"]).await;
+    assert_eq!(run_in(&mut session, &[b"MOVE", b"k", b"2"]).await, int(0));
+
+    // COPY to another database may keep the key's name.
+    assert_eq!(
+        run_in(&mut session, &[b"COPY", b"k", b"k", b"DB", b"3"]).await,
+        int(1)
+    );
+    assert_eq!(
+        run_in(&mut session, &[b"COPY", b"k", b"k", b"DB", b"2"]).await,
+        int(0)
+    );
+    assert_eq!(
+        run_in(
+            &mut session,
+            &[b"COPY", b"k", b"k", b"DB", b"2", b"REPLACE"]
+        )
+        .await,
+        int(1)
+    );
+    run_in(&mut session, &[b"SELECT", b"2"]).await;
+    assert_eq!(run_in(&mut session, &[b"GET", b"k"]).await, bulk(b"other"));
+    assert_eq!(run_in(&mut session, &[b"TTL", b"k"]).await, int(-1));
+    run_in(&mut session, &[b"SELECT", b"3"]).await;
+    assert_eq!(run_in(&mut session, &[b"GET", b"k"]).await, bulk(b"other"));
+}
+
+#[tokio::test]
+async fn test_swapdb_exchanges_data_and_wakes_blocked_clients() {
+    let mut session = new_session(2);
+    let ok = || RespMessage::SimpleString("OK".to_string());
+
+    run_in(&mut session, &[b"SET", b"in-zero", b"0"]).await;
+    run_in(&mut session, &[b"SELECT", b"1"]).await;
+    run_in(&mut session, &[b"RPUSH", b"jobs", b"j1"]).await;
+
+    // A client blocked in database 0 gets the list swapped in from database 1.
+    let waiter = {
+        let mut other = other_client(&session);
+        tokio::spawn(async move { run_in(&mut other, &[b"BLPOP", b"jobs", b"0"]).await })
+    };
+    settle().await;
+    assert_eq!(run_in(&mut session, &[b"SWAPDB", b"0", b"1"]).await, ok());
+    assert_eq!(waiter.await.unwrap(), bulk_array(&[b"jobs", b"j1"]));
+    assert_eq!(
+        run_in(&mut session, &[b"GET", b"in-zero"]).await,
+        bulk(b"0")
+    );
+
+    assert_eq!(run_in(&mut session, &[b"SWAPDB", b"1", b"1"]).await, ok());
+    assert_eq!(
+        run_in(&mut session, &[b"SWAPDB", b"x", b"1"]).await,
+        RespMessage::Error("ERR invalid first DB index".to_string())
+    );
+    assert_eq!(
+        run_in(&mut session, &[b"SWAPDB", b"0", b"2"]).await,
+        RespMessage::Error("ERR DB index is out of range".to_string())
+    );
+}
+
+#[tokio::test]
+async fn test_flushdb_and_flushall() {
+    let mut session = new_session(3);
+    let int = RespMessage::Integer;
+    let ok = || RespMessage::SimpleString("OK".to_string());
+
+    for index in [b"0".as_slice(), b"1", b"2"] {
+        run_in(&mut session, &[b"SELECT", index]).await;
+        run_in(&mut session, &[b"SET", b"a", b"1", b"EX", b"100"]).await;
+        run_in(&mut session, &[b"SET", b"b", b"2"]).await;
+    }
+
+    assert_eq!(run_in(&mut session, &[b"FLUSHDB", b"ASYNC"]).await, ok());
+    assert_eq!(run_in(&mut session, &[b"DBSIZE"]).await, int(0));
+    run_in(&mut session, &[b"SELECT", b"1"]).await;
+    assert_eq!(run_in(&mut session, &[b"DBSIZE"]).await, int(2));
+
+    assert_eq!(
+        run_in(&mut session, &[b"FLUSHALL", b"LATER"]).await,
+        RespMessage::Error("ERR syntax error".to_string())
+    );
+    assert_eq!(run_in(&mut session, &[b"FLUSHALL", b"sync"]).await, ok());
+    for index in [b"0".as_slice(), b"1"] {
+        run_in(&mut session, &[b"SELECT", index]).await;
+        assert_eq!(run_in(&mut session, &[b"DBSIZE"]).await, int(0));
+    }
+}
+
+/// A file path of its own for a test, in the system's temp directory.
+fn temp_path(name: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("xredis-{}-{}", std::process::id(), name))
+}
+
+#[tokio::test]
+async fn test_snapshot_round_trips_every_database() {
+    let mut session = new_session(3);
+    run_in(&mut session, &[b"SET", b"s", b"v", b"EX", b"100"]).await;
+    run_in(
+        &mut session,
+        &[b"ZADD", b"z", b"-inf", b"low", b"1.5", b"m"],
+    )
+    .await;
+    run_in(&mut session, &[b"SELECT", b"2"]).await;
+    run_in(&mut session, &[b"RPUSH", b"l", b"a", b"b"]).await;
+    run_in(&mut session, &[b"HSET", b"h", b"f", b"v"]).await;
+    run_in(&mut session, &[b"SET", b"gone", b"v", b"PX", b"1"]).await;
+    tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+
+    let path = temp_path("round-trip.json");
+    {
+        let guards = session.databases.lock_all().await;
+        write_snapshot(
+            &path,
+            SnapshotFormat::Json,
+            guards.iter().map(|keyspace| &**keyspace),
+        )
+        .unwrap();
+    }
+    let mut keyspaces: Vec<Keyspace> = (0..3).map(|_| Keyspace::default()).collect();
+    let loaded = load_snapshot(&path, SnapshotFormat::Json, &mut keyspaces).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(
+        loaded,
+        LoadedSnapshot {
+            keys: 4,
+            expired: 0
+        }
+    );
+
+    let mut restored = Session::new(
+        Databases::from_keyspaces(keyspaces),
+        Arc::new(Snapshotter::new(&path, SnapshotFormat::Json)),
+    );
+    assert_eq!(run_in(&mut restored, &[b"GET", b"s"]).await, bulk(b"v"));
+    assert_eq!(
+        run_in(&mut restored, &[b"TTL", b"s"]).await,
+        RespMessage::Integer(100)
+    );
+    assert_eq!(
+        run_in(&mut restored, &[b"ZSCORE", b"z", b"low"]).await,
+        bulk(b"-inf")
+    );
+    run_in(&mut restored, &[b"SELECT", b"2"]).await;
+    assert_eq!(
+        run_in(&mut restored, &[b"LRANGE", b"l", b"0", b"-1"]).await,
+        bulk_array(&[b"a", b"b"])
+    );
+    assert_eq!(
+        run_in(&mut restored, &[b"HGET", b"h", b"f"]).await,
+        bulk(b"v")
+    );
+    assert_eq!(
+        run_in(&mut restored, &[b"DBSIZE"]).await,
+        RespMessage::Integer(2)
+    );
+    run_in(&mut restored, &[b"SELECT", b"1"]).await;
+    assert_eq!(
+        run_in(&mut restored, &[b"DBSIZE"]).await,
+        RespMessage::Integer(0)
+    );
+}
+
+#[tokio::test]
+async fn test_load_snapshot_skips_expired_keys_and_rejects_corrupt_files() {
+    let clock = Arc::new(ManualClock::new(1_000_000));
+    let new_keyspaces = || -> Vec<Keyspace> {
+        (0..2)
+            .map(|_| Keyspace::with_clock(clock.clone()))
+            .collect()
+    };
+    let path = temp_path("expired.json");
+    let mut keyspaces = new_keyspaces();
+    {
+        let mut session = Session::new(
+            Databases::from_keyspaces(keyspaces),
+            Arc::new(Snapshotter::new(&path, SnapshotFormat::Json)),
+        );
+        run_in(&mut session, &[b"SET", b"short", b"v", b"PX", b"500"]).await;
+        run_in(&mut session, &[b"SET", b"long", b"v", b"PX", b"5000"]).await;
+        run_in(&mut session, &[b"SET", b"plain", b"v"]).await;
+        let guards = session.databases.lock_all().await;
+        write_snapshot(
+            &path,
+            SnapshotFormat::Json,
+            guards.iter().map(|keyspace| &**keyspace),
+        )
+        .unwrap();
+    }
+    // The server was down for a second.
+    clock.advance(1000);
+    keyspaces = new_keyspaces();
+    assert_eq!(
+        load_snapshot(&path, SnapshotFormat::Json, &mut keyspaces).unwrap(),
+        LoadedSnapshot {
+            keys: 2,
+            expired: 1
+        }
+    );
+    assert!(!keyspaces[0].entries.contains_key(b"short".as_slice()));
+    assert_eq!(keyspaces[0].entries.len(), 2);
+
+    // Nothing is loaded from a file that doesn't parse, or that names a
+    // database this server doesn't have.
+    for corrupt in [b"[[0, [[[107], {\"value\"".as_slice(), b"[[5, []]]"] {
+        std::fs::write(&path, corrupt).unwrap();
+        let mut keyspaces = new_keyspaces();
+        assert!(matches!(
+            load_snapshot(&path, SnapshotFormat::Json, &mut keyspaces),
+            Err(SnapshotError::Corrupt(_))
+        ));
+        assert!(keyspaces[0].entries.is_empty());
+    }
+    std::fs::remove_file(&path).unwrap();
+
+    // A missing file is an empty snapshot.
+    assert_eq!(
+        load_snapshot(&path, SnapshotFormat::Json, &mut new_keyspaces()).unwrap(),
+        LoadedSnapshot::default()
+    );
+}
+
+#[tokio::test]
+async fn test_save_replaces_the_snapshot_atomically_and_sets_lastsave() {
+    let path = temp_path("atomic.json");
+    let mut session = Session::new(
+        Databases::new(2),
+        Arc::new(Snapshotter::new(&path, SnapshotFormat::Json)),
+    );
+    let ok = || RespMessage::SimpleString("OK".to_string());
+
+    let RespMessage::Integer(started) = run_in(&mut session, &[b"LASTSAVE"]).await else {
+        panic!("LASTSAVE should reply with an integer");
+    };
+    run_in(&mut session, &[b"SET", b"k", b"first"]).await;
+    assert_eq!(run_in(&mut session, &[b"SAVE"]).await, ok());
+    let saved = std::fs::read(&path).unwrap();
+    let RespMessage::Integer(last_save) = run_in(&mut session, &[b"LASTSAVE"]).await else {
+        panic!("LASTSAVE should reply with an integer");
+    };
+    assert!(last_save >= started);
+
+    // Block the temporary file's name with a directory, so the next write fails
+    // before the rename: the error is a reply and the old snapshot survives.
+    let mut temp_name = path.file_name().unwrap().to_os_string();
+    temp_name.push(format!(".tmp-{}", std::process::id()));
+    let temp = path.with_file_name(temp_name);
+    std::fs::create_dir(&temp).unwrap();
+    run_in(&mut session, &[b"SET", b"k", b"second"]).await;
+    let RespMessage::Error(e) = run_in(&mut session, &[b"SAVE"]).await else {
+        panic!("SAVE should fail");
+    };
+    assert!(e.starts_with("ERR failed to save"));
+    assert_eq!(std::fs::read(&path).unwrap(), saved);
+    assert_eq!(
+        run_in(&mut session, &[b"LASTSAVE"]).await,
+        RespMessage::Integer(last_save)
+    );
+
+    std::fs::remove_dir(&temp).unwrap();
+    assert_eq!(run_in(&mut session, &[b"SAVE"]).await, ok());
+    assert!(!temp.exists());
+    let mut keyspaces: Vec<Keyspace> = (0..2).map(|_| Keyspace::default()).collect();
+    load_snapshot(&path, SnapshotFormat::Json, &mut keyspaces).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let mut restored = Session::new(
+        Databases::from_keyspaces(keyspaces),
+        Arc::new(Snapshotter::new(&path, SnapshotFormat::Json)),
+    );
+    assert_eq!(
+        run_in(&mut restored, &[b"GET", b"k"]).await,
+        bulk(b"second")
+    );
+}
+
+#[tokio::test]
+async fn test_save_and_load_in_rdb_format() {
+    let path = temp_path("dump.rdb");
+    let mut session = Session::new(
+        Databases::new(2),
+        Arc::new(Snapshotter::new(&path, SnapshotFormat::Rdb)),
+    );
+    run_in(&mut session, &[b"SET", b"s", b"v", b"EX", b"100"]).await;
+    run_in(&mut session, &[b"SADD", b"set", b"1", b"a"]).await;
+    run_in(&mut session, &[b"SELECT", b"1"]).await;
+    run_in(&mut session, &[b"ZADD", b"z", b"2.5", b"m"]).await;
+    run_in(&mut session, &[b"HSET", b"h", b"f", b"v"]).await;
+    run_in(&mut session, &[b"RPUSH", b"l", b"a", b"b"]).await;
+    assert_eq!(
+        run_in(&mut session, &[b"SAVE"]).await,
+        RespMessage::SimpleString("OK".to_string())
+    );
+    assert!(std::fs::read(&path).unwrap().starts_with(b"REDIS"));
+
+    let mut keyspaces: Vec<Keyspace> = (0..2).map(|_| Keyspace::default()).collect();
+    assert_eq!(
+        load_snapshot(&path, SnapshotFormat::Rdb, &mut keyspaces).unwrap(),
+        LoadedSnapshot {
+            keys: 5,
+            expired: 0
+        }
+    );
+    let restored = Databases::from_keyspaces(keyspaces);
+    assert_eq!(dump(&restored).await, dump(&session.databases).await);
+    // A JSON reader can't make sense of it.
+    assert!(matches!(
+        load_snapshot(&path, SnapshotFormat::Json, &mut [Keyspace::default()]),
+        Err(SnapshotError::Corrupt(_))
+    ));
+
+    // Streams can't be written as RDB; the previous file stays.
+    let saved = std::fs::read(&path).unwrap();
+    run_in(&mut session, &[b"XADD", b"st", b"*", b"f", b"v"]).await;
+    let RespMessage::Error(e) = run_in(&mut session, &[b"SAVE"]).await else {
+        panic!("SAVE should fail");
+    };
+    assert!(e.contains("can't be saved in RDB format"));
+    assert_eq!(std::fs::read(&path).unwrap(), saved);
+    std::fs::remove_file(&path).unwrap();
+}
+
+/// The value of `field` in an INFO reply.
+fn info_field(info: &RespMessage, field: &str) -> String {
+    let RespMessage::BulkString(Some(info)) = info else {
+        panic!("INFO should reply with a bulk string");
+    };
+    String::from_utf8_lossy(info)
+        .lines()
+        .find_map(|line| {
+            line.strip_prefix(&format!("{}:", field))
+                .map(str::to_string)
+        })
+        .unwrap_or_else(|| panic!("INFO has no {}", field))
+}
+
+#[tokio::test]
+async fn test_bgsave_writes_a_snapshot_and_resets_the_change_count() {
+    let path = temp_path("bgsave.json");
+    let mut session = Session::new(
+        Databases::new(2),
+        Arc::new(Snapshotter::new(&path, SnapshotFormat::Json)),
+    );
+
+    run_in(&mut session, &[b"SET", b"a", b"1"]).await;
+    run_in(&mut session, &[b"RPUSH", b"l", b"x", b"y"]).await;
+    run_in(&mut session, &[b"GET", b"a"]).await;
+    // Failed writes don't count.
+    run_in(&mut session, &[b"INCR", b"l"]).await;
+    let info = run_in(&mut session, &[b"INFO", b"persistence"]).await;
+    assert_eq!(info_field(&info, "rdb_changes_since_last_save"), "3");
+    assert_eq!(info_field(&info, "rdb_bgsave_in_progress"), "0");
+    assert_eq!(info_field(&info, "rdb_last_bgsave_status"), "ok");
+
+    assert_eq!(
+        run_in(&mut session, &[b"BGSAVE"]).await,
+        RespMessage::SimpleString("Background saving started".to_string())
+    );
+    for _ in 0..200 {
+        if !session.snapshotter.bgsave_in_progress() {
+            break;
+        }
+        settle().await;
+    }
+    let info = run_in(&mut session, &[b"INFO"]).await;
+    assert_eq!(info_field(&info, "rdb_changes_since_last_save"), "0");
+    assert_eq!(info_field(&info, "rdb_bgsave_in_progress"), "0");
+
+    let mut keyspaces: Vec<Keyspace> = (0..2).map(|_| Keyspace::default()).collect();
+    assert_eq!(
+        load_snapshot(&path, SnapshotFormat::Json, &mut keyspaces)
+            .unwrap()
+            .keys,
+        2
+    );
+    std::fs::remove_file(&path).unwrap();
+
+    // Writes made while a background save is copying or writing are kept for the
+    // next one, and a second BGSAVE isn't started meanwhile.
+    let snapshotter = session.snapshotter.clone();
+    let saving = snapshotter.start_bgsave(&session.databases).await.unwrap();
+    run_in(&mut session, &[b"SET", b"b", b"2"]).await;
+    assert_eq!(
+        run_in(&mut session, &[b"BGSAVE"]).await,
+        RespMessage::Error("ERR Background save already in progress".to_string())
+    );
+    saving.await.unwrap();
+    assert_eq!(snapshotter.changes_since_save(), 1);
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(
+        run_in(&mut session, &[b"INFO", b"keyspace"]).await,
+        bulk(b"")
+    );
+}
+
+#[tokio::test]
+async fn test_writes_count_the_keys_they_change() {
+    let mut session = new_session(2);
+    let changes = |session: &Session| session.snapshotter.changes_since_save();
+
+    run_in(&mut session, &[b"DEL", b"missing"]).await;
+    run_in(&mut session, &[b"SREM", b"missing", b"m"]).await;
+    run_in(&mut session, &[b"SET", b"k", b"v", b"NX"]).await;
+    run_in(&mut session, &[b"SET", b"k", b"w", b"NX"]).await;
+    assert_eq!(changes(&session), 1);
+
+    let keys: Vec<Vec<u8>> = (0..1000)
+        .map(|i| format!("key:{}", i).into_bytes())
+        .collect();
+    let mut mset: Vec<&[u8]> = vec![b"MSET"];
+    for key in &keys {
+        mset.extend([key.as_slice(), b"v"]);
+    }
+    run_in(&mut session, &mset).await;
+    assert_eq!(changes(&session), 1001);
+
+    run_in(&mut session, &[b"DEL", b"k", b"key:0", b"missing"]).await;
+    run_in(&mut session, &[b"SADD", b"s", b"a", b"b", b"a"]).await;
+    run_in(&mut session, &[b"MOVE", b"s", b"1"]).await;
+    assert_eq!(changes(&session), 1006);
+    run_in(&mut session, &[b"FLUSHALL"]).await;
+    assert_eq!(changes(&session), 2006);
+}
+
+#[tokio::test]
+async fn test_save_points_and_failed_saves() {
+    let points = [
+        SavePoint {
+            seconds: 300,
+            changes: 10,
+        },
+        SavePoint {
+            seconds: 60,
+            changes: 1000,
+        },
+    ];
+    // A directory that doesn't exist, so every save fails.
+    let snapshotter = Arc::new(Snapshotter::new(
+        temp_path("missing").join("dump.json"),
+        SnapshotFormat::Json,
+    ));
+    let start = snapshotter.last_save();
+
+    snapshotter.record_changes(9);
+    assert!(!snapshotter.save_point_due(&points, start + 1000));
+    snapshotter.record_changes(1);
+    assert!(!snapshotter.save_point_due(&points, start + 299));
+    assert!(snapshotter.save_point_due(&points, start + 300));
+    snapshotter.record_changes(990);
+    assert!(snapshotter.save_point_due(&points, start + 60));
+    assert!(!snapshotter.save_point_due(&[], start + 1000));
+
+    let databases = Databases::new(1);
+    snapshotter
+        .start_bgsave(&databases)
+        .await
+        .unwrap()
+        .await
+        .unwrap();
+    assert!(!snapshotter.last_save_ok());
+    assert_eq!(snapshotter.changes_since_save(), 1000);
+    assert_eq!(snapshotter.last_save(), start);
+    // A failed save is only retried after a short delay.
+    let any_change = [SavePoint {
+        seconds: 0,
+        changes: 1,
+    }];
+    let now = (now_millis() / 1000) as u64;
+    assert!(!snapshotter.save_point_due(&any_change, now));
+    assert!(snapshotter.save_point_due(&any_change, now + 5));
+
+    let mut session = Session::new(databases, snapshotter);
+    let info = run_in(&mut session, &[b"INFO"]).await;
+    assert_eq!(info_field(&info, "rdb_last_bgsave_status"), "err");
+}
+
+/// A session logging to a fresh append-only file at `path`.
+async fn aof_session(databases: usize, path: &std::path::Path) -> Session {
+    let _ = std::fs::remove_file(path);
+    let mut session = new_session(databases);
+    let aof = Arc::new(Aof::open(path, AppendFsync::Always, databases).unwrap());
+    aof.attach(&session.databases).await;
+    session.aof = Some(aof);
+    session
+}
+
+/// Every key of every database, sorted, with consumers' last-seen times (which a
+/// replay can't bring back) zeroed.
+async fn dump(databases: &Databases) -> Vec<Vec<(Vec<u8>, Value, Option<u128>)>> {
+    let mut dump = Vec::new();
+    for keyspace in databases.lock_all().await {
+        let mut entries: Vec<_> = keyspace
+            .live_entries()
+            .map(|(key, entry)| {
+                let mut value = entry.value.clone();
+                if let Value::Stream(stream) = &mut value {
+                    for group in stream.groups.values_mut() {
+                        for consumer in group.consumers.values_mut() {
+                            consumer.seen_time = 0;
+                        }
+                    }
+                }
+                (key.clone(), value, entry.expiry)
+            })
+            .collect();
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        dump.push(entries);
+    }
+    dump
+}
+
+#[tokio::test]
+async fn test_aof_replay_rebuilds_every_database() {
+    let path = temp_path("replay.aof");
+    let mut session = aof_session(3, &path).await;
+    let commands: &[&[&[u8]]] = &[
+        &[b"SET", b"s", b"v", b"EX", b"100"],
+        &[b"SETEX", b"t", b"100", b"v"],
+        &[b"INCRBYFLOAT", b"f", b"0.1"],
+        &[b"SET", b"word", b"abc"],
+        &[b"RPUSH", b"l", b"a", b"b", b"c"],
+        &[b"BLPOP", b"l", b"0"],
+        &[b"SADD", b"set", b"a", b"b", b"c"],
+        &[b"SPOP", b"set"],
+        &[b"XADD", b"x", b"*", b"f", b"v"],
+        &[b"XADD", b"x", b"MAXLEN", b"~", b"10", b"*", b"f", b"w"],
+        &[b"SELECT", b"1"],
+        &[b"HSET", b"h", b"f", b"v"],
+        &[b"EXPIRE", b"h", b"100"],
+        &[b"ZADD", b"z", b"1", b"a", b"2", b"b"],
+        &[b"BZPOPMIN", b"z", b"0"],
+        &[b"SET", b"gone", b"v"],
+        &[b"PEXPIRE", b"gone", b"-1"],
+        &[b"GET", b"s"],
+        &[b"SWAPDB", b"1", b"2"],
+    ];
+    for command in commands {
+        assert!(!matches!(
+            run_in(&mut session, command).await,
+            RespMessage::Error(_)
+        ));
+    }
+    // Failed commands aren't logged.
+    run_in(&mut session, &[b"SELECT", b"0"]).await;
+    assert!(matches!(
+        run_in(&mut session, &[b"INCR", b"word"]).await,
+        RespMessage::Error(_)
+    ));
+
+    let (databases, loaded) = load_aof(&path, 3).await.unwrap();
+    assert_eq!(loaded.truncated_bytes, 0);
+    assert_eq!(dump(&databases).await, dump(&session.databases).await);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[tokio::test]
+async fn test_aof_replays_expirations_as_deletes() {
+    let path = temp_path("expired.aof");
+    let mut session = aof_session(1, &path).await;
+    for key in [b"list".as_slice(), b"counter", b"evicted"] {
+        run_in(&mut session, &[b"SET", key, b"10", b"PX", b"1"]).await;
+    }
+    tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+
+    // Found expired by the write that recreates it as another type...
+    assert_eq!(
+        run_in(&mut session, &[b"LPUSH", b"list", b"a"]).await,
+        RespMessage::Integer(1)
+    );
+    // ... by a read ...
+    assert_eq!(
+        run_in(&mut session, &[b"GET", b"counter"]).await,
+        RespMessage::BulkString(None)
+    );
+    assert_eq!(
+        run_in(&mut session, &[b"INCR", b"counter"]).await,
+        RespMessage::Integer(1)
+    );
+    // ... and by the active expire cycle.
+    let cycle = tokio::spawn(run_active_expire(
+        session.databases.clone(),
+        ActiveExpireConfig::default(),
+        session.aof.clone(),
+    ));
+    for _ in 0..200 {
+        let db = session.databases.get(0);
+        if !db.lock().await.entries.contains_key(b"evicted".as_slice()) {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    cycle.abort();
+    assert_eq!(
+        run_in(&mut session, &[b"SET", b"evicted", b"new", b"NX"]).await,
+        RespMessage::SimpleString("OK".to_string())
+    );
+
+    let (databases, _) = load_aof(&path, 1).await.unwrap();
+    assert_eq!(dump(&databases).await, dump(&session.databases).await);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[tokio::test]
+async fn test_aof_logs_served_pops_right_after_the_write_that_served_them() {
+    let path = temp_path("served.aof");
+    let mut session = aof_session(1, &path).await;
+    let waiters: Vec<_> = [
+        [b"BRPOP".as_slice(), b"k", b"0"].as_slice(),
+        &[b"BLMOVE", b"src", b"dst", b"LEFT", b"RIGHT", b"0"],
+        &[b"BZPOPMIN", b"z", b"0"],
+    ]
+    .into_iter()
+    .map(|command| {
+        let mut other = other_client(&session);
+        let command: Vec<Vec<u8>> = command.iter().map(|arg| arg.to_vec()).collect();
+        tokio::spawn(async move {
+            let command: Vec<&[u8]> = command.iter().map(Vec::as_slice).collect();
+            run_in(&mut other, &command).await
+        })
+    })
+    .collect();
+    settle().await;
+
+    // Each key is taken over as a string straight after the write that serves its
+    // waiter, before the waiter gets to run again.
+    run_in(&mut session, &[b"RPUSH", b"k", b"a"]).await;
+    run_in(&mut session, &[b"SET", b"k", b"str"]).await;
+    run_in(&mut session, &[b"RPUSH", b"src", b"x"]).await;
+    run_in(&mut session, &[b"SET", b"src", b"str"]).await;
+    run_in(&mut session, &[b"ZADD", b"z", b"1", b"m"]).await;
+    run_in(&mut session, &[b"SET", b"z", b"str"]).await;
+    for waiter in waiters {
+        assert!(!matches!(waiter.await.unwrap(), RespMessage::Error(_)));
+    }
+    // One that times out logs nothing and leaves the log free.
+    assert_eq!(
+        run_in(&mut session, &[b"BLPOP", b"none", b"0.01"]).await,
+        RespMessage::NullArray
+    );
+    run_in(&mut session, &[b"SET", b"after", b"v"]).await;
+
+    let (databases, _) = load_aof(&path, 1).await.unwrap();
+    assert_eq!(dump(&databases).await, dump(&session.databases).await);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[tokio::test]
+async fn test_aof_replay_keeps_when_group_entries_were_delivered() {
+    let path = temp_path("delivered.aof");
+    let mut session = aof_session(1, &path).await;
+    run_in(
+        &mut session,
+        &[b"XGROUP", b"CREATE", b"s", b"g", b"$", b"MKSTREAM"],
+    )
+    .await;
+    let mut other = other_client(&session);
+    let waiter = tokio::spawn(async move {
+        run_in(
+            &mut other,
+            &[
+                b"XREADGROUP",
+                b"GROUP",
+                b"g",
+                b"parked",
+                b"BLOCK",
+                b"0",
+                b"STREAMS",
+                b"s",
+                b">",
+            ],
+        )
+        .await
+    });
+    settle().await;
+    run_in(&mut session, &[b"XADD", b"s", b"1-0", b"f", b"a"]).await;
+    assert!(!matches!(waiter.await.unwrap(), RespMessage::Error(_)));
+    for id in [b"2-0".as_slice(), b"3-0", b"4-0"] {
+        run_in(&mut session, &[b"XADD", b"s", id, b"f", b"v"]).await;
+    }
+    let reads: &[&[&[u8]]] = &[
+        &[
+            b"XREADGROUP",
+            b"GROUP",
+            b"g",
+            b"c",
+            b"COUNT",
+            b"2",
+            b"STREAMS",
+            b"s",
+            b">",
+        ],
+        &[b"XREADGROUP", b"GROUP", b"g", b"c", b"STREAMS", b"s", b"0"],
+        &[
+            b"XREADGROUP",
+            b"GROUP",
+            b"g",
+            b"free",
+            b"NOACK",
+            b"STREAMS",
+            b"s",
+            b">",
+        ],
+        &[
+            b"XREADGROUP",
+            b"GROUP",
+            b"g",
+            b"idle",
+            b"STREAMS",
+            b"s",
+            b">",
+        ],
+    ];
+    for read in reads {
+        assert!(!matches!(
+            run_in(&mut session, read).await,
+            RespMessage::Error(_)
+        ));
+    }
+    // Long enough that entries stamped with the time of the replay would look fresh.
+    tokio::time::sleep(std::time::Duration::from_millis(30)).await;
+
+    let (databases, _) = load_aof(&path, 1).await.unwrap();
+    assert_eq!(dump(&databases).await, dump(&session.databases).await);
+    let mut replayed = Session::new(databases, session.snapshotter.clone());
+    // Idle times are read a moment apart, so only compare which entries are idle enough.
+    let without_idle = |reply: RespMessage| match reply {
+        RespMessage::Array(entries) => entries
+            .into_iter()
+            .map(|entry| match entry {
+                RespMessage::Array(mut fields) if fields.len() == 4 => {
+                    fields[2] = RespMessage::Integer(0);
+                    RespMessage::Array(fields)
+                }
+                entry => entry,
+            })
+            .collect(),
+        reply => vec![reply],
+    };
+    for query in [
+        [b"XPENDING".as_slice(), b"s", b"g"].as_slice(),
+        &[b"XPENDING", b"s", b"g", b"IDLE", b"25", b"-", b"+", b"10"],
+    ] {
+        let live = without_idle(run_in(&mut session, query).await);
+        assert_eq!(live.len(), if query.len() == 3 { 4 } else { 3 });
+        assert_eq!(without_idle(run_in(&mut replayed, query).await), live);
+    }
+    let _ = std::fs::remove_file(&path);
+}
+
+#[tokio::test]
+async fn test_aof_reads_only_lock_the_log_for_the_keys_they_expire() {
+    let path = temp_path("reads.aof");
+    let mut session = aof_session(2, &path).await;
+    run_in(&mut session, &[b"SET", b"k", b"v"]).await;
+    run_in(&mut session, &[b"SET", b"gone", b"v", b"PX", b"1"]).await;
+    tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+
+    let aof = session.aof.clone().unwrap();
+    let log = aof.lock().await;
+    let mut writer = other_client(&session);
+    let write = tokio::spawn(async move {
+        run_in(&mut writer, &[b"SELECT", b"1"]).await;
+        run_in(&mut writer, &[b"SET", b"w", b"v"]).await
+    });
+    settle().await;
+    let read = tokio::time::timeout(
+        std::time::Duration::from_secs(1),
+        run_in(&mut session, &[b"GET", b"k"]),
+    );
+    assert_eq!(read.await.unwrap(), bulk(b"v"));
+    assert!(!write.is_finished());
+
+    // One that runs into an expired key has to wait to log its DEL.
+    let mut reader = other_client(&session);
+    let expiring = tokio::spawn(async move { run_in(&mut reader, &[b"GET", b"gone"]).await });
+    settle().await;
+    assert!(!expiring.is_finished());
+    drop(log);
+    assert_eq!(expiring.await.unwrap(), RespMessage::BulkString(None));
+    assert_eq!(
+        write.await.unwrap(),
+        RespMessage::SimpleString("OK".to_string())
+    );
+    run_in(&mut session, &[b"SET", b"gone", b"back", b"NX"]).await;
+
+    let (databases, _) = load_aof(&path, 2).await.unwrap();
+    assert_eq!(dump(&databases).await, dump(&session.databases).await);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[tokio::test]
+async fn test_aof_load_cuts_an_incomplete_last_command() {
+    let path = temp_path("truncated.aof");
+    let mut session = aof_session(1, &path).await;
+    run_in(&mut session, &[b"SET", b"a", b"1"]).await;
+    run_in(&mut session, &[b"SET", b"b", b"2"]).await;
+    let complete = std::fs::metadata(&path).unwrap().len();
+    // A crash in the middle of writing the next command.
+    let mut contents = std::fs::read(&path).unwrap();
+    contents.extend_from_slice(b"*3\r\n$3\r\nSET\r\n$1\r\nc");
+    std::fs::write(&path, &contents).unwrap();
+
+    let (databases, loaded) = load_aof(&path, 1).await.unwrap();
+    assert_eq!(
+        loaded,
+        LoadedAof {
+            commands: 3,
+            truncated_bytes: 18,
+        }
+    );
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), complete);
+    let db = databases.get(0);
+    assert_eq!(run(db, &[b"GET", b"b"]).await, bulk(b"2"));
+    assert_eq!(run(db, &[b"EXISTS", b"c"]).await, RespMessage::Integer(0));
+
+    // Garbage is corruption, not a crash mid-write.
+    std::fs::write(&path, b"*1\r\n+SET\r\n").unwrap();
+    assert!(load_aof(&path, 1).await.is_err());
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn test_aof_logs_commands_in_a_replayable_form() {
+    let now = 1_000_000;
+    let logged = |args: &[&[u8]], reply: RespMessage| {
+        propagated_form(
+            &String::from_utf8_lossy(args[0]).to_uppercase(),
+            args,
+            &reply,
+            now,
+        )
+    };
+    let command = |args: &[&[u8]]| vec![args.iter().map(|arg| arg.to_vec()).collect::<Vec<_>>()];
+
+    assert_eq!(
+        logged(&[b"EXPIRE", b"k", b"10", b"NX"], RespMessage::Integer(1)),
+        command(&[b"PEXPIREAT", b"k", b"1010000"])
+    );
+    assert_eq!(
+        logged(&[b"EXPIRE", b"k", b"10"], RespMessage::Integer(0)),
+        Vec::<Vec<Vec<u8>>>::new()
+    );
+    assert_eq!(
+        logged(&[b"PEXPIRE", b"k", b"0"], RespMessage::Integer(1)),
+        command(&[b"DEL", b"k"])
+    );
+    assert_eq!(
+        logged(
+            &[b"PSETEX", b"k", b"500", b"v"],
+            RespMessage::SimpleString("OK".to_string())
+        ),
+        command(&[b"SET", b"k", b"v", b"PXAT", b"1000500"])
+    );
+    assert_eq!(
+        logged(
+            &[b"SET", b"k", b"v", b"NX", b"ex", b"2"],
+            RespMessage::SimpleString("OK".to_string())
+        ),
+        command(&[b"SET", b"k", b"v", b"NX", b"PXAT", b"1002000"])
+    );
+    assert_eq!(
+        logged(
+            &[b"BRPOP", b"a", b"b", b"0"],
+            RespMessage::Array(vec![bulk(b"b"), bulk(b"v")])
+        ),
+        command(&[b"RPOP", b"b"])
+    );
+    assert_eq!(
+        logged(&[b"BRPOP", b"a", b"1"], RespMessage::NullArray),
+        Vec::<Vec<Vec<u8>>>::new()
+    );
+    assert_eq!(
+        logged(
+            &[
+                b"XADD",
+                b"s",
+                b"NOMKSTREAM",
+                b"MINID",
+                b"=",
+                b"0",
+                b"*",
+                b"f",
+                b"v"
+            ],
+            bulk(b"5-0")
+        ),
+        command(&[
+            b"XADD",
+            b"s",
+            b"NOMKSTREAM",
+            b"MINID",
+            b"=",
+            b"0",
+            b"5-0",
+            b"f",
+            b"v"
+        ])
+    );
+    // XREADGROUP logs what it delivered itself.
+    assert_eq!(
+        logged(
+            &[
+                b"XREADGROUP",
+                b"GROUP",
+                b"g",
+                b"c",
+                b"BLOCK",
+                b"0",
+                b"STREAMS",
+                b"s",
+                b">"
+            ],
+            RespMessage::Array(vec![])
+        ),
+        Vec::<Vec<Vec<u8>>>::new()
+    );
+    assert_eq!(
+        logged(
+            &[b"XCLAIM", b"s", b"g", b"c", b"100", b"1-0", b"2-0", b"IDLE", b"50", b"JUSTID"],
+            RespMessage::Array(vec![bulk(b"2-0")])
+        ),
+        command(&[b"XCLAIM", b"s", b"g", b"c", b"0", b"2-0", b"TIME", b"999950", b"JUSTID"])
+    );
+
+    let ok = || RespMessage::SimpleString("OK".to_string());
+    assert_eq!(
+        logged(
+            &[
+                b"RESTORE",
+                b"k",
+                b"500",
+                b"p",
+                b"IDLETIME",
+                b"5",
+                b"replace"
+            ],
+            ok()
+        ),
+        command(&[b"RESTORE", b"k", b"1000500", b"p", b"ABSTTL", b"REPLACE"])
+    );
+    assert_eq!(
+        logged(&[b"RESTORE", b"k", b"0", b"p"], ok()),
+        command(&[b"RESTORE", b"k", b"0", b"p"])
+    );
+    assert_eq!(
+        logged(&[b"RESTORE", b"k", b"5", b"p", b"ABSTTL", b"REPLACE"], ok()),
+        command(&[b"DEL", b"k"])
+    );
+    assert_eq!(
+        logged(
+            &[b"MIGRATE", b"h", b"1", b"k", b"0", b"10", b"REPLACE"],
+            ok()
+        ),
+        command(&[b"DEL", b"k"])
+    );
+    assert_eq!(
+        logged(
+            &[b"MIGRATE", b"h", b"1", b"", b"0", b"10", b"KEYS", b"a", b"copy"],
+            ok()
+        ),
+        command(&[b"DEL", b"a", b"copy"])
+    );
+    assert_eq!(
+        logged(&[b"MIGRATE", b"h", b"1", b"k", b"0", b"10", b"COPY"], ok()),
+        Vec::<Vec<Vec<u8>>>::new()
+    );
+    assert_eq!(
+        logged(
+            &[b"MIGRATE", b"h", b"1", b"k", b"0", b"10"],
+            RespMessage::SimpleString("NOKEY".to_string())
+        ),
+        Vec::<Vec<Vec<u8>>>::new()
+    );
+}
+
+#[tokio::test]
+async fn test_aof_base_rebuilds_the_dataset() {
+    let path = temp_path("base.aof");
+    let mut session = new_session(2);
+    let commands: &[&[&[u8]]] = &[
+        &[b"SET", b"s", b"v", b"PX", b"100000"],
+        &[b"RPUSH", b"l", b"a", b"b"],
+        &[b"HSET", b"h", b"f", b"v"],
+        &[b"PEXPIRE", b"h", b"100000"],
+        &[b"SADD", b"set", b"a"],
+        &[b"ZADD", b"z", b"-inf", b"low", b"0.5", b"half"],
+        &[b"SELECT", b"1"],
+        &[b"XADD", b"x", b"1-1", b"f", b"v"],
+        &[b"XADD", b"x", b"2-1", b"f", b"w"],
+        &[b"XDEL", b"x", b"2-1"],
+        &[b"XGROUP", b"CREATE", b"x", b"g", b"0"],
+        &[b"XREADGROUP", b"GROUP", b"g", b"c", b"STREAMS", b"x", b">"],
+        &[b"XGROUP", b"CREATECONSUMER", b"x", b"g", b"idle"],
+        &[b"XGROUP", b"CREATE", b"empty", b"g", b"$", b"MKSTREAM"],
+    ];
+    for command in commands {
+        assert!(!matches!(
+            run_in(&mut session, command).await,
+            RespMessage::Error(_)
+        ));
+    }
+
+    {
+        let guards = session.databases.lock_all().await;
+        write_aof_base(&path, guards.iter().map(|keyspace| &**keyspace)).unwrap();
+    }
+    let (databases, _) = load_aof(&path, 2).await.unwrap();
+    assert_eq!(dump(&databases).await, dump(&session.databases).await);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[tokio::test]
+async fn test_bgrewriteaof_compacts_the_log_and_keeps_writes_made_meanwhile() {
+    let path = temp_path("rewrite.aof");
+    let mut session = aof_session(2, &path).await;
+    assert_eq!(
+        run_in(&mut new_session(1), &[b"BGREWRITEAOF"]).await,
+        RespMessage::Error("ERR Append only file is off".to_string())
+    );
+    for _ in 0..100 {
+        run_in(&mut session, &[b"INCR", b"counter"]).await;
+    }
+    run_in(&mut session, &[b"SELECT", b"1"]).await;
+    run_in(&mut session, &[b"RPUSH", b"l", b"a", b"b"]).await;
+    let before = std::fs::metadata(&path).unwrap().len();
+
+    let aof = session.aof.clone().unwrap();
+    let rewrite = aof.start_rewrite(&session.databases).await.unwrap();
+    assert!(aof.start_rewrite(&session.databases).await.is_none());
+    assert_eq!(
+        run_in(&mut session, &[b"BGREWRITEAOF"]).await,
+        RespMessage::Error(
+            "ERR Background append only file rewriting already in progress".to_string()
+        )
+    );
+    // Written after the data was copied, so only the rewrite buffer has them.
+    run_in(&mut session, &[b"RPUSH", b"l", b"c"]).await;
+    run_in(&mut session, &[b"SELECT", b"0"]).await;
+    run_in(&mut session, &[b"SET", b"late", b"v"]).await;
+    rewrite.await.unwrap();
+    assert!(aof.last_rewrite_ok());
+    assert!(!aof.rewrite_in_progress());
+
+    // The rewritten file is live: later writes land in it.
+    run_in(&mut session, &[b"INCR", b"counter"]).await;
+    let after = std::fs::metadata(&path).unwrap().len();
+    assert!(
+        after < before,
+        "{} should be smaller than {}",
+        after,
+        before
+    );
+    let info = run_in(&mut session, &[b"INFO", b"persistence"]).await;
+    assert_eq!(info_field(&info, "aof_current_size"), after.to_string());
+
+    let (databases, loaded) = load_aof(&path, 2).await.unwrap();
+    assert!(loaded.commands < 10);
+    assert_eq!(dump(&databases).await, dump(&session.databases).await);
+    assert_eq!(
+        run(databases.get(0), &[b"GET", b"counter"]).await,
+        bulk(b"101")
+    );
+    let _ = std::fs::remove_file(&path);
+}
+
+#[tokio::test]
+async fn test_aof_rewrite_is_due_after_enough_growth() {
+    let path = temp_path("growth.aof");
+    let mut session = aof_session(1, &path).await;
+    let aof = session.aof.clone().unwrap();
+    run_in(&mut session, &[b"SET", b"k", b"v"]).await;
+    aof.start_rewrite(&session.databases)
+        .await
+        .unwrap()
+        .await
+        .unwrap();
+    let base = aof.lock().await.base_size();
+    assert!(base > 0);
+    assert!(!aof.rewrite_due(100, 0).await);
+
+    while aof.lock().await.current_size() < 2 * base {
+        run_in(&mut session, &[b"SET", b"k", b"v"]).await;
+    }
+    assert!(aof.rewrite_due(100, 0).await);
+    assert!(!aof.rewrite_due(100, 1024 * 1024).await);
+    assert!(!aof.rewrite_due(0, 0).await);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[tokio::test]
+async fn test_dump_and_restore_every_type() {
+    let mut session = new_session(1);
+    let ok = || RespMessage::SimpleString("OK".to_string());
+    run_in(&mut session, &[b"SET", b"string", b"v"]).await;
+    run_in(&mut session, &[b"RPUSH", b"list", b"a", b"b"]).await;
+    run_in(&mut session, &[b"SADD", b"set", b"1", b"x"]).await;
+    run_in(&mut session, &[b"HSET", b"hash", b"f", b"v"]).await;
+    run_in(
+        &mut session,
+        &[b"ZADD", b"zset", b"-inf", b"m", b"2.5", b"n"],
+    )
+    .await;
+    run_in(&mut session, &[b"XADD", b"stream", b"1-1", b"f", b"v"]).await;
+    run_in(&mut session, &[b"XGROUP", b"CREATE", b"stream", b"g", b"0"]).await;
+
+    let keys: [&[u8]; 6] = [b"string", b"list", b"set", b"hash", b"zset", b"stream"];
+    let source = Databases::new(1);
+    let mut copies = Session::new(source.clone(), session.snapshotter.clone());
+    for key in keys {
+        let RespMessage::BulkString(Some(payload)) = run_in(&mut session, &[b"DUMP", key]).await
+        else {
+            panic!("DUMP should reply with a payload");
+        };
+        assert_eq!(
+            run_in(&mut copies, &[b"RESTORE", key, b"0", &payload]).await,
+            ok()
+        );
+        assert_eq!(
+            run_in(&mut copies, &[b"RESTORE", key, b"0", &payload]).await,
+            RespMessage::Error("BUSYKEY Target key name already exists.".to_string())
+        );
+        assert_eq!(
+            run_in(&mut copies, &[b"RESTORE", key, b"0", &payload, b"REPLACE"]).await,
+            ok()
+        );
+    }
+    assert_eq!(dump(&source).await, dump(&session.databases).await);
+    assert_eq!(
+        run_in(&mut session, &[b"DUMP", b"missing"]).await,
+        RespMessage::BulkString(None)
+    );
+
+    // A payload from Redis, with an integer-encoded string.
+    let redis_payload = b"\x00\xc0\n\t\x00\xbem\x06\x89Z(\x00\n";
+    assert_eq!(
+        run_in(&mut session, &[b"RESTORE", b"ten", b"0", redis_payload]).await,
+        ok()
+    );
+    assert_eq!(run_in(&mut session, &[b"GET", b"ten"]).await, bulk(b"10"));
+
+    // TTLs are relative unless ABSTTL is given; one already past restores nothing.
+    let RespMessage::BulkString(Some(payload)) = run_in(&mut session, &[b"DUMP", b"string"]).await
+    else {
+        panic!("DUMP should reply with a payload");
+    };
+    assert_eq!(
+        run_in(
+            &mut session,
+            &[b"RESTORE", b"t", b"5000", &payload, b"IDLETIME", b"10"]
+        )
+        .await,
+        ok()
+    );
+    let RespMessage::Integer(ttl) = run_in(&mut session, &[b"PTTL", b"t"]).await else {
+        panic!("PTTL should reply with an integer");
+    };
+    assert!(ttl > 4000 && ttl <= 5000);
+    let at = (now_millis() + 100_000).to_string();
+    run_in(
+        &mut session,
+        &[b"RESTORE", b"abs", at.as_bytes(), &payload, b"ABSTTL"],
+    )
+    .await;
+    assert_eq!(
+        run_in(&mut session, &[b"PEXPIRETIME", b"abs"]).await,
+        RespMessage::Integer(at.parse().unwrap())
+    );
+    assert_eq!(
+        run_in(
+            &mut session,
+            &[b"RESTORE", b"t", b"1", &payload, b"ABSTTL", b"REPLACE"]
+        )
+        .await,
+        ok()
+    );
+    assert_eq!(
+        run_in(&mut session, &[b"EXISTS", b"t"]).await,
+        RespMessage::Integer(0)
+    );
+
+    let mut corrupt = payload.clone();
+    corrupt[1] ^= 1;
+    // A well-formed payload whose LZF string claims to expand to 2^44 bytes.
+    let mut hostile = vec![0x00, 0xc3, 0x01, 0x81];
+    hostile.extend_from_slice(&(1u64 << 44).to_be_bytes());
+    hostile.extend_from_slice(&[0x00, 9, 0]);
+    hostile.extend_from_slice(&crc64(0, &hostile).to_le_bytes());
+    for (args, error) in [
+        (
+            vec![&b"RESTORE"[..], b"k", b"0", &corrupt],
+            "ERR DUMP payload version or checksum are wrong",
+        ),
+        (
+            vec![&b"RESTORE"[..], b"k", b"0", b"short"],
+            "ERR DUMP payload version or checksum are wrong",
+        ),
+        (
+            vec![&b"RESTORE"[..], b"k", b"0", &hostile],
+            "ERR Bad data format",
+        ),
+        (
+            vec![&b"RESTORE"[..], b"k", b"-1", &payload],
+            "ERR Invalid TTL value, must be >= 0",
+        ),
+        (
+            vec![&b"RESTORE"[..], b"k", b"0", &payload, b"IDLETIME", b"-1"],
+            "ERR Invalid IDLETIME value, must be >= 0",
+        ),
+        (
+            vec![&b"RESTORE"[..], b"k", b"0", &payload, b"FAST"],
+            "ERR syntax error",
+        ),
+    ] {
+        assert_eq!(
+            run_in(&mut session, &args).await,
+            RespMessage::Error(error.to_string())
+        );
+    }
+}
+
+/// Starts a server with `databases` on a free local port, returning the port.
+async fn spawn_server(databases: Databases) -> u16 {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let snapshotter = Arc::new(Snapshotter::new(
+        temp_path("target.json"),
+        SnapshotFormat::Json,
+    ));
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let session = Session::new(databases.clone(), snapshotter.clone());
+            tokio::spawn(handle_client(stream, session));
+        }
+    });
+    port
+}
+
+#[tokio::test]
+async fn test_migrate_moves_keys_to_another_server() {
+    let target = Databases::new(2);
+    let port = spawn_server(target.clone()).await.to_string();
+    let port = port.as_bytes();
+    let mut session = new_session(1);
+    let mut remote = Session::new(target.clone(), session.snapshotter.clone());
+    let ok = || RespMessage::SimpleString("OK".to_string());
+    run_in(&mut session, &[b"SET", b"k", b"v", b"EX", b"100"]).await;
+    run_in(&mut session, &[b"RPUSH", b"a", b"1", b"2"]).await;
+    run_in(&mut session, &[b"SADD", b"b", b"m"]).await;
+
+    assert_eq!(
+        run_in(
+            &mut session,
+            &[b"MIGRATE", b"127.0.0.1", port, b"k", b"1", b"1000"]
+        )
+        .await,
+        ok()
+    );
+    assert_eq!(
+        run_in(&mut session, &[b"EXISTS", b"k"]).await,
+        RespMessage::Integer(0)
+    );
+    run_in(&mut remote, &[b"SELECT", b"1"]).await;
+    assert_eq!(run_in(&mut remote, &[b"GET", b"k"]).await, bulk(b"v"));
+    let RespMessage::Integer(ttl) = run_in(&mut remote, &[b"TTL", b"k"]).await else {
+        panic!("TTL should reply with an integer");
+    };
+    assert!(ttl > 90 && ttl <= 100);
+
+    // Several keys at once, copied rather than moved; missing ones are skipped.
+    assert_eq!(
+        run_in(
+            &mut session,
+            &[
+                b"MIGRATE",
+                b"127.0.0.1",
+                port,
+                b"",
+                b"0",
+                b"1000",
+                b"COPY",
+                b"KEYS",
+                b"a",
+                b"b",
+                b"nope"
+            ],
+        )
+        .await,
+        ok()
+    );
+    assert_eq!(
+        run_in(&mut session, &[b"DBSIZE"]).await,
+        RespMessage::Integer(2)
+    );
+    run_in(&mut remote, &[b"SELECT", b"0"]).await;
+    assert_eq!(
+        run_in(&mut remote, &[b"LRANGE", b"a", b"0", b"-1"]).await,
+        bulk_array(&[b"1", b"2"])
+    );
+
+    // The target already has them: nothing moves without REPLACE.
+    run_in(&mut session, &[b"RPUSH", b"a", b"3"]).await;
+    let migrate_a: [&[u8]; 6] = [b"MIGRATE", b"127.0.0.1", port, b"a", b"0", b"1000"];
+    let RespMessage::Error(e) = run_in(&mut session, &migrate_a).await else {
+        panic!("MIGRATE should fail");
+    };
+    assert_eq!(
+        e,
+        "ERR Target instance replied with error: BUSYKEY Target key name already exists."
+    );
+    assert_eq!(
+        run_in(&mut session, &[b"EXISTS", b"a"]).await,
+        RespMessage::Integer(1)
+    );
+    let mut replace = migrate_a.to_vec();
+    replace.push(b"REPLACE");
+    assert_eq!(run_in(&mut session, &replace).await, ok());
+    assert_eq!(
+        run_in(&mut remote, &[b"LRANGE", b"a", b"0", b"-1"]).await,
+        bulk_array(&[b"1", b"2", b"3"])
+    );
+
+    assert_eq!(
+        run_in(&mut session, &migrate_a).await,
+        RespMessage::SimpleString("NOKEY".to_string())
+    );
+    let RespMessage::Error(e) = run_in(
+        &mut session,
+        &[b"MIGRATE", b"127.0.0.1", port, b"b", b"99", b"1000"],
+    )
+    .await
+    else {
+        panic!("MIGRATE should fail");
+    };
+    assert!(e.starts_with("ERR Target instance replied with error: ERR DB index"));
+
+    // Nothing listens on port 1.
+    let RespMessage::Error(e) = run_in(
+        &mut session,
+        &[b"MIGRATE", b"127.0.0.1", b"1", b"b", b"0", b"100"],
+    )
+    .await
+    else {
+        panic!("MIGRATE should fail");
+    };
+    assert!(e.starts_with("IOERR"));
+    assert_eq!(
+        run_in(
+            &mut session,
+            &[
+                b"MIGRATE",
+                b"127.0.0.1",
+                port,
+                b"b",
+                b"0",
+                b"100",
+                b"KEYS",
+                b"c"
+            ]
+        )
+        .await,
+        RespMessage::Error(
+            "ERR When using MIGRATE KEYS option, the key argument must be set to the empty string"
+                .to_string()
+        )
+    );
+}
@@ -0,0 +1,267 @@
+use crate::handler::active_expire::ExpireStats;
+use crate::handler::blocking::serve_blocked;
+use crate::handler::client_handler::Db;
+use crate::handler::commands::{parse_i64, syntax_error, wrong_arity};
+use crate::handler::databases::{Databases, Session};
+use crate::handler::keyspace::Keyspace;
+use crate::resp::resp_protocol::RespMessage;
+
+fn ok() -> RespMessage {
+    RespMessage::SimpleString("OK".to_string())
+}
+
+/// SELECT index
+pub async fn select(args: &[&[u8]], session: &mut Session) -> RespMessage {
+    if args.len() != 2 {
+        return wrong_arity(args[0]);
+    }
+    match session.databases.parse_index(args[1]) {
+        Ok(index) => {
+            session.selected = index;
+            ok()
+        }
+        Err(e) => e,
+    }
+}
+
+/// SWAPDB index1 index2
+/// Clients connected to either database see the other one's data from now on,
+/// and clients blocked on a key the swap brought in are served.
+pub async fn swapdb(args: &[&[u8]], databases: &Databases) -> RespMessage {
+    if args.len() != 3 {
+        return wrong_arity(args[0]);
+    }
+    if parse_i64(args[1]).is_err() {
+        return RespMessage::Error("ERR invalid first DB index".to_string());
+    }
+    if parse_i64(args[2]).is_err() {
+        return RespMessage::Error("ERR invalid second DB index".to_string());
+    }
+    let (first, second) = match (
+        databases.parse_index(args[1]),
+        databases.parse_index(args[2]),
+    ) {
+        (Ok(first), Ok(second)) => (first, second),
+        (Err(e), _) | (_, Err(e)) => return e,
+    };
+
+    let (mut first, second) = databases.lock_pair(first, second).await;
+    let Some(mut second) = second else {
+        return ok();
+    };
+    first.swap_data(&mut second);
+    first.dirty += 1;
+    for keyspace in [&mut *first, &mut *second] {
+        for key in keyspace.blocked.waited_keys() {
+            serve_blocked(keyspace, &key);
+        }
+    }
+    ok()
+}
+
+/// MOVE key db
+/// Replies 1 if the key was moved, and 0 if it doesn't exist here or already
+/// exists in the target database. The TTL moves with the key.
+pub async fn move_key(args: &[&[u8]], session: &Session) -> RespMessage {
+    if args.len() != 3 {
+        return wrong_arity(args[0]);
+    }
+    let target = match session.databases.parse_index(args[2]) {
+        Ok(target) => target,
+        Err(e) => return e,
+    };
+    let (mut source_db, target_db) = session.databases.lock_pair(session.selected, target).await;
+    let Some(mut target_db) = target_db else {
+        return RespMessage::Error("ERR source and destination objects are the same".to_string());
+    };
+
+    if source_db.lookup_read(args[1]).is_none() || target_db.lookup_read(args[1]).is_some() {
+        return RespMessage::Integer(0);
+    }
+    let Some(entry) = source_db.delete(args[1]) else {
+        return RespMessage::Integer(0);
+    };
+    target_db.insert(args[1].to_vec(), entry);
+    source_db.dirty += 1;
+    serve_blocked(&mut target_db, args[1]);
+    RespMessage::Integer(1)
+}
+
+/// Whether a FLUSHDB / FLUSHALL asked for `ASYNC`.
+fn parse_flush_mode(args: &[&[u8]]) -> Result<bool, RespMessage> {
+    match args {
+        [_] => Ok(false),
+        [_, mode] => match mode.to_ascii_uppercase().as_slice() {
+            b"ASYNC" => Ok(true),
+            b"SYNC" => Ok(false),
+            _ => Err(syntax_error()),
+        },
+        _ => Err(syntax_error()),
+    }
+}
+
+/// Empties `keyspace`. With `ASYNC` the old keys are freed on a background
+/// thread, so a huge database doesn't hold up the server while it's dropped.
+fn flush(keyspace: &mut Keyspace, lazy: bool) {
+    let entries = keyspace.take_entries();
+    keyspace.dirty += entries.len() as u64;
+    if lazy {
+        tokio::task::spawn_blocking(move || drop(entries));
+    }
+}
+
+/// FLUSHDB [ASYNC|SYNC]
+pub async fn flushdb(args: &[&[u8]], db: &Db) -> RespMessage {
+    match parse_flush_mode(args) {
+        Ok(lazy) => {
+            flush(&mut *db.lock().await, lazy);
+            ok()
+        }
+        Err(e) => e,
+    }
+}
+
+/// FLUSHALL [ASYNC|SYNC]
+pub async fn flushall(args: &[&[u8]], databases: &Databases) -> RespMessage {
+    match parse_flush_mode(args) {
+        Ok(lazy) => {
+            for mut keyspace in databases.lock_all().await {
+                flush(&mut keyspace, lazy);
+            }
+            ok()
+        }
+        Err(e) => e,
+    }
+}
+
+fn bgsave_in_progress() -> RespMessage {
+    RespMessage::Error("ERR Background save already in progress".to_string())
+}
+
+/// SAVE: writes every database to the snapshot file, blocking every client until
+/// it's done.
+pub async fn save(args: &[&[u8]], session: &Session) -> RespMessage {
+    if args.len() != 1 {
+        return wrong_arity(args[0]);
+    }
+    let guards = session.databases.lock_all().await;
+    if session.snapshotter.bgsave_in_progress() {
+        return bgsave_in_progress();
+    }
+    match session
+        .snapshotter
+        .save(guards.iter().map(|keyspace| &**keyspace))
+    {
+        Ok(()) => ok(),
+        Err(e) => RespMessage::Error(format!("ERR failed to save: {}", e)),
+    }
+}
+
+/// BGSAVE: writes the snapshot file in the background. Whether it worked shows
+/// in LASTSAVE and `INFO persistence`.
+pub async fn bgsave(args: &[&[u8]], session: &Session) -> RespMessage {
+    if args.len() != 1 {
+        return wrong_arity(args[0]);
+    }
+    match session.snapshotter.start_bgsave(&session.databases).await {
+        Some(_) => RespMessage::SimpleString("Background saving started".to_string()),
+        None => bgsave_in_progress(),
+    }
+}
+
+/// BGREWRITEAOF: rewrites the append-only file in the background as the shortest
+/// one that rebuilds the current data.
+pub async fn bgrewriteaof(args: &[&[u8]], session: &Session) -> RespMessage {
+    if args.len() != 1 {
+        return wrong_arity(args[0]);
+    }
+    let Some(aof) = &session.aof else {
+        return RespMessage::Error("ERR Append only file is off".to_string());
+    };
+    match aof.start_rewrite(&session.databases).await {
+        Some(_) => {
+            RespMessage::SimpleString("Background append only file rewriting started".to_string())
+        }
+        None => RespMessage::Error(
+            "ERR Background append only file rewriting already in progress".to_string(),
+        ),
+    }
+}
+
+/// LASTSAVE: the Unix time of the last successful save.
+pub async fn lastsave(args: &[&[u8]], session: &Session) -> RespMessage {
+    if args.len() != 1 {
+        return wrong_arity(args[0]);
+    }
+    RespMessage::Integer(session.snapshotter.last_save() as i64)
+}
+
+fn status(ok: bool) -> &'static str {
+    if ok {
+        "ok"
+    } else {
+        "err"
+    }
+}
+
+/// INFO [section ...]: only the persistence and stats sections are reported so far.
+pub async fn info(args: &[&[u8]], session: &Session) -> RespMessage {
+    let wanted = |name: &[u8]| {
+        args[1..].is_empty()
+            || args[1..].iter().any(|section| {
+                let section = section.to_ascii_lowercase();
+                section == name || matches!(section.as_slice(), b"default" | b"all" | b"everything")
+            })
+    };
+    let mut info = String::new();
+    if wanted(b"persistence") {
+        persistence_info(session, &mut info).await;
+    }
+    if wanted(b"stats") {
+        let mut stats = ExpireStats::default();
+        for db in session.databases.iter() {
+            let keyspace = db.lock().await;
+            stats.expired_keys += keyspace.expire_stats.expired_keys;
+            stats.time_limit_exits += keyspace.expire_stats.time_limit_exits;
+        }
+        info.push_str(&format!(
+            "# Stats\r\n\
+             expired_keys:{}\r\n\
+             expired_time_cap_reached_count:{}\r\n",
+            stats.expired_keys, stats.time_limit_exits,
+        ));
+    }
+    RespMessage::BulkString(Some(info.into_bytes()))
+}
+
+async fn persistence_info(session: &Session, info: &mut String) {
+    let snapshotter = &session.snapshotter;
+    info.push_str(&format!(
+        "# Persistence\r\n\
+         rdb_changes_since_last_save:{}\r\n\
+         rdb_bgsave_in_progress:{}\r\n\
+         rdb_last_save_time:{}\r\n\
+         rdb_last_bgsave_status:{}\r\n\
+         aof_enabled:{}\r\n",
+        snapshotter.changes_since_save(),
+        snapshotter.bgsave_in_progress() as u8,
+        snapshotter.last_save(),
+        status(snapshotter.last_save_ok()),
+        session.aof.is_some() as u8,
+    ));
+    if let Some(aof) = &session.aof {
+        let log = aof.lock().await;
+        info.push_str(&format!(
+            "aof_rewrite_in_progress:{}\r\n\
+             aof_last_bgrewrite_status:{}\r\n\
+             aof_last_write_status:{}\r\n\
+             aof_current_size:{}\r\n\
+             aof_base_size:{}\r\n",
+            aof.rewrite_in_progress() as u8,
+            status(aof.last_rewrite_ok()),
+            status(log.last_write_ok()),
+            log.current_size(),
+            log.base_size(),
+        ));
+    }
+}
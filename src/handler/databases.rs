@@ -0,0 +1,114 @@
+use crate::handler::aof::Aof;
+use crate::handler::client_handler::Db;
+use crate::handler::commands::parse_i64;
+use crate::handler::keyspace::Keyspace;
+use crate::handler::persistence::Snapshotter;
+use crate::resp::resp_protocol::RespMessage;
+use std::sync::Arc;
+use tokio::sync::{Mutex, MutexGuard};
+
+/*
+Logical databases: numbered, fully separate keyspaces, like Redis's `SELECT n`.
+
+Each database has its own lock, so clients working in different databases don't
+wait on each other. Commands that need several databases at once (MOVE, SWAPDB,
+FLUSHALL, SAVE, ...) lock them in ascending index order, which rules out two such
+commands deadlocking on each other.
+*/
+
+/// Every database of the server, cheap to clone and share between connections.
+#[derive(Clone)]
+pub struct Databases(Arc<Vec<Db>>);
+
+impl Databases {
+    #[cfg(test)]
+    pub fn new(count: usize) -> Databases {
+        Databases::from_keyspaces((0..count).map(|_| Keyspace::default()).collect())
+    }
+
+    pub fn from_keyspaces(keyspaces: Vec<Keyspace>) -> Databases {
+        Databases(Arc::new(
+            keyspaces
+                .into_iter()
+                .map(|keyspace| Arc::new(Mutex::new(keyspace)))
+                .collect(),
+        ))
+    }
+
+    /// How many databases there are.
+    pub fn count(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn get(&self, index: usize) -> &Db {
+        &self.0[index]
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Db> {
+        self.0.iter()
+    }
+
+    /// Parses a command argument as the index of one of the databases.
+    pub fn parse_index(&self, arg: &[u8]) -> Result<usize, RespMessage> {
+        match parse_i64(arg)? {
+            index if index >= 0 && (index as usize) < self.count() => Ok(index as usize),
+            _ => Err(RespMessage::Error(
+                "ERR DB index is out of range".to_string(),
+            )),
+        }
+    }
+
+    /// Locks databases `a` and `b`. The second guard is `None` when they are the
+    /// same database.
+    pub async fn lock_pair(
+        &self,
+        a: usize,
+        b: usize,
+    ) -> (MutexGuard<'_, Keyspace>, Option<MutexGuard<'_, Keyspace>>) {
+        if a == b {
+            return (self.0[a].lock().await, None);
+        }
+        if a < b {
+            let first = self.0[a].lock().await;
+            (first, Some(self.0[b].lock().await))
+        } else {
+            let second = self.0[b].lock().await;
+            (self.0[a].lock().await, Some(second))
+        }
+    }
+
+    /// Locks every database, in index order.
+    pub async fn lock_all(&self) -> Vec<MutexGuard<'_, Keyspace>> {
+        let mut guards = Vec::with_capacity(self.count());
+        for db in self.iter() {
+            guards.push(db.lock().await);
+        }
+        guards
+    }
+}
+
+/// Per-connection state: the database the client has selected, next to the
+/// server-wide state every client shares.
+pub struct Session {
+    pub databases: Databases,
+    pub snapshotter: Arc<Snapshotter>,
+    /// The append-only file, when the server keeps one.
+    pub aof: Option<Arc<Aof>>,
+    pub selected: usize,
+}
+
+impl Session {
+    pub fn new(databases: Databases, snapshotter: Arc<Snapshotter>) -> Session {
+        Session {
+            databases,
+            snapshotter,
+            aof: None,
+            selected: 0,
+        }
+    }
+
+    /// The selected database.
+    pub fn db(&self) -> &Db {
+        self.databases.get(self.selected)
+    }
+}
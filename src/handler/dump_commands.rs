@@ -0,0 +1,268 @@
+use crate::handler::blocking::serve_blocked;
+use crate::handler::client_handler::Db;
+use crate::handler::commands::{bulk, not_an_integer, parse_i64, syntax_error, wrong_arity};
+use crate::handler::rdb::{dump_payload, restore_payload};
+use crate::handler::value::ValueWithExpiry;
+use crate::resp::resp_protocol::{RespDecoder, RespMessage};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+
+/*
+Moving single keys between servers.
+
+DUMP serializes a key's value (see `rdb::dump_payload`), RESTORE creates a key from
+such a payload, and MIGRATE does both across the network: it dumps the keys, sends
+them to the other server as RESTORE commands over a plain RESP connection, and
+deletes them here once they've arrived.
+
+Like Redis, MIGRATE is atomic as far as this database is concerned: it holds the
+database's lock for the whole transfer, so no client sees a key half-moved. The
+timeout bounds how long that can take.
+*/
+
+/// How long MIGRATE waits on the target when given a timeout of 0 or less.
+const DEFAULT_MIGRATE_TIMEOUT_MS: u64 = 1000;
+
+fn ok() -> RespMessage {
+    RespMessage::SimpleString("OK".to_string())
+}
+
+/// DUMP key
+pub async fn dump(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() != 2 {
+        return wrong_arity(args[0]);
+    }
+    let mut db_guard = db.lock().await;
+    let Some(entry) = db_guard.lookup_read(args[1]) else {
+        return RespMessage::BulkString(None);
+    };
+    match dump_payload(&entry.value) {
+        Ok(payload) => RespMessage::BulkString(Some(payload)),
+        Err(e) => RespMessage::Error(format!("ERR {}", e)),
+    }
+}
+
+/// RESTORE key ttl serialized-value [REPLACE] [ABSTTL] [IDLETIME seconds]
+/// A `ttl` of 0 means no expiry. xredis keeps no access times, so IDLETIME is
+/// checked and then ignored.
+pub async fn restore(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() < 4 {
+        return wrong_arity(args[0]);
+    }
+    let (key, payload) = (args[1], args[3]);
+    let mut replace = false;
+    let mut absolute = false;
+    let mut i = 4;
+    while i < args.len() {
+        match (args[i].to_ascii_uppercase().as_slice(), args.get(i + 1)) {
+            (b"REPLACE", _) => replace = true,
+            (b"ABSTTL", _) => absolute = true,
+            (b"IDLETIME", Some(idle)) => {
+                match parse_i64(idle) {
+                    Ok(idle) if idle >= 0 => {}
+                    Ok(_) => {
+                        return RespMessage::Error(
+                            "ERR Invalid IDLETIME value, must be >= 0".to_string(),
+                        )
+                    }
+                    Err(e) => return e,
+                }
+                i += 1;
+            }
+            _ => return syntax_error(),
+        }
+        i += 1;
+    }
+    let ttl = match parse_i64(args[2]) {
+        Ok(ttl) if ttl >= 0 => ttl as u128,
+        Ok(_) => return RespMessage::Error("ERR Invalid TTL value, must be >= 0".to_string()),
+        Err(e) => return e,
+    };
+
+    let mut db_guard = db.lock().await;
+    if !replace && db_guard.lookup_read(key).is_some() {
+        return RespMessage::Error("BUSYKEY Target key name already exists.".to_string());
+    }
+    let value = match restore_payload(payload) {
+        Ok(value) => value,
+        Err(e) => return RespMessage::Error(format!("ERR {}", e)),
+    };
+    let expiry = match ttl {
+        0 => None,
+        ttl if absolute => Some(ttl),
+        ttl => Some(db_guard.now() + ttl),
+    };
+    // A key whose time is already up is restored only to be deleted at once.
+    if expiry.is_some_and(|expiry| expiry <= db_guard.now()) {
+        db_guard.delete(key);
+        db_guard.dirty += 1;
+        return ok();
+    }
+    db_guard.insert(key.to_vec(), ValueWithExpiry { value, expiry });
+    db_guard.dirty += 1;
+    serve_blocked(&mut db_guard, key);
+    ok()
+}
+
+struct MigrateOptions<'a> {
+    host: String,
+    port: u16,
+    keys: Vec<&'a [u8]>,
+    db: &'a [u8],
+    timeout: Duration,
+    copy: bool,
+    replace: bool,
+}
+
+fn parse_migrate<'a>(args: &[&'a [u8]]) -> Result<MigrateOptions<'a>, RespMessage> {
+    if args.len() < 6 {
+        return Err(wrong_arity(args[0]));
+    }
+    let port = std::str::from_utf8(args[2])
+        .ok()
+        .and_then(|port| port.parse::<u16>().ok())
+        .ok_or_else(not_an_integer)?;
+    if parse_i64(args[4])? < 0 {
+        return Err(RespMessage::Error(
+            "ERR DB index is out of range".to_string(),
+        ));
+    }
+    let timeout = match parse_i64(args[5])? {
+        millis if millis > 0 => millis as u64,
+        _ => DEFAULT_MIGRATE_TIMEOUT_MS,
+    };
+    let mut options = MigrateOptions {
+        host: String::from_utf8_lossy(args[1]).into_owned(),
+        port,
+        keys: vec![args[3]],
+        db: args[4],
+        timeout: Duration::from_millis(timeout),
+        copy: false,
+        replace: false,
+    };
+    let mut i = 6;
+    while i < args.len() {
+        match args[i].to_ascii_uppercase().as_slice() {
+            b"COPY" => options.copy = true,
+            b"REPLACE" => options.replace = true,
+            b"KEYS" => {
+                if !args[3].is_empty() {
+                    return Err(RespMessage::Error(
+                        "ERR When using MIGRATE KEYS option, the key argument must be set to the empty string".to_string(),
+                    ));
+                }
+                options.keys = args[i + 1..].to_vec();
+                break;
+            }
+            b"AUTH" | b"AUTH2" => {
+                return Err(RespMessage::Error(
+                    "ERR MIGRATE AUTH is not supported".to_string(),
+                ))
+            }
+            _ => return Err(syntax_error()),
+        }
+        i += 1;
+    }
+    Ok(options)
+}
+
+fn command(parts: &[&[u8]]) -> RespMessage {
+    RespMessage::Array(parts.iter().map(|part| bulk(part)).collect())
+}
+
+/// Sends `requests` to the target in one write and reads back as many replies.
+async fn exchange(
+    options: &MigrateOptions<'_>,
+    requests: &[RespMessage],
+) -> Result<Vec<RespMessage>, RespMessage> {
+    let io_error = |what: &str| RespMessage::Error(format!("IOERR error or timeout {}", what));
+    let connect = TcpStream::connect((options.host.as_str(), options.port));
+    let mut stream = match timeout(options.timeout, connect).await {
+        Ok(Ok(stream)) => stream,
+        _ => return Err(io_error("connecting to the client")),
+    };
+    let mut out = Vec::new();
+    for request in requests {
+        request.encode(&mut out);
+    }
+    match timeout(options.timeout, stream.write_all(&out)).await {
+        Ok(Ok(())) => {}
+        _ => return Err(io_error("writing to target instance")),
+    }
+
+    let mut decoder = RespDecoder::new();
+    let mut replies = Vec::with_capacity(requests.len());
+    let mut buf = vec![0; 16 * 1024];
+    while replies.len() < requests.len() {
+        match decoder.next_frame() {
+            Ok(Some(reply)) => {
+                replies.push(reply);
+                continue;
+            }
+            Ok(None) => {}
+            Err(_) => return Err(io_error("reading from target instance")),
+        }
+        match timeout(options.timeout, stream.read(&mut buf)).await {
+            Ok(Ok(n)) if n > 0 => decoder.feed(&buf[..n]),
+            _ => return Err(io_error("reading from target instance")),
+        }
+    }
+    Ok(replies)
+}
+
+/// MIGRATE host port key|"" destination-db timeout [COPY] [REPLACE] [KEYS key [key ...]]
+/// Replies NOKEY when none of the keys exist. If the target refuses any key,
+/// the reply is its error and every key stays here.
+pub async fn migrate(args: &[&[u8]], db: &Db) -> RespMessage {
+    let options = match parse_migrate(args) {
+        Ok(options) => options,
+        Err(e) => return e,
+    };
+
+    let mut db_guard = db.lock().await;
+    let now = db_guard.now();
+    let mut requests = vec![command(&[b"SELECT", options.db])];
+    let mut found = Vec::new();
+    for &key in &options.keys {
+        let Some(entry) = db_guard.lookup_read(key) else {
+            continue;
+        };
+        let payload = match dump_payload(&entry.value) {
+            Ok(payload) => payload,
+            Err(e) => return RespMessage::Error(format!("ERR {}", e)),
+        };
+        // The target takes a relative TTL, like Redis's RESTORE.
+        let ttl = entry.expiry.map_or(0, |expiry| (expiry - now).max(1));
+        let ttl = ttl.to_string();
+        let mut restore: Vec<&[u8]> = vec![b"RESTORE", key, ttl.as_bytes(), &payload];
+        if options.replace {
+            restore.push(b"REPLACE");
+        }
+        requests.push(command(&restore));
+        found.push(key);
+    }
+    if found.is_empty() {
+        return RespMessage::SimpleString("NOKEY".to_string());
+    }
+
+    let replies = match exchange(&options, &requests).await {
+        Ok(replies) => replies,
+        Err(e) => return e,
+    };
+    if let Some(RespMessage::Error(e)) = replies
+        .iter()
+        .find(|reply| matches!(reply, RespMessage::Error(_)))
+    {
+        return RespMessage::Error(format!("ERR Target instance replied with error: {}", e));
+    }
+    if !options.copy {
+        for key in found {
+            if db_guard.delete(key).is_some() {
+                db_guard.dirty += 1;
+            }
+        }
+    }
+    ok()
+}
@@ -0,0 +1,172 @@
+use crate::handler::client_handler::Db;
+use crate::handler::commands::{parse_i64, wrong_arity};
+use crate::resp::resp_protocol::RespMessage;
+
+/// The unit a TTL command takes or replies in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TimeUnit {
+    Seconds,
+    Milliseconds,
+}
+
+impl TimeUnit {
+    fn to_millis(self, amount: i64) -> Option<i64> {
+        match self {
+            TimeUnit::Seconds => amount.checked_mul(1000),
+            TimeUnit::Milliseconds => Some(amount),
+        }
+    }
+
+    fn in_unit(self, millis: u128) -> i64 {
+        match self {
+            TimeUnit::Seconds => (millis / 1000) as i64,
+            TimeUnit::Milliseconds => millis as i64,
+        }
+    }
+}
+
+/// Whether an EXPIRE-family time is relative to now or a Unix timestamp.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExpireAt {
+    Relative,
+    Absolute,
+}
+
+/// The Unix time in milliseconds that `amount` (in `unit`, relative to `now` or
+/// absolute) stands for, or `None` if it overflows.
+pub fn absolute_millis(amount: i64, unit: TimeUnit, at: ExpireAt, now: i64) -> Option<i64> {
+    unit.to_millis(amount).and_then(|millis| match at {
+        ExpireAt::Relative => millis.checked_add(now),
+        ExpireAt::Absolute => Some(millis),
+    })
+}
+
+pub fn invalid_expire_time(cmd: &[u8]) -> RespMessage {
+    RespMessage::Error(format!(
+        "ERR invalid expire time in '{}' command",
+        String::from_utf8_lossy(cmd).to_lowercase()
+    ))
+}
+
+/// EXPIRE / PEXPIRE / EXPIREAT / PEXPIREAT key time [NX|XX|GT|LT]
+pub async fn expire(args: &[&[u8]], db: &Db, unit: TimeUnit, at: ExpireAt) -> RespMessage {
+    if args.len() < 3 {
+        return wrong_arity(args[0]);
+    }
+    let amount = match parse_i64(args[2]) {
+        Ok(amount) => amount,
+        Err(e) => return e,
+    };
+
+    let (mut nx, mut xx, mut gt, mut lt) = (false, false, false, false);
+    for flag in &args[3..] {
+        match flag.to_ascii_uppercase().as_slice() {
+            b"NX" => nx = true,
+            b"XX" => xx = true,
+            b"GT" => gt = true,
+            b"LT" => lt = true,
+            _ => {
+                return RespMessage::Error(format!(
+                    "ERR Unsupported option {}",
+                    String::from_utf8_lossy(flag)
+                ))
+            }
+        }
+    }
+    if nx && (xx || gt || lt) {
+        return RespMessage::Error(
+            "ERR NX and XX, GT or LT options at the same time are not compatible".to_string(),
+        );
+    }
+    if gt && lt {
+        return RespMessage::Error(
+            "ERR GT and LT options at the same time are not compatible".to_string(),
+        );
+    }
+
+    let mut db_guard = db.lock().await;
+    let now = db_guard.now() as i64;
+    let Some(when) = absolute_millis(amount, unit, at, now) else {
+        return invalid_expire_time(args[0]);
+    };
+
+    let Some(entry) = db_guard.lookup_read(args[1]) else {
+        return RespMessage::Integer(0);
+    };
+
+    // A key without a TTL counts as expiring never, i.e. later than any time.
+    let allowed = match entry.expiry {
+        None => !xx && !gt,
+        Some(current) => {
+            let current = current as i64;
+            !nx && (!gt || when > current) && (!lt || when < current)
+        }
+    };
+    if !allowed {
+        return RespMessage::Integer(0);
+    }
+
+    if when <= now {
+        db_guard.delete(args[1]);
+    } else {
+        db_guard.set_expiry(args[1], Some(when as u128));
+    }
+    db_guard.dirty += 1;
+    RespMessage::Integer(1)
+}
+
+/// TTL / PTTL key: the time left, -1 for a key without a TTL and -2 for a missing key.
+pub async fn ttl(args: &[&[u8]], db: &Db, unit: TimeUnit) -> RespMessage {
+    if args.len() != 2 {
+        return wrong_arity(args[0]);
+    }
+    let mut db_guard = db.lock().await;
+    let now = db_guard.now();
+    match db_guard.lookup_read(args[1]) {
+        None => RespMessage::Integer(-2),
+        Some(entry) => match entry.expiry {
+            None => RespMessage::Integer(-1),
+            Some(expiry) => {
+                let left = expiry.saturating_sub(now);
+                // TTL rounds to the nearest second, like Redis.
+                let left = match unit {
+                    TimeUnit::Seconds => left + 500,
+                    TimeUnit::Milliseconds => left,
+                };
+                RespMessage::Integer(unit.in_unit(left))
+            }
+        },
+    }
+}
+
+/// EXPIRETIME / PEXPIRETIME key: the Unix time the key expires at, -1 for a key
+/// without a TTL and -2 for a missing key.
+pub async fn expiretime(args: &[&[u8]], db: &Db, unit: TimeUnit) -> RespMessage {
+    if args.len() != 2 {
+        return wrong_arity(args[0]);
+    }
+    let mut db_guard = db.lock().await;
+    match db_guard.lookup_read(args[1]) {
+        None => RespMessage::Integer(-2),
+        Some(entry) => match entry.expiry {
+            None => RespMessage::Integer(-1),
+            Some(expiry) => RespMessage::Integer(unit.in_unit(expiry)),
+        },
+    }
+}
+
+/// PERSIST key: drops the key's TTL. Replies 1 if it had one.
+pub async fn persist(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() != 2 {
+        return wrong_arity(args[0]);
+    }
+    let mut db_guard = db.lock().await;
+    let has_ttl = db_guard
+        .lookup_read(args[1])
+        .is_some_and(|entry| entry.expiry.is_some());
+    if has_ttl {
+        db_guard.set_expiry(args[1], None);
+        db_guard.dirty += 1;
+    }
+    RespMessage::Integer(has_ttl as i64)
+}
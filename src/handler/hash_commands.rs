@@ -0,0 +1,346 @@
+use crate::handler::client_handler::Db;
+use crate::handler::commands::{
+    bulk, format_float, parse_float, parse_i64, parse_random_count, pick_random, remove_if_empty,
+    syntax_error, wrong_arity, wrong_type,
+};
+use crate::handler::keyspace::Keyspace;
+use crate::handler::scan::{parse_scan_options, scan_reply};
+use crate::handler::value::{Dict, Value};
+use crate::resp::resp_protocol::RespMessage;
+
+type Hash = Dict<Vec<u8>, Vec<u8>>;
+
+/// Looks up the live hash stored at `key`.
+/// Returns `Ok(None)` for a missing key and a WRONGTYPE error for any other type.
+pub fn get_hash<'a>(
+    keyspace: &'a mut Keyspace,
+    key: &[u8],
+) -> Result<Option<&'a mut Hash>, RespMessage> {
+    match keyspace.lookup_write(key).map(|v| &mut v.value) {
+        Some(Value::Hash(hash)) => Ok(Some(hash)),
+        Some(_) => Err(wrong_type()),
+        None => Ok(None),
+    }
+}
+
+/// Like `get_hash`, but creates an empty hash when the key is missing.
+pub fn get_or_create_hash<'a>(
+    keyspace: &'a mut Keyspace,
+    key: &[u8],
+) -> Result<&'a mut Hash, RespMessage> {
+    let entry = keyspace.lookup_or_insert(key, || Value::Hash(Dict::new()));
+    match &mut entry.value {
+        Value::Hash(hash) => Ok(hash),
+        _ => Err(wrong_type()),
+    }
+}
+
+/// HSET key field value [field value ...]
+/// With `legacy_reply` set this is HMSET, which replies OK instead of a count.
+pub async fn hset(args: &[&[u8]], db: &Db, legacy_reply: bool) -> RespMessage {
+    if args.len() < 4 || !args.len().is_multiple_of(2) {
+        return wrong_arity(args[0]);
+    }
+    let mut db_guard = db.lock().await;
+    let hash = match get_or_create_hash(&mut db_guard, args[1]) {
+        Ok(hash) => hash,
+        Err(e) => return e,
+    };
+
+    let added = args[2..]
+        .chunks(2)
+        .filter(|pair| hash.insert(pair[0].to_vec(), pair[1].to_vec()).is_none())
+        .count();
+    db_guard.dirty += (args.len() / 2 - 1) as u64;
+    if legacy_reply {
+        RespMessage::SimpleString("OK".to_string())
+    } else {
+        RespMessage::Integer(added as i64)
+    }
+}
+
+/// HSETNX key field value
+pub async fn hsetnx(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() != 4 {
+        return wrong_arity(args[0]);
+    }
+    let mut db_guard = db.lock().await;
+    let hash = match get_or_create_hash(&mut db_guard, args[1]) {
+        Ok(hash) => hash,
+        Err(e) => return e,
+    };
+
+    if hash.contains_key(args[2]) {
+        RespMessage::Integer(0)
+    } else {
+        hash.insert(args[2].to_vec(), args[3].to_vec());
+        db_guard.dirty += 1;
+        RespMessage::Integer(1)
+    }
+}
+
+/// HGET key field
+pub async fn hget(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() != 3 {
+        return wrong_arity(args[0]);
+    }
+    let mut db_guard = db.lock().await;
+    match get_hash(&mut db_guard, args[1]) {
+        Ok(hash) => RespMessage::BulkString(hash.and_then(|hash| hash.get(args[2]).cloned())),
+        Err(e) => e,
+    }
+}
+
+/// HMGET key field [field ...]
+pub async fn hmget(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() < 3 {
+        return wrong_arity(args[0]);
+    }
+    let mut db_guard = db.lock().await;
+    match get_hash(&mut db_guard, args[1]) {
+        Ok(hash) => RespMessage::Array(
+            args[2..]
+                .iter()
+                .map(|field| {
+                    RespMessage::BulkString(hash.as_ref().and_then(|h| h.get(*field).cloned()))
+                })
+                .collect(),
+        ),
+        Err(e) => e,
+    }
+}
+
+/// HDEL key field [field ...]
+pub async fn hdel(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() < 3 {
+        return wrong_arity(args[0]);
+    }
+    let key = args[1];
+    let mut db_guard = db.lock().await;
+    let removed = match get_hash(&mut db_guard, key) {
+        Ok(Some(hash)) => args[2..]
+            .iter()
+            .filter(|field| hash.remove(**field).is_some())
+            .count(),
+        Ok(None) => 0,
+        Err(e) => return e,
+    };
+    db_guard.dirty += removed as u64;
+    remove_if_empty(&mut db_guard, key);
+    RespMessage::Integer(removed as i64)
+}
+
+/// HEXISTS key field
+pub async fn hexists(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() != 3 {
+        return wrong_arity(args[0]);
+    }
+    let mut db_guard = db.lock().await;
+    match get_hash(&mut db_guard, args[1]) {
+        Ok(hash) => RespMessage::Integer(hash.is_some_and(|h| h.contains_key(args[2])) as i64),
+        Err(e) => e,
+    }
+}
+
+/// HLEN key
+pub async fn hlen(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() != 2 {
+        return wrong_arity(args[0]);
+    }
+    let mut db_guard = db.lock().await;
+    match get_hash(&mut db_guard, args[1]) {
+        Ok(hash) => RespMessage::Integer(hash.map_or(0, |h| h.len() as i64)),
+        Err(e) => e,
+    }
+}
+
+/// HSTRLEN key field
+pub async fn hstrlen(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() != 3 {
+        return wrong_arity(args[0]);
+    }
+    let mut db_guard = db.lock().await;
+    match get_hash(&mut db_guard, args[1]) {
+        Ok(hash) => RespMessage::Integer(
+            hash.and_then(|h| h.get(args[2]))
+                .map_or(0, |v| v.len() as i64),
+        ),
+        Err(e) => e,
+    }
+}
+
+/// Which parts of each field/value pair HKEYS, HVALS and HGETALL reply with.
+#[derive(Clone, Copy, PartialEq)]
+pub enum HashParts {
+    Fields,
+    Values,
+    Both,
+}
+
+/// HKEYS / HVALS / HGETALL key
+pub async fn hgetall(args: &[&[u8]], db: &Db, parts: HashParts) -> RespMessage {
+    if args.len() != 2 {
+        return wrong_arity(args[0]);
+    }
+    let mut db_guard = db.lock().await;
+    let hash = match get_hash(&mut db_guard, args[1]) {
+        Ok(Some(hash)) => hash,
+        Ok(None) => return RespMessage::Array(vec![]),
+        Err(e) => return e,
+    };
+
+    let mut reply = Vec::with_capacity(hash.len() * if parts == HashParts::Both { 2 } else { 1 });
+    for (field, value) in hash.iter() {
+        if parts != HashParts::Values {
+            reply.push(bulk(field));
+        }
+        if parts != HashParts::Fields {
+            reply.push(bulk(value));
+        }
+    }
+    RespMessage::Array(reply)
+}
+
+/// HINCRBY key field increment
+pub async fn hincrby(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() != 4 {
+        return wrong_arity(args[0]);
+    }
+    let increment = match parse_i64(args[3]) {
+        Ok(increment) => increment,
+        Err(e) => return e,
+    };
+    let mut db_guard = db.lock().await;
+    let current = match get_hash(&mut db_guard, args[1]) {
+        Ok(hash) => hash.and_then(|hash| hash.get(args[2])),
+        Err(e) => return e,
+    };
+
+    let current = match current {
+        Some(value) => match parse_i64(value) {
+            Ok(current) => current,
+            Err(_) => return RespMessage::Error("ERR hash value is not an integer".to_string()),
+        },
+        None => 0,
+    };
+    match current.checked_add(increment) {
+        Some(updated) => match get_or_create_hash(&mut db_guard, args[1]) {
+            Ok(hash) => {
+                hash.insert(args[2].to_vec(), updated.to_string().into_bytes());
+                db_guard.dirty += 1;
+                RespMessage::Integer(updated)
+            }
+            Err(e) => e,
+        },
+        None => RespMessage::Error("ERR increment or decrement would overflow".to_string()),
+    }
+}
+
+/// HINCRBYFLOAT key field increment
+pub async fn hincrbyfloat(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() != 4 {
+        return wrong_arity(args[0]);
+    }
+    let increment = match parse_float(args[3]) {
+        Ok(increment) => increment,
+        Err(e) => return e,
+    };
+    let mut db_guard = db.lock().await;
+    let current = match get_hash(&mut db_guard, args[1]) {
+        Ok(hash) => hash.and_then(|hash| hash.get(args[2])),
+        Err(e) => return e,
+    };
+
+    let current = match current {
+        Some(value) => match parse_float(value) {
+            Ok(current) => current,
+            Err(_) => return RespMessage::Error("ERR hash value is not a float".to_string()),
+        },
+        None => 0.0,
+    };
+    let updated = current + increment;
+    if !updated.is_finite() {
+        return RespMessage::Error("ERR increment would produce NaN or Infinity".to_string());
+    }
+    let formatted = format_float(updated).into_bytes();
+    match get_or_create_hash(&mut db_guard, args[1]) {
+        Ok(hash) => {
+            hash.insert(args[2].to_vec(), formatted.clone());
+            db_guard.dirty += 1;
+            RespMessage::BulkString(Some(formatted))
+        }
+        Err(e) => e,
+    }
+}
+
+/// HRANDFIELD key [count [WITHVALUES]]
+pub async fn hrandfield(args: &[&[u8]], db: &Db) -> RespMessage {
+    if !(2..=4).contains(&args.len()) {
+        return wrong_arity(args[0]);
+    }
+    let count = match args.get(2).map(|arg| parse_random_count(arg)) {
+        None => None,
+        Some(Ok(count)) => Some(count),
+        Some(Err(e)) => return e,
+    };
+    let with_values = match args.get(3) {
+        None => false,
+        Some(arg) if arg.eq_ignore_ascii_case(b"WITHVALUES") => true,
+        Some(_) => return syntax_error(),
+    };
+    let mut db_guard = db.lock().await;
+    let hash = match get_hash(&mut db_guard, args[1]) {
+        Ok(Some(hash)) => hash,
+        Ok(None) if count.is_some() => return RespMessage::Array(vec![]),
+        Ok(None) => return RespMessage::BulkString(None),
+        Err(e) => return e,
+    };
+    let Some(count) = count else {
+        let (field, _) = hash.random().expect("collections are never empty");
+        return bulk(field);
+    };
+    let picked = pick_random(
+        hash.len(),
+        count,
+        || hash.random().expect("collections are never empty"),
+        || hash.iter().collect(),
+    );
+
+    let mut reply = Vec::new();
+    for (field, value) in picked {
+        reply.push(bulk(field));
+        if with_values {
+            reply.push(bulk(value));
+        }
+    }
+    RespMessage::Array(reply)
+}
+
+/// HSCAN key cursor [MATCH pattern] [COUNT count] [NOVALUES]
+pub async fn hscan(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() < 3 {
+        return wrong_arity(args[0]);
+    }
+    let options = match parse_scan_options(&args[2..], &[b"NOVALUES"]) {
+        Ok(options) => options,
+        Err(e) => return e,
+    };
+    let mut db_guard = db.lock().await;
+    let hash = match get_hash(&mut db_guard, args[1]) {
+        Ok(Some(hash)) => hash,
+        Ok(None) => return scan_reply(0, vec![]),
+        Err(e) => return e,
+    };
+
+    let (cursor, page) = hash.scan(options.cursor, options.count);
+    let mut elements = Vec::new();
+    for (field, value) in page {
+        if options.matches(field) {
+            elements.push(bulk(field));
+            if !options.no_values {
+                elements.push(bulk(value));
+            }
+        }
+    }
+    scan_reply(cursor, elements)
+}
@@ -0,0 +1,193 @@
+use crate::handler::blocking::serve_blocked;
+use crate::handler::client_handler::Db;
+use crate::handler::commands::{bulk, syntax_error, wrong_arity};
+use crate::handler::databases::Session;
+use crate::handler::scan::{glob_match, parse_scan_options, scan_reply};
+use crate::resp::resp_protocol::RespMessage;
+
+fn no_such_key() -> RespMessage {
+    RespMessage::Error("ERR no such key".to_string())
+}
+
+/// EXISTS key [key ...] (also TOUCH)
+/// A key named twice is counted twice.
+pub async fn exists(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() < 2 {
+        return wrong_arity(args[0]);
+    }
+    let mut db_guard = db.lock().await;
+    let count = args[1..]
+        .iter()
+        .filter(|key| db_guard.lookup_read(key).is_some())
+        .count();
+    RespMessage::Integer(count as i64)
+}
+
+/// DEL key [key ...] (also UNLINK)
+pub async fn del(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() < 2 {
+        return wrong_arity(args[0]);
+    }
+    let mut db_guard = db.lock().await;
+    // A key whose TTL has passed was already gone, so it isn't counted.
+    let count = args[1..]
+        .iter()
+        .filter(|key| db_guard.delete(key).is_some())
+        .count();
+    db_guard.dirty += count as u64;
+    RespMessage::Integer(count as i64)
+}
+
+/// TYPE key
+pub async fn key_type(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() != 2 {
+        return wrong_arity(args[0]);
+    }
+    let mut db_guard = db.lock().await;
+    let name = db_guard
+        .lookup_read(args[1])
+        .map_or("none", |entry| entry.value.type_name());
+    RespMessage::SimpleString(name.to_string())
+}
+
+/// KEYS pattern
+pub async fn keys(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() != 2 {
+        return wrong_arity(args[0]);
+    }
+    let db_guard = db.lock().await;
+    RespMessage::Array(
+        db_guard
+            .live_entries()
+            .filter(|(key, _)| glob_match(args[1], key))
+            .map(|(key, _)| bulk(key))
+            .collect(),
+    )
+}
+
+/// SCAN cursor [MATCH pattern] [COUNT count] [TYPE type]
+pub async fn scan(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() < 2 {
+        return wrong_arity(args[0]);
+    }
+    let options = match parse_scan_options(&args[1..], &[b"TYPE"]) {
+        Ok(options) => options,
+        Err(e) => return e,
+    };
+    let db_guard = db.lock().await;
+    let (cursor, page) = db_guard.entries.scan(options.cursor, options.count);
+    let elements = page
+        .into_iter()
+        .filter(|(key, entry)| {
+            !db_guard.is_expired(entry)
+                && options.matches(key)
+                && options
+                    .type_name
+                    .as_deref()
+                    .is_none_or(|type_name| type_name == entry.value.type_name().as_bytes())
+        })
+        .map(|(key, _)| bulk(key))
+        .collect();
+    scan_reply(cursor, elements)
+}
+
+/// RANDOMKEY
+pub async fn randomkey(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() != 1 {
+        return wrong_arity(args[0]);
+    }
+    let mut db_guard = db.lock().await;
+    // Expired keys that come up are dropped, so this ends once a live one does
+    // or none are left.
+    loop {
+        let Some((key, entry)) = db_guard.entries.random() else {
+            return RespMessage::BulkString(None);
+        };
+        if !db_guard.is_expired(entry) {
+            return bulk(key);
+        }
+        let key = key.clone();
+        db_guard.expire_if_needed(&key);
+    }
+}
+
+/// DBSIZE
+pub async fn dbsize(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() != 1 {
+        return wrong_arity(args[0]);
+    }
+    RespMessage::Integer(db.lock().await.live_entries().count() as i64)
+}
+
+/// RENAME key newkey
+/// With `only_new` set this is RENAMENX, which leaves an existing `newkey` alone.
+pub async fn rename(args: &[&[u8]], db: &Db, only_new: bool) -> RespMessage {
+    if args.len() != 3 {
+        return wrong_arity(args[0]);
+    }
+    let (source, destination) = (args[1], args[2]);
+    let mut db_guard = db.lock().await;
+    if db_guard.lookup_read(source).is_none() {
+        return no_such_key();
+    }
+    if only_new && db_guard.lookup_read(destination).is_some() {
+        return RespMessage::Integer(0);
+    }
+
+    if source != destination {
+        if let Some(entry) = db_guard.delete(source) {
+            db_guard.insert(destination.to_vec(), entry);
+            serve_blocked(&mut db_guard, destination);
+        }
+    }
+    db_guard.dirty += 1;
+    if only_new {
+        RespMessage::Integer(1)
+    } else {
+        RespMessage::SimpleString("OK".to_string())
+    }
+}
+
+/// COPY source destination [DB destination-db] [REPLACE]
+pub async fn copy(args: &[&[u8]], session: &Session) -> RespMessage {
+    if args.len() < 3 {
+        return wrong_arity(args[0]);
+    }
+    let (source, destination) = (args[1], args[2]);
+    let mut target = session.selected;
+    let mut replace = false;
+    let mut i = 3;
+    while i < args.len() {
+        match (args[i].to_ascii_uppercase().as_slice(), args.get(i + 1)) {
+            (b"REPLACE", _) => {
+                replace = true;
+                i += 1;
+            }
+            (b"DB", Some(index)) => {
+                target = match session.databases.parse_index(index) {
+                    Ok(target) => target,
+                    Err(e) => return e,
+                };
+                i += 2;
+            }
+            _ => return syntax_error(),
+        }
+    }
+    if target == session.selected && source == destination {
+        return RespMessage::Error("ERR source and destination objects are the same".to_string());
+    }
+
+    let (mut source_db, mut target_db) =
+        session.databases.lock_pair(session.selected, target).await;
+    let Some(entry) = source_db.lookup_read(source).cloned() else {
+        return RespMessage::Integer(0);
+    };
+    let target_db = target_db.as_deref_mut().unwrap_or(&mut source_db);
+    if !replace && target_db.lookup_read(destination).is_some() {
+        return RespMessage::Integer(0);
+    }
+    target_db.insert(destination.to_vec(), entry);
+    target_db.dirty += 1;
+    serve_blocked(target_db, destination);
+    RespMessage::Integer(1)
+}
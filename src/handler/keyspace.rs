@@ -0,0 +1,166 @@
+use crate::handler::active_expire::{ExpireStats, VolatileKeys};
+use crate::handler::aof::Propagation;
+use crate::handler::blocking::{Blocked, BlockedClients, BlockingOp};
+use crate::handler::clock::{Clock, SystemClock};
+use crate::handler::value::{Dict, Value, ValueWithExpiry};
+use std::sync::Arc;
+
+/// Everything guarded by the shared `Db` lock: the stored keys and the clients
+/// blocked waiting for some of them. Keeping both behind one lock lets a write
+/// hand its data to a blocked client atomically.
+///
+/// Commands go through the lookup methods below rather than `entries` directly, so
+/// a key whose TTL has passed is treated as missing (and dropped) everywhere alike.
+pub struct Keyspace {
+    pub entries: Dict<Vec<u8>, ValueWithExpiry>,
+    pub blocked: BlockedClients,
+    /// Keys given a TTL, for the active expire cycle to sample.
+    pub volatile: VolatileKeys,
+    pub expire_stats: ExpireStats,
+    /// Keys changed since the count was last taken, like Redis's `server.dirty`.
+    /// Each write command adds what it actually changed: a DEL of a missing key
+    /// adds nothing and an MSET of ten keys adds ten.
+    pub dirty: u64,
+    /// Where expirations and served blocked clients are recorded for the
+    /// append-only file, when it's on.
+    pub propagation: Option<Propagation>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for Keyspace {
+    fn default() -> Self {
+        Keyspace::with_clock(Arc::new(SystemClock))
+    }
+}
+
+impl Keyspace {
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Keyspace {
+        Keyspace {
+            entries: Dict::new(),
+            blocked: BlockedClients::default(),
+            volatile: VolatileKeys::default(),
+            expire_stats: ExpireStats::default(),
+            dirty: 0,
+            propagation: None,
+            clock,
+        }
+    }
+
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    /// The current time, in Unix milliseconds, according to the keyspace's clock.
+    pub fn now(&self) -> u128 {
+        self.clock.now_millis()
+    }
+
+    pub fn is_expired(&self, entry: &ValueWithExpiry) -> bool {
+        entry.expiry.is_some_and(|expiry| self.now() >= expiry)
+    }
+
+    /// Drops `key` if its TTL has passed. Returns whether it did.
+    pub fn expire_if_needed(&mut self, key: &[u8]) -> bool {
+        let expired = self
+            .entries
+            .get(key)
+            .is_some_and(|entry| self.is_expired(entry));
+        if expired {
+            self.entries.remove(key);
+            self.dirty += 1;
+            self.expire_stats.expired_keys += 1;
+            if let Some(propagation) = &self.propagation {
+                propagation.expired(key);
+            }
+        }
+        expired
+    }
+
+    /// Every live entry, skipping keys whose TTL has passed but that haven't been
+    /// dropped yet.
+    pub fn live_entries(&self) -> impl Iterator<Item = (&Vec<u8>, &ValueWithExpiry)> {
+        self.entries
+            .iter()
+            .filter(|(_, entry)| !self.is_expired(entry))
+    }
+
+    /// The live entry at `key`, or `None` if it is missing or expired.
+    pub fn lookup_read(&mut self, key: &[u8]) -> Option<&ValueWithExpiry> {
+        self.expire_if_needed(key);
+        self.entries.get(key)
+    }
+
+    /// Like `lookup_read`, for commands that modify the entry in place.
+    pub fn lookup_write(&mut self, key: &[u8]) -> Option<&mut ValueWithExpiry> {
+        self.expire_if_needed(key);
+        self.entries.get_mut(key)
+    }
+
+    /// The live entry at `key`, first storing `create()` without a TTL if the key is
+    /// missing or expired.
+    pub fn lookup_or_insert(
+        &mut self,
+        key: &[u8],
+        create: impl FnOnce() -> Value,
+    ) -> &mut ValueWithExpiry {
+        self.expire_if_needed(key);
+        self.entries
+            .get_or_insert_with(key.to_vec(), || ValueWithExpiry {
+                value: create(),
+                expiry: None,
+            })
+    }
+
+    /// Stores `entry` at `key`, replacing any previous value and TTL.
+    pub fn insert(&mut self, key: Vec<u8>, entry: ValueWithExpiry) {
+        if entry.expiry.is_some() {
+            self.volatile.insert(&key);
+        }
+        self.entries.insert(key, entry);
+    }
+
+    /// Sets or clears the TTL of the live key `key`. Returns whether the key exists.
+    pub fn set_expiry(&mut self, key: &[u8], expiry: Option<u128>) -> bool {
+        let Some(entry) = self.lookup_write(key) else {
+            return false;
+        };
+        entry.expiry = expiry;
+        if expiry.is_some() {
+            self.volatile.insert(key);
+        }
+        true
+    }
+
+    /// Removes `key`, returning its entry. An expired key counts as already gone.
+    pub fn delete(&mut self, key: &[u8]) -> Option<ValueWithExpiry> {
+        self.expire_if_needed(key);
+        self.entries.remove(key)
+    }
+
+    /// Parks the running command's client until one of `keys` can serve `op`.
+    pub fn block(&mut self, keys: Vec<Vec<u8>>, op: BlockingOp) -> Blocked {
+        if let Some(propagation) = &self.propagation {
+            propagation.parked();
+        }
+        self.blocked.block(keys, op)
+    }
+
+    /// Exchanges all keys, and their TTLs, with `other`. Blocked clients stay
+    /// where they are.
+    pub fn swap_data(&mut self, other: &mut Keyspace) {
+        std::mem::swap(&mut self.entries, &mut other.entries);
+        std::mem::swap(&mut self.volatile, &mut other.volatile);
+    }
+
+    /// The changes counted since the last call, for the save points.
+    pub fn take_dirty(&mut self) -> u64 {
+        std::mem::take(&mut self.dirty)
+    }
+
+    /// Removes every key, handing the old entries back so the caller decides
+    /// where to free them.
+    pub fn take_entries(&mut self) -> Dict<Vec<u8>, ValueWithExpiry> {
+        self.volatile = VolatileKeys::default();
+        std::mem::take(&mut self.entries)
+    }
+}
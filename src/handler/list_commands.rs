@@ -0,0 +1,567 @@
+use crate::handler::blocking::{parse_timeout, serve_blocked, wait_until_served, BlockingOp};
+use crate::handler::client_handler::Db;
+use crate::handler::commands::{parse_i64, remove_if_empty, syntax_error, wrong_arity, wrong_type};
+use crate::handler::keyspace::Keyspace;
+use crate::handler::value::Value;
+use crate::resp::resp_protocol::RespMessage;
+use std::collections::VecDeque;
+
+/// Which end of a list an operation works on.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ListEnd {
+    Left,
+    Right,
+}
+
+/// Looks up the live list stored at `key`.
+/// Returns `Ok(None)` for a missing key and a WRONGTYPE error for any other type.
+pub fn get_list<'a>(
+    keyspace: &'a mut Keyspace,
+    key: &[u8],
+) -> Result<Option<&'a mut VecDeque<Vec<u8>>>, RespMessage> {
+    match keyspace.lookup_write(key).map(|v| &mut v.value) {
+        Some(Value::List(list)) => Ok(Some(list)),
+        Some(_) => Err(wrong_type()),
+        None => Ok(None),
+    }
+}
+
+/// Like `get_list`, but creates an empty list when the key is missing.
+pub fn get_or_create_list<'a>(
+    keyspace: &'a mut Keyspace,
+    key: &[u8],
+) -> Result<&'a mut VecDeque<Vec<u8>>, RespMessage> {
+    let entry = keyspace.lookup_or_insert(key, || Value::List(VecDeque::new()));
+    match &mut entry.value {
+        Value::List(list) => Ok(list),
+        _ => Err(wrong_type()),
+    }
+}
+
+/// Maps a possibly negative index onto `0..len`.
+fn resolve_index(index: i64, len: usize) -> Option<usize> {
+    let index = if index < 0 { len as i64 + index } else { index };
+    (0..len as i64).contains(&index).then_some(index as usize)
+}
+
+/// Maps an inclusive `start..=stop` range with Redis's negative-index and
+/// clamping rules onto `0..len`, or `None` when the range is empty.
+pub fn resolve_range(start: i64, stop: i64, len: usize) -> Option<(usize, usize)> {
+    let len = len as i64;
+    let start = if start < 0 {
+        (len + start).max(0)
+    } else {
+        start
+    };
+    let stop = if stop < 0 {
+        len + stop
+    } else {
+        stop.min(len - 1)
+    };
+    (start <= stop && start < len).then_some((start as usize, stop as usize))
+}
+
+/// LPUSH / RPUSH key element [element ...]
+/// With `only_existing` set this is LPUSHX / RPUSHX, which never create the key.
+pub async fn push(args: &[&[u8]], db: &Db, end: ListEnd, only_existing: bool) -> RespMessage {
+    if args.len() < 3 {
+        return wrong_arity(args[0]);
+    }
+    let key = args[1];
+    let mut db_guard = db.lock().await;
+
+    let list = match get_list(&mut db_guard, key) {
+        Ok(Some(list)) => list,
+        Ok(None) if only_existing => return RespMessage::Integer(0),
+        Ok(None) => match get_or_create_list(&mut db_guard, key) {
+            Ok(list) => list,
+            Err(e) => return e,
+        },
+        Err(e) => return e,
+    };
+
+    for item in &args[2..] {
+        match end {
+            ListEnd::Left => list.push_front(item.to_vec()),
+            ListEnd::Right => list.push_back(item.to_vec()),
+        }
+    }
+    let len = list.len();
+    db_guard.dirty += (args.len() - 2) as u64;
+    serve_blocked(&mut db_guard, key);
+    RespMessage::Integer(len as i64)
+}
+
+/// LPOP / RPOP key [count]
+pub async fn pop(args: &[&[u8]], db: &Db, end: ListEnd) -> RespMessage {
+    if args.len() != 2 && args.len() != 3 {
+        return wrong_arity(args[0]);
+    }
+    let key = args[1];
+    let count = match args.get(2).map(|arg| parse_i64(arg)) {
+        None => None,
+        Some(Ok(count)) if count >= 0 => Some(count as usize),
+        Some(Ok(_)) => {
+            return RespMessage::Error("ERR value is out of range, must be positive".to_string())
+        }
+        Some(Err(e)) => return e,
+    };
+    let mut db_guard = db.lock().await;
+
+    let list = match get_list(&mut db_guard, key) {
+        Ok(Some(list)) => list,
+        Ok(None) if count.is_some() => return RespMessage::NullArray,
+        Ok(None) => return RespMessage::BulkString(None),
+        Err(e) => return e,
+    };
+
+    let mut pop_one = || match end {
+        ListEnd::Left => list.pop_front(),
+        ListEnd::Right => list.pop_back(),
+    };
+    let (reply, popped) = match count {
+        None => {
+            let item = pop_one();
+            let popped = item.is_some() as u64;
+            (RespMessage::BulkString(item), popped)
+        }
+        Some(count) => {
+            let items: Vec<RespMessage> = std::iter::from_fn(pop_one)
+                .take(count)
+                .map(|item| RespMessage::BulkString(Some(item)))
+                .collect();
+            let popped = items.len() as u64;
+            (RespMessage::Array(items), popped)
+        }
+    };
+    db_guard.dirty += popped;
+    remove_if_empty(&mut db_guard, key);
+    reply
+}
+
+/// LLEN key
+pub async fn llen(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() != 2 {
+        return wrong_arity(args[0]);
+    }
+    let mut db_guard = db.lock().await;
+    match get_list(&mut db_guard, args[1]) {
+        Ok(list) => RespMessage::Integer(list.map_or(0, |list| list.len() as i64)),
+        Err(e) => e,
+    }
+}
+
+/// LRANGE key start stop
+pub async fn lrange(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() != 4 {
+        return wrong_arity(args[0]);
+    }
+    let (start, stop) = match (parse_i64(args[2]), parse_i64(args[3])) {
+        (Ok(start), Ok(stop)) => (start, stop),
+        (Err(e), _) | (_, Err(e)) => return e,
+    };
+    let mut db_guard = db.lock().await;
+
+    match get_list(&mut db_guard, args[1]) {
+        Ok(Some(list)) => match resolve_range(start, stop, list.len()) {
+            Some((start, stop)) => RespMessage::Array(
+                list.range(start..=stop)
+                    .map(|item| RespMessage::BulkString(Some(item.clone())))
+                    .collect(),
+            ),
+            None => RespMessage::Array(vec![]),
+        },
+        Ok(None) => RespMessage::Array(vec![]),
+        Err(e) => e,
+    }
+}
+
+/// LINDEX key index
+pub async fn lindex(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() != 3 {
+        return wrong_arity(args[0]);
+    }
+    let index = match parse_i64(args[2]) {
+        Ok(index) => index,
+        Err(e) => return e,
+    };
+    let mut db_guard = db.lock().await;
+
+    match get_list(&mut db_guard, args[1]) {
+        Ok(Some(list)) => RespMessage::BulkString(
+            resolve_index(index, list.len()).map(|index| list[index].clone()),
+        ),
+        Ok(None) => RespMessage::BulkString(None),
+        Err(e) => e,
+    }
+}
+
+/// LSET key index element
+pub async fn lset(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() != 4 {
+        return wrong_arity(args[0]);
+    }
+    let index = match parse_i64(args[2]) {
+        Ok(index) => index,
+        Err(e) => return e,
+    };
+    let mut db_guard = db.lock().await;
+
+    match get_list(&mut db_guard, args[1]) {
+        Ok(Some(list)) => match resolve_index(index, list.len()) {
+            Some(index) => {
+                list[index] = args[3].to_vec();
+                db_guard.dirty += 1;
+                RespMessage::SimpleString("OK".to_string())
+            }
+            None => RespMessage::Error("ERR index out of range".to_string()),
+        },
+        Ok(None) => RespMessage::Error("ERR no such key".to_string()),
+        Err(e) => e,
+    }
+}
+
+/// LREM key count element
+/// A positive count removes from the head, a negative one from the tail, zero removes all.
+pub async fn lrem(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() != 4 {
+        return wrong_arity(args[0]);
+    }
+    let count = match parse_i64(args[2]) {
+        Ok(count) => count,
+        Err(e) => return e,
+    };
+    let key = args[1];
+    let element = args[3];
+    let mut db_guard = db.lock().await;
+
+    let list = match get_list(&mut db_guard, key) {
+        Ok(Some(list)) => list,
+        Ok(None) => return RespMessage::Integer(0),
+        Err(e) => return e,
+    };
+
+    let limit = if count == 0 {
+        usize::MAX
+    } else {
+        count.unsigned_abs() as usize
+    };
+    // From the tail, the matches to keep are the ones before the last `limit`.
+    let skip = if count >= 0 {
+        0
+    } else {
+        let matches = list.iter().filter(|item| *item == element).count();
+        matches.saturating_sub(limit)
+    };
+    let mut seen = 0;
+    let before = list.len();
+    list.retain(|item| {
+        if item != element {
+            return true;
+        }
+        seen += 1;
+        seen <= skip || seen > skip + limit
+    });
+    let removed = before - list.len();
+
+    db_guard.dirty += removed as u64;
+    remove_if_empty(&mut db_guard, key);
+    RespMessage::Integer(removed as i64)
+}
+
+/// LTRIM key start stop
+pub async fn ltrim(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() != 4 {
+        return wrong_arity(args[0]);
+    }
+    let (start, stop) = match (parse_i64(args[2]), parse_i64(args[3])) {
+        (Ok(start), Ok(stop)) => (start, stop),
+        (Err(e), _) | (_, Err(e)) => return e,
+    };
+    let key = args[1];
+    let mut db_guard = db.lock().await;
+
+    let removed = match get_list(&mut db_guard, key) {
+        Ok(Some(list)) => {
+            let len = list.len();
+            match resolve_range(start, stop, len) {
+                Some((start, stop)) => {
+                    list.truncate(stop + 1);
+                    list.drain(..start);
+                }
+                None => list.clear(),
+            }
+            len - list.len()
+        }
+        Ok(None) => 0,
+        Err(e) => return e,
+    };
+    db_guard.dirty += removed as u64;
+
+    remove_if_empty(&mut db_guard, key);
+    RespMessage::SimpleString("OK".to_string())
+}
+
+/// LINSERT key BEFORE|AFTER pivot element
+pub async fn linsert(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() != 5 {
+        return wrong_arity(args[0]);
+    }
+    let after = match args[2].to_ascii_uppercase().as_slice() {
+        b"BEFORE" => false,
+        b"AFTER" => true,
+        _ => return syntax_error(),
+    };
+    let mut db_guard = db.lock().await;
+
+    match get_list(&mut db_guard, args[1]) {
+        Ok(Some(list)) => match list.iter().position(|item| item == args[3]) {
+            Some(pos) => {
+                list.insert(pos + after as usize, args[4].to_vec());
+                let len = list.len();
+                db_guard.dirty += 1;
+                RespMessage::Integer(len as i64)
+            }
+            None => RespMessage::Integer(-1),
+        },
+        Ok(None) => RespMessage::Integer(0),
+        Err(e) => e,
+    }
+}
+
+/// LPOS key element [RANK rank] [COUNT num-matches] [MAXLEN len]
+pub async fn lpos(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() < 3 {
+        return wrong_arity(args[0]);
+    }
+    let element = args[2];
+    let mut rank: i64 = 1;
+    let mut count: Option<usize> = None;
+    let mut maxlen: usize = 0;
+
+    let mut i = 3;
+    while i < args.len() {
+        let Some(value) = args.get(i + 1) else {
+            return syntax_error();
+        };
+        let value = match parse_i64(value) {
+            Ok(value) => value,
+            Err(e) => return e,
+        };
+        match args[i].to_ascii_uppercase().as_slice() {
+            b"RANK" => {
+                if value == 0 {
+                    return RespMessage::Error(
+                        "ERR RANK can't be zero: use 1 to start from the first match, 2 from the second ... or use negative to start from the end of the list".to_string(),
+                    );
+                }
+                if value == i64::MIN {
+                    return RespMessage::Error(
+                        "ERR value is out of range, value must between -9223372036854775807 and 9223372036854775807".to_string(),
+                    );
+                }
+                rank = value;
+            }
+            b"COUNT" => {
+                if value < 0 {
+                    return RespMessage::Error("ERR COUNT can't be negative".to_string());
+                }
+                count = Some(value as usize);
+            }
+            b"MAXLEN" => {
+                if value < 0 {
+                    return RespMessage::Error("ERR MAXLEN can't be negative".to_string());
+                }
+                maxlen = value as usize;
+            }
+            _ => return syntax_error(),
+        }
+        i += 2;
+    }
+
+    let mut db_guard = db.lock().await;
+    let list = match get_list(&mut db_guard, args[1]) {
+        Ok(Some(list)) => list,
+        Ok(None) if count.is_some() => return RespMessage::Array(vec![]),
+        Ok(None) => return RespMessage::BulkString(None),
+        Err(e) => return e,
+    };
+
+    // Scan from the head for a positive rank and from the tail for a negative one,
+    // skipping the first |rank| - 1 matches. MAXLEN caps the number of comparisons.
+    let len = list.len();
+    let scanned = if maxlen == 0 { len } else { maxlen.min(len) };
+    let indices: Box<dyn Iterator<Item = usize>> = if rank > 0 {
+        Box::new(0..scanned)
+    } else {
+        Box::new((len - scanned..len).rev())
+    };
+    let wanted = match count {
+        Some(0) => usize::MAX,
+        Some(count) => count,
+        None => 1,
+    };
+    let matches: Vec<usize> = indices
+        .filter(|&index| list[index] == element)
+        .skip(rank.unsigned_abs() as usize - 1)
+        .take(wanted)
+        .collect();
+
+    match count {
+        Some(_) => RespMessage::Array(
+            matches
+                .into_iter()
+                .map(|index| RespMessage::Integer(index as i64))
+                .collect(),
+        ),
+        None => matches
+            .first()
+            .map_or(RespMessage::BulkString(None), |&index| {
+                RespMessage::Integer(index as i64)
+            }),
+    }
+}
+
+fn parse_list_end(arg: &[u8]) -> Option<ListEnd> {
+    match arg.to_ascii_uppercase().as_slice() {
+        b"LEFT" => Some(ListEnd::Left),
+        b"RIGHT" => Some(ListEnd::Right),
+        _ => None,
+    }
+}
+
+/// Pops an element from `source` at `from` and pushes it onto `destination` at `to`.
+/// Returns `Ok(None)` when the source list does not exist.
+pub fn move_element(
+    keyspace: &mut Keyspace,
+    source: &[u8],
+    destination: &[u8],
+    from: ListEnd,
+    to: ListEnd,
+) -> Result<Option<Vec<u8>>, RespMessage> {
+    if get_list(keyspace, source)?.is_none() {
+        return Ok(None);
+    }
+    // Check the destination before popping so a type error leaves the source untouched.
+    get_list(keyspace, destination)?;
+
+    let Some(list) = get_list(keyspace, source)? else {
+        return Ok(None);
+    };
+    let item = match from {
+        ListEnd::Left => list.pop_front(),
+        ListEnd::Right => list.pop_back(),
+    };
+    let Some(item) = item else {
+        return Ok(None);
+    };
+
+    let list = get_or_create_list(keyspace, destination)?;
+    match to {
+        ListEnd::Left => list.push_front(item.clone()),
+        ListEnd::Right => list.push_back(item.clone()),
+    }
+    remove_if_empty(keyspace, source);
+    Ok(Some(item))
+}
+
+/// LMOVE source destination LEFT|RIGHT LEFT|RIGHT
+pub async fn lmove(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() != 5 {
+        return wrong_arity(args[0]);
+    }
+    let (Some(from), Some(to)) = (parse_list_end(args[3]), parse_list_end(args[4])) else {
+        return syntax_error();
+    };
+    let mut db_guard = db.lock().await;
+
+    match move_element(&mut db_guard, args[1], args[2], from, to) {
+        Ok(item) => {
+            db_guard.dirty += item.is_some() as u64;
+            serve_blocked(&mut db_guard, args[2]);
+            RespMessage::BulkString(item)
+        }
+        Err(e) => e,
+    }
+}
+
+/// BLPOP / BRPOP key [key ...] timeout
+pub async fn blocking_pop(args: &[&[u8]], db: &Db, end: ListEnd) -> RespMessage {
+    if args.len() < 3 {
+        return wrong_arity(args[0]);
+    }
+    let timeout = match parse_timeout(args[args.len() - 1]) {
+        Ok(timeout) => timeout,
+        Err(e) => return e,
+    };
+    let keys = &args[1..args.len() - 1];
+
+    let blocked = {
+        let mut db_guard = db.lock().await;
+        for key in keys {
+            match get_list(&mut db_guard, key) {
+                Ok(Some(list)) => {
+                    let item = match end {
+                        ListEnd::Left => list.pop_front(),
+                        ListEnd::Right => list.pop_back(),
+                    };
+                    remove_if_empty(&mut db_guard, key);
+                    db_guard.dirty += 1;
+                    return RespMessage::Array(vec![
+                        RespMessage::BulkString(Some(key.to_vec())),
+                        RespMessage::BulkString(item),
+                    ]);
+                }
+                Ok(None) => {}
+                Err(e) => return e,
+            }
+        }
+        db_guard.block(
+            keys.iter().map(|key| key.to_vec()).collect(),
+            BlockingOp::Pop(end),
+        )
+    };
+
+    wait_until_served(db, blocked, timeout)
+        .await
+        .unwrap_or(RespMessage::NullArray)
+}
+
+/// BLMOVE source destination LEFT|RIGHT LEFT|RIGHT timeout
+pub async fn blmove(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() != 6 {
+        return wrong_arity(args[0]);
+    }
+    let (Some(from), Some(to)) = (parse_list_end(args[3]), parse_list_end(args[4])) else {
+        return syntax_error();
+    };
+    let timeout = match parse_timeout(args[5]) {
+        Ok(timeout) => timeout,
+        Err(e) => return e,
+    };
+
+    let blocked = {
+        let mut db_guard = db.lock().await;
+        match move_element(&mut db_guard, args[1], args[2], from, to) {
+            Ok(Some(item)) => {
+                db_guard.dirty += 1;
+                serve_blocked(&mut db_guard, args[2]);
+                return RespMessage::BulkString(Some(item));
+            }
+            Ok(None) => {}
+            Err(e) => return e,
+        }
+        db_guard.block(
+            vec![args[1].to_vec()],
+            BlockingOp::Move {
+                from,
+                to,
+                destination: args[2].to_vec(),
+            },
+        )
+    };
+
+    wait_until_served(db, blocked, timeout)
+        .await
+        .unwrap_or(RespMessage::BulkString(None))
+}
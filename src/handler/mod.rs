@@ -1,4 +1,28 @@
+pub mod active_expire;
+pub mod aof;
+pub mod blocking;
 pub mod client_handler;
+pub mod clock;
 pub mod commands;
-pub mod handle_tests;
+#[cfg(test)]
+mod commands_tests;
+pub mod database_commands;
+pub mod databases;
+pub mod dump_commands;
+pub mod expire_commands;
+#[cfg(test)]
+mod handle_tests;
+pub mod hash_commands;
+pub mod key_commands;
+pub mod keyspace;
+pub mod list_commands;
+pub mod persistence;
+pub mod rdb;
+#[cfg(test)]
+mod rdb_tests;
+pub mod scan;
+pub mod set_commands;
+pub mod stream_commands;
+pub mod string_commands;
 pub mod value;
+pub mod zset_commands;
@@ -0,0 +1,333 @@
+use crate::config::{SavePoint, SnapshotFormat};
+use crate::handler::commands::now_millis;
+use crate::handler::databases::Databases;
+use crate::handler::keyspace::Keyspace;
+use crate::handler::rdb::{decode_rdb, encode_rdb};
+use crate::handler::value::ValueWithExpiry;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+/*
+The snapshot file SAVE writes and the server loads on startup.
+
+It is JSON by default. Keys are raw bytes and JSON objects only allow string keys,
+so each non-empty database is written as `[index, [[key, value], ...]]`. It can be
+Redis's RDB format instead (see `rdb`), to move data between xredis and Redis.
+
+A snapshot is written to a temporary file next to the real one, fsynced, and then
+renamed over it. A crash or a full disk mid-write leaves the previous snapshot
+untouched; the rename either happened or it didn't.
+
+SAVE writes while holding every database lock. BGSAVE only holds them long enough
+to clone the live keys, then serializes and writes the copy on a blocking thread
+while clients carry on. Save points start a BGSAVE on their own once enough writes
+have piled up for long enough.
+*/
+
+/// How long to wait after a failed save before a save point tries again (Redis's
+/// `CONFIG_BGSAVE_RETRY_DELAY`).
+const SAVE_RETRY_DELAY_SECS: u64 = 5;
+
+/// A database's index and its `(key, entry)` pairs, as read back from the file.
+pub type SavedDatabase = (usize, Vec<(Vec<u8>, ValueWithExpiry)>);
+
+#[derive(Debug, thiserror::Error)]
+pub enum SnapshotError {
+    #[error("can't read the snapshot: {0}")]
+    Io(#[from] io::Error),
+    #[error("corrupt snapshot: {0}")]
+    Corrupt(String),
+}
+
+impl From<serde_json::Error> for SnapshotError {
+    fn from(e: serde_json::Error) -> Self {
+        SnapshotError::Corrupt(e.to_string())
+    }
+}
+
+/// What loading a snapshot found.
+#[derive(Debug, Default, PartialEq)]
+pub struct LoadedSnapshot {
+    pub keys: usize,
+    /// Keys left out because their TTL passed while the server was down.
+    pub expired: usize,
+}
+
+/// Where a file bound for `path` is written before being renamed into place.
+pub fn temp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".tmp-{}", std::process::id()));
+    path.with_file_name(name)
+}
+
+/// Makes a rename in the directory holding `path` durable.
+#[cfg(unix)]
+pub fn sync_parent_dir(path: &Path) -> io::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir)?.sync_all()
+}
+
+#[cfg(not(unix))]
+pub fn sync_parent_dir(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// Replaces `path` with `contents`, only once they are safely on disk.
+pub fn write_file_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let temp = temp_path(path);
+    let written = File::create(&temp)
+        .and_then(|mut file| {
+            file.write_all(contents)?;
+            file.sync_all()
+        })
+        .and_then(|()| fs::rename(&temp, path));
+    if written.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    written?;
+    sync_parent_dir(path)
+}
+
+/// A database's index and its `(key, entry)` pairs, borrowed for writing.
+type DatabaseRef<'a> = (usize, Vec<(&'a Vec<u8>, &'a ValueWithExpiry)>);
+
+/// Writes `snapshot` in `format` to `path`.
+fn write_databases(
+    path: &Path,
+    format: SnapshotFormat,
+    snapshot: Vec<DatabaseRef<'_>>,
+) -> io::Result<()> {
+    let contents = match format {
+        SnapshotFormat::Json => serde_json::to_vec(&snapshot).map_err(io::Error::other)?,
+        SnapshotFormat::Rdb => encode_rdb(snapshot)?,
+    };
+    write_file_atomically(path, &contents)
+}
+
+fn borrow_saved(snapshot: &[SavedDatabase]) -> Vec<DatabaseRef<'_>> {
+    snapshot
+        .iter()
+        .map(|(index, entries)| (*index, entries.iter().map(|(k, e)| (k, e)).collect()))
+        .collect()
+}
+
+/// Writes every live key of `keyspaces`, numbered in order, to `path`.
+pub fn write_snapshot<'a>(
+    path: &Path,
+    format: SnapshotFormat,
+    keyspaces: impl IntoIterator<Item = &'a Keyspace>,
+) -> io::Result<()> {
+    let snapshot: Vec<_> = keyspaces
+        .into_iter()
+        .enumerate()
+        .map(|(index, keyspace)| (index, keyspace.live_entries().collect::<Vec<_>>()))
+        .filter(|(_, entries)| !entries.is_empty())
+        .collect();
+    write_databases(path, format, snapshot)
+}
+
+/// A copy of every live key of `keyspaces`, for writing once the locks are gone.
+pub fn capture<'a>(keyspaces: impl IntoIterator<Item = &'a Keyspace>) -> Vec<SavedDatabase> {
+    keyspaces
+        .into_iter()
+        .enumerate()
+        .map(|(index, keyspace)| {
+            let entries = keyspace
+                .live_entries()
+                .map(|(key, entry)| (key.clone(), entry.clone()))
+                .collect::<Vec<_>>();
+            (index, entries)
+        })
+        .filter(|(_, entries)| !entries.is_empty())
+        .collect()
+}
+
+/// The server's snapshot file and the state of saving it, shared by every client.
+pub struct Snapshotter {
+    path: PathBuf,
+    format: SnapshotFormat,
+    /// Unix time, in seconds, of the last successful save (startup until then).
+    last_save: AtomicU64,
+    /// Unix time, in seconds, the last save was started.
+    last_attempt: AtomicU64,
+    last_save_ok: AtomicBool,
+    /// Keys changed since the last successful save.
+    dirty: AtomicU64,
+    bgsave_in_progress: AtomicBool,
+}
+
+impl Snapshotter {
+    pub fn new(path: impl Into<PathBuf>, format: SnapshotFormat) -> Snapshotter {
+        Snapshotter {
+            path: path.into(),
+            format,
+            last_save: AtomicU64::new(unix_seconds()),
+            last_attempt: AtomicU64::new(0),
+            last_save_ok: AtomicBool::new(true),
+            dirty: AtomicU64::new(0),
+            bgsave_in_progress: AtomicBool::new(false),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn format(&self) -> SnapshotFormat {
+        self.format
+    }
+
+    /// Unix time, in seconds, of the last successful save.
+    pub fn last_save(&self) -> u64 {
+        self.last_save.load(Ordering::SeqCst)
+    }
+
+    /// Whether the most recent save, foreground or background, succeeded.
+    pub fn last_save_ok(&self) -> bool {
+        self.last_save_ok.load(Ordering::SeqCst)
+    }
+
+    pub fn bgsave_in_progress(&self) -> bool {
+        self.bgsave_in_progress.load(Ordering::SeqCst)
+    }
+
+    pub fn changes_since_save(&self) -> u64 {
+        self.dirty.load(Ordering::SeqCst)
+    }
+
+    /// Counts `changes` more changed keys towards the save points.
+    pub fn record_changes(&self, changes: u64) {
+        self.dirty.fetch_add(changes, Ordering::SeqCst);
+    }
+
+    /// Records how a save that started when `dirty` writes were pending went.
+    /// Writes that came in while it ran still count towards the next one.
+    fn finish_save(&self, result: &io::Result<()>, dirty: u64) {
+        self.last_save_ok.store(result.is_ok(), Ordering::SeqCst);
+        if result.is_ok() {
+            self.last_save.store(unix_seconds(), Ordering::SeqCst);
+            self.dirty.fetch_sub(dirty, Ordering::SeqCst);
+        }
+    }
+
+    /// Writes a snapshot of `keyspaces` in the foreground.
+    pub fn save<'a>(&self, keyspaces: impl IntoIterator<Item = &'a Keyspace>) -> io::Result<()> {
+        let dirty = self.changes_since_save();
+        self.last_attempt.store(unix_seconds(), Ordering::SeqCst);
+        let result = write_snapshot(&self.path, self.format, keyspaces);
+        self.finish_save(&result, dirty);
+        result
+    }
+
+    /// Starts writing a snapshot of `databases` in the background. Returns the
+    /// writing task, or `None` if a background save is already running.
+    pub async fn start_bgsave(self: &Arc<Self>, databases: &Databases) -> Option<JoinHandle<()>> {
+        if self.bgsave_in_progress.swap(true, Ordering::SeqCst) {
+            return None;
+        }
+        let (snapshot, dirty) = {
+            let guards = databases.lock_all().await;
+            let snapshot = capture(guards.iter().map(|keyspace| &**keyspace));
+            (snapshot, self.changes_since_save())
+        };
+        self.last_attempt.store(unix_seconds(), Ordering::SeqCst);
+
+        let snapshotter = Arc::clone(self);
+        Some(tokio::task::spawn_blocking(move || {
+            let result = write_databases(
+                &snapshotter.path,
+                snapshotter.format,
+                borrow_saved(&snapshot),
+            );
+            if let Err(e) = &result {
+                eprintln!("Background saving error: {}", e);
+            }
+            snapshotter.finish_save(&result, dirty);
+            snapshotter
+                .bgsave_in_progress
+                .store(false, Ordering::SeqCst);
+        }))
+    }
+
+    /// Whether one of `points` calls for a save at Unix time `now` (in seconds).
+    pub fn save_point_due(&self, points: &[SavePoint], now: u64) -> bool {
+        // After a failed save, give the disk a moment before trying again.
+        if !self.last_save_ok()
+            && now.saturating_sub(self.last_attempt.load(Ordering::SeqCst)) < SAVE_RETRY_DELAY_SECS
+        {
+            return false;
+        }
+        let elapsed = now.saturating_sub(self.last_save());
+        let dirty = self.changes_since_save();
+        points
+            .iter()
+            .any(|point| dirty >= point.changes && elapsed >= point.seconds)
+    }
+}
+
+/// Checks the save points once a second, forever, starting a BGSAVE when one is due.
+pub async fn run_save_points(
+    databases: Databases,
+    snapshotter: Arc<Snapshotter>,
+    points: Vec<SavePoint>,
+) {
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        interval.tick().await;
+        if !snapshotter.bgsave_in_progress() && snapshotter.save_point_due(&points, unix_seconds())
+        {
+            snapshotter.start_bgsave(&databases).await;
+        }
+    }
+}
+
+fn unix_seconds() -> u64 {
+    (now_millis() / 1000) as u64
+}
+
+/// Loads the snapshot at `path` into `keyspaces`, skipping keys that have expired.
+/// A missing file is an empty snapshot. On error nothing is loaded.
+pub fn load_snapshot(
+    path: &Path,
+    format: SnapshotFormat,
+    keyspaces: &mut [Keyspace],
+) -> Result<LoadedSnapshot, SnapshotError> {
+    let contents = match fs::read(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(LoadedSnapshot::default()),
+        Err(e) => return Err(e.into()),
+    };
+    let snapshot: Vec<SavedDatabase> = match format {
+        SnapshotFormat::Json => serde_json::from_slice(&contents)?,
+        SnapshotFormat::Rdb => decode_rdb(&contents).map_err(SnapshotError::Corrupt)?,
+    };
+    if let Some((index, _)) = snapshot.iter().find(|(index, _)| *index >= keyspaces.len()) {
+        return Err(SnapshotError::Corrupt(format!(
+            "database {} is out of range, the server has {}",
+            index,
+            keyspaces.len()
+        )));
+    }
+
+    let mut loaded = LoadedSnapshot::default();
+    for (index, entries) in snapshot {
+        let keyspace = &mut keyspaces[index];
+        for (key, entry) in entries {
+            if keyspace.is_expired(&entry) {
+                loaded.expired += 1;
+            } else {
+                keyspace.insert(key, entry);
+                loaded.keys += 1;
+            }
+        }
+    }
+    Ok(loaded)
+}
@@ -0,0 +1,739 @@
+use crate::handler::persistence::SavedDatabase;
+use crate::handler::value::{Dict, SortedSet, Value, ValueWithExpiry};
+use std::collections::{BTreeMap, VecDeque};
+use std::io;
+
+/*
+Redis's RDB snapshot format, so xredis can load a `dump.rdb` from Redis and hand
+its own snapshots to Redis tooling.
+
+A file is `REDIS` and a four-digit version, then, per database, a SELECTDB opcode
+and its keys, each as an optional expiry opcode, a value type byte, the key and the
+value. An EOF opcode and a CRC-64 of everything before it end the file.
+
+xredis writes version 9 with the plain encodings every Redis since 5.0 loads:
+strings, lists and sets as length-prefixed strings, hashes as field/value pairs,
+and sorted sets with binary scores. Streams have no such simple encoding, so a
+dataset holding one can't be saved as RDB.
+
+DUMP payloads are a single value in the same encoding, followed by the RDB version
+and a CRC-64, so they can be RESTOREd into Redis and the other way round. Only
+there, a stream is written as its JSON snapshot form under a type of xredis's own.
+
+Reading also takes the compact encodings Redis writes for small or integer-only
+collections (intsets, ziplists, listpacks and quicklists), integer-encoded and
+LZF-compressed strings, and skips the metadata (AUX fields, LRU/LFU hints, function
+libraries) xredis has no use for. Module values and streams are rejected.
+*/
+
+const MAGIC: &[u8] = b"REDIS";
+/// The version xredis writes.
+const RDB_VERSION: u16 = 9;
+/// The newest version xredis reads (Redis 7.4).
+const MAX_RDB_VERSION: u16 = 12;
+
+// Value types.
+const TYPE_STRING: u8 = 0;
+const TYPE_LIST: u8 = 1;
+const TYPE_SET: u8 = 2;
+const TYPE_ZSET: u8 = 3;
+const TYPE_HASH: u8 = 4;
+const TYPE_ZSET_2: u8 = 5;
+const TYPE_LIST_ZIPLIST: u8 = 10;
+const TYPE_SET_INTSET: u8 = 11;
+const TYPE_ZSET_ZIPLIST: u8 = 12;
+const TYPE_HASH_ZIPLIST: u8 = 13;
+const TYPE_LIST_QUICKLIST: u8 = 14;
+const TYPE_HASH_LISTPACK: u8 = 16;
+const TYPE_ZSET_LISTPACK: u8 = 17;
+const TYPE_LIST_QUICKLIST_2: u8 = 18;
+const TYPE_SET_LISTPACK: u8 = 20;
+/// Not a Redis type: a stream in a DUMP payload, as its JSON snapshot form.
+const TYPE_XREDIS_STREAM_JSON: u8 = 0x80;
+
+// Opcodes.
+const OPCODE_FUNCTION2: u8 = 0xf5;
+const OPCODE_MODULE_AUX: u8 = 0xf7;
+const OPCODE_IDLE: u8 = 0xf8;
+const OPCODE_FREQ: u8 = 0xf9;
+const OPCODE_AUX: u8 = 0xfa;
+const OPCODE_RESIZEDB: u8 = 0xfb;
+const OPCODE_EXPIRETIME_MS: u8 = 0xfc;
+const OPCODE_EXPIRETIME: u8 = 0xfd;
+const OPCODE_SELECTDB: u8 = 0xfe;
+const OPCODE_EOF: u8 = 0xff;
+
+// Special string encodings, flagged by a length whose top two bits are set.
+const ENC_INT8: u8 = 0;
+const ENC_INT16: u8 = 1;
+const ENC_INT32: u8 = 2;
+const ENC_LZF: u8 = 3;
+
+/// The longest string Redis accepts (`proto-max-bulk-len`).
+const MAX_STRING_LEN: usize = 512 * 1024 * 1024;
+
+/// The most an LZF stream can expand: a three-byte back-reference makes at most
+/// 264 bytes.
+const LZF_MAX_EXPANSION: usize = 88;
+
+/// The most fields a hash read from a payload makes room for up front. Past that
+/// the table grows as the fields arrive, so a length that claims more than the
+/// payload holds can't reserve a huge, mostly empty table.
+const MAX_PRESIZED_FIELDS: usize = 1 << 16;
+
+/// A quicklist node holding one element as a plain string rather than a listpack.
+const QUICKLIST_NODE_PLAIN: u64 = 1;
+
+/// The table for Redis's CRC-64 (Jones polynomial, reflected, no final XOR).
+const CRC64_TABLE: [u64; 256] = {
+    let mut table = [0u64; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u64;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0x95ac_9329_ac4b_c9b5
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// Continues the CRC-64 `crc` over `data`. Start from 0.
+pub fn crc64(crc: u64, data: &[u8]) -> u64 {
+    data.iter().fold(crc, |crc, &byte| {
+        CRC64_TABLE[((crc ^ byte as u64) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+fn write_length(out: &mut Vec<u8>, len: u64) {
+    if len < 1 << 6 {
+        out.push(len as u8);
+    } else if len < 1 << 14 {
+        out.extend_from_slice(&[0x40 | (len >> 8) as u8, len as u8]);
+    } else if len <= u32::MAX as u64 {
+        out.push(0x80);
+        out.extend_from_slice(&(len as u32).to_be_bytes());
+    } else {
+        out.push(0x81);
+        out.extend_from_slice(&len.to_be_bytes());
+    }
+}
+
+fn write_string(out: &mut Vec<u8>, bytes: &[u8]) {
+    write_length(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+/// The type byte `value` is written with.
+fn value_type(value: &Value) -> u8 {
+    match value {
+        Value::String(_) => TYPE_STRING,
+        Value::List(_) => TYPE_LIST,
+        Value::Set(_) => TYPE_SET,
+        Value::Hash(_) => TYPE_HASH,
+        Value::SortedSet(_) => TYPE_ZSET_2,
+        Value::Stream(_) => TYPE_XREDIS_STREAM_JSON,
+    }
+}
+
+/// Writes `value` in the encoding its `value_type` stands for.
+fn write_value(out: &mut Vec<u8>, value: &Value) -> io::Result<()> {
+    match value {
+        Value::String(bytes) => write_string(out, bytes),
+        Value::List(list) => {
+            write_length(out, list.len() as u64);
+            for item in list {
+                write_string(out, item);
+            }
+        }
+        Value::Set(set) => {
+            write_length(out, set.len() as u64);
+            for member in set {
+                write_string(out, member);
+            }
+        }
+        Value::Hash(hash) => {
+            write_length(out, hash.len() as u64);
+            for (field, value) in hash {
+                write_string(out, field);
+                write_string(out, value);
+            }
+        }
+        Value::SortedSet(zset) => {
+            write_length(out, zset.len() as u64);
+            for (member, score) in zset.iter() {
+                write_string(out, member);
+                out.extend_from_slice(&score.to_le_bytes());
+            }
+        }
+        Value::Stream(stream) => {
+            write_string(out, &serde_json::to_vec(stream).map_err(io::Error::other)?)
+        }
+    }
+    Ok(())
+}
+
+/// Encodes each `(index, entries)` database as an RDB file.
+pub fn encode_rdb<'a, E>(databases: impl IntoIterator<Item = (usize, E)>) -> io::Result<Vec<u8>>
+where
+    E: IntoIterator<Item = (&'a Vec<u8>, &'a ValueWithExpiry)>,
+{
+    let mut out = MAGIC.to_vec();
+    out.extend_from_slice(format!("{:04}", RDB_VERSION).as_bytes());
+    out.push(OPCODE_AUX);
+    write_string(&mut out, b"redis-bits");
+    write_string(&mut out, (usize::BITS as u64).to_string().as_bytes());
+
+    for (index, entries) in databases {
+        let entries: Vec<_> = entries.into_iter().collect();
+        if entries.is_empty() {
+            continue;
+        }
+        out.push(OPCODE_SELECTDB);
+        write_length(&mut out, index as u64);
+        out.push(OPCODE_RESIZEDB);
+        write_length(&mut out, entries.len() as u64);
+        let expires = entries.iter().filter(|(_, entry)| entry.expiry.is_some());
+        write_length(&mut out, expires.count() as u64);
+        for (key, entry) in entries {
+            if let Value::Stream(_) = entry.value {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    format!(
+                        "the stream at '{}' can't be saved in RDB format",
+                        String::from_utf8_lossy(key)
+                    ),
+                ));
+            }
+            if let Some(expiry) = entry.expiry {
+                out.push(OPCODE_EXPIRETIME_MS);
+                out.extend_from_slice(&(expiry as u64).to_le_bytes());
+            }
+            out.push(value_type(&entry.value));
+            write_string(&mut out, key);
+            write_value(&mut out, &entry.value)?;
+        }
+    }
+
+    out.push(OPCODE_EOF);
+    let checksum = crc64(0, &out);
+    out.extend_from_slice(&checksum.to_le_bytes());
+    Ok(out)
+}
+
+/// A length, or the special string encoding that stands in its place.
+enum Length {
+    Len(u64),
+    Encoded(u8),
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Reader<'a> {
+        Reader { data, pos: 0 }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos == self.data.len()
+    }
+
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], String> {
+        let bytes = self
+            .pos
+            .checked_add(n)
+            .and_then(|end| self.data.get(self.pos..end))
+            .ok_or_else(|| "unexpected end of data".to_string())?;
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        Ok(self.bytes(N)?.try_into().expect("N bytes were read"))
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        Ok(self.array::<1>()?[0])
+    }
+
+    fn length_or_encoding(&mut self) -> Result<Length, String> {
+        let first = self.byte()?;
+        match first >> 6 {
+            0 => Ok(Length::Len((first & 0x3f) as u64)),
+            1 => Ok(Length::Len(
+                (((first & 0x3f) as u64) << 8) | self.byte()? as u64,
+            )),
+            2 => match first {
+                0x80 => Ok(Length::Len(u32::from_be_bytes(self.array()?) as u64)),
+                0x81 => Ok(Length::Len(u64::from_be_bytes(self.array()?))),
+                _ => Err(format!("invalid length prefix {:#04x}", first)),
+            },
+            _ => Ok(Length::Encoded(first & 0x3f)),
+        }
+    }
+
+    fn length(&mut self) -> Result<u64, String> {
+        match self.length_or_encoding()? {
+            Length::Len(len) => Ok(len),
+            Length::Encoded(_) => Err("expected a length, found an encoded string".to_string()),
+        }
+    }
+
+    /// A length that counts things to be read, sanity-checked against what's left
+    /// so a corrupt file can't ask for a huge allocation.
+    fn count(&mut self) -> Result<usize, String> {
+        let len = self.length()?;
+        if len > (self.data.len() - self.pos) as u64 {
+            return Err(format!("length {} runs past the end of the data", len));
+        }
+        Ok(len as usize)
+    }
+
+    fn string(&mut self) -> Result<Vec<u8>, String> {
+        match self.length_or_encoding()? {
+            Length::Len(len) => {
+                let len = usize::try_from(len).map_err(|_| "string too long".to_string())?;
+                Ok(self.bytes(len)?.to_vec())
+            }
+            Length::Encoded(ENC_INT8) => Ok((self.byte()? as i8).to_string().into_bytes()),
+            Length::Encoded(ENC_INT16) => {
+                Ok(i16::from_le_bytes(self.array()?).to_string().into_bytes())
+            }
+            Length::Encoded(ENC_INT32) => {
+                Ok(i32::from_le_bytes(self.array()?).to_string().into_bytes())
+            }
+            Length::Encoded(ENC_LZF) => {
+                let compressed_len = self.count()?;
+                let len = usize::try_from(self.length()?)
+                    .ok()
+                    .filter(|&len| {
+                        len <= MAX_STRING_LEN
+                            && len <= compressed_len.saturating_mul(LZF_MAX_EXPANSION)
+                    })
+                    .ok_or_else(|| "bad LZF length".to_string())?;
+                lzf_decompress(self.bytes(compressed_len)?, len)
+            }
+            Length::Encoded(encoding) => Err(format!("unknown string encoding {}", encoding)),
+        }
+    }
+
+    /// A score as written by the old ZSET type: a length byte and ASCII digits,
+    /// with three reserved lengths for NaN and the infinities.
+    fn string_score(&mut self) -> Result<f64, String> {
+        match self.byte()? {
+            253 => Err("NaN score".to_string()),
+            254 => Ok(f64::INFINITY),
+            255 => Ok(f64::NEG_INFINITY),
+            len => parse_score(self.bytes(len as usize)?),
+        }
+    }
+
+    fn binary_score(&mut self) -> Result<f64, String> {
+        let score = f64::from_le_bytes(self.array()?);
+        if score.is_nan() {
+            return Err("NaN score".to_string());
+        }
+        Ok(score)
+    }
+}
+
+fn parse_score(bytes: &[u8]) -> Result<f64, String> {
+    std::str::from_utf8(bytes)
+        .ok()
+        .and_then(|s| s.parse::<f64>().ok())
+        .filter(|score| !score.is_nan())
+        .ok_or_else(|| format!("invalid score '{}'", String::from_utf8_lossy(bytes)))
+}
+
+/// Decompresses LZF data that should come to exactly `len` bytes.
+fn lzf_decompress(input: &[u8], len: usize) -> Result<Vec<u8>, String> {
+    let corrupt = || "corrupt LZF data".to_string();
+    // `len` comes from the file, so the output only grows as the input backs it.
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < input.len() {
+        let control = input[i] as usize;
+        i += 1;
+        if control < 32 {
+            // A run of `control + 1` literal bytes.
+            let run = input.get(i..i + control + 1).ok_or_else(corrupt)?;
+            out.extend_from_slice(run);
+            i += control + 1;
+        } else {
+            // A copy of earlier output: a length, then how far back it starts.
+            let mut copy_len = control >> 5;
+            if copy_len == 7 {
+                copy_len += *input.get(i).ok_or_else(corrupt)? as usize;
+                i += 1;
+            }
+            let offset = ((control & 0x1f) << 8) + *input.get(i).ok_or_else(corrupt)? as usize + 1;
+            i += 1;
+            let start = out.len().checked_sub(offset).ok_or_else(corrupt)?;
+            // The copy can overlap what it produces, so it goes byte by byte.
+            for k in start..start + copy_len + 2 {
+                out.push(out[k]);
+            }
+        }
+        if out.len() > len {
+            return Err(corrupt());
+        }
+    }
+    if out.len() != len {
+        return Err(corrupt());
+    }
+    Ok(out)
+}
+
+/// The elements of a ziplist, integers written out in decimal.
+fn ziplist_entries(data: &[u8]) -> Result<Vec<Vec<u8>>, String> {
+    let mut reader = Reader::new(data);
+    reader.bytes(8)?; // total bytes and offset of the tail
+    let mut entries = Vec::with_capacity(u16::from_le_bytes(reader.array()?) as usize);
+    loop {
+        let prevlen = reader.byte()?;
+        if prevlen == 0xff {
+            break;
+        }
+        if prevlen == 0xfe {
+            reader.bytes(4)?;
+        }
+        let encoding = reader.byte()?;
+        let entry = match encoding >> 6 {
+            0 => reader.bytes((encoding & 0x3f) as usize)?.to_vec(),
+            1 => {
+                let len = (((encoding & 0x3f) as usize) << 8) | reader.byte()? as usize;
+                reader.bytes(len)?.to_vec()
+            }
+            2 => {
+                let len = u32::from_be_bytes(reader.array()?) as usize;
+                reader.bytes(len)?.to_vec()
+            }
+            _ => {
+                let int = match encoding {
+                    0xc0 => i16::from_le_bytes(reader.array()?) as i64,
+                    0xd0 => i32::from_le_bytes(reader.array()?) as i64,
+                    0xe0 => i64::from_le_bytes(reader.array()?),
+                    0xf0 => {
+                        let [a, b, c] = reader.array()?;
+                        // Sign-extend the 24 bits from the top of an i32.
+                        (i32::from_le_bytes([0, a, b, c]) >> 8) as i64
+                    }
+                    0xfe => reader.byte()? as i8 as i64,
+                    0xf1..=0xfd => (encoding & 0x0f) as i64 - 1,
+                    _ => return Err(format!("invalid ziplist encoding {:#04x}", encoding)),
+                };
+                int.to_string().into_bytes()
+            }
+        };
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// The elements of a listpack, integers written out in decimal.
+fn listpack_entries(data: &[u8]) -> Result<Vec<Vec<u8>>, String> {
+    let mut reader = Reader::new(data);
+    reader.bytes(4)?; // total bytes
+    let mut entries = Vec::with_capacity(u16::from_le_bytes(reader.array()?) as usize);
+    loop {
+        let start = reader.pos;
+        let encoding = reader.byte()?;
+        if encoding == 0xff {
+            break;
+        }
+        let entry = if encoding & 0x80 == 0 {
+            (encoding as i64).to_string().into_bytes()
+        } else if encoding & 0xc0 == 0x80 {
+            reader.bytes((encoding & 0x3f) as usize)?.to_vec()
+        } else if encoding & 0xe0 == 0xc0 {
+            // A 13-bit signed integer.
+            let raw = (((encoding & 0x1f) as i64) << 8) | reader.byte()? as i64;
+            let int = if raw >= 1 << 12 { raw - (1 << 13) } else { raw };
+            int.to_string().into_bytes()
+        } else if encoding & 0xf0 == 0xe0 {
+            let len = (((encoding & 0x0f) as usize) << 8) | reader.byte()? as usize;
+            reader.bytes(len)?.to_vec()
+        } else {
+            match encoding {
+                0xf0 => {
+                    let len = u32::from_le_bytes(reader.array()?) as usize;
+                    reader.bytes(len)?.to_vec()
+                }
+                0xf1 => (i16::from_le_bytes(reader.array()?) as i64)
+                    .to_string()
+                    .into_bytes(),
+                0xf2 => {
+                    let [a, b, c] = reader.array()?;
+                    ((i32::from_le_bytes([0, a, b, c]) >> 8) as i64)
+                        .to_string()
+                        .into_bytes()
+                }
+                0xf3 => (i32::from_le_bytes(reader.array()?) as i64)
+                    .to_string()
+                    .into_bytes(),
+                0xf4 => i64::from_le_bytes(reader.array()?).to_string().into_bytes(),
+                _ => return Err(format!("invalid listpack encoding {:#04x}", encoding)),
+            }
+        };
+        // Each entry ends with its own length, for walking the listpack backwards.
+        let entry_len = reader.pos - start;
+        // The limits are exclusive, as in Redis's `lpEncodeBacklen`.
+        reader.bytes(match entry_len {
+            0..=127 => 1,
+            128..16383 => 2,
+            16383..2097151 => 3,
+            2097151..268435455 => 4,
+            _ => 5,
+        })?;
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// The members of an intset, in decimal.
+fn intset_entries(data: &[u8]) -> Result<Vec<Vec<u8>>, String> {
+    let mut reader = Reader::new(data);
+    let width = u32::from_le_bytes(reader.array()?);
+    let len = u32::from_le_bytes(reader.array()?);
+    (0..len)
+        .map(|_| {
+            let int = match width {
+                2 => i16::from_le_bytes(reader.array()?) as i64,
+                4 => i32::from_le_bytes(reader.array()?) as i64,
+                8 => i64::from_le_bytes(reader.array()?),
+                _ => return Err(format!("invalid intset width {}", width)),
+            };
+            Ok(int.to_string().into_bytes())
+        })
+        .collect()
+}
+
+/// Field/value or member/score pairs, as flattened into a ziplist or listpack.
+type Pairs = Vec<(Vec<u8>, Vec<u8>)>;
+
+fn pairs(entries: Vec<Vec<u8>>) -> Result<Pairs, String> {
+    if !entries.len().is_multiple_of(2) {
+        return Err("odd number of elements in a pair encoding".to_string());
+    }
+    let mut entries = entries.into_iter();
+    let mut pairs = Vec::new();
+    while let (Some(a), Some(b)) = (entries.next(), entries.next()) {
+        pairs.push((a, b));
+    }
+    Ok(pairs)
+}
+
+fn hash_from(pairs: Pairs) -> Value {
+    Value::Hash(pairs.into_iter().collect())
+}
+
+fn zset_from(pairs: Pairs) -> Result<Value, String> {
+    let members = pairs
+        .into_iter()
+        .map(|(member, score)| Ok((member, parse_score(&score)?)))
+        .collect::<Result<Vec<_>, String>>()?;
+    Ok(Value::SortedSet(members.into_iter().collect()))
+}
+
+fn read_value(reader: &mut Reader, value_type: u8) -> Result<Value, String> {
+    Ok(match value_type {
+        TYPE_STRING => Value::String(reader.string()?),
+        TYPE_LIST => {
+            let len = reader.count()?;
+            Value::List(
+                (0..len)
+                    .map(|_| reader.string())
+                    .collect::<Result<_, _>>()?,
+            )
+        }
+        TYPE_SET => {
+            let len = reader.count()?;
+            Value::Set(
+                (0..len)
+                    .map(|_| reader.string())
+                    .collect::<Result<_, _>>()?,
+            )
+        }
+        TYPE_ZSET | TYPE_ZSET_2 => {
+            let len = reader.count()?;
+            let mut zset = SortedSet::new();
+            for _ in 0..len {
+                let member = reader.string()?;
+                let score = if value_type == TYPE_ZSET {
+                    reader.string_score()?
+                } else {
+                    reader.binary_score()?
+                };
+                zset.insert(&member, score);
+            }
+            Value::SortedSet(zset)
+        }
+        TYPE_HASH => {
+            let len = reader.count()?;
+            let mut hash = Dict::with_capacity(len.min(MAX_PRESIZED_FIELDS));
+            for _ in 0..len {
+                let field = reader.string()?;
+                hash.insert(field, reader.string()?);
+            }
+            // Repeated fields leave fewer entries than the length said.
+            hash.shrink_to_fit();
+            Value::Hash(hash)
+        }
+        TYPE_LIST_ZIPLIST => Value::List(ziplist_entries(&reader.string()?)?.into()),
+        TYPE_SET_INTSET => Value::Set(intset_entries(&reader.string()?)?.into_iter().collect()),
+        TYPE_SET_LISTPACK => Value::Set(listpack_entries(&reader.string()?)?.into_iter().collect()),
+        TYPE_ZSET_ZIPLIST => zset_from(pairs(ziplist_entries(&reader.string()?)?)?)?,
+        TYPE_ZSET_LISTPACK => zset_from(pairs(listpack_entries(&reader.string()?)?)?)?,
+        TYPE_HASH_ZIPLIST => hash_from(pairs(ziplist_entries(&reader.string()?)?)?),
+        TYPE_HASH_LISTPACK => hash_from(pairs(listpack_entries(&reader.string()?)?)?),
+        TYPE_LIST_QUICKLIST => {
+            let nodes = reader.count()?;
+            let mut list = VecDeque::new();
+            for _ in 0..nodes {
+                list.extend(ziplist_entries(&reader.string()?)?);
+            }
+            Value::List(list)
+        }
+        TYPE_LIST_QUICKLIST_2 => {
+            let nodes = reader.count()?;
+            let mut list = VecDeque::new();
+            for _ in 0..nodes {
+                let container = reader.length()?;
+                let node = reader.string()?;
+                if container == QUICKLIST_NODE_PLAIN {
+                    list.push_back(node);
+                } else {
+                    list.extend(listpack_entries(&node)?);
+                }
+            }
+            Value::List(list)
+        }
+        TYPE_XREDIS_STREAM_JSON => {
+            Value::Stream(serde_json::from_slice(&reader.string()?).map_err(|e| e.to_string())?)
+        }
+        _ => return Err(format!("unsupported value type {}", value_type)),
+    })
+}
+
+#[derive(Debug, PartialEq, thiserror::Error)]
+pub enum PayloadError {
+    #[error("DUMP payload version or checksum are wrong")]
+    VersionOrChecksum,
+    #[error("Bad data format")]
+    BadFormat,
+}
+
+/// The DUMP payload of `value`: its type byte and encoding as in an RDB file,
+/// then the RDB version and a CRC-64 of everything before it, as Redis builds it.
+pub fn dump_payload(value: &Value) -> io::Result<Vec<u8>> {
+    let mut out = vec![value_type(value)];
+    write_value(&mut out, value)?;
+    out.extend_from_slice(&RDB_VERSION.to_le_bytes());
+    let checksum = crc64(0, &out);
+    out.extend_from_slice(&checksum.to_le_bytes());
+    Ok(out)
+}
+
+/// The value in a DUMP payload, from xredis or from Redis.
+pub fn restore_payload(payload: &[u8]) -> Result<Value, PayloadError> {
+    let Some(body_len) = payload.len().checked_sub(10) else {
+        return Err(PayloadError::VersionOrChecksum);
+    };
+    let (body, footer) = payload.split_at(body_len);
+    let version = u16::from_le_bytes([footer[0], footer[1]]);
+    let checksum = u64::from_le_bytes(footer[2..].try_into().expect("8 bytes are left"));
+    if version > MAX_RDB_VERSION || checksum != crc64(0, &payload[..body_len + 2]) {
+        return Err(PayloadError::VersionOrChecksum);
+    }
+
+    let mut reader = Reader::new(body);
+    let value = reader
+        .byte()
+        .and_then(|value_type| read_value(&mut reader, value_type))
+        .map_err(|_| PayloadError::BadFormat)?;
+    if !reader.is_empty() || value.is_empty_collection() {
+        return Err(PayloadError::BadFormat);
+    }
+    Ok(value)
+}
+
+/// Reads an RDB file into its databases' `(key, entry)` pairs. Empty collections
+/// are dropped, since xredis never stores them.
+pub fn decode_rdb(data: &[u8]) -> Result<Vec<SavedDatabase>, String> {
+    let mut reader = Reader::new(data);
+    if reader.bytes(MAGIC.len()).ok() != Some(MAGIC) {
+        return Err("not an RDB file".to_string());
+    }
+    let version = std::str::from_utf8(reader.bytes(4)?)
+        .ok()
+        .and_then(|version| version.parse::<u16>().ok())
+        .filter(|version| (1..=MAX_RDB_VERSION).contains(version))
+        .ok_or_else(|| "unsupported RDB version".to_string())?;
+
+    let mut databases: BTreeMap<usize, Vec<(Vec<u8>, ValueWithExpiry)>> = BTreeMap::new();
+    let mut db = 0;
+    let mut expiry = None;
+    loop {
+        match reader.byte()? {
+            OPCODE_EOF => break,
+            OPCODE_SELECTDB => db = reader.length()? as usize,
+            OPCODE_RESIZEDB => {
+                reader.length()?;
+                reader.length()?;
+            }
+            OPCODE_AUX => {
+                reader.string()?;
+                reader.string()?;
+            }
+            OPCODE_EXPIRETIME_MS => expiry = Some(u64::from_le_bytes(reader.array()?) as u128),
+            OPCODE_EXPIRETIME => {
+                expiry = Some(u32::from_le_bytes(reader.array()?) as u128 * 1000);
+            }
+            OPCODE_IDLE => {
+                reader.length()?;
+            }
+            OPCODE_FREQ => {
+                reader.byte()?;
+            }
+            OPCODE_FUNCTION2 => {
+                reader.string()?;
+            }
+            OPCODE_MODULE_AUX => return Err("module data is not supported".to_string()),
+            value_type => {
+                let key = reader.string()?;
+                let value = read_value(&mut reader, value_type)
+                    .map_err(|e| format!("key '{}': {}", String::from_utf8_lossy(&key), e))?;
+                let entry = ValueWithExpiry {
+                    value,
+                    expiry: expiry.take(),
+                };
+                if !entry.value.is_empty_collection() {
+                    databases.entry(db).or_default().push((key, entry));
+                }
+            }
+        }
+    }
+
+    // Files from version 5 on end with a checksum, or zero when it's turned off.
+    if version >= 5 {
+        let end = reader.pos;
+        let expected = u64::from_le_bytes(reader.array()?);
+        if expected != 0 && expected != crc64(0, &data[..end]) {
+            return Err("checksum mismatch".to_string());
+        }
+    }
+    if !reader.is_empty() {
+        return Err("trailing data after the end of the file".to_string());
+    }
+    Ok(databases.into_iter().collect())
+}
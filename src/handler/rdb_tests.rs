@@ -0,0 +1,306 @@
+use super::rdb::{crc64, decode_rdb, encode_rdb};
+use super::value::{Dict, DictSet, SortedSet, Value, ValueWithExpiry};
+
+fn bytes(items: &[&[u8]]) -> Vec<Vec<u8>> {
+    items.iter().map(|item| item.to_vec()).collect()
+}
+
+fn entry(value: Value, expiry: Option<u128>) -> ValueWithExpiry {
+    ValueWithExpiry { value, expiry }
+}
+
+/// A version 11 file holding `body` (opcodes and keys), with its checksum.
+fn rdb_file(body: &[u8]) -> Vec<u8> {
+    let mut file = b"REDIS0011".to_vec();
+    file.extend_from_slice(body);
+    file.push(0xff);
+    let checksum = crc64(0, &file);
+    file.extend_from_slice(&checksum.to_le_bytes());
+    file
+}
+
+/// The only key of the file made from `body`.
+fn only_value(body: &[u8]) -> Value {
+    let mut databases = decode_rdb(&rdb_file(body)).unwrap();
+    assert_eq!(databases.len(), 1);
+    let (_, mut entries) = databases.remove(0);
+    assert_eq!(entries.len(), 1);
+    entries.remove(0).1.value
+}
+
+/// A key `k` of `value_type` whose value is the string `payload`, the way Redis
+/// wraps ziplists, listpacks and intsets.
+fn packed(value_type: u8, payload: &[u8]) -> Vec<u8> {
+    let mut body = vec![value_type, 1, b'k', payload.len() as u8];
+    body.extend_from_slice(payload);
+    body
+}
+
+#[test]
+fn test_crc64_matches_redis() {
+    assert_eq!(crc64(0, b"123456789"), 0xe9c6_d914_c4b8_d9ca);
+    assert_eq!(crc64(crc64(0, b"1234"), b"56789"), 0xe9c6_d914_c4b8_d9ca);
+}
+
+#[test]
+fn test_round_trips_every_type_with_expiries() {
+    let zset: SortedSet = vec![
+        (b"low".to_vec(), f64::NEG_INFINITY),
+        (b"mid".to_vec(), 1.5),
+        (b"high".to_vec(), 1e300),
+    ]
+    .into_iter()
+    .collect();
+    let long = vec![b'x'; 20_000];
+    let first: Vec<(Vec<u8>, ValueWithExpiry)> = vec![
+        (
+            b"s".to_vec(),
+            entry(Value::String(b"v".to_vec()), Some(1_700_000_000_123)),
+        ),
+        (b"long".to_vec(), entry(Value::String(long), None)),
+        (
+            b"l".to_vec(),
+            entry(Value::List(bytes(&[b"a", b"", b"c"]).into()), None),
+        ),
+        (
+            b"set".to_vec(),
+            entry(
+                Value::Set(bytes(&[b"x", b"y"]).into_iter().collect()),
+                Some(42),
+            ),
+        ),
+    ];
+    let second: Vec<(Vec<u8>, ValueWithExpiry)> = vec![
+        (
+            b"h".to_vec(),
+            entry(
+                Value::Hash(Dict::from([(b"f".to_vec(), b"v".to_vec())])),
+                None,
+            ),
+        ),
+        (b"z".to_vec(), entry(Value::SortedSet(zset), None)),
+    ];
+
+    let file = encode_rdb([
+        (0, first.iter().map(|(k, e)| (k, e)).collect::<Vec<_>>()),
+        (1, Vec::new()),
+        (3, second.iter().map(|(k, e)| (k, e)).collect()),
+    ])
+    .unwrap();
+    assert!(file.starts_with(b"REDIS0009"));
+    assert_eq!(decode_rdb(&file).unwrap(), vec![(0, first), (3, second)]);
+}
+
+#[test]
+fn test_refuses_to_encode_streams() {
+    let stream = (
+        b"st".to_vec(),
+        entry(Value::Stream(Default::default()), None),
+    );
+    let error = encode_rdb([(0, vec![(&stream.0, &stream.1)])]).unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::Unsupported);
+}
+
+#[test]
+fn test_reads_encoded_strings_and_expiries() {
+    // Integers of one, two and four bytes, and LZF: a literal `a` followed by a
+    // back-reference copying it nine more times.
+    let body = [
+        &[0xfd, 0x10, 0, 0, 0, 0, 0xc0, 0xc0, 0xc0, 0x85][..],
+        &[0, 0xc1, 0xc1, 0x30, 0xc1, 0x18, 0xfc][..],
+        &[
+            0, 0xc2, 0x40, 0x42, 0x0f, 0x00, 0xc2, 0xc0, 0xbd, 0xf0, 0xff,
+        ][..],
+        &[0, 1, b'c', 0xc3, 5, 10, 0x00, b'a', 0xe0, 0x00, 0x00][..],
+    ]
+    .concat();
+    let databases = decode_rdb(&rdb_file(&body)).unwrap();
+    let entries = &databases[0].1;
+    let strings: Vec<_> = entries
+        .iter()
+        .map(|(key, entry)| (key.clone(), entry.value.clone(), entry.expiry))
+        .collect();
+    assert_eq!(
+        strings,
+        vec![
+            (
+                b"-64".to_vec(),
+                Value::String(b"-123".to_vec()),
+                Some(16_000)
+            ),
+            (b"12481".to_vec(), Value::String(b"-1000".to_vec()), None),
+            (
+                b"1000000".to_vec(),
+                Value::String(b"-1000000".to_vec()),
+                None
+            ),
+            (b"c".to_vec(), Value::String(b"aaaaaaaaaa".to_vec()), None),
+        ]
+    );
+}
+
+#[test]
+fn test_reads_compact_encodings() {
+    // A ziplist holding `a`, 7, -300 and -2.
+    let ziplist = [
+        &[0; 8][..],
+        &[4, 0],
+        &[0, 0x01, b'a'],
+        &[3, 0xf8],
+        &[2, 0xc0, 0xd4, 0xfe],
+        &[4, 0xf0, 0xfe, 0xff, 0xff],
+        &[0xff],
+    ]
+    .concat();
+    assert_eq!(
+        only_value(&packed(10, &ziplist)),
+        Value::List(bytes(&[b"a", b"7", b"-300", b"-2"]).into())
+    );
+
+    // A listpack holding `f`, 5, -1 and 1000, each followed by its length.
+    let listpack = [
+        &[0; 4][..],
+        &[4, 0],
+        &[0x81, b'f', 2],
+        &[0x05, 1],
+        &[0xdf, 0xff, 2],
+        &[0xf1, 0xe8, 0x03, 3],
+        &[0xff],
+    ]
+    .concat();
+    assert_eq!(
+        only_value(&packed(16, &listpack)),
+        Value::Hash(Dict::from([
+            (b"f".to_vec(), b"5".to_vec()),
+            (b"-1".to_vec(), b"1000".to_vec()),
+        ]))
+    );
+    assert_eq!(
+        only_value(&packed(17, &listpack)),
+        Value::SortedSet(
+            vec![(b"f".to_vec(), 5.0), (b"-1".to_vec(), 1000.0)]
+                .into_iter()
+                .collect()
+        )
+    );
+
+    // An intset of 16-bit integers.
+    let intset = [
+        &[2, 0, 0, 0, 3, 0, 0, 0][..],
+        &[1, 0, 0xfe, 0xff, 0x2c, 0x01],
+    ]
+    .concat();
+    assert_eq!(
+        only_value(&packed(11, &intset)),
+        Value::Set(bytes(&[b"1", b"-2", b"300"]).into_iter().collect())
+    );
+
+    // A quicklist with a plain node and a listpack node.
+    let node = [&[0; 4][..], &[1, 0], &[0x82, b'b', b'c', 3], &[0xff]].concat();
+    let mut quicklist = vec![18, 1, b'k', 2, 1, 1, b'a', 2, node.len() as u8];
+    quicklist.extend_from_slice(&node);
+    assert_eq!(
+        only_value(&quicklist),
+        Value::List(bytes(&[b"a", b"bc"]).into())
+    );
+
+    // The old sorted set type, with scores written as text.
+    let zset = [
+        &[3, 1, b'k', 2][..],
+        &[1, b'a', 3, b'2', b'.', b'5'],
+        &[1, b'b', 255],
+    ]
+    .concat();
+    assert_eq!(
+        only_value(&zset),
+        Value::SortedSet(
+            vec![(b"a".to_vec(), 2.5), (b"b".to_vec(), f64::NEG_INFINITY)]
+                .into_iter()
+                .collect()
+        )
+    );
+}
+
+#[test]
+fn test_reads_listpack_entries_at_back_length_limits() {
+    // Entries of exactly 16383 and 2097151 bytes take one more byte to store their
+    // length than the entries just under them.
+    for (entry_len, backlen) in [(16383, 3), (2097151, 4)] {
+        let string = vec![b's'; entry_len - 5];
+        let mut listpack = vec![0, 0, 0, 0, 2, 0, 0xf0];
+        listpack.extend((string.len() as u32).to_le_bytes());
+        listpack.extend(&string);
+        listpack.extend(vec![0; backlen]);
+        listpack.extend([0x05, 1, 0xff]);
+
+        let mut body = vec![20, 1, b'k', 0x80];
+        body.extend((listpack.len() as u32).to_be_bytes());
+        body.extend(listpack);
+        assert_eq!(
+            only_value(&body),
+            Value::Set([string, b"5".to_vec()].into_iter().collect())
+        );
+    }
+}
+
+#[test]
+fn test_skips_metadata_and_selects_databases() {
+    let body = [
+        &[0xfa, 3, b'v', b'e', b'r', 3, b'7', b'.', b'2'][..],
+        &[0xfe, 2, 0xfb, 1, 0],
+        &[0xf8, 5, 0xf9, 1],
+        &[2, 1, b's', 1, 1, b'm'],
+    ]
+    .concat();
+    assert_eq!(
+        decode_rdb(&rdb_file(&body)).unwrap(),
+        vec![(
+            2,
+            vec![(
+                b"s".to_vec(),
+                entry(Value::Set(DictSet::from([b"m".to_vec()])), None)
+            )]
+        )]
+    );
+}
+
+#[test]
+fn test_rejects_corrupt_files() {
+    let mut file = rdb_file(&[0, 1, b'k', 1, b'v']);
+    assert!(decode_rdb(&file).is_ok());
+
+    // A zero checksum means Redis didn't compute one.
+    let len = file.len();
+    file[len - 8..].fill(0);
+    assert!(decode_rdb(&file).is_ok());
+
+    file[len - 1] = 1;
+    assert_eq!(decode_rdb(&file), Err("checksum mismatch".to_string()));
+    assert!(decode_rdb(&file[..len - 3]).is_err());
+    assert!(decode_rdb(b"REDIS0099").is_err());
+    assert!(decode_rdb(b"{\"json\": true}").is_err());
+    // Streams and module values aren't supported.
+    assert!(decode_rdb(&rdb_file(&[15, 1, b'k', 0])).is_err());
+    // A length that can't fit in what's left of the file.
+    assert!(decode_rdb(&rdb_file(&[1, 1, b'k', 0x80, 0xff, 0xff, 0xff, 0xff])).is_err());
+}
+
+#[test]
+fn test_rejects_lzf_lengths_the_data_cant_back() {
+    // Two compressed bytes claiming to expand to 2^44, and then to 1000.
+    let huge = [
+        &[0, 1, b'k', 0xc3, 2][..],
+        &[0x81, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00, 0x00],
+        &[0x00, b'a'],
+    ]
+    .concat();
+    assert_eq!(
+        decode_rdb(&rdb_file(&huge)),
+        Err("key 'k': bad LZF length".to_string())
+    );
+    let disproportionate = [&[0, 1, b'k', 0xc3, 2, 0x43, 0xe8][..], &[0x00, b'a']].concat();
+    assert_eq!(
+        decode_rdb(&rdb_file(&disproportionate)),
+        Err("key 'k': bad LZF length".to_string())
+    );
+}
@@ -0,0 +1,144 @@
+use crate::handler::commands::{parse_i64, syntax_error};
+use crate::resp::resp_protocol::RespMessage;
+
+/// Options shared by the cursor-based *SCAN commands.
+pub struct ScanOptions {
+    pub cursor: u64,
+    pub pattern: Option<Vec<u8>>,
+    pub count: usize,
+    /// HSCAN's NOVALUES flag.
+    pub no_values: bool,
+    /// SCAN's TYPE filter.
+    pub type_name: Option<Vec<u8>>,
+}
+
+/// Parses `cursor [MATCH pattern] [COUNT count]` plus the options listed in `flags`
+/// (`NOVALUES`, `TYPE type`).
+pub fn parse_scan_options(args: &[&[u8]], flags: &[&[u8]]) -> Result<ScanOptions, RespMessage> {
+    let cursor = std::str::from_utf8(args[0])
+        .ok()
+        .and_then(|s| s.parse::<u64>().ok())
+        .ok_or_else(|| RespMessage::Error("ERR invalid cursor".to_string()))?;
+    let mut options = ScanOptions {
+        cursor,
+        pattern: None,
+        count: 10,
+        no_values: false,
+        type_name: None,
+    };
+
+    let mut i = 1;
+    while i < args.len() {
+        let option = args[i].to_ascii_uppercase();
+        match (option.as_slice(), args.get(i + 1)) {
+            (b"MATCH", Some(pattern)) => {
+                // `*` matches everything, so skip the per-element check.
+                options.pattern = (*pattern != b"*").then(|| pattern.to_vec());
+                i += 2;
+            }
+            (b"COUNT", Some(count)) => {
+                let count = parse_i64(count)?;
+                if count < 1 {
+                    return Err(syntax_error());
+                }
+                options.count = count as usize;
+                i += 2;
+            }
+            (b"NOVALUES", _) if flags.contains(&b"NOVALUES".as_slice()) => {
+                options.no_values = true;
+                i += 1;
+            }
+            (b"TYPE", Some(type_name)) if flags.contains(&b"TYPE".as_slice()) => {
+                options.type_name = Some(type_name.to_ascii_lowercase());
+                i += 2;
+            }
+            _ => return Err(syntax_error()),
+        }
+    }
+    Ok(options)
+}
+
+impl ScanOptions {
+    pub fn matches(&self, name: &[u8]) -> bool {
+        self.pattern
+            .as_deref()
+            .is_none_or(|pattern| glob_match(pattern, name))
+    }
+}
+
+/// Builds the `[cursor, [elements...]]` reply shared by the *SCAN commands.
+pub fn scan_reply(cursor: u64, elements: Vec<RespMessage>) -> RespMessage {
+    RespMessage::Array(vec![
+        RespMessage::BulkString(Some(cursor.to_string().into_bytes())),
+        RespMessage::Array(elements),
+    ])
+}
+
+/// Redis-style glob matching: `*`, `?`, `[abc]`, `[^abc]`, `[a-z]` and `\` escapes.
+///
+/// Runs in O(pattern × string): on a mismatch only the most recent `*` is retried,
+/// one byte further along, since an earlier `*` can never do better.
+pub fn glob_match(pattern: &[u8], string: &[u8]) -> bool {
+    let (mut p, mut s) = (0, 0);
+    // Pattern position just past the last `*`, and where in `string` it stopped absorbing.
+    let mut star: Option<(usize, usize)> = None;
+    while s < string.len() {
+        if pattern.get(p) == Some(&b'*') {
+            while pattern.get(p) == Some(&b'*') {
+                p += 1;
+            }
+            star = Some((p, s));
+            continue;
+        }
+        if let Some(next) = match_token(pattern, p, string[s]) {
+            p = next;
+            s += 1;
+            continue;
+        }
+        let Some((after_star, absorbed)) = star else {
+            return false;
+        };
+        star = Some((after_star, absorbed + 1));
+        p = after_star;
+        s = absorbed + 1;
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+/// Matches the single-byte pattern token at `p` (anything but `*`) against `c`,
+/// returning the position of the next token on success.
+fn match_token(pattern: &[u8], p: usize, c: u8) -> Option<usize> {
+    match pattern.get(p)? {
+        b'?' => Some(p + 1),
+        b'[' => {
+            let mut i = p + 1;
+            let negate = pattern.get(i) == Some(&b'^');
+            if negate {
+                i += 1;
+            }
+            let mut matched = false;
+            while i < pattern.len() && pattern[i] != b']' {
+                if pattern[i] == b'\\' && i + 1 < pattern.len() {
+                    matched |= pattern[i + 1] == c;
+                    i += 2;
+                } else if i + 2 < pattern.len() && pattern[i + 1] == b'-' && pattern[i + 2] != b']'
+                {
+                    let (lo, hi) = (
+                        pattern[i].min(pattern[i + 2]),
+                        pattern[i].max(pattern[i + 2]),
+                    );
+                    matched |= (lo..=hi).contains(&c);
+                    i += 3;
+                } else {
+                    matched |= pattern[i] == c;
+                    i += 1;
+                }
+            }
+            // An unterminated class runs to the end of the pattern, as in Redis.
+            let next = (i + 1).min(pattern.len());
+            (matched != negate).then_some(next)
+        }
+        b'\\' if p + 1 < pattern.len() => (pattern[p + 1] == c).then_some(p + 2),
+        &t => (t == c).then_some(p + 1),
+    }
+}
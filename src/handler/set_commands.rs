@@ -0,0 +1,434 @@
+use crate::handler::client_handler::Db;
+use crate::handler::commands::{
+    bulk, parse_i64, parse_random_count, pick_random, remove_if_empty, syntax_error, wrong_arity,
+    wrong_type,
+};
+use crate::handler::keyspace::Keyspace;
+use crate::handler::scan::{parse_scan_options, scan_reply};
+use crate::handler::value::{DictSet, Value, ValueWithExpiry};
+use crate::resp::resp_protocol::RespMessage;
+
+type Set = DictSet<Vec<u8>>;
+
+/// Looks up the live set stored at `key`.
+/// Returns `Ok(None)` for a missing key and a WRONGTYPE error for any other type.
+pub fn get_set<'a>(
+    keyspace: &'a mut Keyspace,
+    key: &[u8],
+) -> Result<Option<&'a mut Set>, RespMessage> {
+    match keyspace.lookup_write(key).map(|v| &mut v.value) {
+        Some(Value::Set(set)) => Ok(Some(set)),
+        Some(_) => Err(wrong_type()),
+        None => Ok(None),
+    }
+}
+
+/// Like `get_set`, but creates an empty set when the key is missing.
+pub fn get_or_create_set<'a>(
+    keyspace: &'a mut Keyspace,
+    key: &[u8],
+) -> Result<&'a mut Set, RespMessage> {
+    let entry = keyspace.lookup_or_insert(key, || Value::Set(DictSet::new()));
+    match &mut entry.value {
+        Value::Set(set) => Ok(set),
+        _ => Err(wrong_type()),
+    }
+}
+
+/// Looks up several sets at once for the multi-key commands.
+/// Missing keys come back as `None`; any non-set key fails the whole lookup.
+fn get_sets<'a>(
+    keyspace: &'a mut Keyspace,
+    keys: &[&[u8]],
+) -> Result<Vec<Option<&'a Set>>, RespMessage> {
+    for key in keys {
+        get_set(keyspace, key)?;
+    }
+    let keyspace: &'a Keyspace = keyspace;
+    Ok(keys
+        .iter()
+        .map(|key| match keyspace.entries.get(*key).map(|v| &v.value) {
+            Some(Value::Set(set)) => Some(set),
+            _ => None,
+        })
+        .collect())
+}
+
+fn members_reply<'a>(members: impl Iterator<Item = &'a Vec<u8>>) -> RespMessage {
+    RespMessage::Array(members.map(|member| bulk(member)).collect())
+}
+
+/// SADD key member [member ...]
+pub async fn sadd(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() < 3 {
+        return wrong_arity(args[0]);
+    }
+    let mut db_guard = db.lock().await;
+    let added = match get_or_create_set(&mut db_guard, args[1]) {
+        Ok(set) => args[2..]
+            .iter()
+            .filter(|member| set.insert(member.to_vec()))
+            .count(),
+        Err(e) => return e,
+    };
+    db_guard.dirty += added as u64;
+    RespMessage::Integer(added as i64)
+}
+
+/// SREM key member [member ...]
+pub async fn srem(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() < 3 {
+        return wrong_arity(args[0]);
+    }
+    let key = args[1];
+    let mut db_guard = db.lock().await;
+    let removed = match get_set(&mut db_guard, key) {
+        Ok(Some(set)) => args[2..]
+            .iter()
+            .filter(|member| set.remove(**member))
+            .count(),
+        Ok(None) => 0,
+        Err(e) => return e,
+    };
+    db_guard.dirty += removed as u64;
+    remove_if_empty(&mut db_guard, key);
+    RespMessage::Integer(removed as i64)
+}
+
+/// SISMEMBER key member
+pub async fn sismember(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() != 3 {
+        return wrong_arity(args[0]);
+    }
+    let mut db_guard = db.lock().await;
+    match get_set(&mut db_guard, args[1]) {
+        Ok(set) => RespMessage::Integer(set.is_some_and(|set| set.contains(args[2])) as i64),
+        Err(e) => e,
+    }
+}
+
+/// SMISMEMBER key member [member ...]
+pub async fn smismember(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() < 3 {
+        return wrong_arity(args[0]);
+    }
+    let mut db_guard = db.lock().await;
+    match get_set(&mut db_guard, args[1]) {
+        Ok(set) => RespMessage::Array(
+            args[2..]
+                .iter()
+                .map(|member| {
+                    RespMessage::Integer(set.as_ref().is_some_and(|s| s.contains(*member)) as i64)
+                })
+                .collect(),
+        ),
+        Err(e) => e,
+    }
+}
+
+/// SCARD key
+pub async fn scard(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() != 2 {
+        return wrong_arity(args[0]);
+    }
+    let mut db_guard = db.lock().await;
+    match get_set(&mut db_guard, args[1]) {
+        Ok(set) => RespMessage::Integer(set.map_or(0, |set| set.len() as i64)),
+        Err(e) => e,
+    }
+}
+
+/// SMEMBERS key
+pub async fn smembers(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() != 2 {
+        return wrong_arity(args[0]);
+    }
+    let mut db_guard = db.lock().await;
+    match get_set(&mut db_guard, args[1]) {
+        Ok(Some(set)) => members_reply(set.iter()),
+        Ok(None) => RespMessage::Array(vec![]),
+        Err(e) => e,
+    }
+}
+
+/// Parses the optional count of SPOP / SRANDMEMBER.
+fn parse_count(args: &[&[u8]], allow_negative: bool) -> Result<Option<i64>, RespMessage> {
+    match args.get(2) {
+        None => Ok(None),
+        Some(arg) => {
+            let count = if allow_negative {
+                parse_random_count(arg)?
+            } else {
+                parse_i64(arg)?
+            };
+            if count < 0 && !allow_negative {
+                return Err(RespMessage::Error(
+                    "ERR value is out of range, must be positive".to_string(),
+                ));
+            }
+            Ok(Some(count))
+        }
+    }
+}
+
+fn random_member(set: &Set) -> &Vec<u8> {
+    set.random().expect("collections are never empty")
+}
+
+/// SPOP key [count]
+pub async fn spop(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() != 2 && args.len() != 3 {
+        return wrong_arity(args[0]);
+    }
+    let count = match parse_count(args, false) {
+        Ok(count) => count,
+        Err(e) => return e,
+    };
+    let key = args[1];
+    let mut db_guard = db.lock().await;
+    let set = match get_set(&mut db_guard, key) {
+        Ok(Some(set)) => set,
+        Ok(None) if count.is_some() => return RespMessage::Array(vec![]),
+        Ok(None) => return RespMessage::BulkString(None),
+        Err(e) => return e,
+    };
+
+    let picked: Vec<Vec<u8>> = match count {
+        Some(count) => pick_random(
+            set.len(),
+            count,
+            || random_member(set),
+            || set.iter().collect(),
+        ),
+        None => vec![random_member(set)],
+    }
+    .into_iter()
+    .cloned()
+    .collect();
+    for member in &picked {
+        set.remove(member);
+    }
+    db_guard.dirty += picked.len() as u64;
+    remove_if_empty(&mut db_guard, key);
+
+    match count {
+        Some(_) => members_reply(picked.iter()),
+        None => RespMessage::BulkString(picked.into_iter().next()),
+    }
+}
+
+/// SRANDMEMBER key [count]
+pub async fn srandmember(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() != 2 && args.len() != 3 {
+        return wrong_arity(args[0]);
+    }
+    let count = match parse_count(args, true) {
+        Ok(count) => count,
+        Err(e) => return e,
+    };
+    let mut db_guard = db.lock().await;
+    let set = match get_set(&mut db_guard, args[1]) {
+        Ok(Some(set)) => set,
+        Ok(None) if count.is_some() => return RespMessage::Array(vec![]),
+        Ok(None) => return RespMessage::BulkString(None),
+        Err(e) => return e,
+    };
+
+    match count {
+        Some(count) => members_reply(
+            pick_random(
+                set.len(),
+                count,
+                || random_member(set),
+                || set.iter().collect(),
+            )
+            .into_iter(),
+        ),
+        None => bulk(random_member(set)),
+    }
+}
+
+/// SMOVE source destination member
+pub async fn smove(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() != 4 {
+        return wrong_arity(args[0]);
+    }
+    let (source, destination, member) = (args[1], args[2], args[3]);
+    let mut db_guard = db.lock().await;
+
+    let source_has_member = match get_set(&mut db_guard, source) {
+        Ok(Some(set)) => set.contains(member),
+        Ok(None) => return RespMessage::Integer(0),
+        Err(e) => return e,
+    };
+    if let Err(e) = get_set(&mut db_guard, destination) {
+        return e;
+    }
+    if !source_has_member {
+        return RespMessage::Integer(0);
+    }
+    if source == destination {
+        return RespMessage::Integer(1);
+    }
+
+    if let Ok(Some(set)) = get_set(&mut db_guard, source) {
+        set.remove(member);
+    }
+    remove_if_empty(&mut db_guard, source);
+    db_guard.dirty += 1;
+    match get_or_create_set(&mut db_guard, destination) {
+        Ok(set) => {
+            set.insert(member.to_vec());
+            RespMessage::Integer(1)
+        }
+        Err(e) => e,
+    }
+}
+
+/// The set-algebra operations behind SINTER, SUNION, SDIFF and their *STORE forms.
+#[derive(Clone, Copy, PartialEq)]
+pub enum SetOp {
+    Inter,
+    Union,
+    Diff,
+}
+
+/// Applies `op` across `sets`, where `None` stands for a missing (empty) key.
+fn combine(sets: &[Option<&Set>], op: SetOp) -> Set {
+    match op {
+        SetOp::Inter => {
+            if sets.iter().any(|set| set.is_none()) {
+                return Set::new();
+            }
+            let mut sets: Vec<&Set> = sets.iter().flatten().copied().collect();
+            // Probe the others with the members of the smallest set.
+            sets.sort_by_key(|set| set.len());
+            let Some((smallest, rest)) = sets.split_first() else {
+                return Set::new();
+            };
+            smallest
+                .iter()
+                .filter(|member| rest.iter().all(|set| set.contains(*member)))
+                .cloned()
+                .collect()
+        }
+        SetOp::Union => sets
+            .iter()
+            .flatten()
+            .flat_map(|set| set.iter())
+            .cloned()
+            .collect(),
+        SetOp::Diff => match sets.split_first() {
+            Some((Some(first), rest)) => first
+                .iter()
+                .filter(|member| !rest.iter().flatten().any(|set| set.contains(*member)))
+                .cloned()
+                .collect(),
+            _ => Set::new(),
+        },
+    }
+}
+
+/// SINTER / SUNION / SDIFF key [key ...]
+pub async fn set_op(args: &[&[u8]], db: &Db, op: SetOp) -> RespMessage {
+    if args.len() < 2 {
+        return wrong_arity(args[0]);
+    }
+    let mut db_guard = db.lock().await;
+    match get_sets(&mut db_guard, &args[1..]) {
+        Ok(sets) => members_reply(combine(&sets, op).iter()),
+        Err(e) => e,
+    }
+}
+
+/// SINTERSTORE / SUNIONSTORE / SDIFFSTORE destination key [key ...]
+pub async fn set_op_store(args: &[&[u8]], db: &Db, op: SetOp) -> RespMessage {
+    if args.len() < 3 {
+        return wrong_arity(args[0]);
+    }
+    let destination = args[1];
+    let mut db_guard = db.lock().await;
+    let result = match get_sets(&mut db_guard, &args[2..]) {
+        Ok(sets) => combine(&sets, op),
+        Err(e) => return e,
+    };
+
+    // The destination is overwritten whatever it held, and removed if the result is empty.
+    let len = result.len();
+    if result.is_empty() {
+        db_guard.delete(destination);
+    } else {
+        db_guard.insert(
+            destination.to_vec(),
+            ValueWithExpiry {
+                value: Value::Set(result),
+                expiry: None,
+            },
+        );
+    }
+    db_guard.dirty += 1;
+    RespMessage::Integer(len as i64)
+}
+
+/// SINTERCARD numkeys key [key ...] [LIMIT limit]
+pub async fn sintercard(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() < 3 {
+        return wrong_arity(args[0]);
+    }
+    let numkeys = match parse_i64(args[1]) {
+        Ok(numkeys) if numkeys > 0 => numkeys as usize,
+        Ok(_) => return RespMessage::Error("ERR numkeys should be greater than 0".to_string()),
+        Err(e) => return e,
+    };
+    if numkeys > args.len() - 2 {
+        return RespMessage::Error(
+            "ERR Number of keys can't be greater than number of args".to_string(),
+        );
+    }
+    let keys = &args[2..2 + numkeys];
+
+    let mut limit = 0;
+    let options = &args[2 + numkeys..];
+    match options {
+        [] => {}
+        [option, value] if option.eq_ignore_ascii_case(b"LIMIT") => match parse_i64(value) {
+            Ok(value) if value >= 0 => limit = value as usize,
+            Ok(_) => return RespMessage::Error("ERR LIMIT can't be negative".to_string()),
+            Err(e) => return e,
+        },
+        _ => return syntax_error(),
+    }
+
+    let mut db_guard = db.lock().await;
+    let sets = match get_sets(&mut db_guard, keys) {
+        Ok(sets) => sets,
+        Err(e) => return e,
+    };
+    let len = combine(&sets, SetOp::Inter).len();
+    RespMessage::Integer(if limit > 0 { len.min(limit) } else { len } as i64)
+}
+
+/// SSCAN key cursor [MATCH pattern] [COUNT count]
+pub async fn sscan(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() < 3 {
+        return wrong_arity(args[0]);
+    }
+    let options = match parse_scan_options(&args[2..], &[]) {
+        Ok(options) => options,
+        Err(e) => return e,
+    };
+    let mut db_guard = db.lock().await;
+    let set = match get_set(&mut db_guard, args[1]) {
+        Ok(Some(set)) => set,
+        Ok(None) => return scan_reply(0, vec![]),
+        Err(e) => return e,
+    };
+
+    let (cursor, page) = set.scan(options.cursor, options.count);
+    let elements = page
+        .into_iter()
+        .filter(|member| options.matches(member))
+        .map(|member| bulk(member))
+        .collect();
+    scan_reply(cursor, elements)
+}
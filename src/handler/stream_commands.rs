@@ -0,0 +1,1196 @@
+use crate::handler::aof::delivery_form;
+use crate::handler::blocking::{serve_blocked, wait_until_served, BlockingOp};
+use crate::handler::client_handler::Db;
+use crate::handler::commands::{
+    bulk, not_an_integer, now_millis, parse_i64, syntax_error, wrong_arity, wrong_type,
+};
+use crate::handler::keyspace::Keyspace;
+use crate::handler::value::{
+    is_empty_range, ConsumerGroup, Stream, StreamFields, StreamId, TrimStrategy, Value,
+};
+use crate::resp::resp_protocol::RespMessage;
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Bound;
+use std::time::Duration;
+
+/// Looks up the live stream stored at `key`.
+/// Returns `Ok(None)` for a missing key and a WRONGTYPE error for any other type.
+pub fn get_stream<'a>(
+    keyspace: &'a mut Keyspace,
+    key: &[u8],
+) -> Result<Option<&'a mut Stream>, RespMessage> {
+    match keyspace.lookup_write(key).map(|v| &mut v.value) {
+        Some(Value::Stream(stream)) => Ok(Some(stream)),
+        Some(_) => Err(wrong_type()),
+        None => Ok(None),
+    }
+}
+
+/// Like `get_stream`, but creates an empty stream when the key is missing.
+pub fn get_or_create_stream<'a>(
+    keyspace: &'a mut Keyspace,
+    key: &[u8],
+) -> Result<&'a mut Stream, RespMessage> {
+    let entry = keyspace.lookup_or_insert(key, || Value::Stream(Stream::default()));
+    match &mut entry.value {
+        Value::Stream(stream) => Ok(stream),
+        _ => Err(wrong_type()),
+    }
+}
+
+/// Looks up consumer group `group` of the stream at `key`, with Redis's NOGROUP
+/// error when either is missing.
+fn get_group<'a>(
+    keyspace: &'a mut Keyspace,
+    key: &[u8],
+    group: &[u8],
+) -> Result<(&'a mut ConsumerGroup, &'a StreamEntries), RespMessage> {
+    let Stream {
+        entries, groups, ..
+    } = get_stream(keyspace, key)?.ok_or_else(|| no_group(key, group))?;
+    match groups.get_mut(group) {
+        Some(consumer_group) => Ok((consumer_group, entries)),
+        None => Err(no_group(key, group)),
+    }
+}
+
+type StreamEntries = BTreeMap<StreamId, StreamFields>;
+
+fn no_group(key: &[u8], group: &[u8]) -> RespMessage {
+    RespMessage::Error(format!(
+        "NOGROUP No such key '{}' or consumer group '{}'",
+        String::from_utf8_lossy(key),
+        String::from_utf8_lossy(group)
+    ))
+}
+
+fn invalid_id() -> RespMessage {
+    RespMessage::Error("ERR Invalid stream ID specified as stream command argument".to_string())
+}
+
+fn parse_id(arg: &[u8], missing_seq: u64) -> Result<StreamId, RespMessage> {
+    StreamId::parse(arg, missing_seq).ok_or_else(invalid_id)
+}
+
+fn id_reply(id: StreamId) -> RespMessage {
+    RespMessage::BulkString(Some(id.to_string().into_bytes()))
+}
+
+/// `[id, [field, value, ...]]`, or `[id, nil]` for an entry that has since been deleted.
+fn entry_reply(id: StreamId, fields: Option<&StreamFields>) -> RespMessage {
+    let fields = match fields {
+        Some(fields) => RespMessage::Array(
+            fields
+                .iter()
+                .flat_map(|(field, value)| [bulk(field), bulk(value)])
+                .collect(),
+        ),
+        None => RespMessage::NullArray,
+    };
+    RespMessage::Array(vec![id_reply(id), fields])
+}
+
+pub fn entries_reply<'a>(
+    entries: impl IntoIterator<Item = (StreamId, &'a StreamFields)>,
+) -> RespMessage {
+    RespMessage::Array(
+        entries
+            .into_iter()
+            .map(|(id, fields)| entry_reply(id, Some(fields)))
+            .collect(),
+    )
+}
+
+/// One `[key, entries]` element of an XREAD / XREADGROUP reply.
+pub fn stream_reply(key: &[u8], entries: RespMessage) -> RespMessage {
+    RespMessage::Array(vec![bulk(key), entries])
+}
+
+/// Parses an XRANGE-style bound: `-`, `+`, an ID, or `(` plus an ID to exclude it.
+/// A bare millisecond time covers every sequence number within it.
+fn parse_range_bound(arg: &[u8], is_start: bool) -> Result<Bound<StreamId>, RespMessage> {
+    let missing_seq = if is_start { 0 } else { u64::MAX };
+    match arg {
+        b"-" => Ok(Bound::Included(StreamId::MIN)),
+        b"+" => Ok(Bound::Included(StreamId::MAX)),
+        _ => match arg.strip_prefix(b"(") {
+            Some(id) => Ok(Bound::Excluded(parse_id(id, missing_seq)?)),
+            None => Ok(Bound::Included(parse_id(arg, missing_seq)?)),
+        },
+    }
+}
+
+/// Parses `MAXLEN|MINID [=|~] threshold [LIMIT count]` at the start of `args`.
+/// Returns the trim settings and how many arguments they took up.
+pub fn parse_trim(args: &[&[u8]]) -> Result<((TrimStrategy, Option<usize>), usize), RespMessage> {
+    let mut i = 1;
+    let approximate = args.get(i).copied() == Some(b"~");
+    if matches!(args.get(i).copied(), Some(b"~" | b"=")) {
+        i += 1;
+    }
+    let Some(threshold) = args.get(i) else {
+        return Err(syntax_error());
+    };
+    let strategy = if args[0].eq_ignore_ascii_case(b"MAXLEN") {
+        let max = parse_i64(threshold)?;
+        if max < 0 {
+            return Err(RespMessage::Error(
+                "ERR The MAXLEN argument must be >= 0.".to_string(),
+            ));
+        }
+        TrimStrategy::MaxLen(max as u64)
+    } else {
+        TrimStrategy::MinId(parse_id(threshold, 0)?)
+    };
+    i += 1;
+
+    let mut limit = None;
+    if args
+        .get(i)
+        .is_some_and(|arg| arg.eq_ignore_ascii_case(b"LIMIT"))
+    {
+        if !approximate {
+            return Err(RespMessage::Error(
+                "ERR syntax error, LIMIT cannot be used without the special ~ option".to_string(),
+            ));
+        }
+        let count = args.get(i + 1).ok_or_else(syntax_error)?;
+        let count = parse_i64(count)?;
+        if count < 0 {
+            return Err(not_an_integer());
+        }
+        // LIMIT 0 means no limit.
+        limit = (count > 0).then_some(count as usize);
+        i += 2;
+    }
+    // `~` asks for a cheaper, inexact trim; trimming exactly honours it too.
+    Ok(((strategy, limit), i))
+}
+
+/// How XADD picks the ID of a new entry.
+enum IdSpec {
+    /// `*`
+    Auto,
+    /// `ms-*`
+    AutoSeq(u64),
+    Explicit(StreamId),
+}
+
+fn parse_id_spec(arg: &[u8]) -> Result<IdSpec, RespMessage> {
+    if arg == b"*" {
+        return Ok(IdSpec::Auto);
+    }
+    if let Some(ms) = arg.strip_suffix(b"-*") {
+        return match StreamId::parse(ms, 0) {
+            Some(id) if !ms.contains(&b'-') => Ok(IdSpec::AutoSeq(id.ms)),
+            _ => Err(invalid_id()),
+        };
+    }
+    Ok(IdSpec::Explicit(parse_id(arg, 0)?))
+}
+
+fn id_not_greater() -> RespMessage {
+    RespMessage::Error(
+        "ERR The ID specified in XADD is equal or smaller than the target stream top item"
+            .to_string(),
+    )
+}
+
+/// Picks the ID for a new entry, enforcing that IDs only ever grow.
+fn resolve_id(stream: &Stream, spec: &IdSpec) -> Result<StreamId, RespMessage> {
+    let last = stream.last_id;
+    match *spec {
+        IdSpec::Auto => stream.next_id(now_millis() as u64).ok_or_else(|| {
+            RespMessage::Error(
+                "ERR The stream has exhausted the last possible ID, unable to add more items"
+                    .to_string(),
+            )
+        }),
+        IdSpec::AutoSeq(ms) if ms > last.ms => Ok(StreamId { ms, seq: 0 }),
+        IdSpec::AutoSeq(ms) if ms == last.ms => last
+            .seq
+            .checked_add(1)
+            .map(|seq| StreamId { ms, seq })
+            .ok_or_else(id_not_greater),
+        IdSpec::AutoSeq(_) => Err(id_not_greater()),
+        IdSpec::Explicit(StreamId::MIN) => Err(RespMessage::Error(
+            "ERR The ID specified in XADD must be greater than 0-0".to_string(),
+        )),
+        IdSpec::Explicit(id) if id <= last => Err(id_not_greater()),
+        IdSpec::Explicit(id) => Ok(id),
+    }
+}
+
+/// XADD key [NOMKSTREAM] [MAXLEN|MINID [=|~] threshold [LIMIT count]] *|id field value [field value ...]
+pub async fn xadd(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() < 5 {
+        return wrong_arity(args[0]);
+    }
+    let mut no_mkstream = false;
+    let mut trim = None;
+    let mut i = 2;
+    while i < args.len() {
+        let option = args[i].to_ascii_uppercase();
+        match option.as_slice() {
+            b"NOMKSTREAM" => {
+                no_mkstream = true;
+                i += 1;
+            }
+            b"MAXLEN" | b"MINID" => match parse_trim(&args[i..]) {
+                Ok((options, used)) => {
+                    trim = Some(options);
+                    i += used;
+                }
+                Err(e) => return e,
+            },
+            _ => break,
+        }
+    }
+    let Some(id_arg) = args.get(i) else {
+        return wrong_arity(args[0]);
+    };
+    let fields = &args[i + 1..];
+    if fields.is_empty() || !fields.len().is_multiple_of(2) {
+        return wrong_arity(args[0]);
+    }
+    let spec = match parse_id_spec(id_arg) {
+        Ok(spec) => spec,
+        Err(e) => return e,
+    };
+
+    let key = args[1];
+    let mut db_guard = db.lock().await;
+    let stream = match get_stream(&mut db_guard, key) {
+        Ok(None) if no_mkstream => return RespMessage::BulkString(None),
+        Err(e) => return e,
+        Ok(_) => match get_or_create_stream(&mut db_guard, key) {
+            Ok(stream) => stream,
+            Err(e) => return e,
+        },
+    };
+    let id = match resolve_id(stream, &spec) {
+        Ok(id) => id,
+        Err(e) => {
+            // Don't leave behind a stream created just for this failed call.
+            if stream.len() == 0 && stream.last_id == StreamId::MIN && stream.groups.is_empty() {
+                db_guard.delete(key);
+            }
+            return e;
+        }
+    };
+    stream.add(
+        id,
+        fields
+            .chunks(2)
+            .map(|pair| (pair[0].to_vec(), pair[1].to_vec()))
+            .collect(),
+    );
+    if let Some((strategy, limit)) = trim {
+        stream.trim(strategy, limit);
+    }
+    db_guard.dirty += 1;
+    serve_blocked(&mut db_guard, key);
+    id_reply(id)
+}
+
+/// XLEN key
+pub async fn xlen(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() != 2 {
+        return wrong_arity(args[0]);
+    }
+    let mut db_guard = db.lock().await;
+    match get_stream(&mut db_guard, args[1]) {
+        Ok(stream) => RespMessage::Integer(stream.map_or(0, |stream| stream.len() as i64)),
+        Err(e) => e,
+    }
+}
+
+/// XRANGE key start end [COUNT count] / XREVRANGE key end start [COUNT count]
+pub async fn xrange(args: &[&[u8]], db: &Db, reverse: bool) -> RespMessage {
+    if args.len() != 4 && args.len() != 6 {
+        return wrong_arity(args[0]);
+    }
+    let (start, end) = if reverse {
+        (args[3], args[2])
+    } else {
+        (args[2], args[3])
+    };
+    let bounds = parse_range_bound(start, true)
+        .and_then(|start| parse_range_bound(end, false).map(|end| (start, end)));
+    let (start, end) = match bounds {
+        Ok(bounds) => bounds,
+        Err(e) => return e,
+    };
+    let count = match args.get(4..6) {
+        None => None,
+        Some([option, count]) if option.eq_ignore_ascii_case(b"COUNT") => match parse_i64(count) {
+            Ok(count) => Some(count.max(0) as usize),
+            Err(e) => return e,
+        },
+        Some(_) => return syntax_error(),
+    };
+
+    let mut db_guard = db.lock().await;
+    match get_stream(&mut db_guard, args[1]) {
+        Ok(Some(stream)) => entries_reply(stream.range(start, end, reverse, count)),
+        Ok(None) => RespMessage::Array(vec![]),
+        Err(e) => e,
+    }
+}
+
+/// XDEL key id [id ...]
+pub async fn xdel(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() < 3 {
+        return wrong_arity(args[0]);
+    }
+    let ids = match args[2..]
+        .iter()
+        .map(|arg| parse_id(arg, 0))
+        .collect::<Result<Vec<_>, _>>()
+    {
+        Ok(ids) => ids,
+        Err(e) => return e,
+    };
+    let mut db_guard = db.lock().await;
+    let deleted = match get_stream(&mut db_guard, args[1]) {
+        Ok(Some(stream)) => ids
+            .iter()
+            .filter(|id| stream.entries.remove(id).is_some())
+            .count(),
+        Ok(None) => 0,
+        Err(e) => return e,
+    };
+    db_guard.dirty += deleted as u64;
+    RespMessage::Integer(deleted as i64)
+}
+
+/// XTRIM key MAXLEN|MINID [=|~] threshold [LIMIT count]
+pub async fn xtrim(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() < 4 {
+        return wrong_arity(args[0]);
+    }
+    if !args[2].eq_ignore_ascii_case(b"MAXLEN") && !args[2].eq_ignore_ascii_case(b"MINID") {
+        return syntax_error();
+    }
+    let (strategy, limit) = match parse_trim(&args[2..]) {
+        Ok((options, used)) if 2 + used == args.len() => options,
+        Ok(_) => return syntax_error(),
+        Err(e) => return e,
+    };
+    let mut db_guard = db.lock().await;
+    let trimmed = match get_stream(&mut db_guard, args[1]) {
+        Ok(Some(stream)) => stream.trim(strategy, limit),
+        Ok(None) => 0,
+        Err(e) => return e,
+    };
+    db_guard.dirty += trimmed as u64;
+    RespMessage::Integer(trimmed as i64)
+}
+
+/// XSETID key last-id [ENTRIESADDED entries-added] [MAXDELETEDID max-deleted-id]
+/// The two counters only feed lag reporting, which xredis doesn't do, so they are
+/// checked and ignored.
+pub async fn xsetid(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() < 3 {
+        return wrong_arity(args[0]);
+    }
+    let id = match parse_id(args[2], 0) {
+        Ok(id) => id,
+        Err(e) => return e,
+    };
+    for option in args[3..].chunks(2) {
+        let valid = match option {
+            [name, value] if name.eq_ignore_ascii_case(b"ENTRIESADDED") => {
+                parse_i64(value).map(|_| ())
+            }
+            [name, value] if name.eq_ignore_ascii_case(b"MAXDELETEDID") => {
+                parse_id(value, 0).map(|_| ())
+            }
+            _ => Err(syntax_error()),
+        };
+        if let Err(e) = valid {
+            return e;
+        }
+    }
+
+    let mut db_guard = db.lock().await;
+    let stream = match get_stream(&mut db_guard, args[1]) {
+        Ok(Some(stream)) => stream,
+        Ok(None) => return RespMessage::Error("ERR no such key".to_string()),
+        Err(e) => return e,
+    };
+    if stream
+        .entries
+        .last_key_value()
+        .is_some_and(|(&top, _)| id < top)
+    {
+        return RespMessage::Error(
+            "ERR The ID specified in XSETID is smaller than the target stream top item".to_string(),
+        );
+    }
+    stream.last_id = id;
+    db_guard.dirty += 1;
+    RespMessage::SimpleString("OK".to_string())
+}
+
+/// The options shared by XREAD and XREADGROUP.
+struct ReadOptions<'a> {
+    count: Option<usize>,
+    /// `None` doesn't block; `Some(None)` blocks forever.
+    block: Option<Option<Duration>>,
+    no_ack: bool,
+    group: Option<(&'a [u8], &'a [u8])>,
+    keys: Vec<&'a [u8]>,
+    ids: Vec<&'a [u8]>,
+}
+
+fn parse_read_options<'a>(
+    args: &[&'a [u8]],
+    group_read: bool,
+) -> Result<ReadOptions<'a>, RespMessage> {
+    let mut options = ReadOptions {
+        count: None,
+        block: None,
+        no_ack: false,
+        group: None,
+        keys: Vec::new(),
+        ids: Vec::new(),
+    };
+    let mut i = 1;
+    while i < args.len() {
+        let option = args[i].to_ascii_uppercase();
+        match (option.as_slice(), args.get(i + 1)) {
+            (b"COUNT", Some(count)) => {
+                let count = parse_i64(count)?;
+                // A count of zero or less means no limit.
+                options.count = (count > 0).then_some(count as usize);
+                i += 2;
+            }
+            (b"BLOCK", Some(timeout)) => {
+                let timeout = parse_i64(timeout)?;
+                if timeout < 0 {
+                    return Err(RespMessage::Error("ERR timeout is negative".to_string()));
+                }
+                options.block = Some((timeout > 0).then(|| Duration::from_millis(timeout as u64)));
+                i += 2;
+            }
+            (b"GROUP", Some(group)) if group_read && i + 2 < args.len() => {
+                options.group = Some((group, args[i + 2]));
+                i += 3;
+            }
+            (b"NOACK", _) if group_read => {
+                options.no_ack = true;
+                i += 1;
+            }
+            (b"STREAMS", _) => {
+                let streams = &args[i + 1..];
+                if streams.is_empty() || !streams.len().is_multiple_of(2) {
+                    return Err(RespMessage::Error(format!(
+                        "ERR Unbalanced '{}' list of streams: for each stream key an ID{} must be specified.",
+                        String::from_utf8_lossy(args[0]).to_lowercase(),
+                        if group_read { " or '>'" } else { " or '$'" }
+                    )));
+                }
+                let (keys, ids) = streams.split_at(streams.len() / 2);
+                options.keys = keys.to_vec();
+                options.ids = ids.to_vec();
+                break;
+            }
+            _ => return Err(syntax_error()),
+        }
+    }
+    if options.keys.is_empty() {
+        return Err(syntax_error());
+    }
+    if group_read && options.group.is_none() {
+        return Err(RespMessage::Error(
+            "ERR Missing GROUP option for XREADGROUP".to_string(),
+        ));
+    }
+    Ok(options)
+}
+
+/// XREAD [COUNT count] [BLOCK milliseconds] STREAMS key [key ...] id [id ...]
+pub async fn xread(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() < 4 {
+        return wrong_arity(args[0]);
+    }
+    let options = match parse_read_options(args, false) {
+        Ok(options) => options,
+        Err(e) => return e,
+    };
+
+    let blocked = {
+        let mut db_guard = db.lock().await;
+        let mut after = HashMap::new();
+        let mut reply = Vec::new();
+        for (key, id) in options.keys.iter().zip(&options.ids) {
+            let stream = match get_stream(&mut db_guard, key) {
+                Ok(stream) => stream,
+                Err(e) => return e,
+            };
+            // `$` means "only entries added from now on".
+            let id = if *id == b"$" {
+                stream
+                    .as_ref()
+                    .map_or(StreamId::MIN, |stream| stream.last_id)
+            } else {
+                match parse_id(id, 0) {
+                    Ok(id) => id,
+                    Err(e) => return e,
+                }
+            };
+            if let Some(stream) = stream {
+                let entries =
+                    stream.range(Bound::Excluded(id), Bound::Unbounded, false, options.count);
+                if !entries.is_empty() {
+                    reply.push(stream_reply(key, entries_reply(entries)));
+                }
+            }
+            after.insert(key.to_vec(), id);
+        }
+
+        if !reply.is_empty() {
+            return RespMessage::Array(reply);
+        }
+        let Some(timeout) = options.block else {
+            return RespMessage::NullArray;
+        };
+        (
+            db_guard.block(
+                options.keys.iter().map(|key| key.to_vec()).collect(),
+                BlockingOp::ReadStream {
+                    after,
+                    count: options.count,
+                },
+            ),
+            timeout,
+        )
+    };
+
+    let (blocked, timeout) = blocked;
+    wait_until_served(db, blocked, timeout)
+        .await
+        .unwrap_or(RespMessage::NullArray)
+}
+
+/// What `deliver_new` handed a consumer.
+#[derive(Default)]
+pub struct Delivery {
+    pub entries: Vec<(StreamId, StreamFields)>,
+    /// Whether the consumer was created to take them.
+    pub new_consumer: bool,
+}
+
+/// Hands the entries added after the group's last delivered ID to `consumer` at
+/// `now`, recording them as pending unless `no_ack` is set. Returns `None` if the
+/// group does not exist.
+pub fn deliver_new(
+    stream: &mut Stream,
+    group: &[u8],
+    consumer: &[u8],
+    count: Option<usize>,
+    no_ack: bool,
+    now: u128,
+) -> Option<Delivery> {
+    let consumer_group = stream.groups.get_mut(group)?;
+    let new_consumer = !consumer_group.consumers.contains_key(consumer);
+    consumer_group.consumer(consumer, now);
+    let entries: Vec<(StreamId, StreamFields)> = stream
+        .entries
+        .range((
+            Bound::Excluded(consumer_group.last_delivered),
+            Bound::Unbounded,
+        ))
+        .take(count.unwrap_or(usize::MAX))
+        .map(|(id, fields)| (*id, fields.clone()))
+        .collect();
+    for (id, _) in &entries {
+        consumer_group.last_delivered = *id;
+        if !no_ack {
+            consumer_group.assign(*id, consumer, now, 1);
+        }
+    }
+    Some(Delivery {
+        entries,
+        new_consumer,
+    })
+}
+
+/// XREADGROUP GROUP group consumer [COUNT count] [BLOCK milliseconds] [NOACK] STREAMS key [key ...] id [id ...]
+pub async fn xreadgroup(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() < 7 {
+        return wrong_arity(args[0]);
+    }
+    let options = match parse_read_options(args, true) {
+        Ok(options) => options,
+        Err(e) => return e,
+    };
+    let Some((group, consumer)) = options.group else {
+        return syntax_error();
+    };
+    // `>` asks for new entries; an ID re-reads the consumer's own pending history.
+    let mut history = Vec::with_capacity(options.ids.len());
+    for id in &options.ids {
+        if *id == b">" {
+            history.push(None);
+        } else {
+            match parse_id(id, 0) {
+                Ok(id) => history.push(Some(id)),
+                Err(e) => return e,
+            }
+        }
+    }
+
+    let blocked = {
+        let mut db_guard = db.lock().await;
+        for key in &options.keys {
+            if let Err(e) = get_group(&mut db_guard, key, group) {
+                return match e {
+                    RespMessage::Error(e) if e.starts_with("NOGROUP") => {
+                        RespMessage::Error(format!("{} in XREADGROUP with GROUP option", e))
+                    }
+                    e => e,
+                };
+            }
+        }
+
+        let now = db_guard.now();
+        let mut reply = Vec::new();
+        let mut delivered = 0;
+        for (key, after) in options.keys.iter().zip(&history) {
+            let Ok(Some(stream)) = get_stream(&mut db_guard, key) else {
+                continue;
+            };
+            let delivery = match after {
+                None => {
+                    let delivery =
+                        deliver_new(stream, group, consumer, options.count, options.no_ack, now)
+                            .unwrap_or_default();
+                    delivered += delivery.entries.len();
+                    if !delivery.entries.is_empty() {
+                        reply.push(stream_reply(
+                            key,
+                            entries_reply(
+                                delivery.entries.iter().map(|(id, fields)| (*id, fields)),
+                            ),
+                        ));
+                    }
+                    delivery
+                }
+                Some(after) => {
+                    let Some(consumer_group) = stream.groups.get_mut(group) else {
+                        continue;
+                    };
+                    let new_consumer = !consumer_group.consumers.contains_key(consumer);
+                    let pending = &consumer_group.consumer(consumer, now).pending;
+                    let entries = pending
+                        .range((Bound::Excluded(*after), Bound::Unbounded))
+                        .take(options.count.unwrap_or(usize::MAX))
+                        .map(|id| entry_reply(*id, stream.entries.get(id)))
+                        .collect();
+                    reply.push(stream_reply(key, RespMessage::Array(entries)));
+                    Delivery {
+                        entries: Vec::new(),
+                        new_consumer,
+                    }
+                }
+            };
+            if let Some(propagation) = &db_guard.propagation {
+                let logged = delivery_form(key, group, consumer, &delivery, options.no_ack, now);
+                for command in logged {
+                    propagation.propagate(command);
+                }
+            }
+        }
+        db_guard.dirty += delivered as u64;
+
+        if !reply.is_empty() {
+            return RespMessage::Array(reply);
+        }
+        let Some(timeout) = options.block else {
+            return RespMessage::NullArray;
+        };
+        (
+            db_guard.block(
+                options.keys.iter().map(|key| key.to_vec()).collect(),
+                BlockingOp::ReadGroup {
+                    group: group.to_vec(),
+                    consumer: consumer.to_vec(),
+                    count: options.count,
+                    no_ack: options.no_ack,
+                },
+            ),
+            timeout,
+        )
+    };
+
+    let (blocked, timeout) = blocked;
+    wait_until_served(db, blocked, timeout)
+        .await
+        .unwrap_or(RespMessage::NullArray)
+}
+
+/// Resolves an XGROUP ID argument, where `$` means the stream's last ID.
+fn parse_group_id(arg: &[u8], stream: &Stream) -> Result<StreamId, RespMessage> {
+    if arg == b"$" {
+        Ok(stream.last_id)
+    } else {
+        parse_id(arg, 0)
+    }
+}
+
+/// Checks the optional trailing `ENTRIESREAD n` of XGROUP CREATE / SETID.
+/// The counter is only used for lag reporting, which xredis doesn't do, so it is ignored.
+fn check_entries_read(args: &[&[u8]]) -> Result<(), RespMessage> {
+    match args {
+        [] => Ok(()),
+        [option, value] if option.eq_ignore_ascii_case(b"ENTRIESREAD") => {
+            parse_i64(value).map(|_| ())
+        }
+        _ => Err(syntax_error()),
+    }
+}
+
+/// XGROUP CREATE | SETID | DESTROY | CREATECONSUMER | DELCONSUMER key group ...
+pub async fn xgroup(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() < 2 {
+        return wrong_arity(args[0]);
+    }
+    let subcommand = args[1].to_ascii_uppercase();
+    let arity_ok = match subcommand.as_slice() {
+        b"CREATE" => args.len() >= 5,
+        b"SETID" => args.len() >= 5,
+        b"DESTROY" => args.len() == 4,
+        b"CREATECONSUMER" | b"DELCONSUMER" => args.len() == 5,
+        _ => {
+            return RespMessage::Error(format!(
+                "ERR unknown subcommand '{}'. Try XGROUP HELP.",
+                String::from_utf8_lossy(args[1])
+            ))
+        }
+    };
+    if !arity_ok {
+        return wrong_arity(format!("xgroup|{}", String::from_utf8_lossy(args[1])).as_bytes());
+    }
+    let (key, group) = (args[2], args[3]);
+    let mut db_guard = db.lock().await;
+
+    match subcommand.as_slice() {
+        b"CREATE" => {
+            let mkstream = args[5..]
+                .first()
+                .is_some_and(|arg| arg.eq_ignore_ascii_case(b"MKSTREAM"));
+            if let Err(e) = check_entries_read(&args[5 + mkstream as usize..]) {
+                return e;
+            }
+            let stream = match get_stream(&mut db_guard, key) {
+                Ok(Some(stream)) => stream,
+                Ok(None) if mkstream => match get_or_create_stream(&mut db_guard, key) {
+                    Ok(stream) => stream,
+                    Err(e) => return e,
+                },
+                Ok(None) => {
+                    return RespMessage::Error(
+                        "ERR The XGROUP subcommand requires the key to exist. Note that for CREATE you may want to use the MKSTREAM option to create an empty stream automatically."
+                            .to_string(),
+                    )
+                }
+                Err(e) => return e,
+            };
+            let last_delivered = match parse_group_id(args[4], stream) {
+                Ok(id) => id,
+                Err(e) => return e,
+            };
+            if stream.groups.contains_key(group) {
+                return RespMessage::Error(
+                    "BUSYGROUP Consumer Group name already exists".to_string(),
+                );
+            }
+            stream.groups.insert(
+                group.to_vec(),
+                ConsumerGroup {
+                    last_delivered,
+                    ..ConsumerGroup::default()
+                },
+            );
+            db_guard.dirty += 1;
+            RespMessage::SimpleString("OK".to_string())
+        }
+        b"SETID" => {
+            if let Err(e) = check_entries_read(&args[5..]) {
+                return e;
+            }
+            let stream = match get_stream(&mut db_guard, key) {
+                Ok(Some(stream)) => stream,
+                Ok(None) => return no_group(key, group),
+                Err(e) => return e,
+            };
+            let id = match parse_group_id(args[4], stream) {
+                Ok(id) => id,
+                Err(e) => return e,
+            };
+            match stream.groups.get_mut(group) {
+                Some(consumer_group) => {
+                    consumer_group.last_delivered = id;
+                    db_guard.dirty += 1;
+                    RespMessage::SimpleString("OK".to_string())
+                }
+                None => no_group(key, group),
+            }
+        }
+        b"DESTROY" => match get_stream(&mut db_guard, key) {
+            Ok(Some(stream)) => {
+                let destroyed = stream.groups.remove(group).is_some();
+                db_guard.dirty += destroyed as u64;
+                RespMessage::Integer(destroyed as i64)
+            }
+            Ok(None) => no_group(key, group),
+            Err(e) => e,
+        },
+        b"CREATECONSUMER" => match get_group(&mut db_guard, key, group) {
+            Ok((consumer_group, _)) => {
+                let created = !consumer_group.consumers.contains_key(args[4]);
+                consumer_group.consumer(args[4], now_millis());
+                db_guard.dirty += created as u64;
+                RespMessage::Integer(created as i64)
+            }
+            Err(e) => e,
+        },
+        _ => match get_group(&mut db_guard, key, group) {
+            // DELCONSUMER: the consumer's pending entries go with it.
+            Ok((consumer_group, _)) => match consumer_group.consumers.remove(args[4]) {
+                Some(consumer) => {
+                    for id in &consumer.pending {
+                        consumer_group.pending.remove(id);
+                    }
+                    db_guard.dirty += 1;
+                    RespMessage::Integer(consumer.pending.len() as i64)
+                }
+                None => RespMessage::Integer(0),
+            },
+            Err(e) => e,
+        },
+    }
+}
+
+/// XACK key group id [id ...]
+pub async fn xack(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() < 4 {
+        return wrong_arity(args[0]);
+    }
+    let ids = match args[3..]
+        .iter()
+        .map(|arg| parse_id(arg, 0))
+        .collect::<Result<Vec<_>, _>>()
+    {
+        Ok(ids) => ids,
+        Err(e) => return e,
+    };
+    let mut db_guard = db.lock().await;
+    let acknowledged = match get_group(&mut db_guard, args[1], args[2]) {
+        Ok((consumer_group, _)) => ids
+            .into_iter()
+            .filter(|id| consumer_group.acknowledge(*id))
+            .count(),
+        Err(RespMessage::Error(e)) if e.starts_with("NOGROUP") => 0,
+        Err(e) => return e,
+    };
+    db_guard.dirty += acknowledged as u64;
+    RespMessage::Integer(acknowledged as i64)
+}
+
+/// XPENDING key group [[IDLE min-idle-time] start end count [consumer]]
+pub async fn xpending(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() < 3 {
+        return wrong_arity(args[0]);
+    }
+    // The extended form: [IDLE min-idle-time] start end count [consumer]
+    let mut extended = None;
+    if args.len() > 3 {
+        let mut rest = &args[3..];
+        let mut min_idle = 0;
+        if rest[0].eq_ignore_ascii_case(b"IDLE") {
+            if rest.len() < 2 {
+                return syntax_error();
+            }
+            min_idle = match parse_i64(rest[1]) {
+                Ok(idle) => idle.max(0) as u128,
+                Err(e) => return e,
+            };
+            rest = &rest[2..];
+        }
+        if rest.len() != 3 && rest.len() != 4 {
+            return syntax_error();
+        }
+        let start = match parse_range_bound(rest[0], true) {
+            Ok(start) => start,
+            Err(e) => return e,
+        };
+        let end = match parse_range_bound(rest[1], false) {
+            Ok(end) => end,
+            Err(e) => return e,
+        };
+        let count = match parse_i64(rest[2]) {
+            Ok(count) => count.max(0) as usize,
+            Err(e) => return e,
+        };
+        extended = Some((min_idle, start, end, count, rest.get(3).copied()));
+    }
+
+    let mut db_guard = db.lock().await;
+    let consumer_group = match get_group(&mut db_guard, args[1], args[2]) {
+        Ok((consumer_group, _)) => consumer_group,
+        Err(e) => return e,
+    };
+
+    let Some((min_idle, start, end, count, consumer)) = extended else {
+        let pending = &consumer_group.pending;
+        let (Some((first, _)), Some((last, _))) =
+            (pending.first_key_value(), pending.last_key_value())
+        else {
+            return RespMessage::Array(vec![
+                RespMessage::Integer(0),
+                RespMessage::BulkString(None),
+                RespMessage::BulkString(None),
+                RespMessage::NullArray,
+            ]);
+        };
+        let mut consumers: Vec<(&Vec<u8>, usize)> = consumer_group
+            .consumers
+            .iter()
+            .filter(|(_, consumer)| !consumer.pending.is_empty())
+            .map(|(name, consumer)| (name, consumer.pending.len()))
+            .collect();
+        consumers.sort();
+        return RespMessage::Array(vec![
+            RespMessage::Integer(pending.len() as i64),
+            id_reply(*first),
+            id_reply(*last),
+            RespMessage::Array(
+                consumers
+                    .into_iter()
+                    .map(|(name, count)| {
+                        RespMessage::Array(vec![bulk(name), bulk(count.to_string().as_bytes())])
+                    })
+                    .collect(),
+            ),
+        ]);
+    };
+
+    if is_empty_range(start, end) {
+        return RespMessage::Array(vec![]);
+    }
+    let now = now_millis();
+    RespMessage::Array(
+        consumer_group
+            .pending
+            .range((start, end))
+            .filter(|(_, entry)| consumer.is_none_or(|name| entry.consumer == name))
+            .filter(|(_, entry)| now.saturating_sub(entry.delivery_time) >= min_idle)
+            .take(count)
+            .map(|(id, entry)| {
+                RespMessage::Array(vec![
+                    id_reply(*id),
+                    bulk(&entry.consumer),
+                    RespMessage::Integer(now.saturating_sub(entry.delivery_time) as i64),
+                    RespMessage::Integer(entry.delivery_count as i64),
+                ])
+            })
+            .collect(),
+    )
+}
+
+/// XCLAIM key group consumer min-idle-time id [id ...] [IDLE ms] [TIME unix-time-milliseconds]
+/// [RETRYCOUNT count] [FORCE] [JUSTID] [LASTID lastid]
+pub async fn xclaim(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() < 6 {
+        return wrong_arity(args[0]);
+    }
+    let consumer = args[3];
+    let min_idle = match parse_i64(args[4]) {
+        Ok(idle) => idle.max(0) as u128,
+        Err(e) => return e,
+    };
+    // IDs run until the first argument that isn't one; options follow.
+    let mut ids = Vec::new();
+    let mut i = 5;
+    while let Some(id) = args.get(i).and_then(|arg| StreamId::parse(arg, 0)) {
+        ids.push(id);
+        i += 1;
+    }
+    if ids.is_empty() {
+        return invalid_id();
+    }
+
+    let now = now_millis();
+    let mut delivery_time = now;
+    let mut retry_count = None;
+    let mut force = false;
+    let mut just_id = false;
+    let mut last_id = None;
+    while i < args.len() {
+        let option = args[i].to_ascii_uppercase();
+        match (option.as_slice(), args.get(i + 1)) {
+            (b"IDLE", Some(idle)) => {
+                match parse_i64(idle) {
+                    Ok(idle) => delivery_time = now.saturating_sub(idle.max(0) as u128),
+                    Err(e) => return e,
+                }
+                i += 2;
+            }
+            (b"TIME", Some(time)) => {
+                match parse_i64(time) {
+                    Ok(time) => delivery_time = time.max(0) as u128,
+                    Err(e) => return e,
+                }
+                i += 2;
+            }
+            (b"RETRYCOUNT", Some(count)) => {
+                match parse_i64(count) {
+                    Ok(count) => retry_count = Some(count.max(0) as u64),
+                    Err(e) => return e,
+                }
+                i += 2;
+            }
+            (b"LASTID", Some(id)) => {
+                match parse_id(id, 0) {
+                    Ok(id) => last_id = Some(id),
+                    Err(e) => return e,
+                }
+                i += 2;
+            }
+            (b"FORCE", _) => {
+                force = true;
+                i += 1;
+            }
+            (b"JUSTID", _) => {
+                just_id = true;
+                i += 1;
+            }
+            _ => return syntax_error(),
+        }
+    }
+
+    let mut db_guard = db.lock().await;
+    let (consumer_group, entries) = match get_group(&mut db_guard, args[1], args[2]) {
+        Ok(found) => found,
+        Err(e) => return e,
+    };
+    if let Some(last_id) = last_id {
+        consumer_group.last_delivered = consumer_group.last_delivered.max(last_id);
+    }
+    consumer_group.consumer(consumer, now);
+
+    let mut reply = Vec::new();
+    for id in ids {
+        let pending = consumer_group.pending.get(&id).cloned();
+        let Some(fields) = entries.get(&id) else {
+            // The entry was deleted from the stream, so there's nothing left to claim.
+            consumer_group.acknowledge(id);
+            continue;
+        };
+        let count = match pending {
+            Some(entry) if now.saturating_sub(entry.delivery_time) < min_idle => continue,
+            Some(entry) => entry.delivery_count,
+            None if force => 0,
+            None => continue,
+        };
+        let count = retry_count.unwrap_or(if just_id { count } else { count + 1 });
+        consumer_group.assign(id, consumer, delivery_time, count);
+        reply.push(if just_id {
+            id_reply(id)
+        } else {
+            entry_reply(id, Some(fields))
+        });
+    }
+    db_guard.dirty += reply.len() as u64;
+    RespMessage::Array(reply)
+}
+
+/// XAUTOCLAIM key group consumer min-idle-time start [COUNT count] [JUSTID]
+pub async fn xautoclaim(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() < 6 {
+        return wrong_arity(args[0]);
+    }
+    let consumer = args[3];
+    let min_idle = match parse_i64(args[4]) {
+        Ok(idle) => idle.max(0) as u128,
+        Err(e) => return e,
+    };
+    let start = match parse_range_bound(args[5], true) {
+        Ok(start) => start,
+        Err(e) => return e,
+    };
+    let mut count = 100;
+    let mut just_id = false;
+    let mut i = 6;
+    while i < args.len() {
+        let option = args[i].to_ascii_uppercase();
+        match (option.as_slice(), args.get(i + 1)) {
+            (b"COUNT", Some(value)) => {
+                count = match parse_i64(value) {
+                    Ok(value) if value >= 1 => value as usize,
+                    Ok(_) => return RespMessage::Error("ERR COUNT must be > 0".to_string()),
+                    Err(e) => return e,
+                };
+                i += 2;
+            }
+            (b"JUSTID", _) => {
+                just_id = true;
+                i += 1;
+            }
+            _ => return syntax_error(),
+        }
+    }
+
+    let mut db_guard = db.lock().await;
+    let (consumer_group, entries) = match get_group(&mut db_guard, args[1], args[2]) {
+        Ok(found) => found,
+        Err(e) => return e,
+    };
+    let now = now_millis();
+    consumer_group.consumer(consumer, now);
+
+    // Like Redis, look at no more than ten pending entries per requested claim.
+    let scanned: Vec<StreamId> = consumer_group
+        .pending
+        .range((start, Bound::Unbounded))
+        .map(|(id, _)| *id)
+        .take(count * 10 + 1)
+        .collect();
+    let mut claimed = Vec::new();
+    let mut deleted = Vec::new();
+    let mut next = StreamId::MIN;
+    for (position, id) in scanned.iter().enumerate() {
+        if claimed.len() + deleted.len() == count || position == count * 10 {
+            next = *id;
+            break;
+        }
+        let Some(fields) = entries.get(id) else {
+            consumer_group.acknowledge(*id);
+            deleted.push(id_reply(*id));
+            continue;
+        };
+        let Some(entry) = consumer_group.pending.get(id) else {
+            continue;
+        };
+        if now.saturating_sub(entry.delivery_time) < min_idle {
+            continue;
+        }
+        let delivery_count = entry.delivery_count + if just_id { 0 } else { 1 };
+        consumer_group.assign(*id, consumer, now, delivery_count);
+        claimed.push(if just_id {
+            id_reply(*id)
+        } else {
+            entry_reply(*id, Some(fields))
+        });
+    }
+
+    db_guard.dirty += (claimed.len() + deleted.len()) as u64;
+    RespMessage::Array(vec![
+        id_reply(next),
+        RespMessage::Array(claimed),
+        RespMessage::Array(deleted),
+    ])
+}
@@ -0,0 +1,501 @@
+use crate::handler::client_handler::Db;
+use crate::handler::commands::{
+    bulk, format_float, not_an_integer, parse_float, parse_i64, syntax_error, wrong_arity,
+    wrong_type,
+};
+use crate::handler::expire_commands::{absolute_millis, invalid_expire_time, ExpireAt, TimeUnit};
+use crate::handler::keyspace::Keyspace;
+use crate::handler::value::{Value, ValueWithExpiry};
+use crate::resp::resp_protocol::RespMessage;
+
+/// Redis's default `proto-max-bulk-len`: no string may grow past 512 MB.
+const MAX_STRING_LEN: usize = 512 * 1024 * 1024;
+
+fn too_long() -> RespMessage {
+    RespMessage::Error("ERR string exceeds maximum allowed size (proto-max-bulk-len)".to_string())
+}
+
+/// Looks up the live string stored at `key`.
+/// Returns `Ok(None)` for a missing key and a WRONGTYPE error for any other type.
+pub fn get_string<'a>(
+    keyspace: &'a mut Keyspace,
+    key: &[u8],
+) -> Result<Option<&'a mut Vec<u8>>, RespMessage> {
+    match keyspace.lookup_write(key).map(|v| &mut v.value) {
+        Some(Value::String(string)) => Ok(Some(string)),
+        Some(_) => Err(wrong_type()),
+        None => Ok(None),
+    }
+}
+
+/// Like `get_string`, but creates an empty string when the key is missing.
+pub fn get_or_create_string<'a>(
+    keyspace: &'a mut Keyspace,
+    key: &[u8],
+) -> Result<&'a mut Vec<u8>, RespMessage> {
+    let entry = keyspace.lookup_or_insert(key, || Value::String(Vec::new()));
+    match &mut entry.value {
+        Value::String(string) => Ok(string),
+        _ => Err(wrong_type()),
+    }
+}
+
+/// Stores `value` at `key` as a plain string with no TTL, replacing whatever was there.
+pub fn store_string(keyspace: &mut Keyspace, key: &[u8], value: &[u8]) {
+    keyspace.insert(
+        key.to_vec(),
+        ValueWithExpiry {
+            value: Value::String(value.to_vec()),
+            expiry: None,
+        },
+    );
+}
+
+/// The unit and reference point of SET / GETEX's `EX`, `PX`, `EXAT` and `PXAT` options.
+pub fn expiry_option(option: &[u8]) -> Option<(TimeUnit, ExpireAt)> {
+    match option.to_ascii_uppercase().as_slice() {
+        b"EX" => Some((TimeUnit::Seconds, ExpireAt::Relative)),
+        b"PX" => Some((TimeUnit::Milliseconds, ExpireAt::Relative)),
+        b"EXAT" => Some((TimeUnit::Seconds, ExpireAt::Absolute)),
+        b"PXAT" => Some((TimeUnit::Milliseconds, ExpireAt::Absolute)),
+        _ => None,
+    }
+}
+
+/// Parses the argument of an `EX`-style option into an absolute Unix time in
+/// milliseconds. Like Redis, only positive times are accepted.
+pub fn parse_expiry(
+    cmd: &[u8],
+    arg: &[u8],
+    unit: TimeUnit,
+    at: ExpireAt,
+    now: u128,
+) -> Result<u128, RespMessage> {
+    let amount = parse_i64(arg)?;
+    if amount <= 0 {
+        return Err(invalid_expire_time(cmd));
+    }
+    absolute_millis(amount, unit, at, now as i64)
+        .map(|when| when as u128)
+        .ok_or_else(|| invalid_expire_time(cmd))
+}
+
+/// SET key value [NX|XX] [GET] [EX seconds|PX milliseconds|EXAT timestamp|PXAT timestamp|KEEPTTL]
+pub async fn set(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() < 3 {
+        return wrong_arity(args[0]);
+    }
+    let mut db_guard = db.lock().await;
+    let now = db_guard.now();
+
+    let (mut nx, mut xx, mut get, mut keep_ttl) = (false, false, false, false);
+    let mut expiry = None;
+    let mut i = 3;
+    while i < args.len() {
+        match args[i].to_ascii_uppercase().as_slice() {
+            b"NX" if !xx => nx = true,
+            b"XX" if !nx => xx = true,
+            b"GET" => get = true,
+            b"KEEPTTL" if expiry.is_none() => keep_ttl = true,
+            _ => match (expiry_option(args[i]), args.get(i + 1)) {
+                (Some((unit, at)), Some(arg)) if expiry.is_none() && !keep_ttl => {
+                    match parse_expiry(args[0], arg, unit, at, now) {
+                        Ok(when) => expiry = Some(when),
+                        Err(e) => return e,
+                    }
+                    i += 1;
+                }
+                _ => return syntax_error(),
+            },
+        }
+        i += 1;
+    }
+
+    let current = db_guard.lookup_read(args[1]);
+    let exists = current.is_some();
+    let current_expiry = current.and_then(|entry| entry.expiry);
+    let old_value = match current.map(|entry| &entry.value) {
+        Some(Value::String(old)) if get => Some(old.clone()),
+        Some(_) if get => return wrong_type(),
+        _ => None,
+    };
+    let reply = if get {
+        RespMessage::BulkString(old_value)
+    } else {
+        RespMessage::SimpleString("OK".to_string())
+    };
+    if (nx && exists) || (xx && !exists) {
+        return if get {
+            reply
+        } else {
+            RespMessage::BulkString(None)
+        };
+    }
+
+    let expiry = if keep_ttl { current_expiry } else { expiry };
+    // A timestamp already in the past leaves nothing behind.
+    if expiry.is_some_and(|when| when <= now) {
+        db_guard.delete(args[1]);
+    } else {
+        db_guard.insert(
+            args[1].to_vec(),
+            ValueWithExpiry {
+                value: Value::String(args[2].to_vec()),
+                expiry,
+            },
+        );
+    }
+    db_guard.dirty += 1;
+    reply
+}
+
+/// SETNX key value
+pub async fn setnx(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() != 3 {
+        return wrong_arity(args[0]);
+    }
+    let mut db_guard = db.lock().await;
+    if db_guard.lookup_read(args[1]).is_some() {
+        return RespMessage::Integer(0);
+    }
+    store_string(&mut db_guard, args[1], args[2]);
+    db_guard.dirty += 1;
+    RespMessage::Integer(1)
+}
+
+/// SETEX key seconds value / PSETEX key milliseconds value
+pub async fn setex(args: &[&[u8]], db: &Db, unit: TimeUnit) -> RespMessage {
+    if args.len() != 4 {
+        return wrong_arity(args[0]);
+    }
+    let mut db_guard = db.lock().await;
+    let now = db_guard.now();
+    let expiry = match parse_expiry(args[0], args[2], unit, ExpireAt::Relative, now) {
+        Ok(when) => when,
+        Err(e) => return e,
+    };
+    db_guard.insert(
+        args[1].to_vec(),
+        ValueWithExpiry {
+            value: Value::String(args[3].to_vec()),
+            expiry: Some(expiry),
+        },
+    );
+    db_guard.dirty += 1;
+    RespMessage::SimpleString("OK".to_string())
+}
+
+/// APPEND key value
+pub async fn append(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() != 3 {
+        return wrong_arity(args[0]);
+    }
+    let mut db_guard = db.lock().await;
+    let current_len = match get_string(&mut db_guard, args[1]) {
+        Ok(string) => string.map_or(0, |string| string.len()),
+        Err(e) => return e,
+    };
+    if current_len + args[2].len() > MAX_STRING_LEN {
+        return too_long();
+    }
+    let len = match get_or_create_string(&mut db_guard, args[1]) {
+        Ok(string) => {
+            string.extend_from_slice(args[2]);
+            string.len()
+        }
+        Err(e) => return e,
+    };
+    db_guard.dirty += 1;
+    RespMessage::Integer(len as i64)
+}
+
+/// STRLEN key
+pub async fn strlen(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() != 2 {
+        return wrong_arity(args[0]);
+    }
+    let mut db_guard = db.lock().await;
+    match get_string(&mut db_guard, args[1]) {
+        Ok(string) => RespMessage::Integer(string.map_or(0, |string| string.len()) as i64),
+        Err(e) => e,
+    }
+}
+
+/// The byte range GETRANGE returns for `start..=end` of a `len`-byte string, with
+/// Redis's rules: negative offsets count from the end and are then clamped to the
+/// string, and an inverted range is empty.
+fn substring_range(start: i64, end: i64, len: usize) -> Option<(usize, usize)> {
+    let len = len as i64;
+    if start < 0 && end < 0 && start > end {
+        return None;
+    }
+    let start = if start < 0 { len + start } else { start }.max(0);
+    let end = if end < 0 { len + end } else { end }.max(0).min(len - 1);
+    if start > end || len == 0 {
+        return None;
+    }
+    Some((start as usize, end as usize))
+}
+
+/// GETRANGE key start end (also SUBSTR)
+pub async fn getrange(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() != 4 {
+        return wrong_arity(args[0]);
+    }
+    let (start, end) = match (parse_i64(args[2]), parse_i64(args[3])) {
+        (Ok(start), Ok(end)) => (start, end),
+        (Err(e), _) | (_, Err(e)) => return e,
+    };
+    let mut db_guard = db.lock().await;
+    match get_string(&mut db_guard, args[1]) {
+        Ok(string) => {
+            let string = string.map_or(&[][..], |string| string.as_slice());
+            match substring_range(start, end, string.len()) {
+                Some((start, end)) => bulk(&string[start..=end]),
+                None => bulk(b""),
+            }
+        }
+        Err(e) => e,
+    }
+}
+
+/// SETRANGE key offset value
+pub async fn setrange(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() != 4 {
+        return wrong_arity(args[0]);
+    }
+    let offset = match parse_i64(args[2]) {
+        Ok(offset) if offset >= 0 => offset as usize,
+        Ok(_) => return RespMessage::Error("ERR offset is out of range".to_string()),
+        Err(e) => return e,
+    };
+    let value = args[3];
+    let mut db_guard = db.lock().await;
+    let current_len = match get_string(&mut db_guard, args[1]) {
+        Ok(string) => string.map(|string| string.len()),
+        Err(e) => return e,
+    };
+    // Writing nothing changes nothing, and in particular doesn't create the key.
+    if value.is_empty() {
+        return RespMessage::Integer(current_len.unwrap_or(0) as i64);
+    }
+    if offset.saturating_add(value.len()) > MAX_STRING_LEN {
+        return too_long();
+    }
+
+    let len = match get_or_create_string(&mut db_guard, args[1]) {
+        Ok(string) => {
+            let end = offset + value.len();
+            if string.len() < end {
+                string.resize(end, 0);
+            }
+            string[offset..end].copy_from_slice(value);
+            string.len()
+        }
+        Err(e) => return e,
+    };
+    db_guard.dirty += 1;
+    RespMessage::Integer(len as i64)
+}
+
+/// MGET key [key ...]
+/// Missing keys and keys holding another type both come back as nil.
+pub async fn mget(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() < 2 {
+        return wrong_arity(args[0]);
+    }
+    let mut db_guard = db.lock().await;
+    RespMessage::Array(
+        args[1..]
+            .iter()
+            .map(|key| match db_guard.lookup_read(key).map(|v| &v.value) {
+                Some(Value::String(string)) => bulk(string),
+                _ => RespMessage::BulkString(None),
+            })
+            .collect(),
+    )
+}
+
+/// MSET key value [key value ...]
+/// With `only_new` set this is MSETNX, which sets nothing if any key already exists.
+pub async fn mset(args: &[&[u8]], db: &Db, only_new: bool) -> RespMessage {
+    if args.len() < 3 || args.len().is_multiple_of(2) {
+        return wrong_arity(args[0]);
+    }
+    let mut db_guard = db.lock().await;
+    let pairs = args[1..].chunks(2);
+    if only_new
+        && pairs
+            .clone()
+            .any(|pair| db_guard.lookup_read(pair[0]).is_some())
+    {
+        return RespMessage::Integer(0);
+    }
+    for pair in pairs {
+        store_string(&mut db_guard, pair[0], pair[1]);
+        db_guard.dirty += 1;
+    }
+    if only_new {
+        RespMessage::Integer(1)
+    } else {
+        RespMessage::SimpleString("OK".to_string())
+    }
+}
+
+/// GETSET key value: sets the key (dropping any TTL) and replies with its old value.
+pub async fn getset(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() != 3 {
+        return wrong_arity(args[0]);
+    }
+    let mut db_guard = db.lock().await;
+    let old = match get_string(&mut db_guard, args[1]) {
+        Ok(old) => RespMessage::BulkString(old.map(|old| old.clone())),
+        Err(e) => return e,
+    };
+    store_string(&mut db_guard, args[1], args[2]);
+    db_guard.dirty += 1;
+    old
+}
+
+/// GETDEL key
+pub async fn getdel(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() != 2 {
+        return wrong_arity(args[0]);
+    }
+    let mut db_guard = db.lock().await;
+    match get_string(&mut db_guard, args[1]) {
+        Ok(Some(_)) => {
+            db_guard.dirty += 1;
+            match db_guard.delete(args[1]).map(|entry| entry.value) {
+                Some(Value::String(string)) => RespMessage::BulkString(Some(string)),
+                _ => RespMessage::BulkString(None),
+            }
+        }
+        Ok(None) => RespMessage::BulkString(None),
+        Err(e) => e,
+    }
+}
+
+/// GETEX key [EX seconds|PX milliseconds|EXAT timestamp|PXAT timestamp|PERSIST]
+pub async fn getex(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() < 2 {
+        return wrong_arity(args[0]);
+    }
+    let mut db_guard = db.lock().await;
+    let now = db_guard.now();
+
+    // `None` leaves the TTL alone, `Some(None)` is PERSIST.
+    let mut new_expiry: Option<Option<u128>> = None;
+    let mut i = 2;
+    while i < args.len() {
+        match (expiry_option(args[i]), args.get(i + 1)) {
+            (Some((unit, at)), Some(arg)) if new_expiry.is_none() => {
+                match parse_expiry(args[0], arg, unit, at, now) {
+                    Ok(when) => new_expiry = Some(Some(when)),
+                    Err(e) => return e,
+                }
+                i += 2;
+            }
+            (None, _) if args[i].eq_ignore_ascii_case(b"PERSIST") && new_expiry.is_none() => {
+                new_expiry = Some(None);
+                i += 1;
+            }
+            _ => return syntax_error(),
+        }
+    }
+
+    let value = match get_string(&mut db_guard, args[1]) {
+        Ok(Some(value)) => value.clone(),
+        Ok(None) => return RespMessage::BulkString(None),
+        Err(e) => return e,
+    };
+    match new_expiry {
+        Some(Some(when)) if when <= now => {
+            db_guard.delete(args[1]);
+            db_guard.dirty += 1;
+        }
+        Some(expiry) => {
+            db_guard.set_expiry(args[1], expiry);
+            db_guard.dirty += 1;
+        }
+        None => {}
+    }
+    RespMessage::BulkString(Some(value))
+}
+
+/// Adds `delta` to the integer stored at `key`, treating a missing key as 0.
+/// The key keeps its TTL.
+fn add_to_integer(keyspace: &mut Keyspace, key: &[u8], delta: i64) -> RespMessage {
+    let current = match get_string(keyspace, key) {
+        Ok(Some(current)) => match parse_i64(current) {
+            Ok(current) => current,
+            Err(_) => return not_an_integer(),
+        },
+        Ok(None) => 0,
+        Err(e) => return e,
+    };
+    let Some(updated) = current.checked_add(delta) else {
+        return RespMessage::Error("ERR increment or decrement would overflow".to_string());
+    };
+    match get_or_create_string(keyspace, key) {
+        Ok(string) => *string = updated.to_string().into_bytes(),
+        Err(e) => return e,
+    }
+    keyspace.dirty += 1;
+    RespMessage::Integer(updated)
+}
+
+/// INCR key / DECR key
+pub async fn incr(args: &[&[u8]], db: &Db, delta: i64) -> RespMessage {
+    if args.len() != 2 {
+        return wrong_arity(args[0]);
+    }
+    add_to_integer(&mut *db.lock().await, args[1], delta)
+}
+
+/// INCRBY key increment / DECRBY key decrement (with `negate` set)
+pub async fn incrby(args: &[&[u8]], db: &Db, negate: bool) -> RespMessage {
+    if args.len() != 3 {
+        return wrong_arity(args[0]);
+    }
+    let delta = match parse_i64(args[2]) {
+        Ok(delta) if negate => match delta.checked_neg() {
+            Some(delta) => delta,
+            None => return RespMessage::Error("ERR decrement would overflow".to_string()),
+        },
+        Ok(delta) => delta,
+        Err(e) => return e,
+    };
+    add_to_integer(&mut *db.lock().await, args[1], delta)
+}
+
+/// INCRBYFLOAT key increment
+pub async fn incrbyfloat(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() != 3 {
+        return wrong_arity(args[0]);
+    }
+    let increment = match parse_float(args[2]) {
+        Ok(increment) => increment,
+        Err(e) => return e,
+    };
+    let mut db_guard = db.lock().await;
+    let current = match get_string(&mut db_guard, args[1]) {
+        Ok(Some(current)) => match parse_float(current) {
+            Ok(current) => current,
+            Err(e) => return e,
+        },
+        Ok(None) => 0.0,
+        Err(e) => return e,
+    };
+    let updated = current + increment;
+    if !updated.is_finite() {
+        return RespMessage::Error("ERR increment would produce NaN or Infinity".to_string());
+    }
+    let formatted = format_float(updated).into_bytes();
+    match get_or_create_string(&mut db_guard, args[1]) {
+        Ok(string) => *string = formatted.clone(),
+        Err(e) => return e,
+    }
+    db_guard.dirty += 1;
+    RespMessage::BulkString(Some(formatted))
+}
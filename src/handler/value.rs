@@ -1,6 +1,84 @@
-// Define ValueWithExpiry here if it’s not already in commands.rs, or import it
-#[derive(Clone, serde::Serialize, serde::Deserialize)]
+use std::collections::VecDeque;
+
+mod dict;
+#[cfg(test)]
+mod dict_tests;
+mod sorted_set;
+#[cfg(test)]
+mod sorted_set_tests;
+mod stream;
+
+pub use dict::{Dict, DictSet};
+pub use sorted_set::{LexBound, LexRange, ScoreBound, ScoreRange, SortedSet};
+pub use stream::{is_empty_range, ConsumerGroup, Stream, StreamFields, StreamId, TrimStrategy};
+
+/// The typed payload stored under a key.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum Value {
+    String(Vec<u8>),
+    List(VecDeque<Vec<u8>>),
+    Hash(#[serde(with = "map_as_pairs")] Dict<Vec<u8>, Vec<u8>>),
+    Set(DictSet<Vec<u8>>),
+    SortedSet(SortedSet),
+    Stream(Stream),
+}
+
+impl Value {
+    /// The name TYPE replies with.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) => "string",
+            Value::List(_) => "list",
+            Value::Hash(_) => "hash",
+            Value::Set(_) => "set",
+            Value::SortedSet(_) => "zset",
+            Value::Stream(_) => "stream",
+        }
+    }
+
+    /// Collections are deleted once empty; an empty string is still a value.
+    pub fn is_empty_collection(&self) -> bool {
+        match self {
+            Value::String(_) => false,
+            Value::List(list) => list.is_empty(),
+            Value::Hash(hash) => hash.is_empty(),
+            Value::Set(set) => set.is_empty(),
+            Value::SortedSet(zset) => zset.is_empty(),
+            // An empty stream still carries its last ID and groups, so it is kept.
+            Value::Stream(_) => false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
 pub struct ValueWithExpiry {
-    pub value: String,
+    pub value: Value,
     pub expiry: Option<u128>,
 }
+
+/// Serializes a map with byte-string (or other non-string) keys as a list of
+/// `[key, value]` pairs, since JSON objects only allow string keys.
+mod map_as_pairs {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<'a, S, M, K, V>(map: &'a M, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+        &'a M: IntoIterator<Item = (&'a K, &'a V)>,
+        K: Serialize + 'a,
+        V: Serialize + 'a,
+    {
+        serializer.collect_seq(map)
+    }
+
+    pub fn deserialize<'de, D, M, K, V>(deserializer: D) -> Result<M, D::Error>
+    where
+        D: Deserializer<'de>,
+        M: FromIterator<(K, V)>,
+        K: Deserialize<'de>,
+        V: Deserialize<'de>,
+    {
+        let pairs: Vec<(K, V)> = Vec::deserialize(deserializer)?;
+        Ok(pairs.into_iter().collect())
+    }
+}
@@ -0,0 +1,444 @@
+use crate::handler::commands::random_u64;
+use std::borrow::Borrow;
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hash};
+use std::iter::{Flatten, Map};
+use std::slice;
+
+/*
+The hash table behind the keyspace, hashes and sets, modelled on Redis's dict: a
+power-of-two array of buckets, each chaining the entries whose hash lands there.
+
+The standard maps can't hand out a random entry or resume an iteration where an
+earlier call left off, short of copying every entry. With the buckets in view,
+both cost time in proportion to what they return:
+
+- `random` picks buckets at random until it finds a non-empty one. The table
+  shrinks once less than an eighth full, so that takes a few tries at most; one
+  sized for more entries than it got falls back to counting its way to a random
+  entry after `RANDOM_PROBES` misses.
+- `scan` walks the buckets in Redis's reverse-binary cursor order: it increments
+  the cursor from its highest bit down. A bucket's entries land in the same bucket,
+  or in buckets whose low bits match it, at any size, so growing or shrinking the
+  table between calls never moves an entry to a bucket the scan has yet to pass
+  from one it has already visited. Every entry present for the whole scan is
+  returned at least once, though some may come twice.
+
+Resizing rehashes everything at once, where Redis spreads it over later calls.
+*/
+
+/// The fewest buckets a table with anything in it has.
+const MIN_BUCKETS: usize = 4;
+
+/// How many empty buckets `random` tries before walking the entries instead.
+const RANDOM_PROBES: usize = 64;
+
+#[derive(Clone)]
+pub struct Dict<K, V> {
+    /// Empty until the first insert; otherwise a power of two long.
+    buckets: Vec<Vec<(K, V)>>,
+    len: usize,
+    hasher: RandomState,
+}
+
+pub type Iter<'a, K, V> =
+    Map<Flatten<slice::Iter<'a, Vec<(K, V)>>>, fn(&'a (K, V)) -> (&'a K, &'a V)>;
+
+impl<K, V> Default for Dict<K, V> {
+    fn default() -> Self {
+        Dict {
+            buckets: Vec::new(),
+            len: 0,
+            hasher: RandomState::new(),
+        }
+    }
+}
+
+impl<K: Hash + Eq, V> Dict<K, V> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        let mut dict = Self::new();
+        if capacity > 0 {
+            dict.buckets = empty_buckets(capacity.next_power_of_two().max(MIN_BUCKETS));
+        }
+        dict
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn bucket_of<Q: Hash + ?Sized>(&self, key: &Q) -> usize {
+        self.hasher.hash_one(key) as usize & (self.buckets.len() - 1)
+    }
+
+    /// The bucket holding `key`, and where in it.
+    fn position<Q>(&self, key: &Q) -> Option<(usize, usize)>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        if self.len == 0 {
+            return None;
+        }
+        let bucket = self.bucket_of(key);
+        let slot = self.buckets[bucket]
+            .iter()
+            .position(|(k, _)| k.borrow() == key)?;
+        Some((bucket, slot))
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let (bucket, slot) = self.position(key)?;
+        Some(&self.buckets[bucket][slot].1)
+    }
+
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let (bucket, slot) = self.position(key)?;
+        Some(&mut self.buckets[bucket][slot].1)
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.position(key).is_some()
+    }
+
+    /// Stores `value` at `key`, returning the value it replaced.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        if let Some(old) = self.get_mut(&key) {
+            return Some(std::mem::replace(old, value));
+        }
+        self.push(key, value);
+        None
+    }
+
+    /// The value at `key`, first storing `create()` there if there is none.
+    pub fn get_or_insert_with(&mut self, key: K, create: impl FnOnce() -> V) -> &mut V {
+        let (bucket, slot) = match self.position(&key) {
+            Some(position) => position,
+            None => self.push(key, create()),
+        };
+        &mut self.buckets[bucket][slot].1
+    }
+
+    /// Adds an entry for a key not in the table yet, returning where it went.
+    fn push(&mut self, key: K, value: V) -> (usize, usize) {
+        if self.len >= self.buckets.len() {
+            self.resize((self.len * 2).max(MIN_BUCKETS).next_power_of_two());
+        }
+        let bucket = self.bucket_of(&key);
+        self.buckets[bucket].push((key, value));
+        self.len += 1;
+        (bucket, self.buckets[bucket].len() - 1)
+    }
+
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let (bucket, slot) = self.position(key)?;
+        let (_, value) = self.buckets[bucket].swap_remove(slot);
+        self.len -= 1;
+        self.shrink_if_sparse();
+        Some(value)
+    }
+
+    /// Keeps only the entries `keep` returns true for.
+    pub fn retain(&mut self, mut keep: impl FnMut(&K, &mut V) -> bool) {
+        for bucket in &mut self.buckets {
+            bucket.retain_mut(|(key, value)| keep(key, value));
+        }
+        self.len = self.buckets.iter().map(Vec::len).sum();
+        self.shrink_if_sparse();
+    }
+
+    /// Shrinks a table that was sized for more entries than it got.
+    pub fn shrink_to_fit(&mut self) {
+        self.shrink_if_sparse();
+    }
+
+    fn shrink_if_sparse(&mut self) {
+        if self.len == 0 {
+            self.buckets = Vec::new();
+        } else if self.buckets.len() > MIN_BUCKETS && self.len * 8 < self.buckets.len() {
+            self.resize(self.len.next_power_of_two().max(MIN_BUCKETS) * 2);
+        }
+    }
+
+    fn resize(&mut self, buckets: usize) {
+        let old = std::mem::replace(&mut self.buckets, empty_buckets(buckets));
+        for (key, value) in old.into_iter().flatten() {
+            let bucket = self.bucket_of(&key);
+            self.buckets[bucket].push((key, value));
+        }
+    }
+
+    /// A random entry, or `None` if the table is empty.
+    pub fn random(&self) -> Option<(&K, &V)> {
+        if self.len == 0 {
+            return None;
+        }
+        for _ in 0..RANDOM_PROBES {
+            let bucket = &self.buckets[random_u64() as usize & (self.buckets.len() - 1)];
+            if !bucket.is_empty() {
+                let (key, value) = &bucket[random_u64() as usize % bucket.len()];
+                return Some((key, value));
+            }
+        }
+        self.iter().nth(random_u64() as usize % self.len)
+    }
+
+    /// Returns the entries of the next buckets from `cursor`, stopping once it has
+    /// `count` of them or has looked at ten times that many buckets, along with the
+    /// cursor for the following call (`0` once the scan is complete).
+    pub fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<(&K, &V)>) {
+        if self.buckets.is_empty() {
+            return (0, Vec::new());
+        }
+        let mask = (self.buckets.len() - 1) as u64;
+        let mut cursor = cursor;
+        let mut page = Vec::new();
+        let mut visits = count.saturating_mul(10);
+        loop {
+            let bucket = &self.buckets[(cursor & mask) as usize];
+            page.extend(bucket.iter().map(|(key, value)| (key, value)));
+            // Increment the bits under the mask, from the highest one down.
+            cursor |= !mask;
+            cursor = cursor.reverse_bits().wrapping_add(1).reverse_bits();
+            visits -= 1;
+            if cursor == 0 || page.len() >= count || visits == 0 {
+                return (cursor, page);
+            }
+        }
+    }
+}
+
+impl<K, V> Dict<K, V> {
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        self.buckets
+            .iter()
+            .flatten()
+            .map(|(key, value)| (key, value))
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&K, &mut V)> {
+        self.buckets
+            .iter_mut()
+            .flatten()
+            .map(|(key, value)| (&*key, value))
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.iter().map(|(key, _)| key)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        self.iter().map(|(_, value)| value)
+    }
+
+    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut V> {
+        self.iter_mut().map(|(_, value)| value)
+    }
+}
+
+fn empty_buckets<K, V>(len: usize) -> Vec<Vec<(K, V)>> {
+    std::iter::repeat_with(Vec::new).take(len).collect()
+}
+
+impl<'a, K, V> IntoIterator for &'a Dict<K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<K, V> IntoIterator for Dict<K, V> {
+    type Item = (K, V);
+    type IntoIter = Flatten<std::vec::IntoIter<Vec<(K, V)>>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.buckets.into_iter().flatten()
+    }
+}
+
+impl<K: Hash + Eq, V> FromIterator<(K, V)> for Dict<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut dict = Dict::new();
+        dict.extend(iter);
+        dict
+    }
+}
+
+impl<K: Hash + Eq, V> Extend<(K, V)> for Dict<K, V> {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+impl<K: Hash + Eq, V, const N: usize> From<[(K, V); N]> for Dict<K, V> {
+    fn from(entries: [(K, V); N]) -> Self {
+        entries.into_iter().collect()
+    }
+}
+
+impl<K: Hash + Eq, V: PartialEq> PartialEq for Dict<K, V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len == other.len
+            && self
+                .iter()
+                .all(|(key, value)| other.get(key) == Some(value))
+    }
+}
+
+impl<K: fmt::Debug, V: fmt::Debug> fmt::Debug for Dict<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+/// A `Dict` of keys alone, with the set operations the commands use.
+#[derive(Clone)]
+pub struct DictSet<K>(Dict<K, ()>);
+
+impl<K> Default for DictSet<K> {
+    fn default() -> Self {
+        DictSet(Dict::default())
+    }
+}
+
+impl<K: Hash + Eq> DictSet<K> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn contains<Q>(&self, member: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.0.contains_key(member)
+    }
+
+    /// Adds `member`, returning whether it was new.
+    pub fn insert(&mut self, member: K) -> bool {
+        self.0.insert(member, ()).is_none()
+    }
+
+    /// Removes `member`, returning whether it was there.
+    pub fn remove<Q>(&mut self, member: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.0.remove(member).is_some()
+    }
+
+    pub fn random(&self) -> Option<&K> {
+        self.0.random().map(|(member, _)| member)
+    }
+
+    /// Like `Dict::scan`.
+    pub fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<&K>) {
+        let (cursor, page) = self.0.scan(cursor, count);
+        (cursor, page.into_iter().map(|(member, _)| member).collect())
+    }
+}
+
+impl<K> DictSet<K> {
+    pub fn iter(&self) -> impl Iterator<Item = &K> {
+        self.0.keys()
+    }
+}
+
+impl<'a, K> IntoIterator for &'a DictSet<K> {
+    type Item = &'a K;
+    type IntoIter = Map<Iter<'a, K, ()>, fn((&'a K, &'a ())) -> &'a K>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter().map(|(member, _)| member)
+    }
+}
+
+impl<K> IntoIterator for DictSet<K> {
+    type Item = K;
+    type IntoIter = Map<<Dict<K, ()> as IntoIterator>::IntoIter, fn((K, ())) -> K>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter().map(|(member, _)| member)
+    }
+}
+
+impl<K: Hash + Eq> FromIterator<K> for DictSet<K> {
+    fn from_iter<I: IntoIterator<Item = K>>(iter: I) -> Self {
+        DictSet(iter.into_iter().map(|member| (member, ())).collect())
+    }
+}
+
+impl<K: Hash + Eq> Extend<K> for DictSet<K> {
+    fn extend<I: IntoIterator<Item = K>>(&mut self, iter: I) {
+        self.0.extend(iter.into_iter().map(|member| (member, ())));
+    }
+}
+
+impl<K: Hash + Eq, const N: usize> From<[K; N]> for DictSet<K> {
+    fn from(members: [K; N]) -> Self {
+        members.into_iter().collect()
+    }
+}
+
+impl<K: Hash + Eq> PartialEq for DictSet<K> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl<K: fmt::Debug> fmt::Debug for DictSet<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
+
+impl<K: serde::Serialize> serde::Serialize for DictSet<K> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter())
+    }
+}
+
+impl<'de, K: serde::Deserialize<'de> + Hash + Eq> serde::Deserialize<'de> for DictSet<K> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let members: Vec<K> = Vec::deserialize(deserializer)?;
+        Ok(members.into_iter().collect())
+    }
+}
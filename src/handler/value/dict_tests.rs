@@ -0,0 +1,105 @@
+use super::dict::{Dict, DictSet};
+use crate::handler::commands::random_u64;
+use std::collections::{HashMap, HashSet};
+
+#[test]
+fn test_matches_a_hash_map_through_random_edits() {
+    let mut dict = Dict::new();
+    let mut model = HashMap::new();
+    for _ in 0..20_000 {
+        let key = random_u64() % 500;
+        if random_u64().is_multiple_of(3) {
+            assert_eq!(dict.remove(&key), model.remove(&key));
+        } else {
+            let value = random_u64();
+            assert_eq!(dict.insert(key, value), model.insert(key, value));
+        }
+        assert_eq!(dict.len(), model.len());
+    }
+    let mut entries: Vec<(u64, u64)> = dict.iter().map(|(k, v)| (*k, *v)).collect();
+    let mut expected: Vec<(u64, u64)> = model.into_iter().collect();
+    entries.sort();
+    expected.sort();
+    assert_eq!(entries, expected);
+
+    dict.retain(|key, _| key.is_multiple_of(2));
+    assert!(dict.keys().all(|key| key.is_multiple_of(2)));
+    dict.retain(|_, _| false);
+    assert!(dict.is_empty());
+    assert_eq!(dict.scan(0, 10), (0, Vec::new()));
+}
+
+/// Scans `set` from `cursor` with COUNT 5, up to `calls` times.
+fn scan_some(set: &DictSet<u64>, mut cursor: u64, calls: usize, seen: &mut HashSet<u64>) -> u64 {
+    for _ in 0..calls {
+        let (next, page) = set.scan(cursor, 5);
+        seen.extend(page);
+        cursor = next;
+        if cursor == 0 {
+            break;
+        }
+    }
+    cursor
+}
+
+#[test]
+fn test_scan_returns_every_lasting_member_across_resizes() {
+    let mut set: DictSet<u64> = (0..100).collect();
+    let mut seen = HashSet::new();
+
+    let cursor = scan_some(&set, 0, 4, &mut seen);
+    assert_ne!(cursor, 0);
+    // Grow the table well past its size, then shrink it back below.
+    set.extend(1000..5000);
+    let cursor = scan_some(&set, cursor, 20, &mut seen);
+    assert_ne!(cursor, 0);
+    for member in 1000..5000 {
+        set.remove(&member);
+    }
+    for member in (0..100u64).filter(|member| member.is_multiple_of(3)) {
+        set.remove(&member);
+    }
+    scan_some(&set, cursor, usize::MAX, &mut seen);
+
+    assert!((0..100u64)
+        .filter(|member| !member.is_multiple_of(3))
+        .all(|member| seen.contains(&member)));
+}
+
+#[test]
+fn test_scan_pages_cost_about_count() {
+    let set: DictSet<u64> = (0..10_000).collect();
+    let (cursor, page) = set.scan(0, 10);
+    assert_ne!(cursor, 0);
+    // Whole buckets at a time, so a little over COUNT at most.
+    assert!((10..30).contains(&page.len()));
+}
+
+#[test]
+fn test_random_picks_from_every_member() {
+    let mut set: DictSet<u64> = (0..10).collect();
+    let picked: HashSet<u64> = (0..1000).map(|_| *set.random().unwrap()).collect();
+    assert_eq!(picked, (0..10).collect());
+
+    set.extend(10..10_000);
+    for member in 1..10_000 {
+        set.remove(&member);
+    }
+    assert_eq!(set.random(), Some(&0));
+    set.remove(&0);
+    assert_eq!(set.random(), None);
+}
+
+#[test]
+fn test_random_copes_with_a_table_sized_for_more() {
+    let mut dict = Dict::with_capacity(1 << 16);
+    dict.insert(1u64, "one");
+    dict.insert(2, "two");
+    let picked: HashSet<u64> = (0..100).map(|_| *dict.random().unwrap().0).collect();
+    assert_eq!(picked, HashSet::from([1, 2]));
+
+    dict.shrink_to_fit();
+    dict.insert(3, "three");
+    let picked: HashSet<u64> = (0..1000).map(|_| *dict.random().unwrap().0).collect();
+    assert_eq!(picked, HashSet::from([1, 2, 3]));
+}
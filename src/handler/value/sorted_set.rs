@@ -0,0 +1,679 @@
+use crate::handler::commands::random_u64;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+
+/*
+Sorted set storage: a member -> score map for O(1) score lookups, plus a skip list
+ordered by (score, member) for everything positional.
+
+The skip list follows Redis's zskiplist: every forward link also records its span
+(how many level-0 nodes it jumps over), which makes rank lookups and rank-based
+seeks O(log n), and range queries O(log n + m).
+
+Nodes live in an arena (`Vec<Node>`) and link to each other by index, so no unsafe
+code is needed. Slot 0 is the header; freed slots are recycled.
+*/
+
+const MAX_LEVEL: usize = 32;
+const HEAD: usize = 0;
+const NIL: usize = usize::MAX;
+
+#[derive(Clone)]
+struct Level {
+    forward: usize,
+    span: usize,
+}
+
+#[derive(Clone)]
+struct Node {
+    member: Vec<u8>,
+    score: f64,
+    backward: usize,
+    levels: Vec<Level>,
+}
+
+/// Orders two (score, member) pairs the way the skip list does.
+fn compare(score_a: f64, member_a: &[u8], score_b: f64, member_b: &[u8]) -> Ordering {
+    score_a
+        .partial_cmp(&score_b)
+        .unwrap_or(Ordering::Equal)
+        .then_with(|| member_a.cmp(member_b))
+}
+
+#[derive(Clone)]
+struct SkipList {
+    nodes: Vec<Node>,
+    free: Vec<usize>,
+    level: usize,
+    len: usize,
+    tail: usize,
+}
+
+impl SkipList {
+    fn new() -> Self {
+        let header = Node {
+            member: Vec::new(),
+            score: 0.0,
+            backward: NIL,
+            levels: vec![
+                Level {
+                    forward: NIL,
+                    span: 0,
+                };
+                MAX_LEVEL
+            ],
+        };
+        SkipList {
+            nodes: vec![header],
+            free: Vec::new(),
+            level: 1,
+            len: 0,
+            tail: NIL,
+        }
+    }
+
+    fn random_level() -> usize {
+        // Each extra level is kept with probability 1/4, as in Redis.
+        let mut level = 1;
+        while level < MAX_LEVEL && random_u64() & 3 == 0 {
+            level += 1;
+        }
+        level
+    }
+
+    fn forward(&self, node: usize, level: usize) -> usize {
+        self.nodes[node].levels[level].forward
+    }
+
+    fn span(&self, node: usize, level: usize) -> usize {
+        self.nodes[node].levels[level].span
+    }
+
+    /// Whether `node` sorts strictly before (score, member).
+    fn before(&self, node: usize, score: f64, member: &[u8]) -> bool {
+        let n = &self.nodes[node];
+        compare(n.score, &n.member, score, member) == Ordering::Less
+    }
+
+    fn insert(&mut self, score: f64, member: Vec<u8>) {
+        let mut update = [HEAD; MAX_LEVEL];
+        let mut rank = [0usize; MAX_LEVEL];
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            rank[i] = if i == self.level - 1 { 0 } else { rank[i + 1] };
+            loop {
+                let next = self.forward(x, i);
+                if next != NIL && self.before(next, score, &member) {
+                    rank[i] += self.span(x, i);
+                    x = next;
+                } else {
+                    break;
+                }
+            }
+            update[i] = x;
+        }
+
+        let level = Self::random_level();
+        if level > self.level {
+            for i in self.level..level {
+                rank[i] = 0;
+                update[i] = HEAD;
+                self.nodes[HEAD].levels[i].span = self.len;
+            }
+            self.level = level;
+        }
+
+        let node = Node {
+            member,
+            score,
+            backward: if update[0] == HEAD { NIL } else { update[0] },
+            levels: vec![
+                Level {
+                    forward: NIL,
+                    span: 0,
+                };
+                level
+            ],
+        };
+        let new = match self.free.pop() {
+            Some(slot) => {
+                self.nodes[slot] = node;
+                slot
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        };
+
+        for i in 0..level {
+            let prev = update[i];
+            self.nodes[new].levels[i].forward = self.forward(prev, i);
+            self.nodes[prev].levels[i].forward = new;
+            self.nodes[new].levels[i].span = self.span(prev, i) - (rank[0] - rank[i]);
+            self.nodes[prev].levels[i].span = rank[0] - rank[i] + 1;
+        }
+        for (i, &prev) in update.iter().enumerate().take(self.level).skip(level) {
+            self.nodes[prev].levels[i].span += 1;
+        }
+
+        match self.forward(new, 0) {
+            NIL => self.tail = new,
+            next => self.nodes[next].backward = new,
+        }
+        self.len += 1;
+    }
+
+    fn remove(&mut self, score: f64, member: &[u8]) -> bool {
+        let mut update = [HEAD; MAX_LEVEL];
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            loop {
+                let next = self.forward(x, i);
+                if next != NIL && self.before(next, score, member) {
+                    x = next;
+                } else {
+                    break;
+                }
+            }
+            update[i] = x;
+        }
+
+        let target = self.forward(x, 0);
+        if target == NIL
+            || compare(
+                self.nodes[target].score,
+                &self.nodes[target].member,
+                score,
+                member,
+            ) != Ordering::Equal
+        {
+            return false;
+        }
+
+        for (i, &prev) in update.iter().enumerate().take(self.level) {
+            if self.forward(prev, i) == target {
+                self.nodes[prev].levels[i].span += self.span(target, i);
+                self.nodes[prev].levels[i].span -= 1;
+                self.nodes[prev].levels[i].forward = self.forward(target, i);
+            } else {
+                self.nodes[prev].levels[i].span -= 1;
+            }
+        }
+        match self.forward(target, 0) {
+            NIL => self.tail = self.nodes[target].backward,
+            next => self.nodes[next].backward = self.nodes[target].backward,
+        }
+        while self.level > 1 && self.forward(HEAD, self.level - 1) == NIL {
+            self.level -= 1;
+        }
+        self.len -= 1;
+
+        self.nodes[target].member = Vec::new();
+        self.nodes[target].levels = Vec::new();
+        self.free.push(target);
+        true
+    }
+
+    /// 0-based rank of (score, member), if present.
+    fn rank(&self, score: f64, member: &[u8]) -> Option<usize> {
+        let mut rank = 0;
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            loop {
+                let next = self.forward(x, i);
+                if next != NIL
+                    && compare(
+                        self.nodes[next].score,
+                        &self.nodes[next].member,
+                        score,
+                        member,
+                    ) != Ordering::Greater
+                {
+                    rank += self.span(x, i);
+                    x = next;
+                } else {
+                    break;
+                }
+            }
+            if x != HEAD && self.nodes[x].member == member {
+                return Some(rank - 1);
+            }
+        }
+        None
+    }
+
+    /// The node at 0-based `rank`.
+    fn node_at(&self, rank: usize) -> usize {
+        let target = rank + 1;
+        let mut traversed = 0;
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            loop {
+                let next = self.forward(x, i);
+                if next != NIL && traversed + self.span(x, i) <= target {
+                    traversed += self.span(x, i);
+                    x = next;
+                } else {
+                    break;
+                }
+            }
+            if traversed == target {
+                return x;
+            }
+        }
+        NIL
+    }
+
+    /// The first node for which `past_min` holds. `past_min` must be monotonic
+    /// along the list (false, ..., false, true, ..., true).
+    fn first_where(&self, past_min: impl Fn(&Node) -> bool) -> usize {
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            loop {
+                let next = self.forward(x, i);
+                if next != NIL && !past_min(&self.nodes[next]) {
+                    x = next;
+                } else {
+                    break;
+                }
+            }
+        }
+        self.forward(x, 0)
+    }
+
+    /// The last node for which `within_max` holds. `within_max` must be monotonic
+    /// along the list (true, ..., true, false, ..., false).
+    fn last_where(&self, within_max: impl Fn(&Node) -> bool) -> usize {
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            loop {
+                let next = self.forward(x, i);
+                if next != NIL && within_max(&self.nodes[next]) {
+                    x = next;
+                } else {
+                    break;
+                }
+            }
+        }
+        if x == HEAD {
+            NIL
+        } else {
+            x
+        }
+    }
+
+    fn next(&self, node: usize) -> usize {
+        self.forward(node, 0)
+    }
+
+    fn prev(&self, node: usize) -> usize {
+        self.nodes[node].backward
+    }
+}
+
+/// One end of a score range, as given to ZRANGEBYSCORE and friends.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ScoreBound {
+    pub value: f64,
+    pub exclusive: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ScoreRange {
+    pub min: ScoreBound,
+    pub max: ScoreBound,
+}
+
+impl ScoreRange {
+    fn above_min(&self, score: f64) -> bool {
+        if self.min.exclusive {
+            score > self.min.value
+        } else {
+            score >= self.min.value
+        }
+    }
+
+    fn below_max(&self, score: f64) -> bool {
+        if self.max.exclusive {
+            score < self.max.value
+        } else {
+            score <= self.max.value
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.min.value > self.max.value
+            || (self.min.value == self.max.value && (self.min.exclusive || self.max.exclusive))
+    }
+}
+
+/// One end of a lexicographic range, as given to ZRANGEBYLEX and friends.
+#[derive(Clone, Debug, PartialEq)]
+pub enum LexBound {
+    NegInfinity,
+    PosInfinity,
+    Inclusive(Vec<u8>),
+    Exclusive(Vec<u8>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LexRange {
+    pub min: LexBound,
+    pub max: LexBound,
+}
+
+impl LexRange {
+    fn above_min(&self, member: &[u8]) -> bool {
+        match &self.min {
+            LexBound::NegInfinity => true,
+            LexBound::PosInfinity => false,
+            LexBound::Inclusive(min) => member >= min.as_slice(),
+            LexBound::Exclusive(min) => member > min.as_slice(),
+        }
+    }
+
+    fn below_max(&self, member: &[u8]) -> bool {
+        match &self.max {
+            LexBound::NegInfinity => false,
+            LexBound::PosInfinity => true,
+            LexBound::Inclusive(max) => member <= max.as_slice(),
+            LexBound::Exclusive(max) => member < max.as_slice(),
+        }
+    }
+}
+
+/// A sorted set: unique members ordered by score, ties broken by member bytes.
+#[derive(Clone)]
+pub struct SortedSet {
+    scores: HashMap<Vec<u8>, f64>,
+    list: SkipList,
+}
+
+impl Default for SortedSet {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SortedSet {
+    pub fn new() -> Self {
+        SortedSet {
+            scores: HashMap::new(),
+            list: SkipList::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    pub fn score(&self, member: &[u8]) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+
+    /// Adds `member` or updates its score. Returns `true` if the member is new.
+    pub fn insert(&mut self, member: &[u8], score: f64) -> bool {
+        match self.scores.get_mut(member) {
+            Some(current) => {
+                if *current != score {
+                    self.list.remove(*current, member);
+                    self.list.insert(score, member.to_vec());
+                    *current = score;
+                }
+                false
+            }
+            None => {
+                self.scores.insert(member.to_vec(), score);
+                self.list.insert(score, member.to_vec());
+                true
+            }
+        }
+    }
+
+    /// Removes `member`, returning its score if it was present.
+    pub fn remove(&mut self, member: &[u8]) -> Option<f64> {
+        let score = self.scores.remove(member)?;
+        self.list.remove(score, member);
+        Some(score)
+    }
+
+    /// Removes and returns the entry with the lowest score.
+    pub fn pop_min(&mut self) -> Option<(Vec<u8>, f64)> {
+        self.pop_node(self.list.next(HEAD))
+    }
+
+    /// Removes and returns the entry with the highest score.
+    pub fn pop_max(&mut self) -> Option<(Vec<u8>, f64)> {
+        self.pop_node(self.list.tail)
+    }
+
+    fn pop_node(&mut self, node: usize) -> Option<(Vec<u8>, f64)> {
+        if node == NIL {
+            return None;
+        }
+        let (member, score) = self.entry(node);
+        self.remove(&member);
+        Some((member, score))
+    }
+
+    /// 0-based position of `member` in ascending order.
+    pub fn rank(&self, member: &[u8]) -> Option<usize> {
+        let score = self.score(member)?;
+        self.list.rank(score, member)
+    }
+
+    fn entry(&self, node: usize) -> (Vec<u8>, f64) {
+        let node = &self.list.nodes[node];
+        (node.member.clone(), node.score)
+    }
+
+    /// Walks from `start` (inclusive) in the given direction while `keep` holds,
+    /// skipping `offset` entries and returning at most `limit` of them.
+    fn collect_from(
+        &self,
+        start: usize,
+        reverse: bool,
+        offset: usize,
+        limit: Option<usize>,
+        keep: impl Fn(&Node) -> bool,
+    ) -> Vec<(Vec<u8>, f64)> {
+        let mut out = Vec::new();
+        let mut x = start;
+        let mut skipped = 0;
+        while x != NIL && limit.is_none_or(|limit| out.len() < limit) {
+            if !keep(&self.list.nodes[x]) {
+                break;
+            }
+            if skipped < offset {
+                skipped += 1;
+            } else {
+                out.push(self.entry(x));
+            }
+            x = if reverse {
+                self.list.prev(x)
+            } else {
+                self.list.next(x)
+            };
+        }
+        out
+    }
+
+    /// Entries with 0-based ranks `start..=stop`; with `reverse`, ranks count from the highest score.
+    pub fn range_by_rank(&self, start: usize, stop: usize, reverse: bool) -> Vec<(Vec<u8>, f64)> {
+        if start > stop || start >= self.len() {
+            return Vec::new();
+        }
+        let stop = stop.min(self.len() - 1);
+        let first = if reverse {
+            self.list.node_at(self.len() - 1 - start)
+        } else {
+            self.list.node_at(start)
+        };
+        self.collect_from(first, reverse, 0, Some(stop - start + 1), |_| true)
+    }
+
+    pub fn range_by_score(
+        &self,
+        range: &ScoreRange,
+        reverse: bool,
+        offset: usize,
+        limit: Option<usize>,
+    ) -> Vec<(Vec<u8>, f64)> {
+        if range.is_empty() {
+            return Vec::new();
+        }
+        if reverse {
+            let start = self.list.last_where(|n| range.below_max(n.score));
+            self.collect_from(start, true, offset, limit, |n| range.above_min(n.score))
+        } else {
+            let start = self.list.first_where(|n| range.above_min(n.score));
+            self.collect_from(start, false, offset, limit, |n| range.below_max(n.score))
+        }
+    }
+
+    pub fn range_by_lex(
+        &self,
+        range: &LexRange,
+        reverse: bool,
+        offset: usize,
+        limit: Option<usize>,
+    ) -> Vec<(Vec<u8>, f64)> {
+        if reverse {
+            let start = self.list.last_where(|n| range.below_max(&n.member));
+            self.collect_from(start, true, offset, limit, |n| range.above_min(&n.member))
+        } else {
+            let start = self.list.first_where(|n| range.above_min(&n.member));
+            self.collect_from(start, false, offset, limit, |n| range.below_max(&n.member))
+        }
+    }
+
+    /// Number of entries between the first and last nodes, in O(log n).
+    fn count_between(&self, first: usize, last: usize) -> usize {
+        if first == NIL || last == NIL {
+            return 0;
+        }
+        let (first, last) = (&self.list.nodes[first], &self.list.nodes[last]);
+        match (
+            self.list.rank(first.score, &first.member),
+            self.list.rank(last.score, &last.member),
+        ) {
+            (Some(first), Some(last)) if last >= first => last - first + 1,
+            _ => 0,
+        }
+    }
+
+    pub fn count_in_score_range(&self, range: &ScoreRange) -> usize {
+        if range.is_empty() {
+            return 0;
+        }
+        let first = self.list.first_where(|n| range.above_min(n.score));
+        let last = self.list.last_where(|n| range.below_max(n.score));
+        self.count_between(first, last)
+    }
+
+    pub fn count_in_lex_range(&self, range: &LexRange) -> usize {
+        let first = self.list.first_where(|n| range.above_min(&n.member));
+        let last = self.list.last_where(|n| range.below_max(&n.member));
+        self.count_between(first, last)
+    }
+
+    /// All entries in ascending order.
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8], f64)> + '_ {
+        let mut x = self.list.next(HEAD);
+        std::iter::from_fn(move || {
+            if x == NIL {
+                return None;
+            }
+            let node = &self.list.nodes[x];
+            x = self.list.next(x);
+            Some((node.member.as_slice(), node.score))
+        })
+    }
+}
+
+impl FromIterator<(Vec<u8>, f64)> for SortedSet {
+    fn from_iter<I: IntoIterator<Item = (Vec<u8>, f64)>>(entries: I) -> Self {
+        let mut set = SortedSet::new();
+        for (member, score) in entries {
+            set.insert(&member, score);
+        }
+        set
+    }
+}
+
+impl PartialEq for SortedSet {
+    fn eq(&self, other: &Self) -> bool {
+        self.scores == other.scores
+    }
+}
+
+impl fmt::Debug for SortedSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map()
+            .entries(
+                self.iter()
+                    .map(|(member, score)| (String::from_utf8_lossy(member), score)),
+            )
+            .finish()
+    }
+}
+
+// Persisted as a list of [member, score] pairs; the skip list is rebuilt on load.
+impl serde::Serialize for SortedSet {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(
+            self.iter()
+                .map(|(member, score)| (member, PersistedScore(score))),
+        )
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for SortedSet {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let pairs: Vec<(Vec<u8>, PersistedScore)> = serde::Deserialize::deserialize(deserializer)?;
+        Ok(pairs
+            .into_iter()
+            .map(|(member, PersistedScore(score))| (member, score))
+            .collect())
+    }
+}
+
+/// A score as persisted. JSON has no infinities, so those are written as the
+/// strings `"inf"` and `"-inf"`.
+struct PersistedScore(f64);
+
+impl serde::Serialize for PersistedScore {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.0 {
+            f64::INFINITY => serializer.serialize_str("inf"),
+            f64::NEG_INFINITY => serializer.serialize_str("-inf"),
+            score => serializer.serialize_f64(score),
+        }
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for PersistedScore {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(serde::Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Number(f64),
+            Text(String),
+        }
+        match Raw::deserialize(deserializer)? {
+            Raw::Number(score) => Ok(PersistedScore(score)),
+            Raw::Text(text) if text == "inf" => Ok(PersistedScore(f64::INFINITY)),
+            Raw::Text(text) if text == "-inf" => Ok(PersistedScore(f64::NEG_INFINITY)),
+            Raw::Text(text) => Err(serde::de::Error::custom(format!(
+                "invalid score {:?}",
+                text
+            ))),
+        }
+    }
+}
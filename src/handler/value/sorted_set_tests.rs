@@ -0,0 +1,128 @@
+use super::sorted_set::{LexBound, LexRange, ScoreBound, ScoreRange, SortedSet};
+use crate::handler::commands::random_u64;
+
+/// The same entries, sorted naively, to check the skip list against.
+fn model(entries: &[(Vec<u8>, f64)]) -> Vec<(Vec<u8>, f64)> {
+    let mut sorted = entries.to_vec();
+    sorted.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap().then_with(|| a.0.cmp(&b.0)));
+    sorted
+}
+
+fn inclusive(min: f64, max: f64) -> ScoreRange {
+    ScoreRange {
+        min: ScoreBound {
+            value: min,
+            exclusive: false,
+        },
+        max: ScoreBound {
+            value: max,
+            exclusive: false,
+        },
+    }
+}
+
+#[test]
+fn test_matches_sorted_model_under_random_updates() {
+    let mut set = SortedSet::new();
+    let mut entries: Vec<(Vec<u8>, f64)> = Vec::new();
+
+    for _ in 0..2000 {
+        let member = format!("m{}", random_u64() % 200).into_bytes();
+        let score = (random_u64() % 50) as f64;
+        if random_u64().is_multiple_of(3) {
+            let removed = set.remove(&member).is_some();
+            let before = entries.len();
+            entries.retain(|(m, _)| *m != member);
+            assert_eq!(removed, entries.len() != before);
+        } else {
+            let added = set.insert(&member, score);
+            match entries.iter_mut().find(|(m, _)| *m == member) {
+                Some(entry) => {
+                    assert!(!added);
+                    entry.1 = score;
+                }
+                None => {
+                    assert!(added);
+                    entries.push((member, score));
+                }
+            }
+        }
+    }
+
+    let sorted = model(&entries);
+    assert_eq!(set.len(), sorted.len());
+    let iterated: Vec<(Vec<u8>, f64)> = set.iter().map(|(m, s)| (m.to_vec(), s)).collect();
+    assert_eq!(iterated, sorted);
+    for (rank, (member, _)) in sorted.iter().enumerate() {
+        assert_eq!(set.rank(member), Some(rank));
+    }
+
+    let len = sorted.len();
+    assert_eq!(set.range_by_rank(3, 10, false), sorted[3..=10].to_vec());
+    let mut reversed = sorted.clone();
+    reversed.reverse();
+    assert_eq!(set.range_by_rank(0, len - 1, true), reversed);
+
+    let in_range: Vec<_> = sorted
+        .iter()
+        .filter(|(_, s)| (10.0..=20.0).contains(s))
+        .cloned()
+        .collect();
+    assert_eq!(
+        set.range_by_score(&inclusive(10.0, 20.0), false, 0, None),
+        in_range
+    );
+    assert_eq!(
+        set.count_in_score_range(&inclusive(10.0, 20.0)),
+        in_range.len()
+    );
+    assert_eq!(
+        set.range_by_score(&inclusive(10.0, 20.0), false, 2, Some(3)),
+        in_range[2..5].to_vec()
+    );
+}
+
+#[test]
+fn test_lex_ranges_and_exclusive_bounds() {
+    let mut set = SortedSet::new();
+    for member in [b"a", b"b", b"c", b"d", b"e"] {
+        set.insert(member, 0.0);
+    }
+    let range = LexRange {
+        min: LexBound::Exclusive(b"a".to_vec()),
+        max: LexBound::Inclusive(b"d".to_vec()),
+    };
+    let members: Vec<Vec<u8>> = set
+        .range_by_lex(&range, true, 0, None)
+        .into_iter()
+        .map(|(m, _)| m)
+        .collect();
+    assert_eq!(members, vec![b"d".to_vec(), b"c".to_vec(), b"b".to_vec()]);
+    assert_eq!(set.count_in_lex_range(&range), 3);
+
+    let empty = ScoreRange {
+        min: ScoreBound {
+            value: 0.0,
+            exclusive: true,
+        },
+        max: ScoreBound {
+            value: 0.0,
+            exclusive: false,
+        },
+    };
+    assert!(set.range_by_score(&empty, false, 0, None).is_empty());
+    assert_eq!(set.count_in_score_range(&empty), 0);
+}
+
+#[test]
+fn test_infinite_scores_survive_a_json_round_trip() {
+    let mut set = SortedSet::new();
+    set.insert(b"low", f64::NEG_INFINITY);
+    set.insert(b"mid", 1.5);
+    set.insert(b"high", f64::INFINITY);
+
+    let json = serde_json::to_string(&set).unwrap();
+    let loaded: SortedSet = serde_json::from_str(&json).unwrap();
+    assert_eq!(loaded, set);
+    assert_eq!(loaded.rank(b"high"), Some(2));
+}
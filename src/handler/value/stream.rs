@@ -0,0 +1,242 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::ops::Bound;
+
+/// A stream entry ID: milliseconds plus a sequence number within that millisecond.
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+impl StreamId {
+    pub const MIN: StreamId = StreamId { ms: 0, seq: 0 };
+    pub const MAX: StreamId = StreamId {
+        ms: u64::MAX,
+        seq: u64::MAX,
+    };
+
+    /// Parses `ms-seq`, or a bare `ms` whose sequence is `missing_seq`.
+    pub fn parse(arg: &[u8], missing_seq: u64) -> Option<StreamId> {
+        let text = std::str::from_utf8(arg).ok()?;
+        let parse = |part: &str| -> Option<u64> {
+            // `u64::from_str` accepts a leading `+`, which Redis does not.
+            part.bytes()
+                .all(|b| b.is_ascii_digit())
+                .then(|| part.parse().ok())?
+        };
+        match text.split_once('-') {
+            Some((ms, seq)) => Some(StreamId {
+                ms: parse(ms)?,
+                seq: parse(seq)?,
+            }),
+            None => Some(StreamId {
+                ms: parse(text)?,
+                seq: missing_seq,
+            }),
+        }
+    }
+
+    /// The smallest ID greater than this one.
+    pub fn successor(self) -> Option<StreamId> {
+        match self.seq.checked_add(1) {
+            Some(seq) => Some(StreamId { ms: self.ms, seq }),
+            None => Some(StreamId {
+                ms: self.ms.checked_add(1)?,
+                seq: 0,
+            }),
+        }
+    }
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+/// Whether no ID can lie between `start` and `end`. `BTreeMap::range` panics on
+/// such bounds, so callers check first.
+pub fn is_empty_range(start: Bound<StreamId>, end: Bound<StreamId>) -> bool {
+    match (start, end) {
+        (Bound::Included(s), Bound::Included(e)) => s > e,
+        (Bound::Included(s) | Bound::Excluded(s), Bound::Included(e) | Bound::Excluded(e)) => {
+            s >= e
+        }
+        _ => false,
+    }
+}
+
+/// The field/value pairs of one stream entry, in the order they were given.
+pub type StreamFields = Vec<(Vec<u8>, Vec<u8>)>;
+
+/// An entry delivered to a consumer but not yet acknowledged.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct PendingEntry {
+    pub consumer: Vec<u8>,
+    /// When the entry was last delivered, in Unix milliseconds.
+    pub delivery_time: u128,
+    pub delivery_count: u64,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Consumer {
+    /// When the consumer last read or claimed anything, in Unix milliseconds.
+    pub seen_time: u128,
+    /// This consumer's share of the group's pending entries.
+    pub pending: BTreeSet<StreamId>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ConsumerGroup {
+    /// The last entry handed out through `XREADGROUP ... >`.
+    pub last_delivered: StreamId,
+    /// Every pending entry of the group, with the consumer that owns it.
+    #[serde(with = "super::map_as_pairs")]
+    pub pending: BTreeMap<StreamId, PendingEntry>,
+    #[serde(with = "super::map_as_pairs")]
+    pub consumers: HashMap<Vec<u8>, Consumer>,
+}
+
+impl ConsumerGroup {
+    /// Returns the named consumer, creating it if needed.
+    pub fn consumer(&mut self, name: &[u8], now: u128) -> &mut Consumer {
+        let consumer = self.consumers.entry(name.to_vec()).or_default();
+        consumer.seen_time = now;
+        consumer
+    }
+
+    /// Records `id` as delivered to `consumer`, moving it from any previous owner.
+    pub fn assign(&mut self, id: StreamId, consumer: &[u8], delivery_time: u128, count: u64) {
+        if let Some(previous) = self.pending.get(&id) {
+            if let Some(owner) = self.consumers.get_mut(&previous.consumer) {
+                owner.pending.remove(&id);
+            }
+        }
+        self.pending.insert(
+            id,
+            PendingEntry {
+                consumer: consumer.to_vec(),
+                delivery_time,
+                delivery_count: count,
+            },
+        );
+        self.consumers
+            .entry(consumer.to_vec())
+            .or_default()
+            .pending
+            .insert(id);
+    }
+
+    /// Drops `id` from the pending entries list. Returns whether it was pending.
+    pub fn acknowledge(&mut self, id: StreamId) -> bool {
+        let Some(entry) = self.pending.remove(&id) else {
+            return false;
+        };
+        if let Some(owner) = self.consumers.get_mut(&entry.consumer) {
+            owner.pending.remove(&id);
+        }
+        true
+    }
+}
+
+/// How XADD and XTRIM cut a stream down.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TrimStrategy {
+    /// Keep at most this many entries.
+    MaxLen(u64),
+    /// Drop every entry with a smaller ID.
+    MinId(StreamId),
+}
+
+/// An append-only log of entries ordered by ID, plus its consumer groups.
+#[derive(Clone, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Stream {
+    #[serde(with = "super::map_as_pairs")]
+    pub entries: BTreeMap<StreamId, StreamFields>,
+    /// The greatest ID ever added. Deleting entries never lowers it.
+    pub last_id: StreamId,
+    #[serde(with = "super::map_as_pairs")]
+    pub groups: HashMap<Vec<u8>, ConsumerGroup>,
+}
+
+impl Stream {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// The next auto-generated ID at time `now`: the clock's millisecond, or the last
+    /// ID's if the clock went backwards, with the sequence bumped on a tie.
+    pub fn next_id(&self, now: u64) -> Option<StreamId> {
+        if now > self.last_id.ms {
+            Some(StreamId { ms: now, seq: 0 })
+        } else {
+            self.last_id.successor()
+        }
+    }
+
+    pub fn add(&mut self, id: StreamId, fields: StreamFields) {
+        self.entries.insert(id, fields);
+        self.last_id = id;
+    }
+
+    pub fn has_entries_after(&self, id: StreamId) -> bool {
+        self.entries
+            .range((Bound::Excluded(id), Bound::Unbounded))
+            .next()
+            .is_some()
+    }
+
+    /// Entries between `start` and `end`, oldest first (or newest first with `reverse`),
+    /// at most `count` of them.
+    pub fn range(
+        &self,
+        start: Bound<StreamId>,
+        end: Bound<StreamId>,
+        reverse: bool,
+        count: Option<usize>,
+    ) -> Vec<(StreamId, &StreamFields)> {
+        let count = count.unwrap_or(usize::MAX);
+        if is_empty_range(start, end) {
+            return Vec::new();
+        }
+        let range = self.entries.range((start, end)).map(|(id, f)| (*id, f));
+        if reverse {
+            range.rev().take(count).collect()
+        } else {
+            range.take(count).collect()
+        }
+    }
+
+    /// Removes the oldest entries until `strategy` holds, at most `limit` of them.
+    /// Returns how many were removed.
+    pub fn trim(&mut self, strategy: TrimStrategy, limit: Option<usize>) -> usize {
+        let mut removed = 0;
+        while limit.is_none_or(|limit| removed < limit) {
+            let Some((&oldest, _)) = self.entries.first_key_value() else {
+                break;
+            };
+            let excess = match strategy {
+                TrimStrategy::MaxLen(max) => self.entries.len() as u64 > max,
+                TrimStrategy::MinId(min) => oldest < min,
+            };
+            if !excess {
+                break;
+            }
+            self.entries.remove(&oldest);
+            removed += 1;
+        }
+        removed
+    }
+}
@@ -0,0 +1,909 @@
+use crate::handler::blocking::{parse_timeout, serve_blocked, wait_until_served, BlockingOp};
+use crate::handler::client_handler::Db;
+use crate::handler::commands::{
+    format_float, parse_float, parse_i64, remove_if_empty, syntax_error, wrong_arity, wrong_type,
+};
+use crate::handler::keyspace::Keyspace;
+use crate::handler::list_commands::resolve_range;
+use crate::handler::value::{
+    DictSet, LexBound, LexRange, ScoreBound, ScoreRange, SortedSet, Value, ValueWithExpiry,
+};
+use crate::resp::resp_protocol::RespMessage;
+use std::collections::HashMap;
+
+/// Looks up the live sorted set stored at `key`.
+/// Returns `Ok(None)` for a missing key and a WRONGTYPE error for any other type.
+pub fn get_zset<'a>(
+    keyspace: &'a mut Keyspace,
+    key: &[u8],
+) -> Result<Option<&'a mut SortedSet>, RespMessage> {
+    match keyspace.lookup_write(key).map(|v| &mut v.value) {
+        Some(Value::SortedSet(zset)) => Ok(Some(zset)),
+        Some(_) => Err(wrong_type()),
+        None => Ok(None),
+    }
+}
+
+/// Like `get_zset`, but creates an empty sorted set when the key is missing.
+pub fn get_or_create_zset<'a>(
+    keyspace: &'a mut Keyspace,
+    key: &[u8],
+) -> Result<&'a mut SortedSet, RespMessage> {
+    let entry = keyspace.lookup_or_insert(key, || Value::SortedSet(SortedSet::new()));
+    match &mut entry.value {
+        Value::SortedSet(zset) => Ok(zset),
+        _ => Err(wrong_type()),
+    }
+}
+
+/// Formats a score for replies. Infinite scores are spelled `inf` / `-inf`, as in Redis.
+pub fn format_score(score: f64) -> String {
+    if score.is_infinite() {
+        if score > 0.0 { "inf" } else { "-inf" }.to_string()
+    } else {
+        format_float(score)
+    }
+}
+
+fn score_reply(score: f64) -> RespMessage {
+    RespMessage::BulkString(Some(format_score(score).into_bytes()))
+}
+
+/// The `member, score` reply items for one popped or listed entry.
+pub fn score_pair(member: Vec<u8>, score: f64) -> [RespMessage; 2] {
+    [RespMessage::BulkString(Some(member)), score_reply(score)]
+}
+
+/// Replies with the members of `entries`, each followed by its score when `with_scores` is set.
+pub fn entries_reply(entries: Vec<(Vec<u8>, f64)>, with_scores: bool) -> RespMessage {
+    let mut reply = Vec::with_capacity(entries.len() * if with_scores { 2 } else { 1 });
+    for (member, score) in entries {
+        if with_scores {
+            reply.extend(score_pair(member, score));
+        } else {
+            reply.push(RespMessage::BulkString(Some(member)));
+        }
+    }
+    RespMessage::Array(reply)
+}
+
+/// Parses a ZRANGEBYSCORE-style bound: a float, `-inf` / `+inf`, optionally
+/// prefixed with `(` to make it exclusive.
+fn parse_score_bound(arg: &[u8]) -> Result<ScoreBound, RespMessage> {
+    let (exclusive, value) = match arg.strip_prefix(b"(") {
+        Some(rest) => (true, rest),
+        None => (false, arg),
+    };
+    parse_float(value)
+        .map(|value| ScoreBound { value, exclusive })
+        .map_err(|_| RespMessage::Error("ERR min or max is not a float".to_string()))
+}
+
+pub fn parse_score_range(min: &[u8], max: &[u8]) -> Result<ScoreRange, RespMessage> {
+    Ok(ScoreRange {
+        min: parse_score_bound(min)?,
+        max: parse_score_bound(max)?,
+    })
+}
+
+/// Parses a ZRANGEBYLEX-style bound: `-`, `+`, `[member` or `(member`.
+fn parse_lex_bound(arg: &[u8]) -> Result<LexBound, RespMessage> {
+    match arg.split_first() {
+        Some((b'-', [])) => Ok(LexBound::NegInfinity),
+        Some((b'+', [])) => Ok(LexBound::PosInfinity),
+        Some((b'[', member)) => Ok(LexBound::Inclusive(member.to_vec())),
+        Some((b'(', member)) => Ok(LexBound::Exclusive(member.to_vec())),
+        _ => Err(RespMessage::Error(
+            "ERR min or max not valid string range item".to_string(),
+        )),
+    }
+}
+
+pub fn parse_lex_range(min: &[u8], max: &[u8]) -> Result<LexRange, RespMessage> {
+    Ok(LexRange {
+        min: parse_lex_bound(min)?,
+        max: parse_lex_bound(max)?,
+    })
+}
+
+/// The ZADD flags; ZINCRBY behaves like ZADD with only `incr` set.
+#[derive(Default)]
+struct AddFlags {
+    nx: bool,
+    xx: bool,
+    gt: bool,
+    lt: bool,
+    ch: bool,
+    incr: bool,
+}
+
+/// Applies `(score, member)` pairs to the sorted set at `key` and builds the
+/// ZADD / ZINCRBY reply.
+async fn add_members(db: &Db, key: &[u8], pairs: &[(f64, &[u8])], flags: &AddFlags) -> RespMessage {
+    let mut db_guard = db.lock().await;
+    // XX never creates the key.
+    let zset = match get_zset(&mut db_guard, key) {
+        Ok(None) if flags.xx => {
+            return if flags.incr {
+                RespMessage::BulkString(None)
+            } else {
+                RespMessage::Integer(0)
+            };
+        }
+        Err(e) => return e,
+        Ok(_) => match get_or_create_zset(&mut db_guard, key) {
+            Ok(zset) => zset,
+            Err(e) => return e,
+        },
+    };
+
+    let (mut added, mut changed) = (0, 0);
+    let mut incr_result = None;
+    for &(score, member) in pairs {
+        match zset.score(member) {
+            Some(current) => {
+                if flags.nx {
+                    continue;
+                }
+                let updated = if flags.incr { current + score } else { score };
+                if updated.is_nan() {
+                    return RespMessage::Error(
+                        "ERR resulting score is not a number (NaN)".to_string(),
+                    );
+                }
+                if (flags.gt && updated <= current) || (flags.lt && updated >= current) {
+                    continue;
+                }
+                if updated != current {
+                    zset.insert(member, updated);
+                    changed += 1;
+                }
+                incr_result = Some(updated);
+            }
+            None => {
+                if flags.xx {
+                    continue;
+                }
+                zset.insert(member, score);
+                added += 1;
+                incr_result = Some(score);
+            }
+        }
+    }
+
+    db_guard.dirty += (added + changed) as u64;
+    serve_blocked(&mut db_guard, key);
+    if flags.incr {
+        incr_result.map_or(RespMessage::BulkString(None), score_reply)
+    } else if flags.ch {
+        RespMessage::Integer(added + changed)
+    } else {
+        RespMessage::Integer(added)
+    }
+}
+
+/// ZADD key [NX | XX] [GT | LT] [CH] [INCR] score member [score member ...]
+pub async fn zadd(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() < 4 {
+        return wrong_arity(args[0]);
+    }
+    let mut flags = AddFlags::default();
+    let mut i = 2;
+    while i < args.len() {
+        match args[i].to_ascii_uppercase().as_slice() {
+            b"NX" => flags.nx = true,
+            b"XX" => flags.xx = true,
+            b"GT" => flags.gt = true,
+            b"LT" => flags.lt = true,
+            b"CH" => flags.ch = true,
+            b"INCR" => flags.incr = true,
+            _ => break,
+        }
+        i += 1;
+    }
+
+    let rest = &args[i..];
+    if rest.is_empty() || !rest.len().is_multiple_of(2) {
+        return syntax_error();
+    }
+    if flags.nx && flags.xx {
+        return RespMessage::Error(
+            "ERR XX and NX options at the same time are not compatible".to_string(),
+        );
+    }
+    if (flags.gt && flags.lt) || ((flags.gt || flags.lt) && flags.nx) {
+        return RespMessage::Error(
+            "ERR GT, LT, and/or NX options at the same time are not compatible".to_string(),
+        );
+    }
+    if flags.incr && rest.len() > 2 {
+        return RespMessage::Error(
+            "ERR INCR option supports a single increment-element pair".to_string(),
+        );
+    }
+
+    let mut pairs = Vec::with_capacity(rest.len() / 2);
+    for pair in rest.chunks(2) {
+        match parse_float(pair[0]) {
+            Ok(score) => pairs.push((score, pair[1])),
+            Err(e) => return e,
+        }
+    }
+    add_members(db, args[1], &pairs, &flags).await
+}
+
+/// ZINCRBY key increment member
+pub async fn zincrby(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() != 4 {
+        return wrong_arity(args[0]);
+    }
+    let increment = match parse_float(args[2]) {
+        Ok(increment) => increment,
+        Err(e) => return e,
+    };
+    let flags = AddFlags {
+        incr: true,
+        ..AddFlags::default()
+    };
+    add_members(db, args[1], &[(increment, args[3])], &flags).await
+}
+
+/// ZREM key member [member ...]
+pub async fn zrem(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() < 3 {
+        return wrong_arity(args[0]);
+    }
+    let key = args[1];
+    let mut db_guard = db.lock().await;
+    let removed = match get_zset(&mut db_guard, key) {
+        Ok(Some(zset)) => args[2..]
+            .iter()
+            .filter(|member| zset.remove(member).is_some())
+            .count(),
+        Ok(None) => 0,
+        Err(e) => return e,
+    };
+    db_guard.dirty += removed as u64;
+    remove_if_empty(&mut db_guard, key);
+    RespMessage::Integer(removed as i64)
+}
+
+/// ZSCORE key member
+pub async fn zscore(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() != 3 {
+        return wrong_arity(args[0]);
+    }
+    let mut db_guard = db.lock().await;
+    match get_zset(&mut db_guard, args[1]) {
+        Ok(zset) => zset
+            .and_then(|zset| zset.score(args[2]))
+            .map_or(RespMessage::BulkString(None), score_reply),
+        Err(e) => e,
+    }
+}
+
+/// ZCARD key
+pub async fn zcard(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() != 2 {
+        return wrong_arity(args[0]);
+    }
+    let mut db_guard = db.lock().await;
+    match get_zset(&mut db_guard, args[1]) {
+        Ok(zset) => RespMessage::Integer(zset.map_or(0, |zset| zset.len() as i64)),
+        Err(e) => e,
+    }
+}
+
+/// ZCOUNT key min max
+pub async fn zcount(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() != 4 {
+        return wrong_arity(args[0]);
+    }
+    let range = match parse_score_range(args[2], args[3]) {
+        Ok(range) => range,
+        Err(e) => return e,
+    };
+    let mut db_guard = db.lock().await;
+    match get_zset(&mut db_guard, args[1]) {
+        Ok(zset) => {
+            RespMessage::Integer(zset.map_or(0, |zset| zset.count_in_score_range(&range) as i64))
+        }
+        Err(e) => e,
+    }
+}
+
+/// ZLEXCOUNT key min max
+pub async fn zlexcount(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() != 4 {
+        return wrong_arity(args[0]);
+    }
+    let range = match parse_lex_range(args[2], args[3]) {
+        Ok(range) => range,
+        Err(e) => return e,
+    };
+    let mut db_guard = db.lock().await;
+    match get_zset(&mut db_guard, args[1]) {
+        Ok(zset) => {
+            RespMessage::Integer(zset.map_or(0, |zset| zset.count_in_lex_range(&range) as i64))
+        }
+        Err(e) => e,
+    }
+}
+
+/// ZRANK / ZREVRANK key member [WITHSCORE]
+pub async fn zrank(args: &[&[u8]], db: &Db, reverse: bool) -> RespMessage {
+    if !(3..=4).contains(&args.len()) {
+        return wrong_arity(args[0]);
+    }
+    let with_score = match args.get(3) {
+        None => false,
+        Some(arg) if arg.eq_ignore_ascii_case(b"WITHSCORE") => true,
+        Some(_) => return syntax_error(),
+    };
+    let mut db_guard = db.lock().await;
+    let zset = match get_zset(&mut db_guard, args[1]) {
+        Ok(zset) => zset,
+        Err(e) => return e,
+    };
+
+    let found = zset.and_then(|zset| {
+        let rank = zset.rank(args[2])?;
+        let rank = if reverse { zset.len() - 1 - rank } else { rank };
+        Some((rank as i64, zset.score(args[2])?))
+    });
+    match found {
+        Some((rank, score)) if with_score => {
+            RespMessage::Array(vec![RespMessage::Integer(rank), score_reply(score)])
+        }
+        Some((rank, _)) => RespMessage::Integer(rank),
+        None if with_score => RespMessage::NullArray,
+        None => RespMessage::BulkString(None),
+    }
+}
+
+/// What a ZRANGE-style command selects by.
+#[derive(Clone, Copy, PartialEq)]
+pub enum RangeBy {
+    Rank,
+    Score,
+    Lex,
+}
+
+enum RangeQuery {
+    Rank(i64, i64),
+    Score(ScoreRange),
+    Lex(LexRange),
+}
+
+/// A parsed `start stop [options]` range request.
+pub struct RangeRequest {
+    query: RangeQuery,
+    reverse: bool,
+    /// `LIMIT offset count`; a negative count means no limit.
+    limit: Option<(i64, i64)>,
+    pub with_scores: bool,
+}
+
+/// Parses `start stop [BYSCORE | BYLEX] [REV] [LIMIT offset count] [WITHSCORES]`.
+///
+/// `legacy` is set for ZREVRANGE, ZRANGEBYSCORE and friends, whose name already
+/// fixes the selection and direction, so BYSCORE, BYLEX and REV are not accepted.
+pub fn parse_range_request(
+    args: &[&[u8]],
+    legacy: Option<(RangeBy, bool)>,
+) -> Result<RangeRequest, RespMessage> {
+    let (mut by, mut reverse) = legacy.unwrap_or((RangeBy::Rank, false));
+    let mut limit = None;
+    let mut with_scores = false;
+
+    let mut i = 2;
+    while i < args.len() {
+        let option = args[i].to_ascii_uppercase();
+        match option.as_slice() {
+            b"WITHSCORES" => with_scores = true,
+            b"BYSCORE" if legacy.is_none() && by == RangeBy::Rank => by = RangeBy::Score,
+            b"BYLEX" if legacy.is_none() && by == RangeBy::Rank => by = RangeBy::Lex,
+            b"REV" if legacy.is_none() => reverse = true,
+            b"LIMIT" if i + 2 < args.len() => {
+                limit = Some((parse_i64(args[i + 1])?, parse_i64(args[i + 2])?));
+                i += 2;
+            }
+            _ => return Err(syntax_error()),
+        }
+        i += 1;
+    }
+
+    if limit.is_some() && by == RangeBy::Rank {
+        return Err(RespMessage::Error(
+            "ERR syntax error, LIMIT is only supported in combination with either BYSCORE or BYLEX"
+                .to_string(),
+        ));
+    }
+    if with_scores && by == RangeBy::Lex {
+        return Err(RespMessage::Error(
+            "ERR syntax error, WITHSCORES not supported in combination with BYLEX".to_string(),
+        ));
+    }
+
+    // In reverse, score and lex ranges are given from the top: `max min`.
+    let (low, high) = if reverse && by != RangeBy::Rank {
+        (args[1], args[0])
+    } else {
+        (args[0], args[1])
+    };
+    let query = match by {
+        RangeBy::Rank => RangeQuery::Rank(parse_i64(args[0])?, parse_i64(args[1])?),
+        RangeBy::Score => RangeQuery::Score(parse_score_range(low, high)?),
+        RangeBy::Lex => RangeQuery::Lex(parse_lex_range(low, high)?),
+    };
+    Ok(RangeRequest {
+        query,
+        reverse,
+        limit,
+        with_scores,
+    })
+}
+
+/// Runs a parsed range request against `zset`.
+pub fn select_range(zset: &SortedSet, request: &RangeRequest) -> Vec<(Vec<u8>, f64)> {
+    let (offset, count) = match request.limit {
+        Some((offset, _)) if offset < 0 => return Vec::new(),
+        Some((offset, count)) => (offset as usize, (count >= 0).then_some(count as usize)),
+        None => (0, None),
+    };
+    match &request.query {
+        RangeQuery::Rank(start, stop) => match resolve_range(*start, *stop, zset.len()) {
+            Some((start, stop)) => zset.range_by_rank(start, stop, request.reverse),
+            None => Vec::new(),
+        },
+        RangeQuery::Score(range) => zset.range_by_score(range, request.reverse, offset, count),
+        RangeQuery::Lex(range) => zset.range_by_lex(range, request.reverse, offset, count),
+    }
+}
+
+/// ZRANGE key start stop [BYSCORE | BYLEX] [REV] [LIMIT offset count] [WITHSCORES]
+/// With `legacy` set this is one of ZREVRANGE, ZRANGEBYSCORE, ZREVRANGEBYSCORE,
+/// ZRANGEBYLEX or ZREVRANGEBYLEX.
+pub async fn zrange(args: &[&[u8]], db: &Db, legacy: Option<(RangeBy, bool)>) -> RespMessage {
+    if args.len() < 4 {
+        return wrong_arity(args[0]);
+    }
+    let request = match parse_range_request(&args[2..], legacy) {
+        Ok(request) => request,
+        Err(e) => return e,
+    };
+    let mut db_guard = db.lock().await;
+    match get_zset(&mut db_guard, args[1]) {
+        Ok(Some(zset)) => entries_reply(select_range(zset, &request), request.with_scores),
+        Ok(None) => RespMessage::Array(vec![]),
+        Err(e) => e,
+    }
+}
+
+/// Replaces `destination` with `zset` and replies with its size. An empty result
+/// deletes the destination instead; whatever it held before is overwritten either way.
+fn store_zset(keyspace: &mut Keyspace, destination: &[u8], zset: SortedSet) -> RespMessage {
+    let len = zset.len();
+    keyspace.dirty += 1;
+    if zset.is_empty() {
+        keyspace.delete(destination);
+    } else {
+        keyspace.insert(
+            destination.to_vec(),
+            ValueWithExpiry {
+                value: Value::SortedSet(zset),
+                expiry: None,
+            },
+        );
+        serve_blocked(keyspace, destination);
+    }
+    RespMessage::Integer(len as i64)
+}
+
+/// ZRANGESTORE destination source start stop [BYSCORE | BYLEX] [REV] [LIMIT offset count]
+pub async fn zrangestore(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() < 5 {
+        return wrong_arity(args[0]);
+    }
+    let request = match parse_range_request(&args[3..], None) {
+        Ok(request) if request.with_scores => return syntax_error(),
+        Ok(request) => request,
+        Err(e) => return e,
+    };
+    let mut db_guard = db.lock().await;
+    let selected = match get_zset(&mut db_guard, args[2]) {
+        Ok(Some(zset)) => select_range(zset, &request),
+        Ok(None) => Vec::new(),
+        Err(e) => return e,
+    };
+    store_zset(&mut db_guard, args[1], selected.into_iter().collect())
+}
+
+/// ZREMRANGEBYRANK / ZREMRANGEBYSCORE / ZREMRANGEBYLEX key start stop
+pub async fn zremrange(args: &[&[u8]], db: &Db, by: RangeBy) -> RespMessage {
+    if args.len() != 4 {
+        return wrong_arity(args[0]);
+    }
+    let request = match parse_range_request(&args[2..], Some((by, false))) {
+        Ok(request) => request,
+        Err(e) => return e,
+    };
+    let key = args[1];
+    let mut db_guard = db.lock().await;
+    let removed = match get_zset(&mut db_guard, key) {
+        Ok(Some(zset)) => {
+            let selected = select_range(zset, &request);
+            for (member, _) in &selected {
+                zset.remove(member);
+            }
+            selected.len()
+        }
+        Ok(None) => 0,
+        Err(e) => return e,
+    };
+    db_guard.dirty += removed as u64;
+    remove_if_empty(&mut db_guard, key);
+    RespMessage::Integer(removed as i64)
+}
+
+/// ZMSCORE key member [member ...]
+pub async fn zmscore(args: &[&[u8]], db: &Db) -> RespMessage {
+    if args.len() < 3 {
+        return wrong_arity(args[0]);
+    }
+    let mut db_guard = db.lock().await;
+    match get_zset(&mut db_guard, args[1]) {
+        Ok(zset) => RespMessage::Array(
+            args[2..]
+                .iter()
+                .map(|member| {
+                    zset.as_ref()
+                        .and_then(|zset| zset.score(member))
+                        .map_or(RespMessage::BulkString(None), score_reply)
+                })
+                .collect(),
+        ),
+        Err(e) => e,
+    }
+}
+
+/// Which end of a sorted set ZPOPMIN / ZPOPMAX take from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ScoreEnd {
+    Min,
+    Max,
+}
+
+pub fn pop_scored(zset: &mut SortedSet, end: ScoreEnd) -> Option<(Vec<u8>, f64)> {
+    match end {
+        ScoreEnd::Min => zset.pop_min(),
+        ScoreEnd::Max => zset.pop_max(),
+    }
+}
+
+/// ZPOPMIN / ZPOPMAX key [count]
+pub async fn zpop(args: &[&[u8]], db: &Db, end: ScoreEnd) -> RespMessage {
+    if !(2..=3).contains(&args.len()) {
+        return wrong_arity(args[0]);
+    }
+    let count = match args.get(2).map(|arg| parse_i64(arg)) {
+        None => 1,
+        Some(Ok(count)) if count >= 0 => count as usize,
+        Some(Ok(_)) => {
+            return RespMessage::Error("ERR value is out of range, must be positive".to_string())
+        }
+        Some(Err(e)) => return e,
+    };
+    let key = args[1];
+    let mut db_guard = db.lock().await;
+    let mut reply = Vec::new();
+    match get_zset(&mut db_guard, key) {
+        Ok(Some(zset)) => {
+            for _ in 0..count {
+                match pop_scored(zset, end) {
+                    Some((member, score)) => reply.extend(score_pair(member, score)),
+                    None => break,
+                }
+            }
+        }
+        Ok(None) => {}
+        Err(e) => return e,
+    }
+    db_guard.dirty += (reply.len() / 2) as u64;
+    remove_if_empty(&mut db_guard, key);
+    RespMessage::Array(reply)
+}
+
+/// BZPOPMIN / BZPOPMAX key [key ...] timeout
+pub async fn blocking_zpop(args: &[&[u8]], db: &Db, end: ScoreEnd) -> RespMessage {
+    if args.len() < 3 {
+        return wrong_arity(args[0]);
+    }
+    let timeout = match parse_timeout(args[args.len() - 1]) {
+        Ok(timeout) => timeout,
+        Err(e) => return e,
+    };
+    let keys = &args[1..args.len() - 1];
+
+    let blocked = {
+        let mut db_guard = db.lock().await;
+        for key in keys {
+            match get_zset(&mut db_guard, key) {
+                Ok(Some(zset)) => {
+                    let popped = pop_scored(zset, end);
+                    remove_if_empty(&mut db_guard, key);
+                    db_guard.dirty += 1;
+                    let mut reply = vec![RespMessage::BulkString(Some(key.to_vec()))];
+                    if let Some((member, score)) = popped {
+                        reply.extend(score_pair(member, score));
+                    }
+                    return RespMessage::Array(reply);
+                }
+                Ok(None) => {}
+                Err(e) => return e,
+            }
+        }
+        db_guard.block(
+            keys.iter().map(|key| key.to_vec()).collect(),
+            BlockingOp::PopScored(end),
+        )
+    };
+
+    wait_until_served(db, blocked, timeout)
+        .await
+        .unwrap_or(RespMessage::NullArray)
+}
+
+/// The multi-key sorted set operations.
+#[derive(Clone, Copy, PartialEq)]
+pub enum ZsetOp {
+    Union,
+    Inter,
+    Diff,
+}
+
+/// How ZUNION and ZINTER combine the scores of a member found in several inputs.
+#[derive(Clone, Copy)]
+enum Aggregate {
+    Sum,
+    Min,
+    Max,
+}
+
+impl Aggregate {
+    fn apply(self, current: f64, score: f64) -> f64 {
+        match self {
+            // inf + -inf is NaN; Redis scores it as 0.
+            Aggregate::Sum => Some(current + score).filter(|s| !s.is_nan()).unwrap_or(0.0),
+            Aggregate::Min => current.min(score),
+            Aggregate::Max => current.max(score),
+        }
+    }
+}
+
+/// A parsed `numkeys key [key ...] [WEIGHTS weight ...] [AGGREGATE SUM|MIN|MAX] [WITHSCORES]`.
+struct Aggregation<'a> {
+    keys: Vec<&'a [u8]>,
+    weights: Vec<f64>,
+    aggregate: Aggregate,
+    with_scores: bool,
+}
+
+/// Parses the arguments of ZUNION / ZINTER / ZDIFF and their *STORE variants,
+/// starting at `numkeys`. ZDIFF takes no WEIGHTS or AGGREGATE, and the *STORE
+/// variants take no WITHSCORES.
+fn parse_aggregation<'a>(
+    cmd: &[u8],
+    args: &[&'a [u8]],
+    op: ZsetOp,
+    store: bool,
+) -> Result<Aggregation<'a>, RespMessage> {
+    let numkeys = parse_i64(args[0])?;
+    if numkeys < 1 {
+        return Err(RespMessage::Error(format!(
+            "ERR at least 1 input key is needed for '{}' command",
+            String::from_utf8_lossy(cmd).to_lowercase()
+        )));
+    }
+    let numkeys = numkeys as usize;
+    if numkeys > args.len() - 1 {
+        return Err(syntax_error());
+    }
+    let mut aggregation = Aggregation {
+        keys: args[1..=numkeys].to_vec(),
+        weights: vec![1.0; numkeys],
+        aggregate: Aggregate::Sum,
+        with_scores: false,
+    };
+
+    let mut i = numkeys + 1;
+    while i < args.len() {
+        let option = args[i].to_ascii_uppercase();
+        match option.as_slice() {
+            b"WEIGHTS" if op != ZsetOp::Diff && i + numkeys < args.len() => {
+                for (weight, arg) in aggregation.weights.iter_mut().zip(&args[i + 1..]) {
+                    *weight = parse_float(arg).map_err(|_| {
+                        RespMessage::Error("ERR weight value is not a float".to_string())
+                    })?;
+                }
+                i += numkeys;
+            }
+            b"AGGREGATE" if op != ZsetOp::Diff && i + 1 < args.len() => {
+                aggregation.aggregate = match args[i + 1].to_ascii_uppercase().as_slice() {
+                    b"SUM" => Aggregate::Sum,
+                    b"MIN" => Aggregate::Min,
+                    b"MAX" => Aggregate::Max,
+                    _ => return Err(syntax_error()),
+                };
+                i += 1;
+            }
+            b"WITHSCORES" if !store => aggregation.with_scores = true,
+            _ => return Err(syntax_error()),
+        }
+        i += 1;
+    }
+    Ok(aggregation)
+}
+
+/// An input to the multi-key operations. Plain sets take part with every score at 1.
+enum Source<'a> {
+    Zset(&'a SortedSet),
+    Set(&'a DictSet<Vec<u8>>),
+}
+
+impl Source<'_> {
+    fn len(&self) -> usize {
+        match self {
+            Source::Zset(zset) => zset.len(),
+            Source::Set(set) => set.len(),
+        }
+    }
+
+    fn score(&self, member: &[u8]) -> Option<f64> {
+        match self {
+            Source::Zset(zset) => zset.score(member),
+            Source::Set(set) => set.contains(member).then_some(1.0),
+        }
+    }
+
+    fn entries(&self) -> Box<dyn Iterator<Item = (&[u8], f64)> + '_> {
+        match self {
+            Source::Zset(zset) => Box::new(zset.iter()),
+            Source::Set(set) => Box::new(set.iter().map(|member| (member.as_slice(), 1.0))),
+        }
+    }
+}
+
+/// Looks up the inputs of a multi-key operation. Missing keys come back as `None`;
+/// anything but a sorted set or a set fails the whole lookup.
+fn get_sources<'a>(
+    keyspace: &'a mut Keyspace,
+    keys: &[&[u8]],
+) -> Result<Vec<Option<Source<'a>>>, RespMessage> {
+    for key in keys {
+        if let Some(v) = keyspace.lookup_read(key) {
+            if !matches!(v.value, Value::SortedSet(_) | Value::Set(_)) {
+                return Err(wrong_type());
+            }
+        }
+    }
+    let keyspace: &'a Keyspace = keyspace;
+    Ok(keys
+        .iter()
+        .map(|key| match keyspace.entries.get(*key).map(|v| &v.value) {
+            Some(Value::SortedSet(zset)) => Some(Source::Zset(zset)),
+            Some(Value::Set(set)) => Some(Source::Set(set)),
+            _ => None,
+        })
+        .collect())
+}
+
+/// Scales a score by its input's weight; `0 * inf` counts as 0, as in Redis.
+fn weighted(score: f64, weight: f64) -> f64 {
+    Some(score * weight).filter(|s| !s.is_nan()).unwrap_or(0.0)
+}
+
+fn combine(sources: &[Option<Source>], aggregation: &Aggregation, op: ZsetOp) -> SortedSet {
+    let weights = &aggregation.weights;
+    match op {
+        ZsetOp::Union => {
+            let mut scores: HashMap<&[u8], f64> = HashMap::new();
+            for (source, &weight) in sources.iter().zip(weights) {
+                for (member, score) in source.iter().flat_map(|source| source.entries()) {
+                    let score = weighted(score, weight);
+                    scores
+                        .entry(member)
+                        .and_modify(|current| {
+                            *current = aggregation.aggregate.apply(*current, score)
+                        })
+                        .or_insert(score);
+                }
+            }
+            scores
+                .into_iter()
+                .map(|(member, score)| (member.to_vec(), score))
+                .collect()
+        }
+        ZsetOp::Inter => {
+            let Some(sources) = sources
+                .iter()
+                .map(Option::as_ref)
+                .collect::<Option<Vec<_>>>()
+            else {
+                return SortedSet::new();
+            };
+            // Walk the smallest input and probe the others.
+            let smallest = (0..sources.len())
+                .min_by_key(|&i| sources[i].len())
+                .unwrap_or(0);
+            sources[smallest]
+                .entries()
+                .filter_map(|(member, score)| {
+                    let mut total = weighted(score, weights[smallest]);
+                    for (i, other) in sources.iter().enumerate() {
+                        if i != smallest {
+                            let score = weighted(other.score(member)?, weights[i]);
+                            total = aggregation.aggregate.apply(total, score);
+                        }
+                    }
+                    Some((member.to_vec(), total))
+                })
+                .collect()
+        }
+        ZsetOp::Diff => {
+            let Some(Some(first)) = sources.first() else {
+                return SortedSet::new();
+            };
+            first
+                .entries()
+                .filter(|(member, _)| {
+                    sources[1..]
+                        .iter()
+                        .flatten()
+                        .all(|other| other.score(member).is_none())
+                })
+                .map(|(member, score)| (member.to_vec(), score))
+                .collect()
+        }
+    }
+}
+
+/// ZUNION / ZINTER / ZDIFF numkeys key [key ...] [WEIGHTS ...] [AGGREGATE ...] [WITHSCORES]
+pub async fn zset_op(args: &[&[u8]], db: &Db, op: ZsetOp) -> RespMessage {
+    if args.len() < 3 {
+        return wrong_arity(args[0]);
+    }
+    let aggregation = match parse_aggregation(args[0], &args[1..], op, false) {
+        Ok(aggregation) => aggregation,
+        Err(e) => return e,
+    };
+    let mut db_guard = db.lock().await;
+    let result = match get_sources(&mut db_guard, &aggregation.keys) {
+        Ok(sources) => combine(&sources, &aggregation, op),
+        Err(e) => return e,
+    };
+    entries_reply(
+        result
+            .iter()
+            .map(|(member, score)| (member.to_vec(), score))
+            .collect(),
+        aggregation.with_scores,
+    )
+}
+
+/// ZUNIONSTORE / ZINTERSTORE / ZDIFFSTORE destination numkeys key [key ...] [WEIGHTS ...] [AGGREGATE ...]
+pub async fn zset_op_store(args: &[&[u8]], db: &Db, op: ZsetOp) -> RespMessage {
+    if args.len() < 4 {
+        return wrong_arity(args[0]);
+    }
+    let aggregation = match parse_aggregation(args[0], &args[2..], op, true) {
+        Ok(aggregation) => aggregation,
+        Err(e) => return e,
+    };
+    let mut db_guard = db.lock().await;
+    let result = match get_sources(&mut db_guard, &aggregation.keys) {
+        Ok(sources) => combine(&sources, &aggregation, op),
+        Err(e) => return e,
+    };
+    store_zset(&mut db_guard, args[1], result)
+}
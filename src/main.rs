@@ -1,25 +1,164 @@
+mod config;
 mod handler;
 mod resp;
+use config::Config;
+use handler::active_expire::{run_active_expire, ActiveExpireConfig};
+use handler::aof::{load_aof, run_aof_fsync, run_auto_rewrite, write_aof_base, Aof};
 use handler::client_handler::handle_client;
-use std::collections::HashMap;
+use handler::databases::{Databases, Session};
+use handler::keyspace::Keyspace;
+use handler::persistence::{load_snapshot, run_save_points, SnapshotError, Snapshotter};
+use std::path::Path;
 use std::sync::Arc;
+use std::time::Instant;
 use tokio::net::TcpListener;
 use tokio::spawn;
-use tokio::sync::Mutex;
+
+/// Reads the snapshot into fresh databases, or exits if it can't be loaded.
+fn load_databases(config: &Config, snapshotter: &Snapshotter) -> Databases {
+    let new_keyspaces = || (0..config.databases).map(|_| Keyspace::default()).collect();
+    let mut keyspaces: Vec<Keyspace> = new_keyspaces();
+    let started = Instant::now();
+    let path = snapshotter.path().display();
+    match load_snapshot(snapshotter.path(), snapshotter.format(), &mut keyspaces) {
+        Ok(loaded) => println!(
+            "Loaded {} keys from {} in {:.3}s ({} expired keys skipped)",
+            loaded.keys,
+            path,
+            started.elapsed().as_secs_f64(),
+            loaded.expired
+        ),
+        Err(e @ SnapshotError::Corrupt(_)) if config.ignore_corrupt_snapshot => {
+            eprintln!("Ignoring {}: {}. Starting empty.", path, e);
+            keyspaces = new_keyspaces();
+        }
+        Err(e @ SnapshotError::Corrupt(_)) => {
+            eprintln!(
+                "Refusing to start, {}: {}. Pass `--ignore-corrupt-snapshot yes` to start empty.",
+                path, e
+            );
+            std::process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("Refusing to start, {}: {}", path, e);
+            std::process::exit(1);
+        }
+    }
+    Databases::from_keyspaces(keyspaces)
+}
+
+/// Rebuilds the databases from the append-only file, or exits if it can't be replayed.
+async fn replay_aof(config: &Config, path: &Path) -> Databases {
+    let started = Instant::now();
+    match load_aof(path, config.databases).await {
+        Ok((databases, loaded)) => {
+            if loaded.truncated_bytes > 0 {
+                eprintln!(
+                    "{} ended in an incomplete command: dropped its last {} bytes",
+                    path.display(),
+                    loaded.truncated_bytes
+                );
+            }
+            println!(
+                "Replayed {} commands from {} in {:.3}s",
+                loaded.commands,
+                path.display(),
+                started.elapsed().as_secs_f64()
+            );
+            databases
+        }
+        Err(e) => {
+            eprintln!("Refusing to start, {}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Opens the append-only file, first writing one that rebuilds `databases` if
+/// there is none yet. Exits if either fails.
+async fn open_aof(config: &Config, path: &Path, databases: &Databases) -> Arc<Aof> {
+    if !path.exists() {
+        let guards = databases.lock_all().await;
+        if let Err(e) = write_aof_base(path, guards.iter().map(|keyspace| &**keyspace)) {
+            eprintln!("Can't create {}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+    }
+    match Aof::open(path, config.appendfsync, databases.count()) {
+        Ok(aof) => Arc::new(aof),
+        Err(e) => {
+            eprintln!("Can't open {}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+    }
+}
 
 #[tokio::main]
 async fn main() {
-    let listener = TcpListener::bind("127.0.0.1:6379").await.unwrap();
-    println!("🚀 xRedis Lite Server running on port 6379...");
+    let config = match Config::from_args(std::env::args().skip(1)) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("xredis: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let snapshotter = Arc::new(Snapshotter::new(
+        config.snapshot_file(),
+        config.snapshot_format,
+    ));
+    let aof_path = Path::new(&config.appendfilename);
+    // With the append-only file on, it has the latest data; the snapshot only
+    // seeds it the first time.
+    let databases = if config.appendonly && aof_path.exists() {
+        replay_aof(&config, aof_path).await
+    } else {
+        load_databases(&config, &snapshotter)
+    };
+    let aof = if config.appendonly {
+        let aof = open_aof(&config, aof_path, &databases).await;
+        aof.attach(&databases).await;
+        Some(aof)
+    } else {
+        None
+    };
+
+    let listener = TcpListener::bind(("127.0.0.1", config.port)).await.unwrap();
+    println!("🚀 xRedis Lite Server running on port {}...", config.port);
 
-    let db = Arc::new(Mutex::new(HashMap::new())); // Use tokio::sync::Mutex
+    // Evict expired keys in the background, even if no client ever reads them.
+    spawn(run_active_expire(
+        databases.clone(),
+        ActiveExpireConfig::new(config.hz, config.active_expire_effort),
+        aof.clone(),
+    ));
+
+    if !config.save_points.is_empty() {
+        spawn(run_save_points(
+            databases.clone(),
+            Arc::clone(&snapshotter),
+            config.save_points.clone(),
+        ));
+    }
+
+    if let Some(aof) = &aof {
+        spawn(run_aof_fsync(Arc::clone(aof)));
+        spawn(run_auto_rewrite(
+            Arc::clone(aof),
+            databases.clone(),
+            config.auto_aof_rewrite_percentage,
+            config.auto_aof_rewrite_min_size,
+        ));
+    }
 
     loop {
         let (socket, _) = listener.accept().await.unwrap();
-        let db = Arc::clone(&db); // Clone Arc for each client
+        // Each client shares the same databases
+        let mut session = Session::new(databases.clone(), Arc::clone(&snapshotter));
+        session.aof = aof.clone();
 
         spawn(async move {
-            handle_client(socket, db).await;
+            handle_client(socket, session).await;
         });
     }
 }
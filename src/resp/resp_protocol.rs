@@ -1,6 +1,3 @@
-use core::fmt;
-use std::fmt::Display;
-
 /*
 Enum representing the different types of RESP messages that can be serialized or deserialized.
 The RESP protocol defines several types of messages, each with its own specific format.
@@ -13,6 +10,7 @@ This enum provides variants for each of these types:
   - `Some(bytes)` for a string with content (e.g., `$5\r\nHello\r\n`).
   - `None` for a null bulk string (e.g., `$-1\r\n`), used for absent or expired values.
 - `Array`: Represents an array of RESP messages, prefixed with `*` (e.g., `*2\r\n$3\r\nfoo\r\n$3\r\nbar\r\n`).
+- `NullArray`: Represents a null array (`*-1\r\n`), returned e.g. by `LPOP key count` on a missing key.
 */
 
 #[derive(Debug, PartialEq)]
@@ -22,109 +20,261 @@ pub enum RespMessage {
     Integer(i64),
     BulkString(Option<Vec<u8>>),
     Array(Vec<RespMessage>),
+    NullArray,
 }
 
-impl Display for RespMessage {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+impl RespMessage {
+    /// Appends the wire encoding of this message to `out`.
+    /// Bulk strings are copied verbatim, so arbitrary bytes round-trip unchanged.
+    pub fn encode(&self, out: &mut Vec<u8>) {
         match self {
-            RespMessage::SimpleString(s) => write!(f, "+{}\r\n", s),
-            RespMessage::Error(s) => write!(f, "-{}\r\n", s),
-            RespMessage::Integer(i) => write!(f, ":{}\r\n", i),
+            RespMessage::SimpleString(s) => {
+                out.push(b'+');
+                out.extend_from_slice(s.as_bytes());
+                out.extend_from_slice(b"\r\n");
+            }
+            RespMessage::Error(s) => {
+                out.push(b'-');
+                out.extend_from_slice(s.as_bytes());
+                out.extend_from_slice(b"\r\n");
+            }
+            RespMessage::Integer(i) => {
+                out.extend_from_slice(format!(":{}\r\n", i).as_bytes());
+            }
             RespMessage::BulkString(Some(bytes)) => {
-                write!(f, "${}\r\n", bytes.len())?;
-                let s = std::str::from_utf8(bytes).map_err(|_| fmt::Error)?;
-                f.write_str(s)?;
-                write!(f, "\r\n")
+                out.extend_from_slice(format!("${}\r\n", bytes.len()).as_bytes());
+                out.extend_from_slice(bytes);
+                out.extend_from_slice(b"\r\n");
             }
-            RespMessage::BulkString(None) => write!(f, "$-1\r\n"),
+            RespMessage::BulkString(None) => out.extend_from_slice(b"$-1\r\n"),
             RespMessage::Array(a) => {
-                write!(f, "*{}\r\n", a.len())?;
+                out.extend_from_slice(format!("*{}\r\n", a.len()).as_bytes());
                 for m in a {
-                    write!(f, "{}", m)?;
+                    m.encode(out);
                 }
-                Ok(())
             }
+            RespMessage::NullArray => out.extend_from_slice(b"*-1\r\n"),
         }
     }
 }
 
+/// Upper bound on a single bulk string, matching Redis's default `proto-max-bulk-len`.
+const MAX_BULK_LEN: i64 = 512 * 1024 * 1024;
+/// Upper bound on the number of elements in a multi-bulk request.
+const MAX_ARRAY_LEN: usize = 1024 * 1024;
+/// How deeply arrays may nest. Requests are flat and replies nest a few levels at
+/// most, so this only stops a stream of `*1\r\n` from recursing without end.
+const MAX_NESTING: usize = 32;
+
+/// Parses a buffer that must hold exactly one complete message.
+/// The server reads through `RespDecoder`; this is the one-shot form.
+#[allow(dead_code)]
 pub fn parse_resp(input: &[u8]) -> Result<RespMessage, String> {
-    let (msg, remaining) = parse_resp_recursive(input)?;
-    if !remaining.is_empty() {
-        return Err("Trailing data".to_string());
+    let mut decoder = RespDecoder::new();
+    decoder.feed(input);
+    match decoder.next_frame()? {
+        Some(msg) if decoder.pending_len() == 0 => Ok(msg),
+        Some(_) => Err("Trailing data".to_string()),
+        None => Err("Incomplete message".to_string()),
     }
-    Ok(msg)
 }
 
-fn parse_resp_recursive(input: &[u8]) -> Result<(RespMessage, &[u8]), String> {
-    if input.is_empty() {
-        return Err("Empty input".to_string());
+/*
+Incremental decoder for a RESP byte stream.
+
+Bytes read from a connection are appended with `feed`, and `next_frame` hands back
+complete frames one at a time, in the order they were received:
+
+- `Ok(Some(frame))`: a complete frame was decoded and removed from the buffer.
+- `Ok(None)`: the buffer holds no complete frame yet; read more data and try again.
+- `Err(reason)`: the stream violates the protocol and the connection should be dropped.
+
+A frame may be split across any number of reads, and one read may carry several
+pipelined frames.
+
+Decoding never goes over the same bytes twice: the elements of an array are taken
+out of the buffer as each one completes, with the array kept open until its last
+element arrives, and the search for the end of a line picks up where the last
+read left it. A large request arriving in small reads costs time in proportion to
+its size.
+*/
+#[derive(Debug, Default)]
+pub struct RespDecoder {
+    buffer: Vec<u8>,
+    start: usize,
+    /// Arrays begun but not finished, innermost last: how many elements each
+    /// still needs, and those it has.
+    open: Vec<(usize, Vec<RespMessage>)>,
+    /// Bytes taken out of the buffer for the arrays in `open`.
+    open_len: usize,
+    /// How far past `start` the search for the end of a line has got.
+    scanned: usize,
+}
+
+/// One step of decoding, from the start of the undecoded bytes.
+enum Parsed {
+    /// A complete frame other than a non-empty array, and the bytes it took.
+    Frame(RespMessage, usize),
+    /// The header of an array with this many elements, and the bytes it took.
+    ArrayHeader(usize, usize),
+    /// More bytes are needed. The search for the end of the line can start over
+    /// from this offset once they arrive.
+    Incomplete(usize),
+}
+
+impl RespDecoder {
+    pub fn new() -> Self {
+        Self::default()
     }
 
-    match input[0] {
-        b'+' => {
-            let pos = input
-                .windows(2)
-                .position(|w| w == b"\r\n")
-                .ok_or("Missing CRLF for simple string")?;
-            let s = std::str::from_utf8(&input[1..pos]).map_err(|_| "Invalid UTF-8")?;
-            Ok((RespMessage::SimpleString(s.to_string()), &input[pos + 2..]))
+    pub fn feed(&mut self, data: &[u8]) {
+        // Drop already-decoded bytes before growing the buffer so it does not
+        // keep every frame ever received on a long-lived connection.
+        if self.start > 0 {
+            self.buffer.drain(..self.start);
+            self.start = 0;
+        }
+        self.buffer.extend_from_slice(data);
+    }
+
+    /// Bytes fed but not yet decoded: the start of a frame still incomplete.
+    pub fn pending_len(&self) -> usize {
+        self.open_len + self.buffer.len() - self.start
+    }
+
+    pub fn next_frame(&mut self) -> Result<Option<RespMessage>, String> {
+        loop {
+            let mut frame = match parse_item(&self.buffer[self.start..], self.scanned)? {
+                Parsed::Incomplete(scanned) => {
+                    self.scanned = scanned;
+                    return Ok(None);
+                }
+                Parsed::ArrayHeader(count, used) => {
+                    if self.open.len() >= MAX_NESTING {
+                        return Err("Arrays nested too deeply".to_string());
+                    }
+                    self.consume(used);
+                    self.open_len += used;
+                    self.open.push((count, Vec::with_capacity(count.min(64))));
+                    continue;
+                }
+                Parsed::Frame(frame, used) => {
+                    self.consume(used);
+                    if !self.open.is_empty() {
+                        self.open_len += used;
+                    }
+                    frame
+                }
+            };
+            // Add the frame to the array it belongs to, closing every array it
+            // completes.
+            loop {
+                let Some((remaining, elements)) = self.open.last_mut() else {
+                    self.open_len = 0;
+                    return Ok(Some(frame));
+                };
+                elements.push(frame);
+                *remaining -= 1;
+                if *remaining > 0 {
+                    break;
+                }
+                let (_, elements) = self.open.pop().expect("an open array");
+                frame = RespMessage::Array(elements);
+            }
         }
-        b'-' => {
-            let pos = input
-                .windows(2)
-                .position(|w| w == b"\r\n")
-                .ok_or("Missing CRLF for error message")?;
-            let s = std::str::from_utf8(&input[1..pos]).map_err(|_| "Invalid UTF-8")?;
-            Ok((RespMessage::Error(s.to_string()), &input[pos + 2..]))
+    }
+
+    fn consume(&mut self, used: usize) {
+        self.start += used;
+        self.scanned = 0;
+        if self.start == self.buffer.len() {
+            self.buffer.clear();
+            self.start = 0;
+        }
+    }
+}
+
+/// Returns the position of the first CRLF in `input` at or after `from`, if any.
+fn find_crlf(input: &[u8], from: usize) -> Option<usize> {
+    input
+        .get(from..)?
+        .windows(2)
+        .position(|w| w == b"\r\n")
+        .map(|pos| from + pos)
+}
+
+/// Reads the header line of a frame (everything between the type byte and the CRLF),
+/// looking for its end from `scanned` on. Returns `Err` with where to look from
+/// next time when the line is not complete yet.
+fn read_line(input: &[u8], scanned: usize) -> Result<Result<(&str, usize), usize>, String> {
+    match find_crlf(input, scanned) {
+        Some(pos) => {
+            let line = std::str::from_utf8(&input[1..pos]).map_err(|_| "Invalid UTF-8")?;
+            Ok(Ok((line, pos + 2)))
         }
+        // The last byte may be the `\r` of a CRLF split across reads.
+        None => Ok(Err(input.len().saturating_sub(1).max(scanned))),
+    }
+}
+
+/// Decodes one frame from the start of `input`, or only the header of an array,
+/// whose elements come as frames of their own.
+fn parse_item(input: &[u8], scanned: usize) -> Result<Parsed, String> {
+    match input.first() {
+        None => return Ok(Parsed::Incomplete(0)),
+        Some(b'+' | b'-' | b':' | b'$' | b'*') => {}
+        Some(_) => return Err("Invalid message type".to_string()),
+    }
+    let (line, header) = match read_line(input, scanned)? {
+        Ok(line) => line,
+        Err(scanned) => return Ok(Parsed::Incomplete(scanned)),
+    };
+
+    match input[0] {
+        b'+' => Ok(Parsed::Frame(
+            RespMessage::SimpleString(line.to_string()),
+            header,
+        )),
+        b'-' => Ok(Parsed::Frame(RespMessage::Error(line.to_string()), header)),
         b':' => {
-            let pos = input
-                .windows(2)
-                .position(|w| w == b"\r\n")
-                .ok_or("Missing CRLF for integer")?;
-            let s = std::str::from_utf8(&input[1..pos]).map_err(|_| "Invalid UTF-8")?;
-            let i = s.parse().map_err(|_| "Invalid integer")?;
-            Ok((RespMessage::Integer(i), &input[pos + 2..]))
+            let i = line.parse().map_err(|_| "Invalid integer")?;
+            Ok(Parsed::Frame(RespMessage::Integer(i), header))
         }
         b'$' => {
-            let pos = input
-                .windows(2)
-                .position(|w| w == b"\r\n")
-                .ok_or("Missing CRLF in bulk string header")?;
-            let len_str = std::str::from_utf8(&input[1..pos]).map_err(|_| "Invalid UTF-8")?;
-            let len = len_str
+            let len = line
                 .parse::<i64>()
                 .map_err(|_| "Invalid bulk string length")?;
             if len == -1 {
                 // Null bulk string
-                return Ok((RespMessage::BulkString(None), &input[pos + 2..]));
+                return Ok(Parsed::Frame(RespMessage::BulkString(None), header));
+            }
+            if !(0..=MAX_BULK_LEN).contains(&len) {
+                return Err("Invalid bulk string length".to_string());
+            }
+            let end = header + len as usize;
+            if input.len() < end + 2 {
+                // The header's CRLF is found again at once next time.
+                return Ok(Parsed::Incomplete(0));
             }
-            let len = len as usize; // Safe cast since -1 is handled above
-            let start = pos + 2;
-            let end = start + len;
-            if input.len() < end + 2 || &input[end..end + 2] != b"\r\n" {
+            if &input[end..end + 2] != b"\r\n" {
                 return Err("Invalid bulk string data".to_string());
             }
-            let data = input[start..end].to_vec();
-            Ok((RespMessage::BulkString(Some(data)), &input[end + 2..]))
+            let data = input[header..end].to_vec();
+            Ok(Parsed::Frame(RespMessage::BulkString(Some(data)), end + 2))
         }
         b'*' => {
-            let pos = input
-                .windows(2)
-                .position(|w| w == b"\r\n")
-                .ok_or("Missing CRLF in array header")?;
-            let count_str = std::str::from_utf8(&input[1..pos]).map_err(|_| "Invalid UTF-8")?;
-            let count: usize = count_str.parse().map_err(|_| "Invalid array length")?;
-            let mut remaining = &input[pos + 2..];
-            let mut elements = Vec::new();
-            for _ in 0..count {
-                let (element, rem) = parse_resp_recursive(remaining)?;
-                elements.push(element);
-                remaining = rem;
+            if line == "-1" {
+                return Ok(Parsed::Frame(RespMessage::NullArray, header));
+            }
+            let count: usize = line.parse().map_err(|_| "Invalid array length")?;
+            if count > MAX_ARRAY_LEN {
+                return Err("Invalid array length".to_string());
+            }
+            if count == 0 {
+                return Ok(Parsed::Frame(RespMessage::Array(Vec::new()), header));
             }
-            Ok((RespMessage::Array(elements), remaining))
+            Ok(Parsed::ArrayHeader(count, header))
         }
-        _ => Err("Invalid message type".to_string()),
+        _ => unreachable!("checked above"),
     }
 }
@@ -92,3 +92,154 @@ fn test_parse_resp_invalid_bulk_string_length() {
     assert_eq!(result.unwrap_err(), "Invalid bulk string length");
 }
 
+#[test]
+fn test_decoder_waits_for_split_frame() {
+    let mut decoder = RespDecoder::new();
+    decoder.feed(b"*2\r\n$3\r\nGET\r\n$5\r\nhel");
+    assert_eq!(decoder.next_frame(), Ok(None));
+
+    decoder.feed(b"lo\r\n");
+    let expected = RespMessage::Array(vec![
+        RespMessage::BulkString(Some(b"GET".to_vec())),
+        RespMessage::BulkString(Some(b"hello".to_vec())),
+    ]);
+    assert_eq!(decoder.next_frame(), Ok(Some(expected)));
+    assert_eq!(decoder.next_frame(), Ok(None));
+}
+
+#[test]
+fn test_decoder_yields_pipelined_frames_in_order() {
+    let mut decoder = RespDecoder::new();
+    decoder.feed(b"+PING\r\n:1\r\n$3\r\nfoo\r\n*1\r\n$4\r\n");
+
+    assert_eq!(
+        decoder.next_frame(),
+        Ok(Some(RespMessage::SimpleString("PING".to_string())))
+    );
+    assert_eq!(decoder.next_frame(), Ok(Some(RespMessage::Integer(1))));
+    assert_eq!(
+        decoder.next_frame(),
+        Ok(Some(RespMessage::BulkString(Some(b"foo".to_vec()))))
+    );
+    // The trailing array is incomplete and must stay buffered.
+    assert_eq!(decoder.next_frame(), Ok(None));
+
+    decoder.feed(b"PING\r\n");
+    assert_eq!(
+        decoder.next_frame(),
+        Ok(Some(RespMessage::Array(vec![RespMessage::BulkString(
+            Some(b"PING".to_vec())
+        )])))
+    );
+}
+
+#[test]
+fn test_decoder_handles_byte_at_a_time_input() {
+    let input = b"*2\r\n$4\r\nECHO\r\n$2000\r\n";
+    let payload = vec![b'x'; 2000];
+    let mut stream = input.to_vec();
+    stream.extend_from_slice(&payload);
+    stream.extend_from_slice(b"\r\n");
+
+    let mut decoder = RespDecoder::new();
+    let mut frames = Vec::new();
+    for byte in &stream {
+        decoder.feed(std::slice::from_ref(byte));
+        while let Some(frame) = decoder.next_frame().unwrap() {
+            frames.push(frame);
+        }
+    }
+    assert_eq!(
+        frames,
+        vec![RespMessage::Array(vec![
+            RespMessage::BulkString(Some(b"ECHO".to_vec())),
+            RespMessage::BulkString(Some(payload)),
+        ])]
+    );
+}
+
+#[test]
+fn test_decoder_keeps_partly_read_arrays_pending() {
+    let mut decoder = RespDecoder::new();
+    decoder.feed(b"*3\r\n$3\r\nSET\r\n*1\r\n:1\r\n$5\r\nva");
+    assert_eq!(decoder.next_frame(), Ok(None));
+    assert_eq!(decoder.pending_len(), 27);
+
+    decoder.feed(b"lue\r\n+next");
+    assert_eq!(
+        decoder.next_frame(),
+        Ok(Some(RespMessage::Array(vec![
+            RespMessage::BulkString(Some(b"SET".to_vec())),
+            RespMessage::Array(vec![RespMessage::Integer(1)]),
+            RespMessage::BulkString(Some(b"value".to_vec())),
+        ])))
+    );
+    assert_eq!(decoder.next_frame(), Ok(None));
+    assert_eq!(decoder.pending_len(), 5);
+    decoder.feed(b"\r");
+    assert_eq!(decoder.next_frame(), Ok(None));
+    decoder.feed(b"\n");
+    assert_eq!(
+        decoder.next_frame(),
+        Ok(Some(RespMessage::SimpleString("next".to_string())))
+    );
+    assert_eq!(decoder.pending_len(), 0);
+}
+
+#[test]
+fn test_decoder_reports_protocol_errors() {
+    let mut decoder = RespDecoder::new();
+    decoder.feed(b"$3\r\nfoobar\r\n");
+    assert_eq!(
+        decoder.next_frame(),
+        Err("Invalid bulk string data".to_string())
+    );
+
+    let mut decoder = RespDecoder::new();
+    decoder.feed(b"?what\r\n");
+    assert_eq!(
+        decoder.next_frame(),
+        Err("Invalid message type".to_string())
+    );
+
+    let mut decoder = RespDecoder::new();
+    decoder.feed(b"$-5\r\n");
+    assert_eq!(
+        decoder.next_frame(),
+        Err("Invalid bulk string length".to_string())
+    );
+
+    let mut decoder = RespDecoder::new();
+    decoder.feed(&b"*1\r\n".repeat(1_000_000));
+    assert_eq!(
+        decoder.next_frame(),
+        Err("Arrays nested too deeply".to_string())
+    );
+}
+
+#[test]
+fn test_parse_resp_incomplete_message() {
+    let result = parse_resp(b"$5\r\nhel");
+    assert_eq!(result.unwrap_err(), "Incomplete message");
+}
+
+#[test]
+fn test_encode_round_trips_binary_bulk_strings() {
+    let payload = vec![0x00, 0xff, 0xfe, b'\r', b'\n', 0x80];
+    let message = RespMessage::Array(vec![
+        RespMessage::BulkString(Some(payload.clone())),
+        RespMessage::BulkString(None),
+        RespMessage::Integer(-7),
+        RespMessage::SimpleString("OK".to_string()),
+        RespMessage::Error("ERR boom".to_string()),
+    ]);
+
+    let mut out = Vec::new();
+    message.encode(&mut out);
+
+    let mut expected = b"*5\r\n$6\r\n".to_vec();
+    expected.extend_from_slice(&payload);
+    expected.extend_from_slice(b"\r\n$-1\r\n:-7\r\n+OK\r\n-ERR boom\r\n");
+    assert_eq!(out, expected);
+    assert_eq!(parse_resp(&out).unwrap(), message);
+}